@@ -0,0 +1 @@
+ALTER TABLE dispositions DROP COLUMN lot_designation;
@@ -0,0 +1,2 @@
+-- Specific identification: lots named on the disposition row for tax relief
+ALTER TABLE dispositions ADD COLUMN lot_designation TEXT;
@@ -0,0 +1,5 @@
+DROP INDEX dispositions_external_id;
+ALTER TABLE dispositions DROP COLUMN external_id;
+
+DROP INDEX acquisitions_external_id;
+ALTER TABLE acquisitions DROP COLUMN external_id;
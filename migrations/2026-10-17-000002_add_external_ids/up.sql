@@ -0,0 +1,6 @@
+-- External transaction ids from the import file, used to skip rows already imported
+ALTER TABLE acquisitions ADD COLUMN external_id TEXT;
+CREATE UNIQUE INDEX acquisitions_external_id ON acquisitions(external_id);
+
+ALTER TABLE dispositions ADD COLUMN external_id TEXT;
+CREATE UNIQUE INDEX dispositions_external_id ON dispositions(external_id);
@@ -0,0 +1,2 @@
+ALTER TABLE acquisition_dispositions DROP COLUMN fee;
+ALTER TABLE dispositions DROP COLUMN usd_cents_fee;
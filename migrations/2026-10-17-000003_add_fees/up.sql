@@ -0,0 +1,3 @@
+-- USD fee paid on a disposition, and the share of it deducted from each match's proceeds
+ALTER TABLE dispositions ADD COLUMN usd_cents_fee BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE acquisition_dispositions ADD COLUMN fee BIGINT NOT NULL DEFAULT 0;
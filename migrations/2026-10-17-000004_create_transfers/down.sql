@@ -0,0 +1,2 @@
+DROP TABLE transfer_acquisitions;
+DROP TABLE transfers;
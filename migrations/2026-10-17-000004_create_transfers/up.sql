@@ -0,0 +1,17 @@
+-- Custody movements recorded by `transfer`, and the lots each one moved into the destination wallet
+CREATE TABLE transfers (
+  id INTEGER PRIMARY KEY NOT NULL,
+  transfer_date DATETIME NOT NULL,
+  from_wallet TEXT NOT NULL,
+  to_wallet TEXT NOT NULL,
+  satoshis BIGINT NOT NULL,
+  fee_satoshis BIGINT NOT NULL DEFAULT 0,
+  fee_disposition_id INTEGER REFERENCES dispositions(id)
+);
+
+CREATE TABLE transfer_acquisitions (
+  transfer_id INTEGER REFERENCES transfers(id) NOT NULL,
+  acquisition_id INTEGER REFERENCES acquisitions(id) NOT NULL,
+  satoshis BIGINT NOT NULL,
+  PRIMARY KEY(transfer_id, acquisition_id)
+);
@@ -0,0 +1,2 @@
+ALTER TABLE acquisitions DROP COLUMN split_reason;
+ALTER TABLE acquisitions DROP COLUMN parent_acquisition_id;
@@ -0,0 +1,3 @@
+-- The lot a split fragment was carved from, and the command that split it
+ALTER TABLE acquisitions ADD COLUMN parent_acquisition_id INTEGER REFERENCES acquisitions(id);
+ALTER TABLE acquisitions ADD COLUMN split_reason TEXT;
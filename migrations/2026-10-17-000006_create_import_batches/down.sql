@@ -0,0 +1,3 @@
+ALTER TABLE dispositions DROP COLUMN import_batch_id;
+ALTER TABLE acquisitions DROP COLUMN import_batch_id;
+DROP TABLE import_batches;
@@ -0,0 +1,11 @@
+-- One row per run of `import`, so a mistaken import can be undone as a unit
+CREATE TABLE import_batches (
+  id INTEGER PRIMARY KEY NOT NULL,
+  file_name TEXT NOT NULL,
+  file_hash TEXT NOT NULL,
+  imported_at DATETIME NOT NULL,
+  row_count INTEGER NOT NULL
+);
+
+ALTER TABLE acquisitions ADD COLUMN import_batch_id INTEGER REFERENCES import_batches(id);
+ALTER TABLE dispositions ADD COLUMN import_batch_id INTEGER REFERENCES import_batches(id);
@@ -0,0 +1,9 @@
+-- Closed reporting periods. Matches and fair values dated on or before an active
+-- lock's date cannot be changed until it is reopened with a reason.
+CREATE TABLE period_locks (
+  id INTEGER PRIMARY KEY NOT NULL,
+  locked_through DATETIME NOT NULL,
+  closed_at DATETIME NOT NULL,
+  reopened_at DATETIME,
+  reopen_reason TEXT
+);
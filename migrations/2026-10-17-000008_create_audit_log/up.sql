@@ -0,0 +1,25 @@
+-- Append-only record of every command that changed the ledger. Each entry hashes the
+-- previous entry's hash, so a deleted or altered entry breaks the chain, and records a
+-- hash of the ledger tables so changes made outside the tool can be detected.
+CREATE TABLE audit_log (
+  id INTEGER PRIMARY KEY NOT NULL,
+  logged_at DATETIME NOT NULL,
+  command TEXT NOT NULL,
+  arguments TEXT NOT NULL,
+  file_hash TEXT,
+  input_rows INTEGER,
+  rows_changed INTEGER NOT NULL,
+  ledger_hash TEXT NOT NULL,
+  previous_hash TEXT NOT NULL,
+  entry_hash TEXT NOT NULL
+);
+
+CREATE TRIGGER audit_log_no_update BEFORE UPDATE ON audit_log
+BEGIN
+  SELECT RAISE(ABORT, 'audit_log is append-only');
+END;
+
+CREATE TRIGGER audit_log_no_delete BEFORE DELETE ON audit_log
+BEGIN
+  SELECT RAISE(ABORT, 'audit_log is append-only');
+END;
@@ -0,0 +1 @@
+ALTER TABLE acquisition_fair_values DROP COLUMN prior_usd_cents_btc_fair_value;
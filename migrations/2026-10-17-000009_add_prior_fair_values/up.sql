@@ -0,0 +1,2 @@
+-- Each marked lot's carrying value before the mark, so the mark can be undone
+ALTER TABLE acquisition_fair_values ADD COLUMN prior_usd_cents_btc_fair_value BIGINT;
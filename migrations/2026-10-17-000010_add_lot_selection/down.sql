@@ -0,0 +1,3 @@
+DROP TABLE passed_over_lots;
+ALTER TABLE acquisition_dispositions DROP COLUMN selection_rank;
+ALTER TABLE acquisition_dispositions DROP COLUMN lot_method;
@@ -0,0 +1,11 @@
+-- The rule that chose each match's lot and the lot's place in that order, and the tax
+-- candidates a disposition passed over, so the selection report shows what was applied
+ALTER TABLE acquisition_dispositions ADD COLUMN lot_method TEXT;
+ALTER TABLE acquisition_dispositions ADD COLUMN selection_rank INTEGER;
+CREATE TABLE passed_over_lots (
+  disposition_id INTEGER NOT NULL REFERENCES dispositions(id),
+  acquisition_id INTEGER NOT NULL REFERENCES acquisitions(id),
+  selection_rank INTEGER NOT NULL,
+  satoshis BIGINT NOT NULL,
+  PRIMARY KEY (disposition_id, acquisition_id)
+);
@@ -3,4 +3,20 @@ pub mod report;
 pub mod holdings;
 pub mod mark_to_market;
 pub mod allocate;
-pub mod transfer;
\ No newline at end of file
+pub mod transfer;
+pub mod rematch;
+pub mod rebuild;
+pub mod preview;
+pub mod transfer_report;
+pub mod lot_history;
+pub mod undo_import;
+pub mod edit_transaction;
+pub mod period;
+pub mod audit;
+pub mod check;
+pub mod undo_mark_to_market;
+pub mod unrealized;
+pub mod rollforward;
+pub mod journal;
+pub mod form_8949;
+pub mod txf;
\ No newline at end of file
@@ -6,7 +6,9 @@ use rust_decimal::Decimal;
 use serde::Deserialize;
 
 use crate::rounding_div;
-use crate::models::{Acquisition, NewAcquisition};
+use crate::commands::audit::AuditCommand;
+use crate::commands::preview::{ChangePreview, with_preview};
+use crate::models::{Acquisition, AcquisitionFairValue, NewAcquisition};
 use crate::schema::{acquisitions, dispositions, acquisition_fair_values};
 
 #[derive(Debug)]
@@ -44,9 +46,10 @@ struct BucketRecord {
     btc: String,
 }
 
-pub fn allocate(file: &PathBuf, conn: &mut SqliteConnection) -> Result<(), String> {
-    let mut rdr = csv::Reader::from_path(file)
+pub fn allocate(file: &PathBuf, conn: &mut SqliteConnection, dry_run: bool) -> Result<ChangePreview, String> {
+    let contents = std::fs::read(file)
         .map_err(|e| format!("Error reading file {:?}: {}", file, e))?;
+    let mut rdr = csv::Reader::from_reader(contents.as_slice());
 
     let mut buckets: Vec<(String, i64)> = Vec::new();
     for result in rdr.deserialize::<BucketRecord>() {
@@ -59,7 +62,10 @@ pub fn allocate(file: &PathBuf, conn: &mut SqliteConnection) -> Result<(), Strin
         buckets.push((record.wallet, sats));
     }
 
-    conn.transaction::<(), AllocateError, _>(|conn| {
+    let audit = AuditCommand::new("allocate", serde_json::json!({ "file": file }))
+        .with_file(&contents, buckets.len());
+
+    let (_, preview) = with_preview(conn, dry_run, &audit, |conn| conn.transaction::<(), AllocateError, _>(|conn| {
         // Validate that GAAP and tax trackers are equal on all undisposed lots
         let all_lots: Vec<Acquisition> = acquisitions::table
             .filter(acquisitions::undisposed_satoshis.gt(0))
@@ -150,6 +156,10 @@ pub fn allocate(file: &PathBuf, conn: &mut SqliteConnection) -> Result<(), Strin
                         usd_cents_btc_fair_value: lot.usd_cents_btc_fair_value,
                         wallet: "unallocated".to_string(),
                         tax_undisposed_satoshis: excess,
+                        external_id: None,
+                        parent_acquisition_id: Some(lot.id),
+                        split_reason: Some("allocate".to_string()),
+                        import_batch_id: lot.import_batch_id,
                     };
 
                     diesel::insert_into(acquisitions::table)
@@ -165,18 +175,15 @@ pub fn allocate(file: &PathBuf, conn: &mut SqliteConnection) -> Result<(), Strin
                         .map_err(|e| format!("Error fetching new split lot: {}", e))?;
 
                     // Copy acquisition_fair_values from original lot to new lot
-                    let fair_value_ids: Vec<i32> = acquisition_fair_values::table
+                    let fair_value_links: Vec<AcquisitionFairValue> = acquisition_fair_values::table
                         .filter(acquisition_fair_values::acquisition_id.eq(lot.id))
-                        .select(acquisition_fair_values::fair_value_id)
+                        .select(AcquisitionFairValue::as_select())
                         .load(conn)
                         .map_err(|e| format!("Error fetching fair value links: {}", e))?;
 
-                    for fv_id in fair_value_ids {
+                    for link in fair_value_links {
                         diesel::insert_into(acquisition_fair_values::table)
-                            .values((
-                                acquisition_fair_values::acquisition_id.eq(new_acq.id),
-                                acquisition_fair_values::fair_value_id.eq(fv_id),
-                            ))
+                            .values(AcquisitionFairValue { acquisition_id: new_acq.id, ..link })
                             .execute(conn)
                             .map_err(|e| format!("Error copying fair value link: {}", e))?;
                     }
@@ -197,5 +204,7 @@ pub fn allocate(file: &PathBuf, conn: &mut SqliteConnection) -> Result<(), Strin
             .map_err(|e| format!("Error updating disposition wallets: {}", e))?;
 
         Ok(())
-    }).map_err(|e| e.to_string())
+    }).map_err(|e| e.to_string()))?;
+
+    Ok(preview)
 }
@@ -0,0 +1,278 @@
+use chrono::{NaiveDateTime, Timelike, Utc};
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use sha2::{Digest, Sha256};
+
+use crate::models::{Acquisition, AcquisitionDisposition, AcquisitionFairValue, AuditEntry, Disposition, FairValue};
+use crate::models::{ImportBatch, PassedOverLot, PeriodLock, Transfer, TransferAcquisition};
+use crate::schema::{acquisitions, acquisition_dispositions, acquisition_fair_values, audit_log, dispositions, fair_values};
+use crate::schema::{import_batches, passed_over_lots, period_locks, transfers, transfer_acquisitions};
+
+/// The `previous_hash` of the first entry in the chain.
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// A command that changes the ledger, as it will be written to the audit log.
+#[derive(Debug, Clone)]
+pub struct AuditCommand {
+    pub command: String,
+    /// The command's arguments as JSON
+    pub arguments: String,
+    pub file_hash: Option<String>,
+    pub input_rows: Option<i32>,
+}
+
+impl AuditCommand {
+    pub fn new(command: &str, arguments: serde_json::Value) -> Self {
+        AuditCommand {
+            command: command.to_string(),
+            arguments: arguments.to_string(),
+            file_hash: None,
+            input_rows: None,
+        }
+    }
+
+    /// Records the SHA-256 of the input file and the number of rows read from it.
+    pub fn with_file(mut self, contents: &[u8], rows: usize) -> Self {
+        self.file_hash = Some(file_sha256(contents));
+        self.input_rows = Some(rows as i32);
+        self
+    }
+}
+
+/// What `verify_audit` found.
+#[derive(Debug, Default)]
+pub struct AuditVerification {
+    pub entries: usize,
+    /// One message per broken link, altered entry, or ledger change made outside the tool
+    pub breaks: Vec<String>,
+}
+
+pub fn file_sha256(contents: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(contents))
+}
+
+/// The most recent audit log entry. Its `entry_hash` covers the whole chain and the
+/// ledger as it stood after the entry's command, so a copy kept outside the database
+/// shows whether the log was later rewritten.
+pub fn latest_entry(conn: &mut SqliteConnection) -> Result<Option<AuditEntry>, String> {
+    audit_log::table
+        .order(audit_log::id.desc())
+        .select(AuditEntry::as_select())
+        .first(conn)
+        .optional()
+        .map_err(|e| format!("Error fetching last audit log entry: {}", e))
+}
+
+/// Appends `command` to the audit log with a hash of the ledger as it stands now.
+/// Call it inside the command's transaction, after its changes, so the entry is
+/// saved or rolled back with them.
+pub fn record(conn: &mut SqliteConnection, command: &AuditCommand, rows_changed: i64) -> Result<AuditEntry, String> {
+    let last = latest_entry(conn)?;
+
+    let mut entry = AuditEntry {
+        id: last.as_ref().map_or(1, |l| l.id + 1),
+        logged_at: Utc::now().naive_utc().with_nanosecond(0).unwrap(),
+        command: command.command.clone(),
+        arguments: command.arguments.clone(),
+        file_hash: command.file_hash.clone(),
+        input_rows: command.input_rows,
+        rows_changed,
+        ledger_hash: ledger_hash(conn)?,
+        previous_hash: last.map_or(GENESIS_HASH.to_string(), |l| l.entry_hash),
+        entry_hash: String::new(),
+    };
+    entry.entry_hash = entry_hash(&entry);
+
+    diesel::insert_into(audit_log::table)
+        .values(&entry)
+        .execute(conn)
+        .map_err(|e| format!("Error writing audit log entry: {}", e))?;
+    Ok(entry)
+}
+
+/// Recomputes the hash chain and checks that the ledger still matches the hash recorded
+/// by the latest entry. Every break found is reported rather than stopping at the first.
+pub fn verify_audit(conn: &mut SqliteConnection) -> Result<AuditVerification, String> {
+    let entries: Vec<AuditEntry> = audit_log::table
+        .order(audit_log::id.asc())
+        .select(AuditEntry::as_select())
+        .load(conn)
+        .map_err(|e| format!("Error fetching audit log: {}", e))?;
+
+    let mut verification = AuditVerification { entries: entries.len(), breaks: Vec::new() };
+    let mut expected_previous = GENESIS_HASH.to_string();
+    let mut expected_id = 1;
+    for entry in &entries {
+        if entry.id == expected_id + 1 {
+            verification.breaks.push(format!("Entry {} is missing", expected_id));
+        } else if entry.id != expected_id {
+            verification.breaks.push(format!("Entries {} through {} are missing", expected_id, entry.id - 1));
+        }
+        if entry.previous_hash != expected_previous {
+            verification.breaks.push(format!(
+                "Entry {} ({}) does not follow the entry before it", entry.id, entry.command
+            ));
+        }
+        if entry_hash(entry) != entry.entry_hash {
+            verification.breaks.push(format!(
+                "Entry {} ({}) has been altered since it was logged", entry.id, entry.command
+            ));
+        }
+        expected_previous = entry.entry_hash.clone();
+        expected_id = entry.id + 1;
+    }
+
+    if let Some(last) = entries.last() {
+        if ledger_hash(conn)? != last.ledger_hash {
+            verification.breaks.push(format!(
+                "The ledger has changed since entry {} ({}) without an audit log entry", last.id, last.command
+            ));
+        }
+    }
+
+    Ok(verification)
+}
+
+fn entry_hash(entry: &AuditEntry) -> String {
+    let line = format!(
+        "{}|{}|{}|{}|{}|{}|{}|{}|{}",
+        entry.previous_hash,
+        entry.id,
+        entry.logged_at.format("%Y-%m-%d %H:%M:%S"),
+        entry.command,
+        entry.arguments,
+        entry.file_hash.as_deref().unwrap_or(""),
+        entry.input_rows.map_or(String::new(), |r| r.to_string()),
+        entry.rows_changed,
+        entry.ledger_hash,
+    );
+    file_sha256(line.as_bytes())
+}
+
+/// SHA-256 over every lot, disposition, match, passed over lot, fair value and the lots
+/// it marked, transfer and the lots it moved, import batch and period lock.
+fn ledger_hash(conn: &mut SqliteConnection) -> Result<String, String> {
+    let mut hasher = Sha256::new();
+    let mut line = |fields: String| {
+        hasher.update(fields.as_bytes());
+        hasher.update(b"\n");
+    };
+    let date = |d: NaiveDateTime| d.format("%Y-%m-%d %H:%M:%S%.f").to_string();
+
+    let lots: Vec<Acquisition> = acquisitions::table
+        .order(acquisitions::id.asc())
+        .select(Acquisition::as_select())
+        .load(conn)
+        .map_err(|e| format!("Error fetching acquisitions: {}", e))?;
+    for a in lots {
+        line(format!(
+            "acquisition|{}|{}|{}|{}|{}|{}|{}|{}|{:?}|{:?}|{:?}|{:?}",
+            a.id, date(a.acquisition_date), a.satoshis, a.undisposed_satoshis, a.tax_undisposed_satoshis,
+            a.usd_cents_btc_basis, a.usd_cents_btc_fair_value, a.wallet, a.external_id, a.parent_acquisition_id,
+            a.split_reason, a.import_batch_id,
+        ));
+    }
+
+    let disps: Vec<Disposition> = dispositions::table
+        .order(dispositions::id.asc())
+        .select(Disposition::as_select())
+        .load(conn)
+        .map_err(|e| format!("Error fetching dispositions: {}", e))?;
+    for d in disps {
+        line(format!(
+            "disposition|{}|{}|{}|{}|{}|{}|{}|{}|{:?}|{:?}|{:?}",
+            d.id, date(d.disposition_date), d.satoshis, d.undisposed_satoshis, d.tax_undisposed_satoshis,
+            d.usd_cents_btc_basis, d.usd_cents_fee, d.wallet, d.lot_designation, d.external_id, d.import_batch_id,
+        ));
+    }
+
+    let matches: Vec<AcquisitionDisposition> = acquisition_dispositions::table
+        .order((acquisition_dispositions::acquisition_id.asc(), acquisition_dispositions::disposition_id.asc(), acquisition_dispositions::match_type.asc()))
+        .select(AcquisitionDisposition::as_select())
+        .load(conn)
+        .map_err(|e| format!("Error fetching acquisition_dispositions: {}", e))?;
+    for m in matches {
+        line(format!(
+            "match|{}|{}|{}|{}|{}|{}|{}|{}|{:?}|{:?}",
+            m.acquisition_id, m.disposition_id, m.match_type, m.satoshis, m.basis, m.rgl, m.fee, m.term,
+            m.lot_method, m.selection_rank,
+        ));
+    }
+
+    let passed_over: Vec<PassedOverLot> = passed_over_lots::table
+        .order((passed_over_lots::disposition_id.asc(), passed_over_lots::acquisition_id.asc()))
+        .select(PassedOverLot::as_select())
+        .load(conn)
+        .map_err(|e| format!("Error fetching passed over lots: {}", e))?;
+    for p in passed_over {
+        line(format!("passed_over_lot|{}|{}|{}|{}", p.disposition_id, p.acquisition_id, p.selection_rank, p.satoshis));
+    }
+
+    let marks: Vec<FairValue> = fair_values::table
+        .order(fair_values::id.asc())
+        .select(FairValue::as_select())
+        .load(conn)
+        .map_err(|e| format!("Error fetching fair values: {}", e))?;
+    for f in marks {
+        line(format!("fair_value|{}|{}|{}", f.id, date(f.date), f.fair_value_cents));
+    }
+
+    let marked: Vec<AcquisitionFairValue> = acquisition_fair_values::table
+        .order((acquisition_fair_values::fair_value_id.asc(), acquisition_fair_values::acquisition_id.asc()))
+        .select(AcquisitionFairValue::as_select())
+        .load(conn)
+        .map_err(|e| format!("Error fetching acquisition fair values: {}", e))?;
+    for m in marked {
+        line(format!(
+            "acquisition_fair_value|{}|{}|{:?}",
+            m.fair_value_id, m.acquisition_id, m.prior_usd_cents_btc_fair_value,
+        ));
+    }
+
+    let moves: Vec<Transfer> = transfers::table
+        .order(transfers::id.asc())
+        .select(Transfer::as_select())
+        .load(conn)
+        .map_err(|e| format!("Error fetching transfers: {}", e))?;
+    for t in moves {
+        line(format!(
+            "transfer|{}|{}|{}|{}|{}|{}|{:?}",
+            t.id, date(t.transfer_date), t.from_wallet, t.to_wallet, t.satoshis, t.fee_satoshis, t.fee_disposition_id,
+        ));
+    }
+
+    let moved: Vec<TransferAcquisition> = transfer_acquisitions::table
+        .order((transfer_acquisitions::transfer_id.asc(), transfer_acquisitions::acquisition_id.asc()))
+        .select(TransferAcquisition::as_select())
+        .load(conn)
+        .map_err(|e| format!("Error fetching transfer acquisitions: {}", e))?;
+    for m in moved {
+        line(format!("transfer_acquisition|{}|{}|{}", m.transfer_id, m.acquisition_id, m.satoshis));
+    }
+
+    let batches: Vec<ImportBatch> = import_batches::table
+        .order(import_batches::id.asc())
+        .select(ImportBatch::as_select())
+        .load(conn)
+        .map_err(|e| format!("Error fetching import batches: {}", e))?;
+    for b in batches {
+        line(format!(
+            "import_batch|{}|{}|{}|{}|{}",
+            b.id, b.file_name, b.file_hash, date(b.imported_at), b.row_count,
+        ));
+    }
+
+    let locks: Vec<PeriodLock> = period_locks::table
+        .order(period_locks::id.asc())
+        .select(PeriodLock::as_select())
+        .load(conn)
+        .map_err(|e| format!("Error fetching period locks: {}", e))?;
+    for l in locks {
+        line(format!(
+            "period_lock|{}|{}|{}|{:?}|{:?}",
+            l.id, date(l.locked_through), date(l.closed_at), l.reopened_at.map(date), l.reopen_reason,
+        ));
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
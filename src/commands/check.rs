@@ -0,0 +1,194 @@
+use std::collections::{BTreeMap, HashMap};
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use rust_decimal::Decimal;
+
+use crate::LotConfig;
+use crate::rounding_div;
+use crate::commands::import::{carrying_value_as_of, lot_term};
+use crate::models::{Acquisition, AcquisitionDisposition, Disposition, FairValue};
+use crate::schema::{acquisitions, dispositions, acquisition_dispositions, fair_values};
+
+/// What `check_ledger` found.
+#[derive(Debug, Default)]
+pub struct CheckReport {
+    pub lots: usize,
+    pub dispositions: usize,
+    pub matches: usize,
+    /// One message per broken invariant, naming the rows involved
+    pub violations: Vec<String>,
+}
+
+fn usd(cents: i64) -> Decimal {
+    Decimal::new(cents, 2)
+}
+
+fn match_label(match_type: &str) -> &'static str {
+    if match_type == "gaap" { "GAAP" } else { "tax" }
+}
+
+/// Checks the invariants matching relies on and reports every violation rather than
+/// stopping at the first:
+///
+/// - each lot's sats less its undisposed trackers equal the sats its GAAP and tax
+///   matches relieve. Lots split by `allocate` or `transfer` are checked together with
+///   the lot they were split from, since a split moves sats but not matches.
+/// - each disposition is fully matched, its trackers agree with its matches, and its
+///   matches' fees add up to its fee
+/// - no match relieves a lot acquired after the disposition, and each match's term
+///   agrees with its dates
+/// - each match's basis and RGL recompute from the stored prices. GAAP basis under the
+///   `average` method depends on the pool when the match was made and is not recomputed.
+pub fn check_ledger(conn: &mut SqliteConnection, config: &LotConfig) -> Result<CheckReport, String> {
+    let lots: Vec<Acquisition> = acquisitions::table
+        .order(acquisitions::id.asc())
+        .select(Acquisition::as_select())
+        .load(conn)
+        .map_err(|e| format!("Error fetching acquisitions: {}", e))?;
+    let disps: Vec<Disposition> = dispositions::table
+        .order(dispositions::id.asc())
+        .select(Disposition::as_select())
+        .load(conn)
+        .map_err(|e| format!("Error fetching dispositions: {}", e))?;
+    let matches: Vec<AcquisitionDisposition> = acquisition_dispositions::table
+        .order((acquisition_dispositions::disposition_id.asc(), acquisition_dispositions::acquisition_id.asc(), acquisition_dispositions::match_type.asc()))
+        .select(AcquisitionDisposition::as_select())
+        .load(conn)
+        .map_err(|e| format!("Error fetching acquisition_dispositions: {}", e))?;
+    let marks: Vec<FairValue> = fair_values::table
+        .order((fair_values::date.asc(), fair_values::id.asc()))
+        .select(FairValue::as_select())
+        .load(conn)
+        .map_err(|e| format!("Error fetching fair values: {}", e))?;
+
+    let mut report = CheckReport {
+        lots: lots.len(),
+        dispositions: disps.len(),
+        matches: matches.len(),
+        violations: Vec::new(),
+    };
+
+    let lots_by_id: HashMap<i32, &Acquisition> = lots.iter().map(|l| (l.id, l)).collect();
+    let disps_by_id: HashMap<i32, &Disposition> = disps.iter().map(|d| (d.id, d)).collect();
+
+    // Sats relieved per lot and per disposition, and fees deducted per disposition
+    let mut lot_relieved: HashMap<(i32, &str), i64> = HashMap::new();
+    let mut disp_relieved: HashMap<(i32, &str), i64> = HashMap::new();
+    let mut disp_fees: HashMap<(i32, &str), i64> = HashMap::new();
+    for m in &matches {
+        *lot_relieved.entry((m.acquisition_id, m.match_type.as_str())).or_default() += m.satoshis;
+        *disp_relieved.entry((m.disposition_id, m.match_type.as_str())).or_default() += m.satoshis;
+        *disp_fees.entry((m.disposition_id, m.match_type.as_str())).or_default() += m.fee;
+    }
+
+    // Group split lots under the lot they were originally split from
+    let mut families: BTreeMap<i32, Vec<&Acquisition>> = BTreeMap::new();
+    for lot in &lots {
+        let mut root = lot;
+        while let Some(parent) = root.parent_acquisition_id.and_then(|id| lots_by_id.get(&id)) {
+            root = parent;
+        }
+        families.entry(root.id).or_default().push(lot);
+    }
+
+    for (root_id, family) in &families {
+        let name = if family.len() == 1 {
+            format!("Lot {}", root_id)
+        } else {
+            let splits: Vec<String> = family.iter().filter(|l| l.id != *root_id).map(|l| l.id.to_string()).collect();
+            format!("Lot {} with its split lots {}", root_id, splits.join(", "))
+        };
+        let held: i64 = family.iter().map(|l| l.satoshis).sum();
+        for (match_type, tracker) in [("gaap", "undisposed_satoshis"), ("tax", "tax_undisposed_satoshis")] {
+            let undisposed: i64 = family.iter()
+                .map(|l| if match_type == "gaap" { l.undisposed_satoshis } else { l.tax_undisposed_satoshis })
+                .sum();
+            let relieved: i64 = family.iter()
+                .map(|l| lot_relieved.get(&(l.id, match_type)).copied().unwrap_or(0))
+                .sum();
+            if held - undisposed != relieved {
+                report.violations.push(format!(
+                    "{}: satoshis less {} is {}, but its {} matches relieve {} sats",
+                    name, tracker, held - undisposed, match_label(match_type), relieved
+                ));
+            }
+        }
+    }
+
+    for disp in &disps {
+        for match_type in ["gaap", "tax"] {
+            let relieved = disp_relieved.get(&(disp.id, match_type)).copied().unwrap_or(0);
+            let undisposed = if match_type == "gaap" { disp.undisposed_satoshis } else { disp.tax_undisposed_satoshis };
+            if relieved != -disp.satoshis {
+                report.violations.push(format!(
+                    "Disposition {} is for {} sats, but its {} matches relieve {} sats",
+                    disp.id, -disp.satoshis, match_label(match_type), relieved
+                ));
+            }
+            if disp.satoshis + relieved != undisposed {
+                report.violations.push(format!(
+                    "Disposition {} has {} undisposed sats for {} matching, but its matches leave {} unmatched",
+                    disp.id, -undisposed, match_label(match_type), -(disp.satoshis + relieved)
+                ));
+            }
+            let fees = disp_fees.get(&(disp.id, match_type)).copied().unwrap_or(0);
+            if relieved == -disp.satoshis && fees != disp.usd_cents_fee {
+                report.violations.push(format!(
+                    "Disposition {}'s {} matches deduct fees of ${}, but its fee is ${}",
+                    disp.id, match_label(match_type), usd(fees), usd(disp.usd_cents_fee)
+                ));
+            }
+        }
+    }
+
+    for m in &matches {
+        let name = format!("{} match of lot {} to disposition {}",
+            if m.match_type == "gaap" { "GAAP" } else { "Tax" }, m.acquisition_id, m.disposition_id);
+        let (Some(lot), Some(disp)) = (lots_by_id.get(&m.acquisition_id), disps_by_id.get(&m.disposition_id)) else {
+            report.violations.push(format!("{} refers to a lot or disposition that does not exist", name));
+            continue;
+        };
+
+        if disp.disposition_date < lot.acquisition_date {
+            report.violations.push(format!(
+                "{}: the disposition on {} is before the lot was acquired on {}",
+                name, disp.disposition_date.format("%Y-%m-%d"), lot.acquisition_date.format("%Y-%m-%d")
+            ));
+        }
+        let term = lot_term(lot.acquisition_date, disp.disposition_date);
+        if m.term != term {
+            report.violations.push(format!(
+                "{}: term is '{}', but the lot was held {} days, which is '{}'",
+                name, m.term, (disp.disposition_date - lot.acquisition_date).num_days(), term
+            ));
+        }
+
+        let price = if m.match_type == "tax" {
+            Some(lot.usd_cents_btc_basis)
+        } else if config.gaap_lot_method != "average" {
+            Some(carrying_value_as_of(&marks, lot, disp.disposition_date))
+        } else {
+            None
+        };
+        if let Some(price) = price {
+            let basis = rounding_div(m.satoshis as i128 * price as i128, 100_000_000);
+            if m.basis != basis {
+                report.violations.push(format!(
+                    "{}: basis is ${}, but {} sats at ${} per BTC is ${}",
+                    name, usd(m.basis), m.satoshis, usd(price), usd(basis)
+                ));
+            }
+        }
+
+        let proceeds = rounding_div(m.satoshis as i128 * disp.usd_cents_btc_basis as i128, 100_000_000);
+        let rgl = proceeds - m.fee - m.basis;
+        if m.rgl != rgl {
+            report.violations.push(format!(
+                "{}: RGL is ${}, but proceeds of ${} less fee of ${} and basis of ${} is ${}",
+                name, usd(m.rgl), usd(proceeds), usd(m.fee), usd(m.basis), usd(rgl)
+            ));
+        }
+    }
+
+    Ok(report)
+}
@@ -0,0 +1,319 @@
+use std::collections::{BTreeMap, BTreeSet};
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use diesel::Connection;
+use rust_decimal::Decimal;
+use serde::de::value::{Error as ValueError, StrDeserializer};
+
+use crate::LotConfig;
+use crate::commands::audit::AuditCommand;
+use crate::commands::preview::with_preview;
+use crate::commands::rematch::{unwind_from, replay_matches, refresh_carrying_values};
+use crate::models::{Acquisition, AcquisitionDisposition, Disposition, deserialize_bitcoin, deserialize_price, parse_date_str};
+use crate::schema::{acquisitions, dispositions, acquisition_dispositions, acquisition_fair_values, transfers, transfer_acquisitions};
+
+#[derive(Debug)]
+enum EditError {
+    Diesel(diesel::result::Error),
+    Custom(String),
+}
+
+impl From<diesel::result::Error> for EditError {
+    fn from(e: diesel::result::Error) -> Self {
+        EditError::Diesel(e)
+    }
+}
+
+impl From<String> for EditError {
+    fn from(s: String) -> Self {
+        EditError::Custom(s)
+    }
+}
+
+impl std::fmt::Display for EditError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EditError::Diesel(e) => write!(f, "{}", e),
+            EditError::Custom(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+/// Corrections to an acquisition or disposition, in the import file's formats. Fields
+/// left as `None` are unchanged. `bitcoin` is negative for dispositions, as on import.
+#[derive(Debug, Default, Clone)]
+pub struct TransactionEdit {
+    pub date: Option<String>,
+    pub bitcoin: Option<String>,
+    pub price: Option<String>,
+    pub wallet: Option<String>,
+}
+
+/// A disposition whose total realized gain/loss for one match type changed, in cents.
+#[derive(Debug, PartialEq, Eq)]
+pub struct RglChange {
+    pub disposition_id: i32,
+    pub match_type: String,
+    pub before: i64,
+    pub after: i64,
+}
+
+/// Matches replayed by an edit or delete and the realized gain/loss it moved.
+#[derive(Debug, Default)]
+pub struct CorrectionSummary {
+    pub matches_removed: usize,
+    pub matches_created: usize,
+    pub tax_rgl_before: i64,
+    pub tax_rgl_after: i64,
+    pub gaap_rgl_before: i64,
+    pub gaap_rgl_after: i64,
+    pub rgl_changes: Vec<RglChange>,
+}
+
+impl std::fmt::Display for CorrectionSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Matches: {} removed, {} created", self.matches_removed, self.matches_created)?;
+        writeln!(f, "Tax RGL:  ${} -> ${}", Decimal::new(self.tax_rgl_before, 2), Decimal::new(self.tax_rgl_after, 2))?;
+        writeln!(f, "GAAP RGL: ${} -> ${}", Decimal::new(self.gaap_rgl_before, 2), Decimal::new(self.gaap_rgl_after, 2))?;
+        if !self.rgl_changes.is_empty() {
+            writeln!(f, "Dispositions with changed RGL: {}", self.rgl_changes.len())?;
+            for change in &self.rgl_changes {
+                writeln!(f, "  disposition {} {} ${} -> ${}",
+                    change.disposition_id, change.match_type,
+                    Decimal::new(change.before, 2), Decimal::new(change.after, 2))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Applies `edit` to acquisition or disposition `id` (`kind` is "acquisition" or
+/// "disposition"). Every tax and GAAP match for dispositions dated on or after the
+/// earlier of its old and new date is unwound, the row is updated, and matching is
+/// replayed, all in one transaction.
+pub fn edit_transaction(
+    kind: &str,
+    id: i32,
+    edit: &TransactionEdit,
+    conn: &mut SqliteConnection,
+    config: &LotConfig,
+) -> Result<CorrectionSummary, String> {
+    if edit.date.is_none() && edit.bitcoin.is_none() && edit.price.is_none() && edit.wallet.is_none() {
+        return Err("Nothing to edit: provide a new date, bitcoin amount, price or wallet".to_string());
+    }
+    let date = edit.date.as_deref().map(parse_date_str).transpose()?;
+    let satoshis = edit.bitcoin.as_deref()
+        .map(|b| deserialize_bitcoin(StrDeserializer::<ValueError>::new(b)).map_err(|e| e.to_string()))
+        .transpose()?;
+    let price = edit.price.as_deref()
+        .map(|p| deserialize_price(StrDeserializer::<ValueError>::new(p)).map_err(|e| e.to_string()))
+        .transpose()?;
+
+    let audit = AuditCommand::new("edit-transaction", serde_json::json!({
+        "kind": kind,
+        "id": id,
+        "date": edit.date,
+        "bitcoin": edit.bitcoin,
+        "price": edit.price,
+        "wallet": edit.wallet,
+    }));
+    correct(conn, config, &audit, |conn| match kind {
+        "acquisition" => {
+            let lot = find_editable_lot(conn, id)?;
+            if satoshis.is_some_and(|s| s <= 0) {
+                return Err(format!("Acquisition {} must keep a positive Bitcoin amount", id));
+            }
+            let from = date.map_or(lot.acquisition_date, |d| d.min(lot.acquisition_date));
+            let unwound = unwind_from(conn, config, from)?;
+
+            // Every match on the lot was unwound, so its trackers start over from the new amount
+            let sats = satoshis.unwrap_or(lot.satoshis);
+            let basis = price.unwrap_or(lot.usd_cents_btc_basis);
+            diesel::update(acquisitions::table.find(id))
+                .set((
+                    acquisitions::acquisition_date.eq(date.unwrap_or(lot.acquisition_date)),
+                    acquisitions::satoshis.eq(sats),
+                    acquisitions::undisposed_satoshis.eq(sats),
+                    acquisitions::tax_undisposed_satoshis.eq(sats),
+                    acquisitions::usd_cents_btc_basis.eq(basis),
+                    acquisitions::usd_cents_btc_fair_value.eq(basis),
+                    acquisitions::wallet.eq(edit.wallet.as_ref().unwrap_or(&lot.wallet)),
+                ))
+                .execute(conn)
+                .map_err(|e| format!("Error updating acquisition {}: {}", id, e))?;
+            Ok(unwound)
+        }
+        "disposition" => {
+            let disp = find_editable_disposition(conn, id)?;
+            if satoshis.is_some_and(|s| s >= 0) {
+                return Err(format!("Disposition {} must keep a negative Bitcoin amount", id));
+            }
+            let from = date.map_or(disp.disposition_date, |d| d.min(disp.disposition_date));
+            let unwound = unwind_from(conn, config, from)?;
+
+            let sats = satoshis.unwrap_or(disp.satoshis);
+            diesel::update(dispositions::table.find(id))
+                .set((
+                    dispositions::disposition_date.eq(date.unwrap_or(disp.disposition_date)),
+                    dispositions::satoshis.eq(sats),
+                    dispositions::undisposed_satoshis.eq(sats),
+                    dispositions::tax_undisposed_satoshis.eq(sats),
+                    dispositions::usd_cents_btc_basis.eq(price.unwrap_or(disp.usd_cents_btc_basis)),
+                    dispositions::wallet.eq(edit.wallet.as_ref().unwrap_or(&disp.wallet)),
+                ))
+                .execute(conn)
+                .map_err(|e| format!("Error updating disposition {}: {}", id, e))?;
+            Ok(unwound)
+        }
+        _ => Err(format!("Unsupported transaction kind '{}'. Must be 'acquisition' or 'disposition'.", kind)),
+    })
+}
+
+/// Deletes acquisition or disposition `id` (`kind` is "acquisition" or "disposition"),
+/// unwinding every match from its date forward and replaying matching without it.
+pub fn delete_transaction(
+    kind: &str,
+    id: i32,
+    conn: &mut SqliteConnection,
+    config: &LotConfig,
+) -> Result<CorrectionSummary, String> {
+    let audit = AuditCommand::new("delete-transaction", serde_json::json!({ "kind": kind, "id": id }));
+    correct(conn, config, &audit, |conn| match kind {
+        "acquisition" => {
+            let lot = find_editable_lot(conn, id)?;
+            let unwound = unwind_from(conn, config, lot.acquisition_date)?;
+            diesel::delete(acquisition_fair_values::table.filter(acquisition_fair_values::acquisition_id.eq(id)))
+                .execute(conn)
+                .map_err(|e| format!("Error deleting acquisition fair values: {}", e))?;
+            diesel::delete(acquisitions::table.find(id))
+                .execute(conn)
+                .map_err(|e| format!("Error deleting acquisition {}: {}", id, e))?;
+            Ok(unwound)
+        }
+        "disposition" => {
+            let disp = find_editable_disposition(conn, id)?;
+            let unwound = unwind_from(conn, config, disp.disposition_date)?;
+            diesel::delete(dispositions::table.find(id))
+                .execute(conn)
+                .map_err(|e| format!("Error deleting disposition {}: {}", id, e))?;
+            Ok(unwound)
+        }
+        _ => Err(format!("Unsupported transaction kind '{}'. Must be 'acquisition' or 'disposition'.", kind)),
+    })
+}
+
+/// Runs `change`, which unwinds matches, rewrites rows and returns the number of
+/// matches it unwound, then replays matching and diffs realized gain/loss.
+fn correct<F>(conn: &mut SqliteConnection, config: &LotConfig, audit: &AuditCommand, change: F) -> Result<CorrectionSummary, String>
+where
+    F: FnOnce(&mut SqliteConnection) -> Result<usize, String>,
+{
+    with_preview(conn, false, audit, |conn| conn.transaction::<CorrectionSummary, EditError, _>(|conn| {
+        let before = load_matches(conn)?;
+        let unwound = change(conn)?;
+        replay_matches(conn, config, unwound)?;
+        // An edited price resets the lot to cost; reapply any marks it was held for
+        if config.gaap_lot_method != "average" {
+            refresh_carrying_values(conn)?;
+        }
+        let after = load_matches(conn)?;
+
+        let before_totals = rgl_by_disposition(&before);
+        let after_totals = rgl_by_disposition(&after);
+        let keys: BTreeSet<&(i32, String)> = before_totals.keys().chain(after_totals.keys()).collect();
+        let rgl_changes: Vec<RglChange> = keys.into_iter()
+            .map(|key| RglChange {
+                disposition_id: key.0,
+                match_type: key.1.clone(),
+                before: before_totals.get(key).copied().unwrap_or(0),
+                after: after_totals.get(key).copied().unwrap_or(0),
+            })
+            .filter(|change| change.before != change.after)
+            .collect();
+
+        Ok(CorrectionSummary {
+            matches_removed: unwound,
+            matches_created: after.len() + unwound - before.len(),
+            tax_rgl_before: total_rgl(&before, "tax"),
+            tax_rgl_after: total_rgl(&after, "tax"),
+            gaap_rgl_before: total_rgl(&before, "gaap"),
+            gaap_rgl_after: total_rgl(&after, "gaap"),
+            rgl_changes,
+        })
+    }).map_err(|e| e.to_string())).map(|(summary, _)| summary)
+}
+
+/// Loads lot `id`, refusing lots whose amounts are tied to other lots by a split or transfer.
+fn find_editable_lot(conn: &mut SqliteConnection, id: i32) -> Result<Acquisition, String> {
+    let lot: Acquisition = acquisitions::table
+        .find(id)
+        .select(Acquisition::as_select())
+        .first(conn)
+        .optional()
+        .map_err(|e| format!("Error fetching acquisition {}: {}", id, e))?
+        .ok_or_else(|| format!("Acquisition {} does not exist", id))?;
+
+    let children: i64 = acquisitions::table
+        .filter(acquisitions::parent_acquisition_id.eq(id))
+        .count()
+        .get_result(conn)
+        .map_err(|e| format!("Error checking lots split from acquisition {}: {}", id, e))?;
+    let transferred: i64 = transfer_acquisitions::table
+        .filter(transfer_acquisitions::acquisition_id.eq(id))
+        .count()
+        .get_result(conn)
+        .map_err(|e| format!("Error checking transfers of acquisition {}: {}", id, e))?;
+    if lot.split_reason.is_some() || children > 0 || transferred > 0 {
+        return Err(format!(
+            "Acquisition {} has been split or transferred and cannot be edited or deleted on its own. \
+             See `lot-history {}` for its lineage.",
+            id, id
+        ));
+    }
+    Ok(lot)
+}
+
+/// Loads disposition `id`, refusing network fees recorded by `transfer`.
+fn find_editable_disposition(conn: &mut SqliteConnection, id: i32) -> Result<Disposition, String> {
+    let disp: Disposition = dispositions::table
+        .find(id)
+        .select(Disposition::as_select())
+        .first(conn)
+        .optional()
+        .map_err(|e| format!("Error fetching disposition {}: {}", id, e))?
+        .ok_or_else(|| format!("Disposition {} does not exist", id))?;
+
+    let transfer_id: Option<i32> = transfers::table
+        .filter(transfers::fee_disposition_id.eq(id))
+        .select(transfers::id)
+        .first(conn)
+        .optional()
+        .map_err(|e| format!("Error checking transfers for disposition {}: {}", id, e))?;
+    if let Some(transfer_id) = transfer_id {
+        return Err(format!(
+            "Disposition {} is the network fee of transfer {} and cannot be edited or deleted on its own",
+            id, transfer_id
+        ));
+    }
+    Ok(disp)
+}
+
+fn load_matches(conn: &mut SqliteConnection) -> Result<Vec<AcquisitionDisposition>, String> {
+    acquisition_dispositions::table
+        .select(AcquisitionDisposition::as_select())
+        .load(conn)
+        .map_err(|e| format!("Error fetching acquisition_dispositions: {}", e))
+}
+
+fn rgl_by_disposition(matches: &[AcquisitionDisposition]) -> BTreeMap<(i32, String), i64> {
+    let mut totals = BTreeMap::new();
+    for m in matches {
+        *totals.entry((m.disposition_id, m.match_type.clone())).or_insert(0) += m.rgl;
+    }
+    totals
+}
+
+fn total_rgl(matches: &[AcquisitionDisposition], match_type: &str) -> i64 {
+    matches.iter().filter(|m| m.match_type == match_type).map(|m| m.rgl).sum()
+}
+
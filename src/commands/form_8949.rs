@@ -0,0 +1,217 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+use anyhow::Error;
+use chrono::{Datelike, NaiveDate, NaiveDateTime};
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use rust_decimal::Decimal;
+
+use crate::commands::report::query_acq_disps;
+use crate::models::{Form8949Row, ReportDates, ScheduleDLine};
+use crate::schema::transfer_acquisitions;
+
+/// Brokers report digital asset basis on Form 1099-DA for lots acquired from this date on.
+const BASIS_REPORTING_START: NaiveDate = NaiveDate::from_ymd_opt(2026, 1, 1).unwrap();
+
+/// How `form_8949` shapes the rows.
+#[derive(Debug, Default)]
+pub struct Form8949Options {
+    /// Combine the lots in a box sold on the same day into one row
+    pub aggregate: bool,
+    /// Wallets held at a broker that reports their sales on Form 1099-DA (or 1099-B)
+    pub broker_wallets: Vec<String>,
+}
+
+/// One Form 8949 row before formatting, in cents.
+struct Sale {
+    form_box: char,
+    /// `None` once lots acquired on different dates are aggregated
+    acquired: Option<NaiveDateTime>,
+    sold: NaiveDateTime,
+    satoshis: i64,
+    proceeds: i64,
+    basis: i64,
+    adjustment: i64,
+    gain: i64,
+}
+
+/// The Form 8949 box for a sale. Sales from 2025 on use the digital asset boxes G-L,
+/// and earlier sales the 1099-B boxes A-F.
+fn form_8949_box(term: &str, sale_year: i32, broker_reported: bool, basis_reported: bool) -> char {
+    let boxes = match (term, sale_year >= 2025) {
+        ("short", true) => ['G', 'H', 'I'],
+        ("short", false) => ['A', 'B', 'C'],
+        (_, true) => ['J', 'K', 'L'],
+        (_, false) => ['D', 'E', 'F'],
+    };
+    match (broker_reported, basis_reported) {
+        (true, true) => boxes[0],
+        (true, false) => boxes[1],
+        _ => boxes[2],
+    }
+}
+
+fn schedule_d_line(form_box: char) -> &'static str {
+    match form_box {
+        'A' | 'G' => "1b",
+        'B' | 'H' => "2",
+        'C' | 'I' => "3",
+        'D' | 'J' => "8b",
+        'E' | 'K' => "9",
+        _ => "10",
+    }
+}
+
+fn is_short_term(form_box: char) -> bool {
+    matches!(form_box, 'A' | 'B' | 'C' | 'G' | 'H' | 'I')
+}
+
+fn usd(cents: i64) -> Decimal {
+    Decimal::new(cents, 2)
+}
+
+fn box_totals_row(form_box: char, sales: &[&Sale]) -> Form8949Row {
+    let adjustment: i64 = sales.iter().map(|s| s.adjustment).sum();
+    Form8949Row {
+        part: if is_short_term(form_box) { "I" } else { "II" }.to_string(),
+        form_box,
+        description: "Totals".to_string(),
+        date_acquired: String::new(),
+        date_sold: String::new(),
+        proceeds: usd(sales.iter().map(|s| s.proceeds).sum()),
+        cost_basis: usd(sales.iter().map(|s| s.basis).sum()),
+        adjustment_code: String::new(),
+        adjustment: (adjustment != 0).then(|| usd(adjustment)),
+        gain_loss: usd(sales.iter().map(|s| s.gain).sum()),
+    }
+}
+
+/// Writes the tax matches for `beg` through `end` as Form 8949 rows, split into Part I
+/// (short term) and Part II (long term) and by box, with each box's totals, and a
+/// Schedule D file of the box totals and net gain or loss. Sales from `broker_wallets`
+/// report gross proceeds with fees as a code E adjustment; other sales report proceeds
+/// net of fees. Both dates must fall in one tax year.
+pub fn form_8949(beg: &String, end: &String, options: &Form8949Options, conn: &mut SqliteConnection) -> Result<(), Error> {
+    let dates: ReportDates = serde_json::from_str(&format!(r#"{{ "beginning_date": "{}", "ending_date": "{}" }}"#, beg, end)).expect("Failed to deserialize provided dates");
+    let beg_date_hms = dates.beginning_date.date().and_hms_opt(0, 0, 0).unwrap();
+    let end_date_hms = dates.ending_date.date().and_hms_opt(23, 59, 59).unwrap();
+    if beg_date_hms.year() != end_date_hms.year() {
+        return Err(anyhow::anyhow!(
+            "Form 8949 covers one tax year, but {} - {} spans {} through {}",
+            dates.beginning_date.date(), dates.ending_date.date(), beg_date_hms.year(), end_date_hms.year()
+        ));
+    }
+
+    // A broker reports basis only for lots bought in the same account, not transferred in
+    let transferred: HashSet<i32> = transfer_acquisitions::table
+        .select(transfer_acquisitions::acquisition_id)
+        .load::<i32>(conn)?
+        .into_iter()
+        .collect();
+
+    let mut sales: Vec<Sale> = Vec::new();
+    for term in ["short", "long"] {
+        for (disposition, lot, acq_disp) in query_acq_disps(beg_date_hms, end_date_hms, term, "tax", conn) {
+            let broker_reported = options.broker_wallets.contains(&disposition.wallet);
+            let basis_reported = broker_reported
+                && lot.acquisition_date.date() >= BASIS_REPORTING_START
+                && lot.wallet == disposition.wallet
+                && !transferred.contains(&lot.id);
+            let gross_proceeds = acq_disp.rgl + acq_disp.fee + acq_disp.basis;
+
+            sales.push(Sale {
+                form_box: form_8949_box(term, disposition.disposition_date.year(), broker_reported, basis_reported),
+                acquired: Some(lot.acquisition_date),
+                sold: disposition.disposition_date,
+                satoshis: acq_disp.satoshis,
+                proceeds: if broker_reported { gross_proceeds } else { gross_proceeds - acq_disp.fee },
+                basis: acq_disp.basis,
+                adjustment: if broker_reported { -acq_disp.fee } else { 0 },
+                gain: acq_disp.rgl,
+            });
+        }
+    }
+    sales.sort_by_key(|s| (s.form_box, s.sold, s.acquired));
+
+    if options.aggregate {
+        let mut aggregated: Vec<Sale> = Vec::new();
+        for sale in sales {
+            match aggregated.last_mut() {
+                Some(last) if last.form_box == sale.form_box && last.sold.date() == sale.sold.date() => {
+                    if last.acquired.map(|d| d.date()) != sale.acquired.map(|d| d.date()) {
+                        last.acquired = None;
+                    }
+                    last.satoshis += sale.satoshis;
+                    last.proceeds += sale.proceeds;
+                    last.basis += sale.basis;
+                    last.adjustment += sale.adjustment;
+                    last.gain += sale.gain;
+                }
+                _ => aggregated.push(sale),
+            }
+        }
+        sales = aggregated;
+    }
+
+    let file_path: PathBuf = PathBuf::from(format!("./reports/form_8949_{}_{}.csv", dates.beginning_date.date(), dates.ending_date.date()));
+    let mut wtr = csv::Writer::from_path(file_path)?;
+    let mut boxes: Vec<char> = sales.iter().map(|s| s.form_box).collect();
+    boxes.dedup();
+    for form_box in &boxes {
+        let box_sales: Vec<&Sale> = sales.iter().filter(|s| s.form_box == *form_box).collect();
+        for sale in &box_sales {
+            wtr.serialize(Form8949Row {
+                part: if is_short_term(*form_box) { "I" } else { "II" }.to_string(),
+                form_box: *form_box,
+                description: format!("{} BTC", Decimal::new(sale.satoshis, 8).normalize()),
+                date_acquired: sale.acquired.map_or_else(|| "VARIOUS".to_string(), |d| d.format("%m/%d/%Y").to_string()),
+                date_sold: sale.sold.format("%m/%d/%Y").to_string(),
+                proceeds: usd(sale.proceeds),
+                cost_basis: usd(sale.basis),
+                adjustment_code: if sale.adjustment != 0 { "E" } else { "" }.to_string(),
+                adjustment: (sale.adjustment != 0).then(|| usd(sale.adjustment)),
+                gain_loss: usd(sale.gain),
+            })?;
+        }
+        wtr.serialize(box_totals_row(*form_box, &box_sales))?;
+    }
+    wtr.flush()?;
+
+    let file_path: PathBuf = PathBuf::from(format!("./reports/schedule_d_{}_{}.csv", dates.beginning_date.date(), dates.ending_date.date()));
+    let mut wtr = csv::Writer::from_path(file_path)?;
+    let mut short_term = Decimal::ZERO;
+    let mut long_term = Decimal::ZERO;
+    for form_box in &boxes {
+        let totals = box_totals_row(*form_box, &sales.iter().filter(|s| s.form_box == *form_box).collect::<Vec<_>>());
+        if is_short_term(*form_box) {
+            short_term += totals.gain_loss;
+        } else {
+            long_term += totals.gain_loss;
+        }
+        wtr.serialize(ScheduleDLine {
+            line: schedule_d_line(*form_box).to_string(),
+            description: format!("Totals from Form 8949 with Box {} checked", form_box),
+            proceeds: Some(totals.proceeds),
+            cost_basis: Some(totals.cost_basis),
+            adjustments: totals.adjustment,
+            gain_loss: totals.gain_loss,
+        })?;
+    }
+    for (line, description, gain) in [
+        ("7", "Net short-term capital gain or (loss)", short_term),
+        ("15", "Net long-term capital gain or (loss)", long_term),
+        ("16", "Net capital gain or (loss)", short_term + long_term),
+    ] {
+        wtr.serialize(ScheduleDLine {
+            line: line.to_string(),
+            description: description.to_string(),
+            proceeds: None,
+            cost_basis: None,
+            adjustments: None,
+            gain_loss: gain,
+        })?;
+    }
+    wtr.flush()?;
+
+    Ok(())
+}
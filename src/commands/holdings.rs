@@ -1,8 +1,13 @@
+use std::collections::HashMap;
 use std::path::PathBuf;
+use chrono::NaiveDateTime;
 
 use crate::{
-    models::{Acquisition, AcquisitionDisposition, Holding, HoldingsDate},
-    schema::{acquisitions, dispositions, acquisition_dispositions},
+    LotConfig,
+    rounding_div,
+    commands::import::carrying_value_as_of,
+    models::{Acquisition, AcquisitionDisposition, FairValue, Holding, HoldingsDate},
+    schema::{acquisitions, dispositions, acquisition_dispositions, fair_values},
 };
 use anyhow::Ok;
 use diesel::prelude::*;
@@ -10,7 +15,11 @@ use diesel::sqlite::SqliteConnection;
 use rust_decimal::{prelude::FromPrimitive, Decimal, RoundingStrategy};
 use rust_decimal_macros::dec;
 
-pub fn holdings(date: &String, view: &str, conn: &mut SqliteConnection) -> Result<(), anyhow::Error> {
+/// Writes the lots held at the end of `date`. Quantities add back disposals dated after
+/// `date`, and fair values use the latest mark to market on or before `date` (or cost),
+/// so a report for a past date is the same however many marks have run since. Under the
+/// `average` GAAP method fair values are the pool's carrying value on `date` instead.
+pub fn holdings(date: &String, view: &str, conn: &mut SqliteConnection, config: &LotConfig) -> Result<(), anyhow::Error> {
     if !["tax", "gaap"].contains(&view) {
         return Err(anyhow::anyhow!("Invalid view '{}'. Must be 'tax' or 'gaap'.", view));
     }
@@ -25,23 +34,59 @@ pub fn holdings(date: &String, view: &str, conn: &mut SqliteConnection) -> Resul
     ));
     let mut wtr = csv::Writer::from_path(file_path).unwrap();
 
+    let mut total_btc = dec!(0);
+    let mut total_undisposed_btc = dec!(0);
+    let mut total_basis = dec!(0);
+    let mut total_fair_value = dec!(0);
+
+    for holding in holdings_on(conn, config, view, holdings_date.date)? {
+        total_btc += holding.btc;
+        total_undisposed_btc += holding.undisposed_btc;
+        total_basis += holding.usd_basis;
+        total_fair_value += holding.usd_fair_value;
+
+        wtr.serialize(holding).unwrap();
+    }
+
+    wtr.write_record(&[
+        String::from(""),
+        String::from(""),
+        total_btc.to_string(),
+        total_undisposed_btc.to_string(),
+        total_basis.to_string(),
+        total_fair_value.to_string(),
+    ])
+    .unwrap();
+
+    Ok(())
+}
+
+/// The lots held at the end of `date` under `view`'s undisposed tracker, valued as in
+/// the holdings report. Lots with nothing left on `date` are skipped.
+pub fn holdings_on(
+    conn: &mut SqliteConnection,
+    config: &LotConfig,
+    view: &str,
+    date: NaiveDateTime,
+) -> Result<Vec<Holding>, anyhow::Error> {
     let use_tax = view == "tax";
     let match_type_filter = if use_tax { "tax" } else { "gaap" };
 
     let all_holdings: Vec<Acquisition> = acquisitions::table
-        .filter(acquisitions::acquisition_date.le(holdings_date.date))
+        .filter(acquisitions::acquisition_date.le(date))
         .select(Acquisition::as_select())
-        .load(conn)
-        .unwrap();
+        .load(conn)?;
 
     let subsequent_acq_disps: Vec<AcquisitionDisposition> =
         AcquisitionDisposition::belonging_to(&all_holdings)
             .inner_join(dispositions::table)
-            .filter(dispositions::disposition_date.gt(holdings_date.date))
+            .filter(dispositions::disposition_date.gt(date))
             .filter(acquisition_dispositions::match_type.eq(match_type_filter))
             .select(AcquisitionDisposition::as_select())
             .load(conn)?;
 
+    let fair_values = carrying_values_on(conn, config, &all_holdings, date)?;
+
     let holdings_with_subsequent_acq_disps: Vec<(Acquisition, i64)> = subsequent_acq_disps
         .grouped_by(&all_holdings)
         .into_iter()
@@ -49,7 +94,7 @@ pub fn holdings(date: &String, view: &str, conn: &mut SqliteConnection) -> Resul
         .map(|(acq_disps, holding)| {
             (
                 holding,
-                if acq_disps.len() > 0 {
+                if !acq_disps.is_empty() {
                     acq_disps.iter().map(|l| l.satoshis).sum()
                 } else {
                     0
@@ -58,11 +103,7 @@ pub fn holdings(date: &String, view: &str, conn: &mut SqliteConnection) -> Resul
         })
         .collect();
 
-    let mut total_btc = dec!(0);
-    let mut total_undisposed_btc = dec!(0);
-    let mut total_basis = dec!(0);
-    let mut total_fair_value = dec!(0);
-
+    let mut holdings = Vec::new();
     for (lot, subsequent_disposals) in holdings_with_subsequent_acq_disps {
         let btc = Decimal::from_i64(lot.satoshis).unwrap() / dec!(100_000_000);
         let current_undisposed = if use_tax {
@@ -76,6 +117,7 @@ pub fn holdings(date: &String, view: &str, conn: &mut SqliteConnection) -> Resul
         if undisposed_btc == dec!(0) {
             continue;
         }
+        let fair_value_cents = fair_values[&lot.id];
         let holding = Holding {
             wallet: lot.wallet.clone(),
             acquisition_date: lot.acquisition_date,
@@ -84,27 +126,141 @@ pub fn holdings(date: &String, view: &str, conn: &mut SqliteConnection) -> Resul
             usd_basis: (Decimal::from_i64(lot.usd_cents_btc_basis).unwrap() / dec!(100)
                 * undisposed_btc)
                 .round_dp_with_strategy(2, RoundingStrategy::MidpointAwayFromZero),
-            usd_fair_value: (Decimal::from_i64(lot.usd_cents_btc_fair_value).unwrap() / dec!(100)
+            usd_fair_value: (Decimal::from_i64(fair_value_cents).unwrap() / dec!(100)
                 * undisposed_btc)
                 .round_dp_with_strategy(2, RoundingStrategy::MidpointAwayFromZero),
         };
-        total_btc += holding.btc;
-        total_undisposed_btc += holding.undisposed_btc;
-        total_basis += holding.usd_basis;
-        total_fair_value += holding.usd_fair_value;
+        holdings.push(holding);
+    }
 
-        wtr.serialize(holding).unwrap();
+    Ok(holdings)
+}
+
+/// Each of `lots`' GAAP carrying value per BTC at the end of `date`: the latest mark to
+/// market on or before `date`, or cost, or under the `average` method the pool's value.
+pub fn carrying_values_on(
+    conn: &mut SqliteConnection,
+    config: &LotConfig,
+    lots: &[Acquisition],
+    date: NaiveDateTime,
+) -> Result<HashMap<i32, i64>, anyhow::Error> {
+    // Marks are matched to lots by date rather than through `acquisition_fair_values`,
+    // which has no links for lots relieved when a mark ran and later rematched
+    let marks: Vec<FairValue> = fair_values::table
+        .filter(fair_values::date.le(date))
+        .order((fair_values::date.asc(), fair_values::id.asc()))
+        .select(FairValue::as_select())
+        .load(conn)?;
+    if config.gaap_lot_method == "average" {
+        // The pool's average depends on every lot in it, including lots since relieved
+        let pool: Vec<Acquisition> = acquisitions::table
+            .filter(acquisitions::acquisition_date.le(date))
+            .select(Acquisition::as_select())
+            .load(conn)?;
+        let carrying = replay_pool(conn, &pool, &marks, date)?.carrying;
+        Ok(lots.iter().map(|lot| (lot.id, carrying[&lot.id])).collect())
+    } else {
+        Ok(lots.iter().map(|lot| (lot.id, carrying_value_as_of(&marks, lot, date))).collect())
     }
+}
 
-    wtr.write_record(&[
-        String::from(""),
-        String::from(""),
-        total_btc.to_string(),
-        total_undisposed_btc.to_string(),
-        total_basis.to_string(),
-        total_fair_value.to_string(),
-    ])
-    .unwrap();
+/// `sats` at `cents_per_btc`, rounded as the holdings report rounds each lot.
+pub(crate) fn usd_value(sats: i64, cents_per_btc: i64) -> Decimal {
+    (Decimal::from_i64(cents_per_btc).unwrap() / dec!(100) * (Decimal::from_i64(sats).unwrap() / dec!(100_000_000)))
+        .round_dp_with_strategy(2, RoundingStrategy::MidpointAwayFromZero)
+}
 
-    Ok(())
+/// A disposition's restatement of the average pool: the change in the pool's value from
+/// rounding its average carrying value to a whole cent per BTC.
+pub(crate) struct PoolRestatement {
+    pub date: NaiveDateTime,
+    pub amount: Decimal,
+}
+
+struct PoolReplay {
+    /// Each lot's carrying value per BTC at the end of the replay
+    carrying: HashMap<i32, i64>,
+    restatements: Vec<PoolRestatement>,
+}
+
+/// The average pool's restatements by dispositions dated from `beg` through `end`.
+pub(crate) fn pool_restatements(
+    conn: &mut SqliteConnection,
+    beg: NaiveDateTime,
+    end: NaiveDateTime,
+) -> Result<Vec<PoolRestatement>, anyhow::Error> {
+    let marks: Vec<FairValue> = fair_values::table
+        .filter(fair_values::date.le(end))
+        .order((fair_values::date.asc(), fair_values::id.asc()))
+        .select(FairValue::as_select())
+        .load(conn)?;
+    let pool: Vec<Acquisition> = acquisitions::table
+        .filter(acquisitions::acquisition_date.le(end))
+        .select(Acquisition::as_select())
+        .load(conn)?;
+    let restatements = replay_pool(conn, &pool, &marks, end)?.restatements;
+    Ok(restatements.into_iter().filter(|r| r.date >= beg).collect())
+}
+
+/// Replays the weighted-average pool from cost through `date`, as GAAP matching did:
+/// each mark sets the lots held on its date to the mark, and each disposition restates
+/// the lots held on its date to their average carrying value.
+fn replay_pool(
+    conn: &mut SqliteConnection,
+    lots: &[Acquisition],
+    marks: &[FairValue],
+    date: NaiveDateTime,
+) -> Result<PoolReplay, anyhow::Error> {
+    let gaap_acq_disps: Vec<(AcquisitionDisposition, i32, NaiveDateTime)> =
+        AcquisitionDisposition::belonging_to(lots)
+            .inner_join(dispositions::table)
+            .filter(acquisition_dispositions::match_type.eq("gaap"))
+            .order((dispositions::disposition_date.asc(), dispositions::id.asc()))
+            .select((AcquisitionDisposition::as_select(), dispositions::id, dispositions::disposition_date))
+            .load(conn)?;
+
+    // Each lot's GAAP undisposed sats before its first disposal, and its cost
+    let mut undisposed: HashMap<i32, i64> = lots.iter().map(|l| (l.id, l.undisposed_satoshis)).collect();
+    for (acq_disp, _, _) in &gaap_acq_disps {
+        *undisposed.get_mut(&acq_disp.acquisition_id).unwrap() += acq_disp.satoshis;
+    }
+    let mut carrying: HashMap<i32, i64> = lots.iter().map(|l| (l.id, l.usd_cents_btc_basis)).collect();
+    let mut restatements = Vec::new();
+
+    let mut pending_marks = marks.iter().peekable();
+    let mut disposals = gaap_acq_disps.iter().filter(|(_, _, d)| *d <= date).peekable();
+    while let Some(&(_, disposition_id, disposition_date)) = disposals.peek() {
+        while let Some(mark) = pending_marks.next_if(|m| m.date <= *disposition_date) {
+            for lot in lots.iter().filter(|l| l.acquisition_date <= mark.date && undisposed[&l.id] > 0) {
+                carrying.insert(lot.id, mark.fair_value_cents);
+            }
+        }
+
+        let pool: Vec<&Acquisition> = lots.iter()
+            .filter(|l| l.acquisition_date <= *disposition_date && undisposed[&l.id] > 0)
+            .collect();
+        let pool_sats: i64 = pool.iter().map(|l| undisposed[&l.id]).sum();
+        if pool_sats > 0 {
+            let pool_value: i128 = pool.iter().map(|l| undisposed[&l.id] as i128 * carrying[&l.id] as i128).sum();
+            let average = rounding_div(pool_value, pool_sats as i128);
+            let mut amount = Decimal::ZERO;
+            for lot in pool {
+                amount += usd_value(undisposed[&lot.id], average) - usd_value(undisposed[&lot.id], carrying[&lot.id]);
+                carrying.insert(lot.id, average);
+            }
+            restatements.push(PoolRestatement { date: *disposition_date, amount });
+        }
+
+        let disposition_id = *disposition_id;
+        while let Some((acq_disp, _, _)) = disposals.next_if(|(_, id, _)| *id == disposition_id) {
+            *undisposed.get_mut(&acq_disp.acquisition_id).unwrap() -= acq_disp.satoshis;
+        }
+    }
+    for mark in pending_marks {
+        for lot in lots.iter().filter(|l| l.acquisition_date <= mark.date && undisposed[&l.id] > 0) {
+            carrying.insert(lot.id, mark.fair_value_cents);
+        }
+    }
+
+    Ok(PoolReplay { carrying, restatements })
 }
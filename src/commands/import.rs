@@ -1,4 +1,6 @@
+use std::collections::HashSet;
 use std::path::PathBuf;
+use chrono::{NaiveDateTime, Utc};
 use diesel::prelude::*;
 use diesel::sqlite::SqliteConnection;
 use diesel::Connection;
@@ -6,9 +8,14 @@ use core::cmp::min;
 
 use crate::LotConfig;
 use crate::rounding_div;
-use crate::models::AcquisitionDisposition;
+use crate::commands::rematch::rematch_from;
+use crate::commands::transfer::TransferHistory;
+use crate::commands::audit::{AuditCommand, file_sha256};
+use crate::commands::preview::{ChangePreview, with_preview};
+use crate::models::{AcquisitionDisposition, PassedOverLot};
 use crate::models::{NewRecord, Acquisition, NewDisposition, NewAcquisition, Disposition};
-use crate::schema::{acquisitions, dispositions, acquisition_dispositions};
+use crate::models::{LotDesignation, parse_lot_designation, FairValue, Fee, NewImportBatch};
+use crate::schema::{acquisitions, dispositions, acquisition_dispositions, fair_values, import_batches, passed_over_lots};
 
 #[derive(Debug)]
 enum ImportError {
@@ -37,32 +44,134 @@ impl std::fmt::Display for ImportError {
     }
 }
 
-pub fn import_transactions(file: &PathBuf, conn: &mut SqliteConnection, config: &LotConfig) -> Result<(), String> {
-    let mut rdr = csv::Reader::from_path(&file)
+/// Options controlling how `import_transactions_with_options` treats the import file.
+#[derive(Debug, Default, Clone)]
+pub struct ImportOptions {
+    /// Skip rows whose `TxId` has already been imported instead of rejecting the file
+    pub skip_existing: bool,
+    /// Run the import and matching, then roll everything back
+    pub dry_run: bool,
+}
+
+/// What an import did with each row of the file.
+#[derive(Debug, Default)]
+pub struct ImportSummary {
+    /// The `import_batches` row recording this run; pass it to `undo-import` to reverse it
+    pub batch_id: i32,
+    pub inserted_acquisitions: usize,
+    pub inserted_dispositions: usize,
+    /// Dispositions recorded for fees paid in bitcoin
+    pub inserted_fee_dispositions: usize,
+    /// `TxId`s of rows that were inserted
+    pub inserted: Vec<String>,
+    /// `TxId`s of rows skipped because they were already imported
+    pub skipped: Vec<String>,
+    /// Existing matches unwound and replayed because imported rows preceded them
+    pub rematched: usize,
+    /// Lots, dispositions and matches the import created or removed
+    pub changes: ChangePreview,
+}
+
+pub fn import_transactions(file: &PathBuf, conn: &mut SqliteConnection, config: &LotConfig) -> Result<ImportSummary, String> {
+    import_transactions_with_options(file, conn, config, &ImportOptions::default())
+}
+
+pub fn import_transactions_with_options(
+    file: &PathBuf,
+    conn: &mut SqliteConnection,
+    config: &LotConfig,
+    options: &ImportOptions,
+) -> Result<ImportSummary, String> {
+    let contents = std::fs::read(file)
         .map_err(|e| format!("Error reading file {:?}: {}", file, e))?;
+    let mut rdr = csv::Reader::from_reader(contents.as_slice());
 
     let mut records: Vec<NewRecord> = rdr.deserialize::<NewRecord>()
         .map(|r| r.map_err(|e| format!("Error parsing CSV record: {}", e)))
         .collect::<Result<Vec<_>, _>>()?;
-    records.sort_by(|a, b| a.date.timestamp().cmp(&b.date.timestamp()));
+    records.sort_by_key(|a| a.date.and_utc().timestamp());
+
+    let batch = NewImportBatch {
+        file_name: file.to_string_lossy().to_string(),
+        file_hash: file_sha256(&contents),
+        imported_at: Utc::now().naive_utc(),
+        row_count: records.len() as i32,
+    };
+
+    for record in &records {
+        if let Some(lot) = &record.lot {
+            if record.bitcoin > 0 {
+                return Err(format!(
+                    "Lot designation '{}' on the acquisition dated {} is not allowed; only dispositions can name lots.",
+                    lot, record.date.format("%Y-%m-%d")
+                ));
+            }
+            parse_lot_designation(lot)?;
+        }
+    }
+
+    let audit = AuditCommand::new("import", serde_json::json!({
+        "file": file,
+        "skip_existing": options.skip_existing,
+    })).with_file(&contents, records.len());
+
+    let (mut summary, changes) = with_preview(conn, options.dry_run, &audit, |conn| conn.transaction::<ImportSummary, ImportError, _>(|conn| {
+        let mut summary = ImportSummary::default();
+        diesel::insert_into(import_batches::table)
+            .values(&batch)
+            .execute(conn)
+            .map_err(|e| format!("Error saving import batch: {}", e))?;
+        summary.batch_id = import_batches::table
+            .order(import_batches::id.desc())
+            .select(import_batches::id)
+            .first(conn)
+            .map_err(|e| format!("Error fetching import batch: {}", e))?;
+
+        let mut seen_tx_ids: HashSet<String> = HashSet::new();
+        let mut earliest_date: Option<NaiveDateTime> = None;
 
-    conn.transaction::<(), ImportError, _>(|conn| {
         for record in records {
+            if let Some(tx_id) = &record.tx_id {
+                if !seen_tx_ids.insert(tx_id.clone()) || tx_id_exists(conn, tx_id)? {
+                    if options.skip_existing {
+                        summary.skipped.push(tx_id.clone());
+                        continue;
+                    }
+                    return Err(ImportError::Custom(format!(
+                        "TxId '{}' has already been imported. Re-run with --skip-existing to skip rows already present. \
+                         All changes have been rolled back.",
+                        tx_id
+                    )));
+                }
+                summary.inserted.push(tx_id.clone());
+            }
+            earliest_date = Some(earliest_date.map_or(record.date, |d| d.min(record.date)));
+
             match record.bitcoin.gt(&0) {
                 true => {
+                    // USD fees on a purchase are capitalized into the lot's cost per BTC
+                    let basis = match record.fee {
+                        Some(Fee::Usd(fee)) => record.price + rounding_div(fee as i128 * 100_000_000, record.bitcoin as i128),
+                        _ => record.price,
+                    };
                     let new_acquisition = NewAcquisition {
                         acquisition_date: record.date,
                         satoshis: record.bitcoin,
                         undisposed_satoshis: record.bitcoin,
-                        usd_cents_btc_basis: record.price,
-                        usd_cents_btc_fair_value: record.price,
-                        wallet: record.wallet,
+                        usd_cents_btc_basis: basis,
+                        usd_cents_btc_fair_value: basis,
+                        wallet: record.wallet.clone(),
                         tax_undisposed_satoshis: record.bitcoin,
+                        external_id: record.tx_id,
+                        parent_acquisition_id: None,
+                        split_reason: None,
+                        import_batch_id: Some(summary.batch_id),
                     };
                     diesel::insert_into(acquisitions::table)
                         .values(&new_acquisition)
                         .execute(conn)
                         .map_err(|e| format!("Error saving acquisition: {}", e))?;
+                    summary.inserted_acquisitions += 1;
                 },
                 false => {
                     let new_disposition = NewDisposition {
@@ -70,125 +179,397 @@ pub fn import_transactions(file: &PathBuf, conn: &mut SqliteConnection, config:
                         satoshis: record.bitcoin,
                         undisposed_satoshis: record.bitcoin,
                         usd_cents_btc_basis: record.price,
-                        wallet: record.wallet,
+                        wallet: record.wallet.clone(),
                         tax_undisposed_satoshis: record.bitcoin,
+                        lot_designation: record.lot,
+                        external_id: record.tx_id,
+                        usd_cents_fee: match record.fee {
+                            Some(Fee::Usd(fee)) => fee,
+                            _ => 0,
+                        },
+                        import_batch_id: Some(summary.batch_id),
                     };
                     diesel::insert_into(dispositions::table)
                         .values(&new_disposition)
                         .execute(conn)
                         .map_err(|e| format!("Error saving disposition: {}", e))?;
+                    summary.inserted_dispositions += 1;
                 }
             }
+
+            // A fee paid in bitcoin spends those sats, so it is its own disposition at the row's price
+            if let Some(Fee::Btc(fee_sats)) = record.fee {
+                let fee_disposition = NewDisposition {
+                    disposition_date: record.date,
+                    satoshis: -fee_sats,
+                    undisposed_satoshis: -fee_sats,
+                    usd_cents_btc_basis: record.price,
+                    wallet: record.wallet,
+                    tax_undisposed_satoshis: -fee_sats,
+                    lot_designation: None,
+                    external_id: None,
+                    usd_cents_fee: 0,
+                    import_batch_id: Some(summary.batch_id),
+                };
+                diesel::insert_into(dispositions::table)
+                    .values(&fee_disposition)
+                    .execute(conn)
+                    .map_err(|e| format!("Error saving fee disposition: {}", e))?;
+                summary.inserted_fee_dispositions += 1;
+            }
         }
 
-        // GAAP matching pass
-        fifo_match(conn, "gaap", "universal", true)?;
+        // Rows dated before existing matches invalidate those matches: unwind them and
+        // replay matching in date order. Otherwise only the new dispositions are matched.
+        match earliest_date {
+            Some(from) => summary.rematched = rematch_from(conn, config, from)?,
+            None => match_all(conn, config)?,
+        }
 
-        // Tax matching pass
-        fifo_match(conn, "tax", &config.tax_lot_scope, false)?;
+        Ok(summary)
+    }).map_err(|e| e.to_string()))?;
 
-        Ok(())
-    }).map_err(|e| e.to_string())
+    summary.changes = changes;
+    Ok(summary)
 }
 
-fn fifo_match(
+/// The part of a disposition's USD fee deducted from the proceeds of a match relieving
+/// `sats_disposed` when `sats_remaining` of the disposition were still unmatched. Shares
+/// are allocated cumulatively so a disposition's matches always add up to its whole fee.
+fn fee_share(disp: &Disposition, sats_remaining: i64, sats_disposed: i64) -> i64 {
+    if disp.usd_cents_fee == 0 {
+        return 0;
+    }
+    let total = -disp.satoshis as i128;
+    let matched_before = total - sats_remaining as i128;
+    let fee = disp.usd_cents_fee as i128;
+    rounding_div(fee * (matched_before + sats_disposed as i128), total) - rounding_div(fee * matched_before, total)
+}
+
+/// Whether an acquisition or disposition has already been imported with `tx_id`.
+fn tx_id_exists(conn: &mut SqliteConnection, tx_id: &str) -> Result<bool, String> {
+    let acquisitions_found: i64 = acquisitions::table
+        .filter(acquisitions::external_id.eq(tx_id))
+        .count()
+        .get_result(conn)
+        .map_err(|e| format!("Error checking acquisition TxId: {}", e))?;
+    let dispositions_found: i64 = dispositions::table
+        .filter(dispositions::external_id.eq(tx_id))
+        .count()
+        .get_result(conn)
+        .map_err(|e| format!("Error checking disposition TxId: {}", e))?;
+    Ok(acquisitions_found + dispositions_found > 0)
+}
+
+/// Holding period of a lot disposed on `disposition_date`: "long" when held more
+/// than one year (> 365 days), otherwise "short".
+pub fn lot_term(acquisition_date: NaiveDateTime, disposition_date: NaiveDateTime) -> &'static str {
+    if (disposition_date - acquisition_date).num_days() > 365 { "long" } else { "short" }
+}
+
+/// Min-tax preference for relieving a lot at `disposal_price`, lowest first:
+/// long-term losses, short-term losses, long-term gains, then short-term gains.
+/// A lot disposed at its basis counts as a loss. Returns the rank and a label.
+pub fn tax_lot_category(term: &str, basis_price: i64, disposal_price: i64) -> (u8, &'static str) {
+    match (basis_price >= disposal_price, term == "long") {
+        (true, true) => (0, "long-term loss"),
+        (true, false) => (1, "short-term loss"),
+        (false, true) => (2, "long-term gain"),
+        (false, false) => (3, "short-term gain"),
+    }
+}
+
+/// Orders candidate lots in the sequence they should be relieved under `method`.
+/// HIFO and min-tax rank by the price the match will use: fair value for GAAP, cost for tax.
+fn order_lots(lots: &mut [Acquisition], method: &str, use_fair_value: bool, disp_lot: &Disposition) {
+    let price = |lot: &Acquisition| {
+        if use_fair_value { lot.usd_cents_btc_fair_value } else { lot.usd_cents_btc_basis }
+    };
+    match method {
+        "lifo" => lots.sort_by(|a, b| {
+            b.acquisition_date.cmp(&a.acquisition_date).then(b.id.cmp(&a.id))
+        }),
+        "hifo" => lots.sort_by(|a, b| {
+            price(b).cmp(&price(a))
+                .then(a.acquisition_date.cmp(&b.acquisition_date))
+                .then(a.id.cmp(&b.id))
+        }),
+        "min-tax" => lots.sort_by_key(|lot| {
+            let term = lot_term(lot.acquisition_date, disp_lot.disposition_date);
+            let (rank, _) = tax_lot_category(term, price(lot), disp_lot.usd_cents_btc_basis);
+            (rank, -price(lot), lot.acquisition_date, lot.id)
+        }),
+        _ => lots.sort_by(|a, b| {
+            a.acquisition_date.cmp(&b.acquisition_date).then(a.id.cmp(&b.id))
+        }),
+    }
+}
+
+/// Resolves the lots named on a disposition's `Lot` designation, in the order named.
+/// Every named lot must have been in the disposition's wallet on its date.
+fn designated_lots(
+    conn: &mut SqliteConnection,
+    history: &TransferHistory,
+    disp_lot: &Disposition,
+    designation: &str,
+) -> Result<Vec<Acquisition>, String> {
+    let mut lots: Vec<Acquisition> = Vec::new();
+
+    for named in parse_lot_designation(designation)? {
+        let found: Vec<Acquisition> = match &named {
+            LotDesignation::Id(id) => acquisitions::table
+                .find(id)
+                .select(Acquisition::as_select())
+                .load(conn)
+                .map_err(|e| format!("Error querying designated lot: {}", e))?,
+            LotDesignation::Lot { date, wallet, satoshis } => acquisitions::table
+                .filter(acquisitions::acquisition_date.ge(date.date().and_hms_opt(0, 0, 0).unwrap()))
+                .filter(acquisitions::acquisition_date.le(date.date().and_hms_opt(23, 59, 59).unwrap()))
+                .filter(acquisitions::wallet.eq(wallet))
+                .filter(acquisitions::satoshis.eq(satoshis))
+                .order(acquisitions::id.asc())
+                .select(Acquisition::as_select())
+                .load(conn)
+                .map_err(|e| format!("Error querying designated lot: {}", e))?,
+        };
+
+        if found.is_empty() {
+            return Err(format!(
+                "Lot designation '{}' on disposition dated {} does not match any acquisition. \
+                 All changes have been rolled back.",
+                designation, disp_lot.disposition_date.format("%Y-%m-%d")
+            ));
+        }
+
+        for lot in found {
+            let (wallet, _) = history.position(&lot, disp_lot);
+            if wallet != disp_lot.wallet {
+                return Err(format!(
+                    "Lot {} named on disposition dated {} is in wallet '{}', but the disposition is from wallet '{}'. \
+                     All changes have been rolled back.",
+                    lot.id, disp_lot.disposition_date.format("%Y-%m-%d"), wallet, disp_lot.wallet
+                ));
+            }
+            if !lots.iter().any(|l| l.id == lot.id) {
+                lots.push(lot);
+            }
+        }
+    }
+
+    Ok(lots)
+}
+
+/// Runs the GAAP then tax matching passes over every disposition with unmatched sats.
+pub fn match_all(conn: &mut SqliteConnection, config: &LotConfig) -> Result<(), String> {
+    match_gaap(conn, config)?;
+
+    // Tax matching pass
+    match_lots(conn, "tax", &config.tax_lot_scope, &config.tax_lot_method, false)?;
+
+    Ok(())
+}
+
+/// Matches unmatched dispositions against GAAP lots only.
+pub fn match_gaap(conn: &mut SqliteConnection, config: &LotConfig) -> Result<(), String> {
+    match_lots(conn, "gaap", "universal", &config.gaap_lot_method, true)
+}
+
+/// GAAP carrying value per BTC of `lot` on `date`: the most recent mark-to-market dated
+/// between the lot's acquisition and `date`, otherwise the lot's cost.
+pub fn carrying_value_as_of(marks: &[FairValue], lot: &Acquisition, date: NaiveDateTime) -> i64 {
+    marks.iter()
+        .filter(|m| m.date >= lot.acquisition_date && m.date <= date)
+        .max_by_key(|m| (m.date, m.id))
+        .map_or(lot.usd_cents_btc_basis, |m| m.fair_value_cents)
+}
+
+/// Sets every lot held on the mark date to the mark's fair value, as `mark_to_market` does.
+fn apply_mark(conn: &mut SqliteConnection, mark: &FairValue) -> Result<(), String> {
+    diesel::update(acquisitions::table)
+        .filter(acquisitions::acquisition_date.le(mark.date))
+        .filter(acquisitions::undisposed_satoshis.gt(0))
+        .set(acquisitions::usd_cents_btc_fair_value.eq(mark.fair_value_cents))
+        .execute(conn)
+        .map_err(|e| format!("Error applying mark to market of {}: {}", mark.date.format("%Y-%m-%d"), e))?;
+    Ok(())
+}
+
+fn match_lots(
     conn: &mut SqliteConnection,
     match_type: &str,
     scope: &str,
+    method: &str,
     use_fair_value: bool,
 ) -> Result<(), String> {
-    let undisposed_disps: Vec<Disposition> = if match_type == "gaap" {
+    let mut undisposed_disps: Vec<Disposition> = if match_type == "gaap" {
         dispositions::table
             .filter(dispositions::undisposed_satoshis.lt(0))
-            .order(dispositions::disposition_date.asc())
+            .order((dispositions::disposition_date.asc(), dispositions::id.asc()))
             .select(Disposition::as_select())
             .load(conn)
             .map_err(|e| format!("Error fetching dispositions: {}", e))?
     } else {
         dispositions::table
             .filter(dispositions::tax_undisposed_satoshis.lt(0))
-            .order(dispositions::disposition_date.asc())
+            .order((dispositions::disposition_date.asc(), dispositions::id.asc()))
             .select(Disposition::as_select())
             .load(conn)
             .map_err(|e| format!("Error fetching dispositions: {}", e))?
     };
 
+    let marks: Vec<FairValue> = if use_fair_value {
+        fair_values::table
+            .order((fair_values::date.asc(), fair_values::id.asc()))
+            .select(FairValue::as_select())
+            .load(conn)
+            .map_err(|e| format!("Error fetching fair values: {}", e))?
+    } else {
+        Vec::new()
+    };
+    let pooled = method == "average" && use_fair_value;
+    // Tax relief is scoped to, and reserves sats for, the wallets lots were in at the time
+    let history = TransferHistory::load(conn)?;
+    // Transfers take effect before other dispositions dated the same, and so do their fees
+    undisposed_disps.sort_by_key(|d| (d.disposition_date, !history.is_fee(d)));
+
+    // The average pool's carrying value is only stored on the lots, so marks dated after
+    // the last matched disposition are replayed in date order between dispositions
+    let mut pending_marks: Vec<&FairValue> = Vec::new();
+    if pooled {
+        let last_matched: Option<NaiveDateTime> = dispositions::table
+            .filter(dispositions::undisposed_satoshis.eq(0))
+            .select(diesel::dsl::max(dispositions::disposition_date))
+            .first(conn)
+            .map_err(|e| format!("Error fetching last matched disposition: {}", e))?;
+        pending_marks = marks.iter()
+            .filter(|m| last_matched.is_none_or(|d| m.date > d))
+            .rev()
+            .collect();
+    }
+
     for disp_lot in undisposed_disps {
+        while pending_marks.last().is_some_and(|m| m.date <= disp_lot.disposition_date) {
+            apply_mark(conn, pending_marks.pop().unwrap())?;
+        }
+
         let mut remaining = if match_type == "gaap" {
             disp_lot.undisposed_satoshis
         } else {
             disp_lot.tax_undisposed_satoshis
         };
 
-        while remaining != 0 {
-            // Build acquisition query based on scope and match_type
-            let acq_lot: Acquisition = if match_type == "gaap" {
-                let mut query = acquisitions::table
-                    .filter(acquisitions::undisposed_satoshis.gt(0))
-                    .order((acquisitions::acquisition_date.asc(), acquisitions::id.asc()))
-                    .into_boxed();
-                if scope == "wallet" {
-                    query = query.filter(acquisitions::wallet.eq(&disp_lot.wallet));
+        // The rule that orders the candidates, saved on each match it makes
+        let mut rule = method;
+        let candidates: Vec<Acquisition> = match (&disp_lot.lot_designation, match_type) {
+            // Specific identification overrides the configured method for tax relief
+            (Some(designation), "tax") => {
+                let mut lots = designated_lots(conn, &history, &disp_lot, designation)?;
+                for lot in lots.iter_mut() {
+                    let (_, reserved) = history.position(lot, &disp_lot);
+                    lot.tax_undisposed_satoshis = (lot.tax_undisposed_satoshis - reserved).max(0);
                 }
-                query
-                    .select(Acquisition::as_select())
-                    .first(conn)
-                    .optional()
-                    .map_err(|e| format!("Error querying acquisition lots: {}", e))?
-                    .ok_or_else(|| {
-                        let scope_msg = if scope == "wallet" {
-                            format!(" in wallet '{}'", disp_lot.wallet)
-                        } else {
-                            String::new()
-                        };
-                        format!(
-                            "No undisposed acquisition lots available{} for {} FIFO matching. \
-                             Disposition on {} for {} sats cannot be matched. \
-                             All changes have been rolled back.",
-                            scope_msg, match_type,
-                            disp_lot.disposition_date.format("%Y-%m-%d"),
-                            -disp_lot.satoshis
-                        )
-                    })?
-            } else {
+                let available: i64 = lots.iter().map(|l| l.tax_undisposed_satoshis).sum();
+                if available < -remaining {
+                    return Err(format!(
+                        "Lot designation '{}' on disposition dated {} names lots with {} tax undisposed sats, need {} sats. \
+                         All changes have been rolled back.",
+                        designation,
+                        disp_lot.disposition_date.format("%Y-%m-%d"),
+                        available, -remaining
+                    ));
+                }
+                rule = "specific-id";
+                lots
+            }
+            _ => {
+                // Candidate lots: anything still undisposed in scope and held on the disposition date
                 let mut query = acquisitions::table
-                    .filter(acquisitions::tax_undisposed_satoshis.gt(0))
-                    .order((acquisitions::acquisition_date.asc(), acquisitions::id.asc()))
+                    .filter(acquisitions::acquisition_date.le(disp_lot.disposition_date))
                     .into_boxed();
-                if scope == "wallet" {
-                    query = query.filter(acquisitions::wallet.eq(&disp_lot.wallet));
-                }
-                query
+                query = if match_type == "gaap" {
+                    query.filter(acquisitions::undisposed_satoshis.gt(0))
+                } else {
+                    query.filter(acquisitions::tax_undisposed_satoshis.gt(0))
+                };
+                let mut lots: Vec<Acquisition> = query
                     .select(Acquisition::as_select())
-                    .first(conn)
-                    .optional()
-                    .map_err(|e| format!("Error querying acquisition lots: {}", e))?
-                    .ok_or_else(|| {
-                        let scope_msg = if scope == "wallet" {
-                            format!(" in wallet '{}'", disp_lot.wallet)
-                        } else {
-                            String::new()
-                        };
-                        format!(
-                            "No undisposed acquisition lots available{} for {} FIFO matching. \
-                             Disposition on {} for {} sats cannot be matched. \
-                             All changes have been rolled back.",
-                            scope_msg, match_type,
-                            disp_lot.disposition_date.format("%Y-%m-%d"),
-                            -disp_lot.satoshis
-                        )
-                    })?
-            };
+                    .load(conn)
+                    .map_err(|e| format!("Error querying acquisition lots: {}", e))?;
+                // A transfer fee is paid from the earliest lots in the wallet it left, whatever
+                // the configured tax scope and method
+                let fee = match_type == "tax" && history.is_fee(&disp_lot);
+                if match_type == "tax" {
+                    // A lot counts in the wallet it was in on the disposition date, less what
+                    // a later transfer moves on
+                    lots.retain_mut(|lot| {
+                        let (wallet, reserved) = history.position(lot, &disp_lot);
+                        let in_scope = (scope != "wallet" && !fee) || wallet == disp_lot.wallet;
+                        lot.tax_undisposed_satoshis -= reserved;
+                        in_scope && lot.tax_undisposed_satoshis > 0
+                    });
+                }
+                if use_fair_value && !pooled {
+                    for lot in lots.iter_mut() {
+                        lot.usd_cents_btc_fair_value = carrying_value_as_of(&marks, lot, disp_lot.disposition_date);
+                    }
+                }
+                if fee {
+                    rule = "fifo";
+                }
+                order_lots(&mut lots, rule, use_fair_value, &disp_lot);
+                lots
+            }
+        };
+
+        // Weighted-average cost: restate every lot in the pool to the pool's average
+        // carrying value so the relieved basis and the remaining holdings agree
+        let average_price = if pooled {
+            let pool_sats: i64 = candidates.iter().map(|l| l.undisposed_satoshis).sum();
+            if pool_sats > 0 {
+                let pool_value: i128 = candidates.iter()
+                    .map(|l| l.undisposed_satoshis as i128 * l.usd_cents_btc_fair_value as i128)
+                    .sum();
+                let average = rounding_div(pool_value, pool_sats as i128);
+                let pool_ids: Vec<i32> = candidates.iter().map(|l| l.id).collect();
+                diesel::update(acquisitions::table.filter(acquisitions::id.eq_any(pool_ids)))
+                    .set(acquisitions::usd_cents_btc_fair_value.eq(average))
+                    .execute(conn)
+                    .map_err(|e| format!("Error restating pool carrying value: {}", e))?;
+                Some(average)
+            } else {
+                None
+            }
+        } else {
+            None
+        };
 
+        let mut passed_over: Vec<PassedOverLot> = Vec::new();
+        for (rank, acq_lot) in candidates.into_iter().enumerate() {
             let acq_undisposed = if match_type == "gaap" {
                 acq_lot.undisposed_satoshis
             } else {
                 acq_lot.tax_undisposed_satoshis
             };
+            if acq_undisposed <= 0 {
+                continue;
+            }
+            if remaining == 0 {
+                if match_type == "tax" {
+                    passed_over.push(PassedOverLot {
+                        disposition_id: disp_lot.id,
+                        acquisition_id: acq_lot.id,
+                        selection_rank: rank as i32 + 1,
+                        satoshis: acq_undisposed,
+                    });
+                }
+                continue;
+            }
 
             let sats_disposed = min(-remaining, acq_undisposed);
 
-            let price_per_btc = if use_fair_value {
+            let price_per_btc = if let Some(average) = average_price {
+                average
+            } else if use_fair_value {
                 acq_lot.usd_cents_btc_fair_value
             } else {
                 acq_lot.usd_cents_btc_basis
@@ -196,7 +577,8 @@ fn fifo_match(
 
             let basis: i64 = rounding_div(sats_disposed as i128 * price_per_btc as i128, 100_000_000);
             let fv_disposed_cents = rounding_div(sats_disposed as i128 * disp_lot.usd_cents_btc_basis as i128, 100_000_000);
-            let rgl = fv_disposed_cents - basis;
+            let fee = fee_share(&disp_lot, -remaining, sats_disposed);
+            let rgl = fv_disposed_cents - fee - basis;
             let term = disp_lot.disposition_date - acq_lot.acquisition_date;
 
             if term.num_seconds() < 0 {
@@ -214,7 +596,10 @@ fn fifo_match(
                 satoshis: sats_disposed,
                 basis,
                 rgl,
-                term: if term.num_days() > 365 { String::from("long") } else { String::from("short") },
+                term: lot_term(acq_lot.acquisition_date, disp_lot.disposition_date).to_string(),
+                fee,
+                lot_method: Some(rule.to_string()),
+                selection_rank: Some(rank as i32 + 1),
             };
 
             // Update the appropriate undisposed tracker
@@ -247,6 +632,31 @@ fn fifo_match(
 
             remaining += sats_disposed;
         }
+
+        diesel::insert_into(passed_over_lots::table)
+            .values(&passed_over)
+            .execute(conn)
+            .map_err(|e| format!("Error inserting passed over lots: {}", e))?;
+
+        if remaining != 0 {
+            let scope_msg = if scope == "wallet" {
+                format!(" in wallet '{}'", disp_lot.wallet)
+            } else {
+                String::new()
+            };
+            return Err(format!(
+                "No undisposed acquisition lots available{} for {} {} matching. \
+                 Disposition on {} for {} sats cannot be matched. \
+                 All changes have been rolled back.",
+                scope_msg, match_type, method.to_uppercase(),
+                disp_lot.disposition_date.format("%Y-%m-%d"),
+                -disp_lot.satoshis
+            ));
+        }
+    }
+
+    while let Some(mark) = pending_marks.pop() {
+        apply_mark(conn, mark)?;
     }
     Ok(())
 }
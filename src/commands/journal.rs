@@ -0,0 +1,382 @@
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use crate::{LotConfig, rounding_div};
+use crate::commands::holdings::carrying_values_on;
+use crate::commands::rollforward::{mark_adjustments, reconcile};
+use crate::models::{Acquisition, Disposition, JournalLine, ReportDates, Transfer};
+use crate::schema::{acquisitions, acquisition_dispositions, dispositions, transfer_acquisitions, transfers};
+
+/// Account roles a chart-of-accounts file can map, with the account used when it does not.
+const DEFAULT_ACCOUNTS: [(&str, &str); 5] = [
+    ("digital_assets", "Digital Assets"),
+    ("cash", "Cash"),
+    ("realized_gain_loss", "Realized Gain/Loss on Digital Assets"),
+    ("unrealized_gain_loss", "Unrealized Gain/Loss on Digital Assets"),
+    ("network_fees", "Network Fees"),
+];
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct AccountRecord {
+    role: String,
+    #[serde(default)]
+    wallet: Option<String>,
+    account: String,
+}
+
+/// The GL account for each role, and the digital assets sub-account for each wallet.
+#[derive(Debug)]
+pub struct ChartOfAccounts {
+    accounts: HashMap<String, String>,
+    wallets: HashMap<String, String>,
+}
+
+impl Default for ChartOfAccounts {
+    fn default() -> Self {
+        Self {
+            accounts: DEFAULT_ACCOUNTS.iter().map(|(role, account)| (role.to_string(), account.to_string())).collect(),
+            wallets: HashMap::new(),
+        }
+    }
+}
+
+impl ChartOfAccounts {
+    /// Reads a `Role,Wallet,Account` CSV. Roles left out keep their default account. A
+    /// `digital_assets` row with a `Wallet` names that wallet's sub-account.
+    pub fn load(file: &PathBuf) -> Result<Self, String> {
+        let contents = std::fs::read(file)
+            .map_err(|e| format!("Error reading file {:?}: {}", file, e))?;
+        let mut rdr = csv::Reader::from_reader(contents.as_slice());
+
+        let mut chart = Self::default();
+        for result in rdr.deserialize::<AccountRecord>() {
+            let record = result.map_err(|e| format!("Error parsing chart of accounts CSV: {}", e))?;
+            if !chart.accounts.contains_key(&record.role) {
+                let roles: Vec<&str> = DEFAULT_ACCOUNTS.iter().map(|(role, _)| *role).collect();
+                return Err(format!("Unknown account role '{}'. Must be one of: {}", record.role, roles.join(", ")));
+            }
+            match record.wallet.filter(|w| !w.trim().is_empty()) {
+                Some(wallet) if record.role == "digital_assets" => {
+                    chart.wallets.insert(wallet, record.account);
+                }
+                Some(wallet) => {
+                    return Err(format!(
+                        "Account '{}' is mapped to wallet '{}', but only digital_assets has wallet sub-accounts",
+                        record.account, wallet
+                    ));
+                }
+                None => {
+                    chart.accounts.insert(record.role, record.account);
+                }
+            }
+        }
+        Ok(chart)
+    }
+
+    fn account(&self, role: &str) -> String {
+        self.accounts[role].clone()
+    }
+
+    /// `wallet`'s mapped sub-account, or else the digital assets account and the wallet name
+    fn digital_assets(&self, wallet: &str) -> String {
+        self.wallets.get(wallet).cloned()
+            .unwrap_or_else(|| format!("{}:{}", self.accounts["digital_assets"], wallet))
+    }
+
+    /// Whether `account` is a wallet's digital assets sub-account.
+    fn is_digital_assets(&self, account: &str) -> bool {
+        self.wallets.values().any(|mapped| mapped == account)
+            || account.strip_prefix(self.accounts["digital_assets"].as_str()).is_some_and(|rest| rest.starts_with(':'))
+    }
+}
+
+/// What `journal` wrote.
+#[derive(Debug, Default)]
+pub struct JournalSummary {
+    pub entries: usize,
+    pub total_debits: Decimal,
+}
+
+struct Entry {
+    date: NaiveDateTime,
+    /// Same-day entries are ordered purchases, transfers, dispositions, then marks
+    kind: u8,
+    source_id: i32,
+    memo: String,
+    /// Signed cents per account, debits positive
+    lines: Vec<(String, i64)>,
+}
+
+impl Entry {
+    fn new(date: NaiveDateTime, kind: u8, source_id: i32, memo: String) -> Self {
+        Self { date, kind, source_id, memo, lines: Vec::new() }
+    }
+
+    fn post(&mut self, account: String, cents: i64) {
+        match self.lines.iter_mut().find(|(a, _)| *a == account) {
+            Some((_, total)) => *total += cents,
+            None => self.lines.push((account, cents)),
+        }
+    }
+}
+
+fn btc(sats: i64) -> Decimal {
+    Decimal::new(sats, 8).normalize()
+}
+
+fn value_cents(sats: i64, cents_per_btc: i64) -> i64 {
+    rounding_div(sats as i128 * cents_per_btc as i128, 100_000_000)
+}
+
+/// The wallet `lot` was in at the end of `date`. Transfers dated after `date` moved it
+/// from the first one's `from_wallet`, and a lot split off by a transfer was part of its
+/// parent until then.
+fn wallet_on(
+    lot: &Acquisition,
+    date: NaiveDateTime,
+    lots: &HashMap<i32, Acquisition>,
+    moves: &HashMap<i32, Vec<(NaiveDateTime, String)>>,
+) -> String {
+    let lot_moves = moves.get(&lot.id).map(Vec::as_slice).unwrap_or_default();
+    if lot.split_reason.as_deref() == Some("transfer") {
+        if let (Some(parent_id), Some((created, _))) = (lot.parent_acquisition_id, lot_moves.first()) {
+            if date < *created {
+                return wallet_on(&lots[&parent_id], date, lots, moves);
+            }
+        }
+    }
+    lot_moves.iter()
+        .find(|(moved, _)| *moved > date)
+        .map_or_else(|| lot.wallet.clone(), |(_, from_wallet)| from_wallet.clone())
+}
+
+fn root_of(lot: &Acquisition, lots: &HashMap<i32, Acquisition>) -> i32 {
+    match lot.parent_acquisition_id {
+        Some(parent_id) => root_of(&lots[&parent_id], lots),
+        None => lot.id,
+    }
+}
+
+/// Writes double-entry GL journal entries for `beg` through `end`: purchases (digital
+/// assets against cash), transfers between wallet sub-accounts at carrying value,
+/// dispositions (cash, digital assets relieved at GAAP basis, and realized gain/loss),
+/// and each mark to market's adjustment against unrealized gain/loss. Fails without
+/// writing if the digital assets postings do not tie to the roll-forward for the period.
+pub fn journal(beg: &String, end: &String, chart: &ChartOfAccounts, conn: &mut SqliteConnection, config: &LotConfig) -> Result<JournalSummary, anyhow::Error> {
+    let dates: ReportDates = serde_json::from_str(&format!(r#"{{ "beginning_date": "{}", "ending_date": "{}" }}"#, beg, end)).expect("Failed to deserialize provided dates");
+    let beg_date_hms = dates.beginning_date.date().and_hms_opt(0, 0, 0).unwrap();
+    let end_date_hms = dates.ending_date.date().and_hms_opt(23, 59, 59).unwrap();
+
+    let lots: HashMap<i32, Acquisition> = acquisitions::table
+        .select(Acquisition::as_select())
+        .load(conn)?
+        .into_iter()
+        .map(|lot| (lot.id, lot))
+        .collect();
+    let mut moves: HashMap<i32, Vec<(NaiveDateTime, String)>> = HashMap::new();
+    let lot_moves: Vec<(i32, NaiveDateTime, String)> = transfer_acquisitions::table
+        .inner_join(transfers::table)
+        .order((transfers::transfer_date.asc(), transfers::id.asc()))
+        .select((transfer_acquisitions::acquisition_id, transfers::transfer_date, transfers::from_wallet))
+        .load(conn)?;
+    for (lot_id, moved, from_wallet) in lot_moves {
+        moves.entry(lot_id).or_default().push((moved, from_wallet));
+    }
+
+    let mut entries: Vec<Entry> = Vec::new();
+
+    // A purchase split since by `allocate` or `transfer` is one entry across its lots
+    let mut purchases: HashMap<i32, Entry> = HashMap::new();
+    let mut purchased: Vec<&Acquisition> = lots.values()
+        .filter(|l| l.acquisition_date >= beg_date_hms && l.acquisition_date <= end_date_hms)
+        .collect();
+    purchased.sort_by_key(|l| l.id);
+    let mut purchased_sats: HashMap<i32, i64> = HashMap::new();
+    for lot in purchased {
+        let root_id = root_of(lot, &lots);
+        let cost = value_cents(lot.satoshis, lot.usd_cents_btc_basis);
+        let entry = purchases.entry(root_id)
+            .or_insert_with(|| Entry::new(lot.acquisition_date, 0, root_id, String::new()));
+        entry.post(chart.digital_assets(&wallet_on(lot, lot.acquisition_date, &lots, &moves)), cost);
+        entry.post(chart.account("cash"), -cost);
+        *purchased_sats.entry(root_id).or_default() += lot.satoshis;
+    }
+    for (root_id, mut entry) in purchases {
+        entry.memo = format!("Purchase of {} BTC (lot {})", btc(purchased_sats[&root_id]), root_id);
+        entries.push(entry);
+    }
+
+    let period_transfers: Vec<Transfer> = transfers::table
+        .filter(transfers::transfer_date.ge(beg_date_hms))
+        .filter(transfers::transfer_date.le(end_date_hms))
+        .select(Transfer::as_select())
+        .load(conn)?;
+    let fee_dispositions: HashMap<i32, i32> = transfers::table
+        .filter(transfers::fee_disposition_id.is_not_null())
+        .select((transfers::fee_disposition_id.assume_not_null(), transfers::id))
+        .load(conn)?
+        .into_iter()
+        .collect();
+    for transfer in &period_transfers {
+        let moved: Vec<(i32, i64)> = transfer_acquisitions::table
+            .filter(transfer_acquisitions::transfer_id.eq(transfer.id))
+            .select((transfer_acquisitions::acquisition_id, transfer_acquisitions::satoshis))
+            .load(conn)?;
+        let moved_ids: HashSet<i32> = moved.iter().map(|(lot_id, _)| *lot_id).collect();
+        let moved_lots: Vec<Acquisition> = acquisitions::table
+            .filter(acquisitions::id.eq_any(&moved_ids))
+            .select(Acquisition::as_select())
+            .load(conn)?;
+        let carrying = carrying_values_on(conn, config, &moved_lots, transfer.transfer_date)?;
+        let value: i64 = moved.iter().map(|(lot_id, sats)| value_cents(*sats, carrying[lot_id])).sum();
+        let moved_sats: i64 = moved.iter().map(|(_, sats)| sats).sum();
+
+        let mut entry = Entry::new(transfer.transfer_date, 1, transfer.id, format!(
+            "Transfer of {} BTC from {} to {} (transfer {})",
+            btc(moved_sats), transfer.from_wallet, transfer.to_wallet, transfer.id
+        ));
+        entry.post(chart.digital_assets(&transfer.to_wallet), value);
+        entry.post(chart.digital_assets(&transfer.from_wallet), -value);
+        entries.push(entry);
+    }
+
+    let period_dispositions: Vec<Disposition> = dispositions::table
+        .filter(dispositions::disposition_date.ge(beg_date_hms))
+        .filter(dispositions::disposition_date.le(end_date_hms))
+        .select(Disposition::as_select())
+        .load(conn)?;
+    for disposition in &period_dispositions {
+        let relieved: Vec<(i32, i64, i64)> = acquisition_dispositions::table
+            .filter(acquisition_dispositions::disposition_id.eq(disposition.id))
+            .filter(acquisition_dispositions::match_type.eq("gaap"))
+            .select((acquisition_dispositions::acquisition_id, acquisition_dispositions::basis, acquisition_dispositions::rgl))
+            .load(conn)?;
+        let basis: i64 = relieved.iter().map(|(_, basis, _)| basis).sum();
+        let rgl: i64 = relieved.iter().map(|(_, _, rgl)| rgl).sum();
+
+        // A transfer's network fee is spent rather than sold, so its proceeds are an expense
+        let mut entry = match fee_dispositions.get(&disposition.id) {
+            Some(transfer_id) => {
+                let mut entry = Entry::new(disposition.disposition_date, 2, disposition.id, format!(
+                    "Network fee of {} BTC for transfer {} (disposition {})",
+                    btc(-disposition.satoshis), transfer_id, disposition.id
+                ));
+                entry.post(chart.account("network_fees"), basis + rgl);
+                entry
+            }
+            None => {
+                let mut entry = Entry::new(disposition.disposition_date, 2, disposition.id, format!(
+                    "Sale of {} BTC (disposition {})",
+                    btc(-disposition.satoshis), disposition.id
+                ));
+                entry.post(chart.account("cash"), basis + rgl);
+                entry
+            }
+        };
+        // GAAP relief is universal, so each lot is relieved from the wallet it was in
+        for (lot_id, basis, _) in &relieved {
+            let lot = &lots[lot_id];
+            entry.post(chart.digital_assets(&wallet_on(lot, disposition.disposition_date, &lots, &moves)), -basis);
+        }
+        entry.post(chart.account("realized_gain_loss"), -rgl);
+        entries.push(entry);
+    }
+
+    let mut marks: Vec<Entry> = Vec::new();
+    for adjustment in mark_adjustments(conn, config, beg_date_hms, end_date_hms)? {
+        if marks.last().is_none_or(|entry| entry.date != adjustment.date) {
+            marks.push(Entry::new(adjustment.date, 3, marks.len() as i32, format!(
+                "Mark to market at ${} per BTC",
+                Decimal::new(adjustment.fair_value_cents, 2)
+            )));
+        }
+        let entry = marks.last_mut().unwrap();
+        let lot = &lots[&adjustment.lot_id];
+        let change = value_cents(adjustment.satoshis, adjustment.fair_value_cents)
+            - value_cents(adjustment.satoshis, adjustment.prior_cents);
+        entry.post(chart.digital_assets(&wallet_on(lot, adjustment.date, &lots, &moves)), change);
+        entry.post(chart.account("unrealized_gain_loss"), -change);
+    }
+    entries.extend(marks);
+
+    entries.sort_by_key(|e| (e.date, e.kind, e.source_id));
+
+    // Digital assets should move by what the roll-forward's purchases, disposals and
+    // remeasurement lines say; its restatement and rounding lines have no entries
+    let rollforward = reconcile(conn, config, beg_date_hms, end_date_hms)?;
+    let expected = rollforward.purchases - rollforward.disposals + rollforward.remeasurement;
+    let assets_changed: i64 = entries.iter()
+        .flat_map(|entry| &entry.lines)
+        .filter(|(account, _)| chart.is_digital_assets(account))
+        .map(|(_, cents)| cents)
+        .sum();
+    if Decimal::new(assets_changed, 2) != expected {
+        return Err(anyhow::anyhow!(
+            "The journal does not tie to the roll-forward: digital assets change by ${}, but purchases ${} \
+             less disposals ${} plus remeasurement ${} is ${}",
+            Decimal::new(assets_changed, 2), rollforward.purchases, rollforward.disposals,
+            rollforward.remeasurement, expected
+        ));
+    }
+
+    let mut rows = Vec::new();
+    let mut summary = JournalSummary::default();
+    for entry in entries {
+        let debits: i64 = entry.lines.iter().map(|(_, cents)| *cents).filter(|cents| *cents > 0).sum();
+        let lines: Vec<(String, i64)> = entry.lines.into_iter().filter(|(_, cents)| *cents != 0).collect();
+        if lines.is_empty() {
+            continue;
+        }
+
+        summary.entries += 1;
+        summary.total_debits += Decimal::new(debits, 2);
+        for (account, cents) in lines {
+            rows.push(JournalLine {
+                entry: summary.entries as i32,
+                date: entry.date.date(),
+                account,
+                debit: (cents > 0).then(|| Decimal::new(cents, 2)),
+                credit: (cents < 0).then(|| Decimal::new(-cents, 2)),
+                memo: entry.memo.clone(),
+            });
+        }
+    }
+
+    ensure_balanced(&rows).map_err(|e| anyhow::anyhow!("{}", e))?;
+
+    let file_path: PathBuf = PathBuf::from(format!("./reports/journal_{}_{}.csv", dates.beginning_date.date(), dates.ending_date.date()));
+    let mut wtr = csv::Writer::from_path(file_path)?;
+    for row in rows {
+        wtr.serialize(row)?;
+    }
+    wtr.flush()?;
+
+    Ok(summary)
+}
+
+/// Fails unless every entry's debits equal its credits and the journal's total debits
+/// equal its total credits. `rows` are in entry order.
+pub fn ensure_balanced(rows: &[JournalLine]) -> Result<(), String> {
+    let debits = |lines: &[JournalLine]| -> Decimal { lines.iter().filter_map(|l| l.debit).sum() };
+    let credits = |lines: &[JournalLine]| -> Decimal { lines.iter().filter_map(|l| l.credit).sum() };
+
+    for lines in rows.chunk_by(|a, b| a.entry == b.entry) {
+        if debits(lines) != credits(lines) {
+            return Err(format!(
+                "Journal entry {} on {} ({}) does not balance: debits ${}, credits ${}",
+                lines[0].entry, lines[0].date, lines[0].memo, debits(lines), credits(lines)
+            ));
+        }
+    }
+    if debits(rows) != credits(rows) {
+        return Err(format!("The journal does not balance: debits ${}, credits ${}", debits(rows), credits(rows)));
+    }
+
+    Ok(())
+}
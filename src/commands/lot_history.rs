@@ -0,0 +1,76 @@
+use std::fmt::Write;
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use rust_decimal::Decimal;
+
+use crate::models::{Acquisition, AcquisitionDisposition, Disposition};
+use crate::schema::{acquisitions, dispositions, acquisition_dispositions};
+
+/// Renders the lineage of lot `id` as an indented tree: the original purchase it descends
+/// from, every fragment split off by `allocate` or `transfer`, and each lot's tax and GAAP
+/// matches. The requested lot is marked with `*`.
+pub fn lot_history(id: i32, conn: &mut SqliteConnection) -> Result<String, String> {
+    let mut root = find_lot(conn, id)?;
+    while let Some(parent_id) = root.parent_acquisition_id {
+        root = find_lot(conn, parent_id)?;
+    }
+
+    let mut out = String::new();
+    write_lot(conn, &root, id, 0, &mut out)?;
+    Ok(out)
+}
+
+fn find_lot(conn: &mut SqliteConnection, id: i32) -> Result<Acquisition, String> {
+    acquisitions::table
+        .find(id)
+        .select(Acquisition::as_select())
+        .first(conn)
+        .optional()
+        .map_err(|e| format!("Error fetching acquisition {}: {}", id, e))?
+        .ok_or_else(|| format!("Acquisition {} does not exist", id))
+}
+
+fn write_lot(conn: &mut SqliteConnection, lot: &Acquisition, selected: i32, depth: usize, out: &mut String) -> Result<(), String> {
+    let indent = "    ".repeat(depth);
+    let marker = if lot.id == selected { "* " } else { "" };
+    let origin = match (&lot.split_reason, lot.parent_acquisition_id) {
+        (Some(reason), Some(parent_id)) => format!("split from lot {} by {}", parent_id, reason),
+        _ => "original purchase".to_string(),
+    };
+    writeln!(
+        out,
+        "{}{}lot {} {} wallet '{}' {} BTC at ${} ({}); tax undisposed {} BTC, GAAP undisposed {} BTC",
+        indent, marker, lot.id, lot.acquisition_date.format("%Y-%m-%d"), lot.wallet,
+        Decimal::new(lot.satoshis, 8), Decimal::new(lot.usd_cents_btc_basis, 2), origin,
+        Decimal::new(lot.tax_undisposed_satoshis, 8), Decimal::new(lot.undisposed_satoshis, 8),
+    ).unwrap();
+
+    let matches: Vec<(AcquisitionDisposition, Disposition)> = acquisition_dispositions::table
+        .inner_join(dispositions::table)
+        .filter(acquisition_dispositions::acquisition_id.eq(lot.id))
+        .order((dispositions::disposition_date.asc(), dispositions::id.asc(), acquisition_dispositions::match_type.desc()))
+        .select((AcquisitionDisposition::as_select(), Disposition::as_select()))
+        .load(conn)
+        .map_err(|e| format!("Error fetching matches for lot {}: {}", lot.id, e))?;
+    for (acq_disp, disp) in &matches {
+        writeln!(
+            out,
+            "{}  {} disposition {} {} wallet '{}' {} BTC basis ${} rgl ${} {}",
+            indent, acq_disp.match_type, disp.id, disp.disposition_date.format("%Y-%m-%d"), disp.wallet,
+            Decimal::new(acq_disp.satoshis, 8), Decimal::new(acq_disp.basis, 2), Decimal::new(acq_disp.rgl, 2),
+            acq_disp.term,
+        ).unwrap();
+    }
+
+    let children: Vec<Acquisition> = acquisitions::table
+        .filter(acquisitions::parent_acquisition_id.eq(lot.id))
+        .order(acquisitions::id.asc())
+        .select(Acquisition::as_select())
+        .load(conn)
+        .map_err(|e| format!("Error fetching lots split from lot {}: {}", lot.id, e))?;
+    for child in &children {
+        write_lot(conn, child, selected, depth + 1, out)?;
+    }
+
+    Ok(())
+}
@@ -1,30 +1,160 @@
+use std::collections::HashMap;
 use diesel::prelude::*;
 use diesel::sqlite::SqliteConnection;
+use diesel::Connection;
 use rust_decimal::{Decimal, prelude::FromPrimitive, RoundingStrategy};
 use rust_decimal_macros::dec;
 use std::path::PathBuf;
+use crate::LotConfig;
+use crate::commands::audit::AuditCommand;
+use crate::commands::holdings::carrying_values_on;
+use crate::commands::period::ensure_open;
+use crate::commands::preview::{ChangePreview, with_preview};
+use crate::commands::rematch::rematch_gaap_from;
 use crate::schema::acquisition_fair_values::*;
-use crate::schema::{fair_values, acquisition_fair_values};
-use crate::schema::acquisitions::{self, undisposed_satoshis, acquisition_date, usd_cents_btc_fair_value};
+use crate::schema::{fair_values, acquisition_fair_values, acquisition_dispositions, dispositions};
+use crate::schema::acquisitions::{self, acquisition_date, usd_cents_btc_fair_value};
 use crate::models::{FairValue, NewFairValue, Acquisition, FairValueHolding};
 
-pub fn mark_to_market(price: &String, date: &String, conn: &mut SqliteConnection) -> Result<(), anyhow::Error> {
+#[derive(Debug)]
+enum MarkError {
+    Diesel(diesel::result::Error),
+    Custom(String),
+}
+
+impl From<diesel::result::Error> for MarkError {
+    fn from(e: diesel::result::Error) -> Self {
+        MarkError::Diesel(e)
+    }
+}
+
+impl From<String> for MarkError {
+    fn from(s: String) -> Self {
+        MarkError::Custom(s)
+    }
+}
+
+impl From<anyhow::Error> for MarkError {
+    fn from(e: anyhow::Error) -> Self {
+        MarkError::Custom(e.to_string())
+    }
+}
+
+impl std::fmt::Display for MarkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MarkError::Diesel(e) => write!(f, "{}", e),
+            MarkError::Custom(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+/// What `mark_to_market` did.
+#[derive(Debug, Default)]
+pub struct MarkToMarketSummary {
+    /// Lots held on the mark date
+    pub lots_marked: usize,
+    /// GAAP matches for dispositions after the mark date, unwound and replayed so their
+    /// basis reflects the mark
+    pub rematched: usize,
+    /// The lots, dispositions and matches the mark changed
+    pub changes: ChangePreview,
+}
+
+/// Marks every lot held at the end of `date` to `price` and writes the mark-to-market
+/// report. Quantities are as of `date`, adding back later GAAP disposals, and
+/// GAAP dispositions dated after `date` are rematched against the new carrying value; tax
+/// matches are left alone. A mark dated before an existing mark is refused, since later
+/// marks must be undone first. With `dry_run` nothing is saved and no report is written.
+pub fn mark_to_market(price: &String, date: &String, conn: &mut SqliteConnection, config: &LotConfig, dry_run: bool) -> Result<MarkToMarketSummary, anyhow::Error> {
     let mut fair_value: NewFairValue = serde_json::from_str(&format!(r#"{{ "fair_value_cents": "{}", "date": "{}" }}"#, price, date)).expect("Failed to deserialize provided date/price");
 
     fair_value.date = fair_value.date.date().and_hms_opt(23, 59, 59).unwrap();
 
-    let fair_value_inserted: FairValue = diesel::insert_into(fair_values::table)
-        .values(&fair_value)
-        .get_result(conn)
-        .expect(format!("Error inserting {:?} into the Fair Values table", fair_value).as_str());
+    let audit = AuditCommand::new("mark-to-market", serde_json::json!({ "price": price, "date": date }));
+    let ((mut summary, rows), changes) = with_preview(conn, dry_run, &audit, |conn| conn.transaction::<(MarkToMarketSummary, Vec<FairValueHolding>), MarkError, _>(|conn| {
+        ensure_open(conn, fair_value.date, "The mark to market")?;
+
+        let later: Option<FairValue> = fair_values::table
+            .filter(fair_values::date.gt(fair_value.date))
+            .order((fair_values::date.desc(), fair_values::id.desc()))
+            .select(FairValue::as_select())
+            .first(conn)
+            .optional()?;
+        if let Some(later) = later {
+            return Err(MarkError::Custom(format!(
+                "A mark to market dated {} already exists. Undo it with `undo-mark-to-market` and mark in date order.",
+                later.date.format("%Y-%m-%d")
+            )));
+        }
+
+        let lots: Vec<Acquisition> = acquisitions::table
+            .filter(acquisition_date.le(fair_value.date))
+            .select(Acquisition::as_select())
+            .load(conn)?;
+
+        // MTM is a GAAP operation — add back GAAP disposals dated after the mark to find
+        // what each lot held on the mark date
+        let subsequent: Vec<(i32, i64)> = acquisition_dispositions::table
+            .inner_join(dispositions::table)
+            .filter(acquisition_dispositions::match_type.eq("gaap"))
+            .filter(dispositions::disposition_date.gt(fair_value.date))
+            .select((acquisition_dispositions::acquisition_id, acquisition_dispositions::satoshis))
+            .load(conn)?;
+        let mut held: HashMap<i32, i64> = lots.iter().map(|l| (l.id, l.undisposed_satoshis)).collect();
+        for (lot_id, sats) in &subsequent {
+            if let Some(undisposed) = held.get_mut(lot_id) {
+                *undisposed += sats;
+            }
+        }
+        let marked_lots: Vec<Acquisition> = lots.into_iter().filter(|l| held[&l.id] > 0).collect();
+        let previous = carrying_values_on(conn, config, &marked_lots, fair_value.date)?;
+
+        let fair_value_inserted: FairValue = diesel::insert_into(fair_values::table)
+            .values(&fair_value)
+            .get_result(conn)?;
+
+        let mut rows = Vec::new();
+        for lot in &marked_lots {
+            // Use GAAP tracker for the report (MTM is a GAAP operation)
+            let undisposed_btc = Decimal::from_i64(held[&lot.id]).unwrap() / dec!(100_000_000);
+            let current_usd_fair_value_price = Decimal::from_i64(fair_value.fair_value_cents).unwrap() / dec!(100);
+            let previous_usd_fair_value = undisposed_btc * Decimal::from_i64(previous[&lot.id]).unwrap() / dec!(100);
+            let current_usd_fair_value = undisposed_btc * current_usd_fair_value_price;
+
+            rows.push(FairValueHolding {
+                wallet: lot.wallet.clone(),
+                acquisition_date: lot.acquisition_date,
+                btc: Decimal::from_i64(lot.satoshis).unwrap() / dec!(100_000_000),
+                undisposed_btc,
+                usd_basis: (Decimal::from_i64(lot.usd_cents_btc_basis).unwrap() / dec!(100) * undisposed_btc).round_dp_with_strategy(2, RoundingStrategy::MidpointAwayFromZero),
+                previous_usd_fair_value: previous_usd_fair_value.round_dp_with_strategy(2, RoundingStrategy::MidpointAwayFromZero),
+                current_usd_fair_value: current_usd_fair_value.round_dp_with_strategy(2, RoundingStrategy::MidpointAwayFromZero),
+                fair_value_adjustment: (current_usd_fair_value - previous_usd_fair_value).round_dp_with_strategy(2, RoundingStrategy::MidpointAwayFromZero),
+            });
 
-    // MTM is a GAAP operation — only include lots with GAAP undisposed satoshis
-    let undisposed_lots: Vec<Acquisition> = acquisitions::table
-                                                .filter(undisposed_satoshis.gt(0))
-                                                .filter(acquisition_date.le(fair_value.date))
-                                                .select(Acquisition::as_select())
-                                                .load(conn)
-                                                .expect("Error fetching Undisposed Lots");
+            diesel::insert_into(acquisition_fair_values::table)
+                .values((
+                    acquisition_id.eq(lot.id),
+                    fair_value_id.eq(fair_value_inserted.id),
+                    prior_usd_cents_btc_fair_value.eq(previous[&lot.id]),
+                ))
+                .execute(conn)?;
+            diesel::update(acquisitions::table.find(lot.id))
+                .set(usd_cents_btc_fair_value.eq(fair_value.fair_value_cents))
+                .execute(conn)?;
+        }
+
+        // Dispositions after the mark relieved marked lots at the carrying value before it
+        let relieved_after = subsequent.iter().any(|(lot_id, _)| held.get(lot_id).is_some_and(|sats| *sats > 0));
+        let rematched = if relieved_after { rematch_gaap_from(conn, config, fair_value.date)? } else { 0 };
+
+        Ok((MarkToMarketSummary { lots_marked: marked_lots.len(), rematched, ..Default::default() }, rows))
+    }).map_err(|e| e.to_string())).map_err(|e| anyhow::anyhow!("{}", e))?;
+    summary.changes = changes;
+    if dry_run {
+        return Ok(summary);
+    }
 
     let file_path: PathBuf = PathBuf::from(format!("./reports/mark-to-market-{}.csv", fair_value.date.date()));
     let mut wtr = csv::Writer::from_path(file_path).unwrap();
@@ -36,25 +166,7 @@ pub fn mark_to_market(price: &String, date: &String, conn: &mut SqliteConnection
     let mut total_current_usd_fair_value = dec!(0);
     let mut total_fair_value_adjustment = dec!(0);
 
-    for lot in undisposed_lots {
-        // Use GAAP tracker for the report (MTM is a GAAP operation)
-        let undisposed_btc = Decimal::from_i64(lot.undisposed_satoshis).unwrap() / dec!(100_000_000);
-        let current_usd_fair_value_price = Decimal::from_i64(fair_value.fair_value_cents).unwrap() / dec!(100);
-        let previous_usd_fair_value = undisposed_btc * Decimal::from_i64(lot.usd_cents_btc_fair_value).unwrap() / dec!(100);
-        let current_usd_fair_value = undisposed_btc * current_usd_fair_value_price;
-
-        let fv_lot = FairValueHolding {
-            wallet: lot.wallet.clone(),
-            acquisition_date: lot.acquisition_date,
-            btc: Decimal::from_i64(lot.satoshis).unwrap() / dec!(100_000_000),
-            undisposed_btc,
-            usd_basis: (Decimal::from_i64(lot.usd_cents_btc_basis).unwrap() / dec!(100) * undisposed_btc).round_dp_with_strategy(2, RoundingStrategy::MidpointAwayFromZero),
-            previous_usd_fair_value: previous_usd_fair_value.round_dp_with_strategy(2, RoundingStrategy::MidpointAwayFromZero),
-            current_usd_fair_value: current_usd_fair_value.round_dp_with_strategy(2, RoundingStrategy::MidpointAwayFromZero),
-            fair_value_adjustment: (current_usd_fair_value - previous_usd_fair_value).round_dp_with_strategy(2, RoundingStrategy::MidpointAwayFromZero),
-        };
-
-
+    for fv_lot in rows {
         total_btc += fv_lot.btc;
         total_undisposed_btc += fv_lot.undisposed_btc;
         total_usd_basis += fv_lot.usd_basis;
@@ -63,11 +175,6 @@ pub fn mark_to_market(price: &String, date: &String, conn: &mut SqliteConnection
         total_fair_value_adjustment += fv_lot.fair_value_adjustment;
 
         wtr.serialize(fv_lot)?;
-
-        diesel::insert_into(acquisition_fair_values::table)
-            .values((acquisition_id.eq(lot.id), fair_value_id.eq(fair_value_inserted.id)))
-            .execute(conn)
-            .expect("Error inserting acquisition_fair_value");
     }
 
     wtr.write_record(&[
@@ -81,12 +188,5 @@ pub fn mark_to_market(price: &String, date: &String, conn: &mut SqliteConnection
         total_fair_value_adjustment.to_string(),
     ]).unwrap();
 
-    diesel::update(acquisitions::table)
-        .filter(acquisition_date.le(fair_value.date))
-        .filter(undisposed_satoshis.gt(0))
-        .set(usd_cents_btc_fair_value.eq(fair_value.fair_value_cents))
-        .execute(conn)
-        .expect("Error updating Acquistion Lot Fair Value");
-
-    Ok(())
+    Ok(summary)
 }
@@ -0,0 +1,116 @@
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use diesel::Connection;
+
+use crate::commands::audit::{AuditCommand, record};
+use crate::models::{NewPeriodLock, PeriodLock, parse_date_str};
+use crate::schema::period_locks;
+
+#[derive(Debug)]
+enum PeriodError {
+    Diesel(diesel::result::Error),
+    Custom(String),
+}
+
+impl From<diesel::result::Error> for PeriodError {
+    fn from(e: diesel::result::Error) -> Self {
+        PeriodError::Diesel(e)
+    }
+}
+
+impl From<String> for PeriodError {
+    fn from(s: String) -> Self {
+        PeriodError::Custom(s)
+    }
+}
+
+impl std::fmt::Display for PeriodError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PeriodError::Diesel(e) => write!(f, "{}", e),
+            PeriodError::Custom(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+/// The end of the latest closed period, if any period is closed.
+pub fn locked_through(conn: &mut SqliteConnection) -> Result<Option<NaiveDateTime>, String> {
+    period_locks::table
+        .filter(period_locks::reopened_at.is_null())
+        .select(diesel::dsl::max(period_locks::locked_through))
+        .first(conn)
+        .map_err(|e| format!("Error fetching period locks: {}", e))
+}
+
+/// Fails with a message naming `what` when `date` falls in a closed period.
+pub fn ensure_open(conn: &mut SqliteConnection, date: NaiveDateTime, what: &str) -> Result<(), String> {
+    match locked_through(conn)? {
+        Some(lock) if date <= lock => Err(format!(
+            "{} is dated {}, but the period is closed through {}. Run `reopen-period` to change it. \
+             All changes have been rolled back.",
+            what, date.format("%Y-%m-%d"), lock.format("%Y-%m-%d")
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Closes every period through the end of `through`. Later imports, transfers,
+/// allocations and marks to market may not change matches or fair values dated on or
+/// before that day.
+pub fn close_period(through: &str, conn: &mut SqliteConnection) -> Result<PeriodLock, String> {
+    let lock_date = parse_date_str(through)?.date().and_hms_opt(23, 59, 59).unwrap();
+
+    conn.transaction::<PeriodLock, PeriodError, _>(|conn| {
+        if let Some(lock) = locked_through(conn)?.filter(|lock| *lock >= lock_date) {
+            return Err(PeriodError::Custom(format!("The period is already closed through {}", lock.format("%Y-%m-%d"))));
+        }
+
+        diesel::insert_into(period_locks::table)
+            .values(&NewPeriodLock { locked_through: lock_date, closed_at: Utc::now().naive_utc() })
+            .execute(conn)
+            .map_err(|e| format!("Error saving period lock: {}", e))?;
+        let lock: PeriodLock = period_locks::table
+            .order(period_locks::id.desc())
+            .select(PeriodLock::as_select())
+            .first(conn)
+            .map_err(|e| format!("Error fetching period lock: {}", e))?;
+
+        record(conn, &AuditCommand::new("close-period", serde_json::json!({ "through": through })), 1)?;
+        Ok(lock)
+    }).map_err(|e| e.to_string())
+}
+
+/// Reopens the latest closed period, recording `reason` on its lock. Any earlier
+/// closed period stays closed. Returns the reopened lock.
+pub fn reopen_period(reason: &str, conn: &mut SqliteConnection) -> Result<PeriodLock, String> {
+    if reason.trim().is_empty() {
+        return Err("A reason is required to reopen a closed period".to_string());
+    }
+    conn.transaction::<PeriodLock, PeriodError, _>(|conn| {
+        let lock: PeriodLock = period_locks::table
+            .filter(period_locks::reopened_at.is_null())
+            .order((period_locks::locked_through.desc(), period_locks::id.desc()))
+            .select(PeriodLock::as_select())
+            .first(conn)
+            .optional()
+            .map_err(|e| format!("Error fetching period locks: {}", e))?
+            .ok_or_else(|| "No period is closed".to_string())?;
+
+        diesel::update(period_locks::table.find(lock.id))
+            .set((
+                period_locks::reopened_at.eq(Utc::now().naive_utc()),
+                period_locks::reopen_reason.eq(reason.trim()),
+            ))
+            .execute(conn)
+            .map_err(|e| format!("Error reopening period: {}", e))?;
+        let lock: PeriodLock = period_locks::table
+            .find(lock.id)
+            .select(PeriodLock::as_select())
+            .first(conn)
+            .map_err(|e| format!("Error fetching period lock: {}", e))?;
+
+        record(conn, &AuditCommand::new("reopen-period", serde_json::json!({ "reason": reason.trim() })), 1)?;
+        Ok(lock)
+    }).map_err(|e| e.to_string())
+}
@@ -0,0 +1,288 @@
+use std::collections::HashMap;
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use diesel::Connection;
+use rust_decimal::Decimal;
+
+use crate::commands::audit::{AuditCommand, record};
+use crate::commands::period::{ensure_open, locked_through};
+use crate::models::{Acquisition, AcquisitionDisposition, Disposition};
+use crate::schema::{acquisitions, dispositions, acquisition_dispositions};
+
+#[derive(Debug)]
+enum PreviewError {
+    Diesel(diesel::result::Error),
+    Custom(String),
+    /// Raised after a dry run to roll the transaction back
+    DryRun,
+}
+
+impl From<diesel::result::Error> for PreviewError {
+    fn from(e: diesel::result::Error) -> Self {
+        PreviewError::Diesel(e)
+    }
+}
+
+impl From<String> for PreviewError {
+    fn from(s: String) -> Self {
+        PreviewError::Custom(s)
+    }
+}
+
+impl std::fmt::Display for PreviewError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PreviewError::Diesel(e) => write!(f, "{}", e),
+            PreviewError::Custom(s) => write!(f, "{}", s),
+            PreviewError::DryRun => write!(f, "Dry run rolled back"),
+        }
+    }
+}
+
+/// Everything a command changed in the lot, disposition and match tables.
+#[derive(Debug, Default)]
+pub struct ChangePreview {
+    pub lots_created: Vec<Acquisition>,
+    /// Existing lots whose satoshis were reduced to split off a new lot, as (before, after)
+    pub lots_split: Vec<(Acquisition, Acquisition)>,
+    /// Lots moved to another wallet, as (lot id, from wallet, to wallet)
+    pub wallet_reassignments: Vec<(i32, String, String)>,
+    pub dispositions_created: Vec<Disposition>,
+    /// Dispositions moved to another wallet, as (disposition id, from wallet, to wallet)
+    pub disposition_reassignments: Vec<(i32, String, String)>,
+    pub matches_created: Vec<AcquisitionDisposition>,
+    pub matches_removed: Vec<AcquisitionDisposition>,
+}
+
+impl ChangePreview {
+    pub fn is_empty(&self) -> bool {
+        self.lots_created.is_empty()
+            && self.lots_split.is_empty()
+            && self.wallet_reassignments.is_empty()
+            && self.dispositions_created.is_empty()
+            && self.disposition_reassignments.is_empty()
+            && self.matches_created.is_empty()
+            && self.matches_removed.is_empty()
+    }
+
+    /// The number of lots, dispositions and matches created, split, moved or removed.
+    pub fn change_count(&self) -> usize {
+        self.lots_created.len()
+            + self.lots_split.len()
+            + self.wallet_reassignments.len()
+            + self.dispositions_created.len()
+            + self.disposition_reassignments.len()
+            + self.matches_created.len()
+            + self.matches_removed.len()
+    }
+}
+
+fn btc(sats: i64) -> Decimal {
+    Decimal::new(sats, 8)
+}
+
+fn usd(cents: i64) -> Decimal {
+    Decimal::new(cents, 2)
+}
+
+impl std::fmt::Display for ChangePreview {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "No changes");
+        }
+        if !self.lots_created.is_empty() {
+            writeln!(f, "Lots created: {}", self.lots_created.len())?;
+            for lot in &self.lots_created {
+                writeln!(f, "  lot {} {} wallet '{}' {} BTC at ${} cost, ${} carrying value",
+                    lot.id, lot.acquisition_date.format("%Y-%m-%d"), lot.wallet,
+                    btc(lot.satoshis), usd(lot.usd_cents_btc_basis), usd(lot.usd_cents_btc_fair_value))?;
+            }
+        }
+        if !self.lots_split.is_empty() {
+            writeln!(f, "Lots split: {}", self.lots_split.len())?;
+            for (before, after) in &self.lots_split {
+                writeln!(f, "  lot {} {} wallet '{}' {} BTC -> {} BTC",
+                    before.id, before.acquisition_date.format("%Y-%m-%d"), before.wallet,
+                    btc(before.satoshis), btc(after.satoshis))?;
+            }
+        }
+        if !self.wallet_reassignments.is_empty() {
+            writeln!(f, "Wallet reassignments: {}", self.wallet_reassignments.len())?;
+            for (id, from, to) in &self.wallet_reassignments {
+                writeln!(f, "  lot {} '{}' -> '{}'", id, from, to)?;
+            }
+        }
+        if !self.dispositions_created.is_empty() {
+            writeln!(f, "Dispositions created: {}", self.dispositions_created.len())?;
+            for disp in &self.dispositions_created {
+                write!(f, "  disposition {} {} wallet '{}' {} BTC at ${}",
+                    disp.id, disp.disposition_date.format("%Y-%m-%d"), disp.wallet,
+                    btc(-disp.satoshis), usd(disp.usd_cents_btc_basis))?;
+                if disp.usd_cents_fee != 0 {
+                    write!(f, " fee ${}", usd(disp.usd_cents_fee))?;
+                }
+                writeln!(f)?;
+            }
+        }
+        if !self.disposition_reassignments.is_empty() {
+            writeln!(f, "Disposition wallet reassignments: {}", self.disposition_reassignments.len())?;
+            for (id, from, to) in &self.disposition_reassignments {
+                writeln!(f, "  disposition {} '{}' -> '{}'", id, from, to)?;
+            }
+        }
+        for (label, matches) in [("Matches removed", &self.matches_removed), ("Matches created", &self.matches_created)] {
+            if matches.is_empty() {
+                continue;
+            }
+            writeln!(f, "{}: {}", label, matches.len())?;
+            for m in matches {
+                writeln!(f, "  {} lot {} -> disposition {} {} BTC basis ${} rgl ${} {}",
+                    m.match_type, m.acquisition_id, m.disposition_id,
+                    btc(m.satoshis), usd(m.basis), usd(m.rgl), m.term)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Snapshot {
+    acquisitions: Vec<Acquisition>,
+    dispositions: Vec<Disposition>,
+    matches: Vec<AcquisitionDisposition>,
+}
+
+impl Snapshot {
+    fn take(conn: &mut SqliteConnection) -> Result<Snapshot, String> {
+        Ok(Snapshot {
+            acquisitions: acquisitions::table
+                .select(Acquisition::as_select())
+                .load(conn)
+                .map_err(|e| format!("Error fetching acquisitions: {}", e))?,
+            dispositions: dispositions::table
+                .select(Disposition::as_select())
+                .load(conn)
+                .map_err(|e| format!("Error fetching dispositions: {}", e))?,
+            matches: acquisition_dispositions::table
+                .select(AcquisitionDisposition::as_select())
+                .load(conn)
+                .map_err(|e| format!("Error fetching acquisition_dispositions: {}", e))?,
+        })
+    }
+
+    fn disposition_dates(&self) -> HashMap<i32, NaiveDateTime> {
+        self.dispositions.iter().map(|d| (d.id, d.disposition_date)).collect()
+    }
+
+    fn diff(self, after: Snapshot) -> ChangePreview {
+        let mut preview = ChangePreview::default();
+
+        let mut before_lots: HashMap<i32, Acquisition> =
+            self.acquisitions.into_iter().map(|a| (a.id, a)).collect();
+        for lot in after.acquisitions {
+            match before_lots.remove(&lot.id) {
+                None => preview.lots_created.push(lot),
+                Some(before) => {
+                    if before.wallet != lot.wallet {
+                        preview.wallet_reassignments.push((lot.id, before.wallet.clone(), lot.wallet.clone()));
+                    }
+                    if lot.satoshis < before.satoshis {
+                        preview.lots_split.push((before, lot));
+                    }
+                }
+            }
+        }
+
+        let mut before_disps: HashMap<i32, Disposition> =
+            self.dispositions.into_iter().map(|d| (d.id, d)).collect();
+        for disp in after.dispositions {
+            match before_disps.remove(&disp.id) {
+                None => preview.dispositions_created.push(disp),
+                Some(before) => {
+                    if before.wallet != disp.wallet {
+                        preview.disposition_reassignments.push((disp.id, before.wallet, disp.wallet));
+                    }
+                }
+            }
+        }
+
+        // A replayed match that comes out identical is not a change; one that differs
+        // is reported as removed and recreated
+        let mut before_matches: HashMap<(i32, i32, String), AcquisitionDisposition> =
+            self.matches.into_iter().map(|m| (match_key(&m), m)).collect();
+        for m in after.matches {
+            match before_matches.remove(&match_key(&m)) {
+                Some(before) if before == m => {}
+                Some(before) => {
+                    preview.matches_removed.push(before);
+                    preview.matches_created.push(m);
+                }
+                None => preview.matches_created.push(m),
+            }
+        }
+        preview.matches_removed.extend(before_matches.into_values());
+        preview.matches_removed.sort_by_key(match_key);
+
+        preview
+    }
+}
+
+/// A match's primary key: (acquisition id, disposition id, match type).
+fn match_key(m: &AcquisitionDisposition) -> (i32, i32, String) {
+    (m.acquisition_id, m.disposition_id, m.match_type.clone())
+}
+
+/// Fails if any match created or removed belongs to a disposition in a closed period.
+/// A removed match is dated by its disposition before the change, a created one after.
+fn ensure_matches_open(
+    conn: &mut SqliteConnection,
+    preview: &ChangePreview,
+    dates_before: &HashMap<i32, NaiveDateTime>,
+    dates_after: &HashMap<i32, NaiveDateTime>,
+) -> Result<(), String> {
+    let Some(lock) = locked_through(conn)? else {
+        return Ok(());
+    };
+    let changed = preview.matches_removed.iter().map(|m| (m, dates_before))
+        .chain(preview.matches_created.iter().map(|m| (m, dates_after)))
+        .filter_map(|(m, dates)| dates.get(&m.disposition_id).map(|date| (m, *date)))
+        .find(|(_, date)| *date <= lock);
+    match changed {
+        Some((m, date)) => ensure_open(conn, date, &format!("A {} match for disposition {}", m.match_type, m.disposition_id)),
+        None => Ok(()),
+    }
+}
+
+/// Runs `f` inside a transaction and reports what it changed. When `dry_run` is set,
+/// the transaction is rolled back after the changes are captured, so nothing is saved.
+/// Changes to matches in a closed period roll the transaction back with an error.
+/// Saved changes are recorded in the audit log as `audit`.
+pub fn with_preview<T, F>(conn: &mut SqliteConnection, dry_run: bool, audit: &AuditCommand, f: F) -> Result<(T, ChangePreview), String>
+where
+    F: FnOnce(&mut SqliteConnection) -> Result<T, String>,
+{
+    let mut outcome: Option<(T, ChangePreview)> = None;
+
+    let result = conn.transaction::<(), PreviewError, _>(|conn| {
+        let before = Snapshot::take(conn)?;
+        let value = f(conn)?;
+        let after = Snapshot::take(conn)?;
+        let dates_before = before.disposition_dates();
+        let dates_after = after.disposition_dates();
+        let preview = before.diff(after);
+        ensure_matches_open(conn, &preview, &dates_before, &dates_after)?;
+
+        if dry_run {
+            outcome = Some((value, preview));
+            return Err(PreviewError::DryRun);
+        }
+        record(conn, audit, preview.change_count() as i64)?;
+        outcome = Some((value, preview));
+        Ok(())
+    });
+
+    match result {
+        Ok(()) | Err(PreviewError::DryRun) => Ok(outcome.expect("preview captured before commit")),
+        Err(e) => Err(e.to_string()),
+    }
+}
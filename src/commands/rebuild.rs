@@ -0,0 +1,138 @@
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use diesel::Connection;
+
+use crate::LotConfig;
+use crate::commands::import::match_all;
+use crate::commands::audit::AuditCommand;
+use crate::commands::preview::with_preview;
+use crate::commands::rematch::refresh_carrying_values;
+use crate::commands::transfer::TransferHistory;
+use crate::models::{Acquisition, AcquisitionDisposition};
+use crate::schema::{acquisitions, dispositions, acquisition_dispositions, passed_over_lots};
+
+#[derive(Debug)]
+enum RebuildError {
+    Diesel(diesel::result::Error),
+    Custom(String),
+}
+
+impl From<diesel::result::Error> for RebuildError {
+    fn from(e: diesel::result::Error) -> Self {
+        RebuildError::Diesel(e)
+    }
+}
+
+impl From<String> for RebuildError {
+    fn from(s: String) -> Self {
+        RebuildError::Custom(s)
+    }
+}
+
+impl std::fmt::Display for RebuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RebuildError::Diesel(e) => write!(f, "{}", e),
+            RebuildError::Custom(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+/// Match counts and realized gain/loss totals (in cents) before and after a rebuild.
+#[derive(Debug, Default)]
+pub struct RebuildSummary {
+    pub matches_removed: usize,
+    pub matches_created: usize,
+    pub tax_rgl_before: i64,
+    pub tax_rgl_after: i64,
+    pub gaap_rgl_before: i64,
+    pub gaap_rgl_after: i64,
+}
+
+/// Discards every match, resets the undisposed trackers and GAAP carrying values to
+/// their original amounts, and replays tax and GAAP matching under `config`. Recorded
+/// marks to market are reapplied in date order so GAAP bases are reconstructed, and
+/// recorded transfers are honoured so each disposition draws on the lots in its wallet
+/// on its date.
+pub fn rebuild(conn: &mut SqliteConnection, config: &LotConfig) -> Result<RebuildSummary, String> {
+    let audit = AuditCommand::new("rebuild", serde_json::json!({
+        "tax_lot_method": config.tax_lot_method,
+        "tax_lot_scope": config.tax_lot_scope,
+        "gaap_lot_method": config.gaap_lot_method,
+    }));
+
+    with_preview(conn, false, &audit, |conn| conn.transaction::<RebuildSummary, RebuildError, _>(|conn| {
+        let mut summary = RebuildSummary::default();
+
+        let before = load_matches(conn)?;
+        summary.matches_removed = before.len();
+        summary.tax_rgl_before = total_rgl(&before, "tax");
+        summary.gaap_rgl_before = total_rgl(&before, "gaap");
+
+        diesel::delete(acquisition_dispositions::table)
+            .execute(conn)
+            .map_err(|e| format!("Error deleting acquisition_dispositions: {}", e))?;
+        diesel::delete(passed_over_lots::table)
+            .execute(conn)
+            .map_err(|e| format!("Error deleting passed over lots: {}", e))?;
+
+        diesel::update(acquisitions::table)
+            .set((
+                acquisitions::undisposed_satoshis.eq(acquisitions::satoshis),
+                acquisitions::tax_undisposed_satoshis.eq(acquisitions::satoshis),
+                acquisitions::usd_cents_btc_fair_value.eq(acquisitions::usd_cents_btc_basis),
+            ))
+            .execute(conn)
+            .map_err(|e| format!("Error resetting acquisitions: {}", e))?;
+
+        // Transfer splits divide `satoshis` after disposals, so tax trackers restart
+        // from the sats each lot held before its first disposition
+        let lots: Vec<Acquisition> = acquisitions::table
+            .select(Acquisition::as_select())
+            .load(conn)
+            .map_err(|e| format!("Error fetching acquisitions: {}", e))?;
+        let opening = TransferHistory::load(conn)?.opening_tax_satoshis(&lots);
+        for lot in &lots {
+            if opening[&lot.id] != lot.satoshis {
+                diesel::update(acquisitions::table.find(lot.id))
+                    .set(acquisitions::tax_undisposed_satoshis.eq(opening[&lot.id]))
+                    .execute(conn)
+                    .map_err(|e| format!("Error resetting transferred lot: {}", e))?;
+            }
+        }
+
+        diesel::update(dispositions::table)
+            .set((
+                dispositions::undisposed_satoshis.eq(dispositions::satoshis),
+                dispositions::tax_undisposed_satoshis.eq(dispositions::satoshis),
+            ))
+            .execute(conn)
+            .map_err(|e| format!("Error resetting dispositions: {}", e))?;
+
+        match_all(conn, config)?;
+
+        // The average pool applies marks as it replays; other methods price matches
+        // from the mark history and only need the remaining lots brought up to date
+        if config.gaap_lot_method != "average" {
+            refresh_carrying_values(conn)?;
+        }
+
+        let after = load_matches(conn)?;
+        summary.matches_created = after.len();
+        summary.tax_rgl_after = total_rgl(&after, "tax");
+        summary.gaap_rgl_after = total_rgl(&after, "gaap");
+
+        Ok(summary)
+    }).map_err(|e| e.to_string())).map(|(summary, _)| summary)
+}
+
+fn load_matches(conn: &mut SqliteConnection) -> Result<Vec<AcquisitionDisposition>, String> {
+    acquisition_dispositions::table
+        .select(AcquisitionDisposition::as_select())
+        .load(conn)
+        .map_err(|e| format!("Error fetching acquisition_dispositions: {}", e))
+}
+
+fn total_rgl(matches: &[AcquisitionDisposition], match_type: &str) -> i64 {
+    matches.iter().filter(|m| m.match_type == match_type).map(|m| m.rgl).sum()
+}
@@ -0,0 +1,181 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+
+use crate::LotConfig;
+use crate::commands::import::{match_all, match_gaap, carrying_value_as_of};
+use crate::models::{Acquisition, AcquisitionDisposition, FairValue};
+use crate::schema::{acquisitions, dispositions, acquisition_dispositions, fair_values, passed_over_lots};
+
+/// Unwinds every `match_type` match whose disposition is dated on or after `from`
+/// (or every match when `from` is `None`), restoring the acquisition and disposition
+/// undisposed trackers. Returns the number of matches removed.
+pub fn unwind_matches(
+    conn: &mut SqliteConnection,
+    match_type: &str,
+    from: Option<NaiveDateTime>,
+) -> Result<usize, String> {
+    let mut query = acquisition_dispositions::table
+        .inner_join(dispositions::table)
+        .filter(acquisition_dispositions::match_type.eq(match_type))
+        .into_boxed();
+    if let Some(from) = from {
+        query = query.filter(dispositions::disposition_date.ge(from));
+    }
+    let matches: Vec<AcquisitionDisposition> = query
+        .select(AcquisitionDisposition::as_select())
+        .load(conn)
+        .map_err(|e| format!("Error fetching {} matches to unwind: {}", match_type, e))?;
+
+    for acq_disp in &matches {
+        if match_type == "gaap" {
+            diesel::update(acquisitions::table.find(acq_disp.acquisition_id))
+                .set(acquisitions::undisposed_satoshis.eq(acquisitions::undisposed_satoshis + acq_disp.satoshis))
+                .execute(conn)
+                .map_err(|e| format!("Error restoring acquisition undisposed sats: {}", e))?;
+
+            diesel::update(dispositions::table.find(acq_disp.disposition_id))
+                .set(dispositions::undisposed_satoshis.eq(dispositions::undisposed_satoshis - acq_disp.satoshis))
+                .execute(conn)
+                .map_err(|e| format!("Error restoring disposition undisposed sats: {}", e))?;
+        } else {
+            diesel::update(acquisitions::table.find(acq_disp.acquisition_id))
+                .set(acquisitions::tax_undisposed_satoshis.eq(acquisitions::tax_undisposed_satoshis + acq_disp.satoshis))
+                .execute(conn)
+                .map_err(|e| format!("Error restoring acquisition tax undisposed sats: {}", e))?;
+
+            diesel::update(dispositions::table.find(acq_disp.disposition_id))
+                .set(dispositions::tax_undisposed_satoshis.eq(dispositions::tax_undisposed_satoshis - acq_disp.satoshis))
+                .execute(conn)
+                .map_err(|e| format!("Error restoring disposition tax undisposed sats: {}", e))?;
+        }
+
+        diesel::delete(
+            acquisition_dispositions::table.find((acq_disp.acquisition_id, acq_disp.disposition_id, &acq_disp.match_type))
+        )
+            .execute(conn)
+            .map_err(|e| format!("Error deleting acquisition_disposition: {}", e))?;
+    }
+
+    // The tax candidates a replay passes over are recorded again with its matches
+    if match_type == "tax" {
+        let disposition_ids: Vec<i32> = matches.iter().map(|m| m.disposition_id).collect();
+        diesel::delete(passed_over_lots::table.filter(passed_over_lots::disposition_id.eq_any(disposition_ids)))
+            .execute(conn)
+            .map_err(|e| format!("Error deleting passed over lots: {}", e))?;
+    }
+
+    Ok(matches.len())
+}
+
+/// Unwinds tax and GAAP matches for dispositions dated on or after `from` and replays
+/// matching in date order. Under the `average` GAAP method the pooled carrying value
+/// cannot be rolled back to a point in time, so any affected GAAP match or mark causes
+/// the whole GAAP book to be replayed from cost. Returns the number of matches unwound.
+/// Callers are expected to run this inside a transaction.
+pub fn rematch_from(conn: &mut SqliteConnection, config: &LotConfig, from: NaiveDateTime) -> Result<usize, String> {
+    let unwound = unwind_from(conn, config, from)?;
+    replay_matches(conn, config, unwound)?;
+    Ok(unwound)
+}
+
+/// The unwinding half of `rematch_from`, for callers that change acquisitions or
+/// dispositions before matching is replayed with `replay_matches`.
+pub fn unwind_from(conn: &mut SqliteConnection, config: &LotConfig, from: NaiveDateTime) -> Result<usize, String> {
+    let unwound = unwind_matches(conn, "tax", Some(from))?;
+    Ok(unwound + unwind_gaap_from(conn, config, from)?)
+}
+
+/// Unwinds and replays only the GAAP matches affected by a change dated `from`, leaving
+/// tax matches as they are. For changes to carrying value, which have no bearing on tax
+/// basis. Returns the number of GAAP matches unwound.
+pub fn rematch_gaap_from(conn: &mut SqliteConnection, config: &LotConfig, from: NaiveDateTime) -> Result<usize, String> {
+    let unwound = unwind_gaap_from(conn, config, from)?;
+    match_gaap(conn, config)?;
+
+    if unwound > 0 && config.gaap_lot_method != "average" {
+        refresh_carrying_values(conn)?;
+    }
+
+    Ok(unwound)
+}
+
+/// The GAAP half of `unwind_from`.
+fn unwind_gaap_from(conn: &mut SqliteConnection, config: &LotConfig, from: NaiveDateTime) -> Result<usize, String> {
+    if config.gaap_lot_method != "average" {
+        return unwind_matches(conn, "gaap", Some(from));
+    }
+
+    let affected: i64 = acquisition_dispositions::table
+        .inner_join(dispositions::table)
+        .filter(acquisition_dispositions::match_type.eq("gaap"))
+        .filter(dispositions::disposition_date.ge(from))
+        .count()
+        .get_result(conn)
+        .map_err(|e| format!("Error counting affected GAAP matches: {}", e))?;
+    // A mark on or after `from` has already restated the pool a replayed match draws on
+    let later_marks: i64 = fair_values::table
+        .filter(fair_values::date.ge(from))
+        .count()
+        .get_result(conn)
+        .map_err(|e| format!("Error counting later fair values: {}", e))?;
+    if affected == 0 && later_marks == 0 {
+        return Ok(0);
+    }
+
+    let unwound = unwind_matches(conn, "gaap", None)?;
+    reset_fair_values_to_cost(conn)?;
+    Ok(unwound)
+}
+
+/// Matches every disposition left unmatched by `unwind_from`, then brings carrying
+/// values up to date if `unwound` matches were removed.
+pub fn replay_matches(conn: &mut SqliteConnection, config: &LotConfig, unwound: usize) -> Result<(), String> {
+    match_all(conn, config)?;
+
+    if unwound > 0 && config.gaap_lot_method != "average" {
+        refresh_carrying_values(conn)?;
+    }
+
+    Ok(())
+}
+
+/// Resets every lot's GAAP carrying value to its cost ahead of a full GAAP replay.
+pub fn reset_fair_values_to_cost(conn: &mut SqliteConnection) -> Result<(), String> {
+    diesel::update(acquisitions::table)
+        .set(acquisitions::usd_cents_btc_fair_value.eq(acquisitions::usd_cents_btc_basis))
+        .execute(conn)
+        .map_err(|e| format!("Error resetting fair values to cost: {}", e))?;
+    Ok(())
+}
+
+/// Brings each undisposed lot's stored fair value in line with the latest mark it was
+/// held for. Rematching can leave lots undisposed that were fully relieved when an
+/// earlier mark ran, and those lots missed the mark.
+pub fn refresh_carrying_values(conn: &mut SqliteConnection) -> Result<(), String> {
+    let marks: Vec<FairValue> = fair_values::table
+        .select(FairValue::as_select())
+        .load(conn)
+        .map_err(|e| format!("Error fetching fair values: {}", e))?;
+    if marks.is_empty() {
+        return Ok(());
+    }
+
+    let lots: Vec<Acquisition> = acquisitions::table
+        .filter(acquisitions::undisposed_satoshis.gt(0))
+        .select(Acquisition::as_select())
+        .load(conn)
+        .map_err(|e| format!("Error fetching undisposed lots: {}", e))?;
+
+    for lot in lots {
+        let carrying_value = carrying_value_as_of(&marks, &lot, NaiveDateTime::MAX);
+        if carrying_value != lot.usd_cents_btc_fair_value {
+            diesel::update(acquisitions::table.find(lot.id))
+                .set(acquisitions::usd_cents_btc_fair_value.eq(carrying_value))
+                .execute(conn)
+                .map_err(|e| format!("Error refreshing lot carrying value: {}", e))?;
+        }
+    }
+
+    Ok(())
+}
@@ -9,14 +9,15 @@ use diesel::sqlite::SqliteConnection;
 use rust_decimal::{Decimal, prelude::FromPrimitive, RoundingStrategy};
 use rust_decimal_macros::dec;
 use crate::rounding_div;
-use crate::models::{TaxRGL, GaapRGL};
+use crate::commands::import::{lot_term, tax_lot_category};
+use crate::models::{TaxRGL, GaapRGL, LotSelection, PassedOverLot};
 use crate::models::ReportDates;
 use crate::models::{AcquisitionDisposition, Disposition, Acquisition};
-use crate::schema::{acquisition_dispositions, dispositions, acquisitions};
+use crate::schema::{acquisition_dispositions, dispositions, acquisitions, passed_over_lots};
 
 pub fn report(beg: &String, end: &String, view: &str, conn: &mut SqliteConnection) -> Result<(), Error> {
-    if !["tax", "gaap", "both"].contains(&view) {
-        return Err(anyhow::anyhow!("Invalid view '{}'. Must be 'tax', 'gaap', or 'both'.", view));
+    if !["tax", "gaap", "both", "selection"].contains(&view) {
+        return Err(anyhow::anyhow!("Invalid view '{}'. Must be 'tax', 'gaap', 'both', or 'selection'.", view));
     }
 
     let dates: ReportDates = serde_json::from_str(&format!(r#"{{ "beginning_date": "{}", "ending_date": "{}" }}"#, beg, end)).expect("Failed to deserialize provided dates");
@@ -37,10 +38,16 @@ pub fn report(beg: &String, end: &String, view: &str, conn: &mut SqliteConnectio
         report_gaap_term(&mut wtr, beg_date_hms, end_date_hms, "long".to_string(), conn);
     }
 
+    if view == "selection" {
+        let file_path: PathBuf = PathBuf::from(format!("./reports/lot_selection_{}_{}.csv", dates.beginning_date.date(), dates.ending_date.date()));
+        let mut wtr = csv::Writer::from_path(file_path).unwrap();
+        report_lot_selection(&mut wtr, beg_date_hms, end_date_hms, conn)?;
+    }
+
     Ok(())
 }
 
-fn query_acq_disps(
+pub(crate) fn query_acq_disps(
     beg: NaiveDateTime,
     end: NaiveDateTime,
     term: &str,
@@ -63,6 +70,7 @@ pub fn report_tax_term(wtr: &mut Writer<File>, beg: NaiveDateTime, end: NaiveDat
 
     let mut total_disposed_btc = dec!(0);
     let mut total_disposal_fmv = dec!(0);
+    let mut total_fee = dec!(0);
     let mut total_basis = dec!(0);
     let mut total_rgl = dec!(0);
 
@@ -83,10 +91,12 @@ pub fn report_tax_term(wtr: &mut Writer<File>, beg: NaiveDateTime, end: NaiveDat
             basis,
             rgl: (Decimal::from_i64(acq_disp.2.rgl).unwrap() / dec!(100)).round_dp_with_strategy(2, RoundingStrategy::MidpointAwayFromZero),
             term: term.clone(),
+            fee: (Decimal::from_i64(acq_disp.2.fee).unwrap() / dec!(100)).round_dp_with_strategy(2, RoundingStrategy::MidpointAwayFromZero),
         };
 
         total_disposed_btc += rgl.disposed_btc;
         total_disposal_fmv += rgl.disposal_fmv;
+        total_fee += rgl.fee;
         total_basis += rgl.basis;
         total_rgl += rgl.rgl;
 
@@ -106,6 +116,7 @@ pub fn report_tax_term(wtr: &mut Writer<File>, beg: NaiveDateTime, end: NaiveDat
                 total_basis.to_string(),
                 total_rgl.to_string(),
                 term.clone(),
+                total_fee.to_string(),
             ]).unwrap();
 
             wtr.write_record(&[
@@ -118,6 +129,7 @@ pub fn report_tax_term(wtr: &mut Writer<File>, beg: NaiveDateTime, end: NaiveDat
                 String::from(""),
                 String::from(""),
                 String::from(""),
+                String::from(""),
             ]).unwrap();
         }
     }
@@ -128,6 +140,7 @@ pub fn report_gaap_term(wtr: &mut Writer<File>, beg: NaiveDateTime, end: NaiveDa
 
     let mut total_disposed_btc = dec!(0);
     let mut total_disposal_fmv = dec!(0);
+    let mut total_fee = dec!(0);
     let mut total_cost_basis = dec!(0);
     let mut total_basis = dec!(0);
     let mut total_fmv_disposed = dec!(0);
@@ -147,7 +160,9 @@ pub fn report_gaap_term(wtr: &mut Writer<File>, beg: NaiveDateTime, end: NaiveDa
 
         let cost_per_btc = (Decimal::from_i64(acq_disp.1.usd_cents_btc_basis).unwrap() / dec!(100)).round_dp_with_strategy(2, RoundingStrategy::MidpointAwayFromZero);
         let disposal_fmv_per_btc = (Decimal::from_i64(acq_disp.0.usd_cents_btc_basis).unwrap() / dec!(100)).round_dp_with_strategy(2, RoundingStrategy::MidpointAwayFromZero);
-        let gaap_per_btc = (Decimal::from_i64(acq_disp.1.usd_cents_btc_fair_value).unwrap() / dec!(100)).round_dp_with_strategy(2, RoundingStrategy::MidpointAwayFromZero);
+        // Carrying value per BTC at the time of relief, so pooled (average cost) and
+        // later-remarked lots report the value actually relieved
+        let gaap_per_btc = (Decimal::from_i64(acq_disp.2.basis).unwrap() / dec!(100) / sats_dec).round_dp_with_strategy(2, RoundingStrategy::MidpointAwayFromZero);
 
         let rgl = GaapRGL {
             acquisition_date: acq_disp.1.acquisition_date,
@@ -162,10 +177,12 @@ pub fn report_gaap_term(wtr: &mut Writer<File>, beg: NaiveDateTime, end: NaiveDa
             fmv_disposed,
             rgl: (Decimal::from_i64(acq_disp.2.rgl).unwrap() / dec!(100)).round_dp_with_strategy(2, RoundingStrategy::MidpointAwayFromZero),
             term: term.clone(),
+            fee: (Decimal::from_i64(acq_disp.2.fee).unwrap() / dec!(100)).round_dp_with_strategy(2, RoundingStrategy::MidpointAwayFromZero),
         };
 
         total_disposed_btc += rgl.disposed_btc;
         total_disposal_fmv += rgl.disposal_fmv;
+        total_fee += rgl.fee;
         total_cost_basis += rgl.cost_basis;
         total_basis += rgl.basis;
         total_fmv_disposed += rgl.fmv_disposed;
@@ -190,6 +207,7 @@ pub fn report_gaap_term(wtr: &mut Writer<File>, beg: NaiveDateTime, end: NaiveDa
                 total_fmv_disposed.to_string(),
                 total_rgl.to_string(),
                 term.clone(),
+                total_fee.to_string(),
             ]).unwrap();
 
             wtr.write_record(&[
@@ -205,7 +223,85 @@ pub fn report_gaap_term(wtr: &mut Writer<File>, beg: NaiveDateTime, end: NaiveDa
                 String::from(""),
                 String::from(""),
                 String::from(""),
+                String::from(""),
             ]).unwrap();
         }
     }
 }
+
+/// Lists each tax disposition's candidate lots in the order they were ranked when it was
+/// matched: the lots relieved, then the lots passed over with the BTC they had available.
+/// Each row's method is the rule saved with the match, so the report does not depend on
+/// the current lot configuration.
+pub fn report_lot_selection(wtr: &mut Writer<File>, beg: NaiveDateTime, end: NaiveDateTime, conn: &mut SqliteConnection) -> Result<(), Error> {
+    let mut acq_disps = query_acq_disps(beg, end, "long", "tax", conn);
+    acq_disps.extend(query_acq_disps(beg, end, "short", "tax", conn));
+    acq_disps.sort_by_key(|acq_disp| {
+        (acq_disp.0.disposition_date, acq_disp.0.id, acq_disp.2.selection_rank.unwrap_or(i32::MAX), acq_disp.1.acquisition_date, acq_disp.1.id)
+    });
+
+    let disposition_ids: Vec<i32> = acq_disps.iter().map(|acq_disp| acq_disp.0.id).collect();
+    let passed_over: Vec<(PassedOverLot, Acquisition)> = passed_over_lots::table
+        .inner_join(acquisitions::table)
+        .filter(passed_over_lots::disposition_id.eq_any(disposition_ids))
+        .order((passed_over_lots::disposition_id.asc(), passed_over_lots::selection_rank.asc()))
+        .select((PassedOverLot::as_select(), Acquisition::as_select()))
+        .load(conn)?;
+
+    let price = |cents: i64| (Decimal::from_i64(cents).unwrap() / dec!(100)).round_dp_with_strategy(2, RoundingStrategy::MidpointAwayFromZero);
+    for matches in acq_disps.chunk_by(|a, b| a.0.id == b.0.id) {
+        let disposition = &matches[0].0;
+        // Matches made before the rule was saved have no method or rank
+        let method = matches[0].2.lot_method.clone().unwrap_or_default();
+        let reason = |term: &str, lot: &Acquisition| -> String {
+            match method.as_str() {
+                "specific-id" => "named on the disposition",
+                "min-tax" => tax_lot_category(term, lot.usd_cents_btc_basis, disposition.usd_cents_btc_basis).1,
+                "lifo" => "last in, first out",
+                "hifo" => "highest cost first",
+                "fifo" => "first in, first out",
+                _ => "not recorded",
+            }.to_string()
+        };
+
+        for (disposition, lot, acq_disp) in matches {
+            wtr.serialize(LotSelection {
+                disposition_date: disposition.disposition_date,
+                wallet: disposition.wallet.clone(),
+                rank: acq_disp.selection_rank,
+                acquisition_id: lot.id,
+                acquisition_date: lot.acquisition_date,
+                disposed_btc: Decimal::from_i64(acq_disp.satoshis).unwrap() / dec!(100_000_000),
+                cost_per_btc: price(lot.usd_cents_btc_basis),
+                disposal_fmv_per_btc: price(disposition.usd_cents_btc_basis),
+                rgl: price(acq_disp.rgl),
+                term: acq_disp.term.clone(),
+                reason: reason(&acq_disp.term, lot),
+                method: method.clone(),
+                available_btc: None,
+            })?;
+        }
+
+        for (candidate, lot) in passed_over.iter().filter(|(candidate, _)| candidate.disposition_id == disposition.id) {
+            let term = lot_term(lot.acquisition_date, disposition.disposition_date);
+            wtr.serialize(LotSelection {
+                disposition_date: disposition.disposition_date,
+                wallet: disposition.wallet.clone(),
+                rank: Some(candidate.selection_rank),
+                acquisition_id: lot.id,
+                acquisition_date: lot.acquisition_date,
+                disposed_btc: dec!(0),
+                cost_per_btc: price(lot.usd_cents_btc_basis),
+                disposal_fmv_per_btc: price(disposition.usd_cents_btc_basis),
+                rgl: dec!(0),
+                term: term.to_string(),
+                reason: format!("passed over; {}", reason(term, lot)),
+                method: method.clone(),
+                available_btc: Some(Decimal::from_i64(candidate.satoshis).unwrap() / dec!(100_000_000)),
+            })?;
+        }
+    }
+
+    wtr.flush()?;
+    Ok(())
+}
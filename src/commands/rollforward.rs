@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use chrono::{Duration, NaiveDateTime};
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use rust_decimal::{Decimal, prelude::FromPrimitive};
+use rust_decimal_macros::dec;
+
+use crate::LotConfig;
+use crate::commands::holdings::{carrying_values_on, holdings_on, pool_restatements, usd_value};
+use crate::models::{Acquisition, FairValue, ReportDates, RollforwardLine};
+use crate::schema::{acquisitions, acquisition_dispositions, dispositions, fair_values};
+
+/// The reconciliation written by `rollforward`, in USD unless named `_btc`.
+#[derive(Debug, Default)]
+pub struct Rollforward {
+    pub opening_btc: Decimal,
+    pub opening: Decimal,
+    pub purchases_btc: Decimal,
+    pub purchases: Decimal,
+    pub disposals_btc: Decimal,
+    /// GAAP carrying value of the BTC disposed
+    pub disposals: Decimal,
+    /// Proceeds less fees less carrying value of the BTC disposed
+    pub realized_gain: Decimal,
+    /// Fair value adjustments from the marks to market in the period
+    pub remeasurement: Decimal,
+    /// Under the `average` method, the change in the pool's value from rounding its
+    /// average carrying value to a whole cent per BTC at each disposal in the period
+    pub restatement: Decimal,
+    /// Closing less the sum of the lines above, from rounding each lot and match to the cent
+    pub rounding: Decimal,
+    pub closing_btc: Decimal,
+    pub closing: Decimal,
+}
+
+fn btc(sats: i64) -> Decimal {
+    Decimal::from_i64(sats).unwrap() / dec!(100_000_000)
+}
+
+/// GAAP sats each lot held at the end of `date`: what is undisposed now plus what
+/// dispositions dated after `date` relieved.
+fn held_on(lots: &[Acquisition], gaap_matches: &[(i32, i64, NaiveDateTime)], date: NaiveDateTime) -> HashMap<i32, i64> {
+    let mut held: HashMap<i32, i64> = lots.iter()
+        .filter(|l| l.acquisition_date <= date)
+        .map(|l| (l.id, l.undisposed_satoshis))
+        .collect();
+    for (lot_id, sats, disposition_date) in gaap_matches {
+        if *disposition_date > date {
+            if let Some(undisposed) = held.get_mut(lot_id) {
+                *undisposed += sats;
+            }
+        }
+    }
+    held
+}
+
+/// One lot's remeasurement by a mark to market.
+pub(crate) struct MarkAdjustment {
+    pub date: NaiveDateTime,
+    pub fair_value_cents: i64,
+    pub lot_id: i32,
+    /// GAAP sats the lot held on the mark date
+    pub satoshis: i64,
+    /// The lot's carrying value per BTC before the mark
+    pub prior_cents: i64,
+}
+
+/// The lots remeasured by each mark to market dated from `beg` through `end`. Marks on
+/// the same date net to the last of them.
+pub(crate) fn mark_adjustments(
+    conn: &mut SqliteConnection,
+    config: &LotConfig,
+    beg: NaiveDateTime,
+    end: NaiveDateTime,
+) -> Result<Vec<MarkAdjustment>, anyhow::Error> {
+    let marks: Vec<FairValue> = fair_values::table
+        .filter(fair_values::date.ge(beg))
+        .filter(fair_values::date.le(end))
+        .order((fair_values::date.asc(), fair_values::id.asc()))
+        .select(FairValue::as_select())
+        .load(conn)?;
+    let lots: Vec<Acquisition> = acquisitions::table
+        .filter(acquisitions::acquisition_date.le(end))
+        .select(Acquisition::as_select())
+        .load(conn)?;
+    let gaap_matches: Vec<(i32, i64, NaiveDateTime)> = acquisition_dispositions::table
+        .inner_join(dispositions::table)
+        .filter(acquisition_dispositions::match_type.eq("gaap"))
+        .select((acquisition_dispositions::acquisition_id, acquisition_dispositions::satoshis, dispositions::disposition_date))
+        .load(conn)?;
+
+    let mut adjustments = Vec::new();
+    for (i, mark) in marks.iter().enumerate() {
+        if marks.get(i + 1).is_some_and(|next| next.date == mark.date) {
+            continue;
+        }
+        let held = held_on(&lots, &gaap_matches, mark.date);
+        let prior = carrying_values_on(conn, config, &lots, mark.date - Duration::seconds(1))?;
+        for lot in lots.iter().filter(|l| held.get(&l.id).is_some_and(|sats| *sats > 0)) {
+            adjustments.push(MarkAdjustment {
+                date: mark.date,
+                fair_value_cents: mark.fair_value_cents,
+                lot_id: lot.id,
+                satoshis: held[&lot.id],
+                prior_cents: prior[&lot.id],
+            });
+        }
+    }
+    Ok(adjustments)
+}
+
+/// Writes the ASU 2023-08 roll-forward of GAAP holdings from the start of `beg` to the
+/// end of `end`: opening fair value, purchases at cost, disposals at carrying value,
+/// realized gains, remeasurement from the marks to market in the period, and closing
+/// fair value. Opening and closing are the holdings report's totals for the day before
+/// `beg` and for `end`. Fails if the lines do not tie to them beyond rounding.
+pub fn rollforward(beg: &String, end: &String, conn: &mut SqliteConnection, config: &LotConfig) -> Result<Rollforward, anyhow::Error> {
+    let dates: ReportDates = serde_json::from_str(&format!(r#"{{ "beginning_date": "{}", "ending_date": "{}" }}"#, beg, end)).expect("Failed to deserialize provided dates");
+    let beg_date_hms = dates.beginning_date.date().and_hms_opt(0, 0, 0).unwrap();
+    let end_date_hms = dates.ending_date.date().and_hms_opt(23, 59, 59).unwrap();
+    if end_date_hms < beg_date_hms {
+        return Err(anyhow::anyhow!(
+            "The ending date {} is before the beginning date {}",
+            dates.ending_date.date(), dates.beginning_date.date()
+        ));
+    }
+    let opening_date = beg_date_hms - Duration::seconds(1);
+    let rollforward = reconcile(conn, config, beg_date_hms, end_date_hms)?;
+
+    let file_path: PathBuf = PathBuf::from(format!("./reports/rollforward_{}_{}.csv", dates.beginning_date.date(), dates.ending_date.date()));
+    let mut wtr = csv::Writer::from_path(file_path)?;
+    let mut lines = vec![
+        (format!("Opening balance {}", opening_date.date()), Some(rollforward.opening_btc), rollforward.opening),
+        ("Purchases".to_string(), Some(rollforward.purchases_btc), rollforward.purchases),
+        ("Disposals at carrying value".to_string(), Some(-rollforward.disposals_btc), -rollforward.disposals),
+        ("Remeasurement".to_string(), None, rollforward.remeasurement),
+    ];
+    if config.gaap_lot_method == "average" {
+        lines.push(("Average cost restatement".to_string(), None, rollforward.restatement));
+    }
+    lines.extend([
+        ("Rounding".to_string(), None, rollforward.rounding),
+        (format!("Closing balance {}", dates.ending_date.date()), Some(rollforward.closing_btc), rollforward.closing),
+        ("Realized gain (loss) on disposals".to_string(), None, rollforward.realized_gain),
+    ]);
+    for (line, btc, usd) in lines {
+        wtr.serialize(RollforwardLine { line, btc, usd })?;
+    }
+    wtr.flush()?;
+
+    Ok(rollforward)
+}
+
+/// The roll-forward from `beg` through `end`, checked against the holdings report's
+/// totals for the second before `beg` and for `end`.
+pub(crate) fn reconcile(
+    conn: &mut SqliteConnection,
+    config: &LotConfig,
+    beg_date_hms: NaiveDateTime,
+    end_date_hms: NaiveDateTime,
+) -> Result<Rollforward, anyhow::Error> {
+    let opening_date = beg_date_hms - Duration::seconds(1);
+
+    let opening = holdings_on(conn, config, "gaap", opening_date)?;
+    let closing = holdings_on(conn, config, "gaap", end_date_hms)?;
+
+    let mut rollforward = Rollforward {
+        opening_btc: opening.iter().map(|h| h.undisposed_btc).sum(),
+        opening: opening.iter().map(|h| h.usd_fair_value).sum(),
+        closing_btc: closing.iter().map(|h| h.undisposed_btc).sum(),
+        closing: closing.iter().map(|h| h.usd_fair_value).sum(),
+        ..Rollforward::default()
+    };
+
+    // Split lots keep their purchase date, so a purchase split since counts across its lots
+    let purchased: Vec<Acquisition> = acquisitions::table
+        .filter(acquisitions::acquisition_date.ge(beg_date_hms))
+        .filter(acquisitions::acquisition_date.le(end_date_hms))
+        .select(Acquisition::as_select())
+        .load(conn)?;
+    for lot in &purchased {
+        rollforward.purchases_btc += btc(lot.satoshis);
+        rollforward.purchases += usd_value(lot.satoshis, lot.usd_cents_btc_basis);
+    }
+
+    let disposed: Vec<(i64, i64, i64)> = acquisition_dispositions::table
+        .inner_join(dispositions::table)
+        .filter(acquisition_dispositions::match_type.eq("gaap"))
+        .filter(dispositions::disposition_date.ge(beg_date_hms))
+        .filter(dispositions::disposition_date.le(end_date_hms))
+        .select((acquisition_dispositions::satoshis, acquisition_dispositions::basis, acquisition_dispositions::rgl))
+        .load(conn)?;
+    for (sats, basis, rgl) in &disposed {
+        rollforward.disposals_btc += btc(*sats);
+        rollforward.disposals += Decimal::new(*basis, 2);
+        rollforward.realized_gain += Decimal::new(*rgl, 2);
+    }
+
+    let adjustments = mark_adjustments(conn, config, beg_date_hms, end_date_hms)?;
+    for adjustment in &adjustments {
+        rollforward.remeasurement += usd_value(adjustment.satoshis, adjustment.fair_value_cents)
+            - usd_value(adjustment.satoshis, adjustment.prior_cents);
+    }
+    let lots_marked = adjustments.len();
+    if config.gaap_lot_method == "average" {
+        rollforward.restatement = pool_restatements(conn, beg_date_hms, end_date_hms)?
+            .iter()
+            .map(|r| r.amount)
+            .sum();
+    }
+
+    let expected_btc = rollforward.opening_btc + rollforward.purchases_btc - rollforward.disposals_btc;
+    if expected_btc != rollforward.closing_btc {
+        return Err(anyhow::anyhow!(
+            "The roll-forward does not tie: {} BTC opening plus {} BTC purchased less {} BTC disposed is {} BTC, \
+             but holdings on {} are {} BTC",
+            rollforward.opening_btc, rollforward.purchases_btc, rollforward.disposals_btc, expected_btc,
+            end_date_hms.date(), rollforward.closing_btc
+        ));
+    }
+
+    let expected = rollforward.opening + rollforward.purchases - rollforward.disposals
+        + rollforward.remeasurement + rollforward.restatement;
+    rollforward.rounding = rollforward.closing - expected;
+    // Every lot and match amount is rounded to the cent on its own, so each can be off by
+    // half a cent
+    let rounded_amounts = opening.len() + purchased.len() + disposed.len() + 2 * lots_marked + closing.len();
+    let tolerance = Decimal::from(rounded_amounts) * dec!(0.005);
+    if rollforward.rounding.abs() > tolerance {
+        return Err(anyhow::anyhow!(
+            "The roll-forward does not tie: opening ${} plus purchases ${} less disposals ${} plus remeasurement ${} \
+             plus average cost restatement ${} is ${}, but holdings on {} are ${}",
+            rollforward.opening, rollforward.purchases, rollforward.disposals, rollforward.remeasurement,
+            rollforward.restatement, expected, end_date_hms.date(), rollforward.closing
+        ));
+    }
+
+    Ok(rollforward)
+}
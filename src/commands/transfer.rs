@@ -1,3 +1,5 @@
+use std::cmp::min;
+use std::collections::HashMap;
 use std::path::PathBuf;
 use chrono::NaiveDateTime;
 use diesel::prelude::*;
@@ -6,9 +8,14 @@ use diesel::Connection;
 use rust_decimal::Decimal;
 use serde::Deserialize;
 
+use crate::LotConfig;
 use crate::rounding_div;
-use crate::models::{Acquisition, NewAcquisition, parse_date_str};
-use crate::schema::acquisitions;
+use crate::commands::rematch::{unwind_from, replay_matches};
+use crate::commands::audit::AuditCommand;
+use crate::commands::preview::{ChangePreview, with_preview};
+use crate::models::{Acquisition, Disposition, NewAcquisition, NewDisposition, FairValue, parse_date_str, deserialize_price};
+use crate::models::{AcquisitionFairValue, NewTransfer, Transfer, TransferAcquisition};
+use crate::schema::{acquisitions, acquisition_fair_values, dispositions, fair_values, transfers, transfer_acquisitions};
 
 #[derive(Debug)]
 enum TransferError {
@@ -45,67 +52,176 @@ struct TransferRecord {
     to: String,
     #[serde(rename = "BTC")]
     btc: String,
+    /// On-chain network fee in BTC, paid out of `btc`
+    #[serde(default)]
+    fee: Option<String>,
+    /// USD price of one BTC used to value the fee
+    #[serde(default, deserialize_with = "deserialize_optional_price")]
+    price: Option<i64>,
 }
 
-pub fn transfer(file: &PathBuf, conn: &mut SqliteConnection) -> Result<(), String> {
-    let mut rdr = csv::Reader::from_path(file)
+fn deserialize_optional_price<'de, D>(deserializer: D) -> Result<Option<i64>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let price_str = String::deserialize(deserializer)?;
+    if price_str.trim().is_empty() {
+        return Ok(None);
+    }
+    deserialize_price(serde::de::value::StrDeserializer::<D::Error>::new(&price_str)).map(Some)
+}
+
+fn btc_to_sats(btc: &str) -> Result<i64, String> {
+    let btc = btc.trim();
+    let amount = Decimal::from_str_exact(btc.strip_suffix("BTC").unwrap_or(btc).trim())
+        .map_err(|e| format!("Invalid BTC amount '{}': {}", btc, e))?;
+    (amount * Decimal::from(100_000_000i64)).round()
+        .to_string().parse::<i64>()
+        .map_err(|e| format!("Error converting BTC to sats: {}", e))
+}
+
+struct TransferRow {
+    date: NaiveDateTime,
+    from: String,
+    to: String,
+    sats: i64,
+    fee_sats: i64,
+    price: Option<i64>,
+}
+
+/// Moves lots between wallets and records each movement in `transfers`, linked to the
+/// lots that arrived in the destination. Lots leave in FIFO order as they stood on the
+/// transfer date, and matches for dispositions dated on or after it are replayed under
+/// `config`. A `Fee` on a row is spent from the source wallet's earliest lots as a
+/// disposition, and only the remaining BTC reaches the destination.
+pub fn transfer(file: &PathBuf, conn: &mut SqliteConnection, config: &LotConfig, dry_run: bool) -> Result<ChangePreview, String> {
+    let contents = std::fs::read(file)
         .map_err(|e| format!("Error reading file {:?}: {}", file, e))?;
+    let mut rdr = csv::Reader::from_reader(contents.as_slice());
 
-    let mut records: Vec<(NaiveDateTime, String, String, i64)> = Vec::new();
+    let mut records: Vec<TransferRow> = Vec::new();
     for result in rdr.deserialize::<TransferRecord>() {
         let record = result.map_err(|e| format!("Error parsing transfer CSV: {}", e))?;
         let date = parse_date_str(&record.date)?;
-        let btc = Decimal::from_str_exact(&record.btc)
-            .map_err(|e| format!("Invalid BTC amount '{}': {}", record.btc, e))?;
-        let sats = (btc * Decimal::from(100_000_000i64)).round()
-            .to_string().parse::<i64>()
-            .map_err(|e| format!("Error converting BTC to sats: {}", e))?;
+        let sats = btc_to_sats(&record.btc)?;
         if sats <= 0 {
             return Err(format!("Transfer BTC amount must be positive, got '{}'", record.btc));
         }
-        records.push((date, record.from, record.to, sats));
+        let fee_sats = match record.fee.as_deref().map(str::trim) {
+            Some(fee) if !fee.is_empty() => btc_to_sats(fee)?,
+            _ => 0,
+        };
+        if fee_sats < 0 || fee_sats >= sats {
+            return Err(format!(
+                "Transfer fee on {} must be at least zero and less than the {} BTC sent",
+                date.format("%Y-%m-%d"), record.btc
+            ));
+        }
+        records.push(TransferRow { date, from: record.from, to: record.to, sats, fee_sats, price: record.price });
     }
 
-    records.sort_by_key(|(date, _, _, _)| date.and_utc().timestamp());
+    records.sort_by_key(|t| t.date.and_utc().timestamp());
+
+    let audit = AuditCommand::new("transfer", serde_json::json!({ "file": file }))
+        .with_file(&contents, records.len());
+
+    let (_, preview) = with_preview(conn, dry_run, &audit, |conn| conn.transaction::<(), TransferError, _>(|conn| {
+        for record in &records {
+            let (from_wallet, to_wallet) = (&record.from, &record.to);
 
-    conn.transaction::<(), TransferError, _>(|conn| {
-        for (_, from_wallet, to_wallet, transfer_sats) in &records {
-            let lots: Vec<Acquisition> = acquisitions::table
-                .filter(acquisitions::wallet.eq(from_wallet))
+            // Later dispositions are replayed once the lots have moved
+            let unwound = unwind_from(conn, config, record.date)?;
+
+            // Lots in the source wallet on the transfer date, less sats a later transfer moves on
+            let history = TransferHistory::load(conn)?;
+            let lots: Vec<(Acquisition, i64)> = acquisitions::table
+                .filter(acquisitions::acquisition_date.le(record.date))
                 .filter(acquisitions::tax_undisposed_satoshis.gt(0))
                 .order((acquisitions::acquisition_date.asc(), acquisitions::id.asc()))
                 .select(Acquisition::as_select())
                 .load(conn)
-                .map_err(|e| format!("Error querying lots: {}", e))?;
+                .map_err(|e| format!("Error querying lots: {}", e))?
+                .into_iter()
+                .filter_map(|lot| {
+                    let (wallet, reserved) = history.position_on(&lot, record.date);
+                    let available = lot.tax_undisposed_satoshis - reserved;
+                    (wallet == from_wallet.as_str() && available > 0).then_some((lot, available))
+                })
+                .collect();
+
+            // The fee is spent from the source wallet; only the rest reaches the destination
+            let fee_disposition_id = if record.fee_sats > 0 {
+                Some(record_fee(conn, record)?)
+            } else {
+                None
+            };
+
+            let new_transfer = NewTransfer {
+                transfer_date: record.date,
+                from_wallet: from_wallet.clone(),
+                to_wallet: to_wallet.clone(),
+                satoshis: record.sats,
+                fee_satoshis: record.fee_sats,
+                fee_disposition_id,
+            };
+            diesel::insert_into(transfers::table)
+                .values(&new_transfer)
+                .execute(conn)
+                .map_err(|e| format!("Error saving transfer: {}", e))?;
+            let transfer_id: i32 = transfers::table
+                .order(transfers::id.desc())
+                .select(transfers::id)
+                .first(conn)
+                .map_err(|e| format!("Error fetching new transfer: {}", e))?;
+
+            // Fees for this wallet's transfers that day, including this one, were unwound or
+            // are new; they are matched from the earliest lots, so those stay behind
+            let fees: Vec<i64> = transfers::table
+                .inner_join(dispositions::table)
+                .filter(transfers::transfer_date.eq(record.date))
+                .filter(transfers::from_wallet.eq(from_wallet))
+                .select(dispositions::tax_undisposed_satoshis)
+                .load(conn)
+                .map_err(|e| format!("Error fetching transfer fees: {}", e))?;
+            let mut fee_left: i64 = -fees.iter().sum::<i64>();
 
-            let total_available: i64 = lots.iter().map(|l| l.tax_undisposed_satoshis).sum();
-            if total_available < *transfer_sats {
+            let total_available: i64 = lots.iter().map(|(_, available)| available).sum();
+            let needed = fee_left + record.sats - record.fee_sats;
+            if total_available < needed {
                 return Err(TransferError::Custom(format!(
                     "Insufficient BTC in wallet '{}': available {} sats, need {} sats. All changes have been rolled back.",
-                    from_wallet, total_available, transfer_sats
+                    from_wallet, total_available, needed
                 )));
             }
 
-            let mut remaining = *transfer_sats;
+            let mut remaining = record.sats - record.fee_sats;
 
-            for lot in &lots {
+            for (lot, available) in &lots {
                 if remaining == 0 {
                     break;
                 }
 
+                let fee_spent = min(fee_left, *available);
+                fee_left -= fee_spent;
+                let movable = available - fee_spent;
+                if movable == 0 {
+                    continue;
+                }
+
                 let tax_undisposed = lot.tax_undisposed_satoshis;
 
-                if tax_undisposed <= remaining {
+                if movable == tax_undisposed && tax_undisposed <= remaining {
                     // Whole lot fits — just reassign wallet
                     diesel::update(acquisitions::table.find(lot.id))
                         .set(acquisitions::wallet.eq(to_wallet))
                         .execute(conn)
                         .map_err(|e| format!("Error updating lot wallet: {}", e))?;
+                    record_moved_lot(conn, transfer_id, lot.id, tax_undisposed)?;
 
                     remaining -= tax_undisposed;
                 } else {
                     // Lot must split
-                    let transfer_sats_from_lot = remaining;
+                    let transfer_sats_from_lot = min(remaining, movable);
 
                     let transferred_gaap = rounding_div(
                         lot.undisposed_satoshis as i128 * transfer_sats_from_lot as i128,
@@ -135,18 +251,230 @@ pub fn transfer(file: &PathBuf, conn: &mut SqliteConnection) -> Result<(), Strin
                         usd_cents_btc_fair_value: lot.usd_cents_btc_fair_value,
                         wallet: to_wallet.clone(),
                         tax_undisposed_satoshis: transfer_sats_from_lot,
+                        external_id: None,
+                        parent_acquisition_id: Some(lot.id),
+                        split_reason: Some("transfer".to_string()),
+                        import_batch_id: lot.import_batch_id,
                     };
 
                     diesel::insert_into(acquisitions::table)
                         .values(&new_lot)
                         .execute(conn)
                         .map_err(|e| format!("Error inserting split lot: {}", e))?;
+                    let new_lot_id: i32 = acquisitions::table
+                        .order(acquisitions::id.desc())
+                        .select(acquisitions::id)
+                        .first(conn)
+                        .map_err(|e| format!("Error fetching new split lot: {}", e))?;
+                    record_moved_lot(conn, transfer_id, new_lot_id, transfer_sats_from_lot)?;
 
-                    remaining = 0;
+                    // The new lot shares the original's marks, so undoing a mark restores both
+                    let fair_value_links: Vec<AcquisitionFairValue> = acquisition_fair_values::table
+                        .filter(acquisition_fair_values::acquisition_id.eq(lot.id))
+                        .select(AcquisitionFairValue::as_select())
+                        .load(conn)
+                        .map_err(|e| format!("Error fetching fair value links: {}", e))?;
+                    for link in fair_value_links {
+                        diesel::insert_into(acquisition_fair_values::table)
+                            .values(AcquisitionFairValue { acquisition_id: new_lot_id, ..link })
+                            .execute(conn)
+                            .map_err(|e| format!("Error copying fair value link: {}", e))?;
+                    }
+
+                    remaining -= transfer_sats_from_lot;
                 }
             }
+
+            replay_matches(conn, config, unwound)?;
         }
 
         Ok(())
-    }).map_err(|e| e.to_string())
+    }).map_err(|e| e.to_string()))?;
+
+    Ok(preview)
+}
+
+/// Records a transfer's network fee as a disposition from the source wallet, matched when
+/// the transfer replays matching. The fee is valued at the row's `Price`, or else the
+/// latest mark to market.
+fn record_fee(conn: &mut SqliteConnection, record: &TransferRow) -> Result<i32, String> {
+    let price = match record.price {
+        Some(price) => price,
+        None => {
+            let mark: Option<FairValue> = fair_values::table
+                .filter(fair_values::date.le(record.date))
+                .order((fair_values::date.desc(), fair_values::id.desc()))
+                .select(FairValue::as_select())
+                .first(conn)
+                .optional()
+                .map_err(|e| format!("Error looking up fee price: {}", e))?;
+            mark.map(|m| m.fair_value_cents).ok_or_else(|| format!(
+                "Transfer fee on {} needs a Price: no mark to market is recorded on or before that date. \
+                 All changes have been rolled back.",
+                record.date.format("%Y-%m-%d")
+            ))?
+        }
+    };
+
+    let fee_disposition = NewDisposition {
+        disposition_date: record.date,
+        satoshis: -record.fee_sats,
+        undisposed_satoshis: -record.fee_sats,
+        usd_cents_btc_basis: price,
+        wallet: record.from.clone(),
+        tax_undisposed_satoshis: -record.fee_sats,
+        lot_designation: None,
+        external_id: None,
+        usd_cents_fee: 0,
+        import_batch_id: None,
+    };
+    diesel::insert_into(dispositions::table)
+        .values(&fee_disposition)
+        .execute(conn)
+        .map_err(|e| format!("Error saving transfer fee disposition: {}", e))?;
+    let disposition_id: i32 = dispositions::table
+        .order(dispositions::id.desc())
+        .select(dispositions::id)
+        .first(conn)
+        .map_err(|e| format!("Error fetching transfer fee disposition: {}", e))?;
+    Ok(disposition_id)
+}
+
+fn record_moved_lot(conn: &mut SqliteConnection, transfer_id: i32, acquisition_id: i32, satoshis: i64) -> Result<(), String> {
+    diesel::insert_into(transfer_acquisitions::table)
+        .values(TransferAcquisition { transfer_id, acquisition_id, satoshis })
+        .execute(conn)
+        .map_err(|e| format!("Error linking transferred lot: {}", e))?;
+    Ok(())
+}
+
+/// Orders transfers the way they were applied: by date, then by id.
+type TransferKey = (NaiveDateTime, i32);
+
+/// One lot's move out of a wallet, from `transfer_acquisitions`.
+struct LotMove {
+    transfer_id: i32,
+    transfer_date: NaiveDateTime,
+    from_wallet: String,
+    /// Tax sats the transfer moved
+    satoshis: i64,
+}
+
+impl LotMove {
+    fn key(&self) -> TransferKey {
+        (self.transfer_date, self.transfer_id)
+    }
+}
+
+/// The recorded transfers, for matching a disposition against the lots as they stood on
+/// its date rather than as transfers have since left them. A lot's `wallet` is where its
+/// last transfer put it, and a lot split off by a transfer is its own row from the start,
+/// so replaying matching needs each lot's wallet as of each disposition and the sats
+/// later transfers take out of it.
+pub(crate) struct TransferHistory {
+    moves: HashMap<i32, Vec<LotMove>>,
+    /// For each lot, when transfers split lots off it and the tax sats each took
+    splits: HashMap<i32, Vec<(TransferKey, i64)>>,
+    /// Each transfer fee disposition's transfer, by disposition id
+    fees: HashMap<i32, TransferKey>,
+}
+
+impl TransferHistory {
+    pub(crate) fn load(conn: &mut SqliteConnection) -> Result<Self, String> {
+        let links: Vec<(i32, i64, Transfer)> = transfer_acquisitions::table
+            .inner_join(transfers::table)
+            .order((transfers::transfer_date.asc(), transfers::id.asc()))
+            .select((transfer_acquisitions::acquisition_id, transfer_acquisitions::satoshis, Transfer::as_select()))
+            .load(conn)
+            .map_err(|e| format!("Error fetching transfer history: {}", e))?;
+        let mut moves: HashMap<i32, Vec<LotMove>> = HashMap::new();
+        for (lot_id, satoshis, transfer) in links {
+            moves.entry(lot_id).or_default().push(LotMove {
+                transfer_id: transfer.id,
+                transfer_date: transfer.transfer_date,
+                from_wallet: transfer.from_wallet,
+                satoshis,
+            });
+        }
+
+        // A lot split off by a transfer was created by its first move
+        let split_lots: Vec<(i32, Option<i32>)> = acquisitions::table
+            .filter(acquisitions::split_reason.eq("transfer"))
+            .select((acquisitions::id, acquisitions::parent_acquisition_id))
+            .load(conn)
+            .map_err(|e| format!("Error fetching split lots: {}", e))?;
+        let mut splits: HashMap<i32, Vec<(TransferKey, i64)>> = HashMap::new();
+        for (lot_id, parent_id) in split_lots {
+            if let (Some(parent_id), Some(created)) = (parent_id, moves.get(&lot_id).and_then(|m| m.first())) {
+                splits.entry(parent_id).or_default().push((created.key(), created.satoshis));
+            }
+        }
+
+        let fees: HashMap<i32, TransferKey> = transfers::table
+            .filter(transfers::fee_disposition_id.is_not_null())
+            .select(Transfer::as_select())
+            .load(conn)
+            .map_err(|e| format!("Error fetching transfer fees: {}", e))?
+            .into_iter()
+            .filter_map(|t| t.fee_disposition_id.map(|id| (id, (t.transfer_date, t.id))))
+            .collect();
+
+        Ok(Self { moves, splits, fees })
+    }
+
+    /// The wallet `lot` was in when `disposition` was made, and the tax sats of it that a
+    /// later transfer moves on, which the disposition cannot relieve. Transfers take effect
+    /// before dispositions dated the same, except a transfer's own fee, which is spent
+    /// from the source wallet before any of its lots move.
+    pub(crate) fn position<'a>(&'a self, lot: &'a Acquisition, disposition: &Disposition) -> (&'a str, i64) {
+        match self.fees.get(&disposition.id) {
+            Some(&key) => self.position_before(lot, key),
+            None => self.position_on(lot, disposition.disposition_date),
+        }
+    }
+
+    /// Each lot's tax sats before any disposition. A lot split off by a transfer starts
+    /// with the sats that transfer moved, and the lot it was split from keeps the rest of
+    /// the original purchase. Splits share out the `satoshis` of lots that had already
+    /// been partly disposed, so `satoshis` alone double counts those disposals.
+    pub(crate) fn opening_tax_satoshis(&self, lots: &[Acquisition]) -> HashMap<i32, i64> {
+        let mut children: HashMap<i32, Vec<&Acquisition>> = HashMap::new();
+        for lot in lots.iter().filter(|l| l.split_reason.as_deref() == Some("transfer")) {
+            if let Some(parent_id) = lot.parent_acquisition_id {
+                children.entry(parent_id).or_default().push(lot);
+            }
+        }
+        fn purchased(lot: &Acquisition, children: &HashMap<i32, Vec<&Acquisition>>) -> i64 {
+            lot.satoshis + children.get(&lot.id).map_or(0, |c| c.iter().map(|child| purchased(child, children)).sum())
+        }
+
+        lots.iter().map(|lot| {
+            let received = match (lot.split_reason.as_deref(), self.moves.get(&lot.id).and_then(|m| m.first())) {
+                (Some("transfer"), Some(created)) => created.satoshis,
+                _ => purchased(lot, &children),
+            };
+            let split_off: i64 = self.splits.get(&lot.id).map_or(0, |s| s.iter().map(|(_, sats)| sats).sum());
+            (lot.id, received - split_off)
+        }).collect()
+    }
+
+    /// `position` for a transfer dated `date`, after any transfers recorded for that date.
+    pub(crate) fn position_on<'a>(&'a self, lot: &'a Acquisition, date: NaiveDateTime) -> (&'a str, i64) {
+        self.position_before(lot, (date, i32::MAX))
+    }
+
+    /// Whether `disposition` is a transfer's network fee.
+    pub(crate) fn is_fee(&self, disposition: &Disposition) -> bool {
+        self.fees.contains_key(&disposition.id)
+    }
+
+    fn position_before<'a>(&'a self, lot: &'a Acquisition, cutoff: TransferKey) -> (&'a str, i64) {
+        let Some(next) = self.moves.get(&lot.id).and_then(|m| m.iter().find(|m| m.key() >= cutoff)) else {
+            return (&lot.wallet, 0);
+        };
+        // Lots split off this one after its next move hold sats that move carried
+        let split_later: i64 = self.splits.get(&lot.id)
+            .map_or(0, |s| s.iter().filter(|(created, _)| *created > next.key()).map(|(_, sats)| sats).sum());
+        (&next.from_wallet, next.satoshis - split_later)
+    }
 }
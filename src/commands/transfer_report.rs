@@ -0,0 +1,73 @@
+use std::path::PathBuf;
+use anyhow::Error;
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use rust_decimal::{Decimal, prelude::FromPrimitive, RoundingStrategy};
+use rust_decimal_macros::dec;
+
+use crate::rounding_div;
+use crate::models::{Acquisition, AcquisitionDisposition, ReportDates, Transfer, TransferAcquisition, TransferMovement};
+use crate::schema::{acquisitions, acquisition_dispositions, transfers, transfer_acquisitions};
+
+/// Exports every transfer dated within the period to `./reports/transfers_{beg}_{end}.csv`,
+/// one row per lot that arrived in the destination wallet, followed by a `fee` row per
+/// lot relieved by the transfer's network fee.
+pub fn transfer_report(beg: &String, end: &String, conn: &mut SqliteConnection) -> Result<(), Error> {
+    let dates: ReportDates = serde_json::from_str(&format!(r#"{{ "beginning_date": "{}", "ending_date": "{}" }}"#, beg, end)).expect("Failed to deserialize provided dates");
+    let beg_date_hms = dates.beginning_date.date().and_hms_opt(0, 0, 0).unwrap();
+    let end_date_hms = dates.ending_date.date().and_hms_opt(23, 59, 59).unwrap();
+
+    let file_path: PathBuf = PathBuf::from(format!("./reports/transfers_{}_{}.csv", dates.beginning_date.date(), dates.ending_date.date()));
+    let mut wtr = csv::Writer::from_path(file_path)?;
+
+    let transfers: Vec<Transfer> = transfers::table
+        .filter(transfers::transfer_date.ge(beg_date_hms))
+        .filter(transfers::transfer_date.le(end_date_hms))
+        .order((transfers::transfer_date.asc(), transfers::id.asc()))
+        .select(Transfer::as_select())
+        .load(conn)?;
+
+    for transfer in &transfers {
+        let moved: Vec<(TransferAcquisition, Acquisition)> = transfer_acquisitions::table
+            .inner_join(acquisitions::table)
+            .filter(transfer_acquisitions::transfer_id.eq(transfer.id))
+            .order(acquisitions::id.asc())
+            .select((TransferAcquisition::as_select(), Acquisition::as_select()))
+            .load(conn)?;
+        for (link, lot) in &moved {
+            wtr.serialize(movement(transfer, "transfer", &transfer.to_wallet, lot, link.satoshis))?;
+        }
+
+        if let Some(fee_disposition_id) = transfer.fee_disposition_id {
+            let relieved: Vec<(AcquisitionDisposition, Acquisition)> = acquisition_dispositions::table
+                .inner_join(acquisitions::table)
+                .filter(acquisition_dispositions::disposition_id.eq(fee_disposition_id))
+                .filter(acquisition_dispositions::match_type.eq("tax"))
+                .order(acquisitions::id.asc())
+                .select((AcquisitionDisposition::as_select(), Acquisition::as_select()))
+                .load(conn)?;
+            for (acq_disp, lot) in &relieved {
+                wtr.serialize(movement(transfer, "fee", "", lot, acq_disp.satoshis))?;
+            }
+        }
+    }
+
+    wtr.flush()?;
+    Ok(())
+}
+
+fn movement(transfer: &Transfer, kind: &str, to_wallet: &str, lot: &Acquisition, satoshis: i64) -> TransferMovement {
+    let basis_cents = rounding_div(satoshis as i128 * lot.usd_cents_btc_basis as i128, 100_000_000);
+    TransferMovement {
+        transfer_id: transfer.id,
+        transfer_date: transfer.transfer_date,
+        kind: kind.to_string(),
+        from_wallet: transfer.from_wallet.clone(),
+        to_wallet: to_wallet.to_string(),
+        acquisition_id: lot.id,
+        acquisition_date: lot.acquisition_date,
+        btc: Decimal::from_i64(satoshis).unwrap() / dec!(100_000_000),
+        cost_per_btc: (Decimal::from_i64(lot.usd_cents_btc_basis).unwrap() / dec!(100)).round_dp_with_strategy(2, RoundingStrategy::MidpointAwayFromZero),
+        basis: (Decimal::from_i64(basis_cents).unwrap() / dec!(100)).round_dp_with_strategy(2, RoundingStrategy::MidpointAwayFromZero),
+    }
+}
@@ -0,0 +1,48 @@
+use std::fmt::Write;
+use std::path::PathBuf;
+use anyhow::Error;
+use chrono::Utc;
+use diesel::sqlite::SqliteConnection;
+use rust_decimal::Decimal;
+
+use crate::commands::report::query_acq_disps;
+use crate::models::ReportDates;
+
+/// TXF reference number for a sale of `term`. Self-tracked sales are not reported on a
+/// 1099-B, so they go to Form 8949 Box C (short, 321) or Box F (long, 323). TXF V042 has
+/// no codes for the digital asset boxes G-L, so sales a broker reports from 2025 on still
+/// need to be moved to those boxes by hand; `form-8949` places them correctly.
+fn txf_reference(term: &str) -> u32 {
+    if term == "long" { 323 } else { 321 }
+}
+
+/// Writes the tax matches for `beg` through `end` to `./reports/rgl_tax_<beg>_<end>.txf`
+/// in TXF V042, one detailed (format 5) record per match with proceeds net of fees.
+pub fn txf(beg: &String, end: &String, conn: &mut SqliteConnection) -> Result<(), Error> {
+    let dates: ReportDates = serde_json::from_str(&format!(r#"{{ "beginning_date": "{}", "ending_date": "{}" }}"#, beg, end)).expect("Failed to deserialize provided dates");
+    let beg_date_hms = dates.beginning_date.date().and_hms_opt(0, 0, 0).unwrap();
+    let end_date_hms = dates.ending_date.date().and_hms_opt(23, 59, 59).unwrap();
+
+    // TXF lines end in CR LF, and each record ends with a `^` line
+    let mut txf = String::new();
+    write!(txf, "V042\r\nA{}\r\nD{}\r\n^\r\n", env!("CARGO_PKG_NAME"), Utc::now().format("%m/%d/%Y"))?;
+    for term in ["short", "long"] {
+        for (disposition, lot, acq_disp) in query_acq_disps(beg_date_hms, end_date_hms, term, "tax", conn) {
+            write!(
+                txf,
+                "TD\r\nN{}\r\nC1\r\nL1\r\nP{} BTC\r\nD{}\r\nD{}\r\n${}\r\n${}\r\n^\r\n",
+                txf_reference(term),
+                Decimal::new(acq_disp.satoshis, 8).normalize(),
+                lot.acquisition_date.format("%m/%d/%Y"),
+                disposition.disposition_date.format("%m/%d/%Y"),
+                Decimal::new(acq_disp.basis, 2),
+                Decimal::new(acq_disp.basis + acq_disp.rgl, 2),
+            )?;
+        }
+    }
+
+    let file_path: PathBuf = PathBuf::from(format!("./reports/rgl_tax_{}_{}.txf", dates.beginning_date.date(), dates.ending_date.date()));
+    std::fs::write(file_path, txf)?;
+
+    Ok(())
+}
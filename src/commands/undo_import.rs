@@ -0,0 +1,129 @@
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use diesel::Connection;
+
+use crate::LotConfig;
+use crate::commands::audit::AuditCommand;
+use crate::commands::preview::with_preview;
+use crate::commands::rematch::{unwind_from, replay_matches};
+use crate::models::{Acquisition, Disposition, ImportBatch};
+use crate::schema::{acquisitions, dispositions, acquisition_fair_values, import_batches, transfer_acquisitions};
+
+#[derive(Debug)]
+enum UndoImportError {
+    Diesel(diesel::result::Error),
+    Custom(String),
+}
+
+impl From<diesel::result::Error> for UndoImportError {
+    fn from(e: diesel::result::Error) -> Self {
+        UndoImportError::Diesel(e)
+    }
+}
+
+impl From<String> for UndoImportError {
+    fn from(s: String) -> Self {
+        UndoImportError::Custom(s)
+    }
+}
+
+impl std::fmt::Display for UndoImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UndoImportError::Diesel(e) => write!(f, "{}", e),
+            UndoImportError::Custom(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+/// What `undo_import` removed from the ledger.
+#[derive(Debug, Default)]
+pub struct UndoImportSummary {
+    pub file_name: String,
+    pub deleted_acquisitions: usize,
+    pub deleted_dispositions: usize,
+    /// Matches unwound, including the batch's own; the rest were replayed
+    pub unwound: usize,
+}
+
+/// Reverses import batch `batch_id`: unwinds every match for dispositions dated on or
+/// after the batch's earliest row, deletes the batch's acquisitions and dispositions,
+/// and replays matching so later dispositions that consumed the batch's lots are
+/// matched against the lots that remain.
+pub fn undo_import(batch_id: i32, conn: &mut SqliteConnection, config: &LotConfig) -> Result<UndoImportSummary, String> {
+    let audit = AuditCommand::new("undo-import", serde_json::json!({ "batch": batch_id }));
+    with_preview(conn, false, &audit, |conn| conn.transaction::<UndoImportSummary, UndoImportError, _>(|conn| {
+        let batch: ImportBatch = import_batches::table
+            .find(batch_id)
+            .select(ImportBatch::as_select())
+            .first(conn)
+            .optional()
+            .map_err(|e| format!("Error fetching import batch {}: {}", batch_id, e))?
+            .ok_or_else(|| format!("Import batch {} does not exist", batch_id))?;
+
+        let lots: Vec<Acquisition> = acquisitions::table
+            .filter(acquisitions::import_batch_id.eq(batch_id))
+            .select(Acquisition::as_select())
+            .load(conn)
+            .map_err(|e| format!("Error fetching acquisitions of import batch {}: {}", batch_id, e))?;
+        let disps: Vec<Disposition> = dispositions::table
+            .filter(dispositions::import_batch_id.eq(batch_id))
+            .select(Disposition::as_select())
+            .load(conn)
+            .map_err(|e| format!("Error fetching dispositions of import batch {}: {}", batch_id, e))?;
+
+        // Splits and transfers reshape lots in ways that cannot be unwound by deleting rows
+        if let Some(lot) = lots.iter().find(|l| l.split_reason.is_some()) {
+            return Err(UndoImportError::Custom(format!(
+                "Lot {} of import batch {} was split from lot {} by {}. \
+                 Batches whose lots have since been split or transferred cannot be undone.",
+                lot.id, batch_id, lot.parent_acquisition_id.unwrap_or_default(),
+                lot.split_reason.as_deref().unwrap_or_default()
+            )));
+        }
+        let lot_ids: Vec<i32> = lots.iter().map(|l| l.id).collect();
+        let transferred: Option<i32> = transfer_acquisitions::table
+            .filter(transfer_acquisitions::acquisition_id.eq_any(&lot_ids))
+            .select(transfer_acquisitions::acquisition_id)
+            .first(conn)
+            .optional()
+            .map_err(|e| format!("Error checking transfers of import batch {}: {}", batch_id, e))?;
+        if let Some(lot_id) = transferred {
+            return Err(UndoImportError::Custom(format!(
+                "Lot {} of import batch {} was moved by a transfer. \
+                 Batches whose lots have since been split or transferred cannot be undone.",
+                lot_id, batch_id
+            )));
+        }
+
+        let earliest = lots.iter().map(|l| l.acquisition_date)
+            .chain(disps.iter().map(|d| d.disposition_date))
+            .min();
+        let unwound = match earliest {
+            Some(from) => unwind_from(conn, config, from)?,
+            None => 0,
+        };
+
+        diesel::delete(acquisition_fair_values::table.filter(acquisition_fair_values::acquisition_id.eq_any(&lot_ids)))
+            .execute(conn)
+            .map_err(|e| format!("Error deleting acquisition fair values: {}", e))?;
+        let deleted_dispositions = diesel::delete(dispositions::table.filter(dispositions::import_batch_id.eq(batch_id)))
+            .execute(conn)
+            .map_err(|e| format!("Error deleting dispositions: {}", e))?;
+        let deleted_acquisitions = diesel::delete(acquisitions::table.filter(acquisitions::import_batch_id.eq(batch_id)))
+            .execute(conn)
+            .map_err(|e| format!("Error deleting acquisitions: {}", e))?;
+        diesel::delete(import_batches::table.find(batch_id))
+            .execute(conn)
+            .map_err(|e| format!("Error deleting import batch: {}", e))?;
+
+        replay_matches(conn, config, unwound)?;
+
+        Ok(UndoImportSummary {
+            file_name: batch.file_name,
+            deleted_acquisitions,
+            deleted_dispositions,
+            unwound,
+        })
+    }).map_err(|e| e.to_string())).map(|(summary, _)| summary)
+}
@@ -0,0 +1,144 @@
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use diesel::Connection;
+
+use crate::LotConfig;
+use crate::commands::audit::AuditCommand;
+use crate::commands::period::ensure_open;
+use crate::commands::preview::with_preview;
+use crate::commands::rematch::rematch_gaap_from;
+use crate::models::{AcquisitionFairValue, FairValue, parse_date_str};
+use crate::schema::{acquisitions, acquisition_dispositions, acquisition_fair_values, dispositions, fair_values};
+
+#[derive(Debug)]
+enum UndoMarkError {
+    Diesel(diesel::result::Error),
+    Custom(String),
+}
+
+impl From<diesel::result::Error> for UndoMarkError {
+    fn from(e: diesel::result::Error) -> Self {
+        UndoMarkError::Diesel(e)
+    }
+}
+
+impl From<String> for UndoMarkError {
+    fn from(s: String) -> Self {
+        UndoMarkError::Custom(s)
+    }
+}
+
+impl std::fmt::Display for UndoMarkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UndoMarkError::Diesel(e) => write!(f, "{}", e),
+            UndoMarkError::Custom(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+/// What `undo_mark_to_market` reversed.
+#[derive(Debug, Default)]
+pub struct UndoMarkSummary {
+    pub fair_value_cents: i64,
+    /// Lots set back to their carrying value before the mark
+    pub lots_restored: usize,
+    /// GAAP matches unwound and replayed without the mark
+    pub unwound: usize,
+}
+
+/// Reverses the latest mark to market, which must be dated `date`: restores each marked
+/// lot's prior carrying value and deletes the `fair_values` row and its links. Fails if
+/// a GAAP disposition dated after the mark relieved a marked lot, unless `rematch` is
+/// set, in which case those GAAP dispositions are unwound and replayed without the mark.
+/// Tax matches are left alone.
+pub fn undo_mark_to_market(
+    date: &str,
+    rematch: bool,
+    conn: &mut SqliteConnection,
+    config: &LotConfig,
+) -> Result<UndoMarkSummary, String> {
+    let mark_date = parse_date_str(date)?.date().and_hms_opt(23, 59, 59).unwrap();
+    let audit = AuditCommand::new("undo-mark-to-market", serde_json::json!({ "date": date, "rematch": rematch }));
+
+    with_preview(conn, false, &audit, |conn| conn.transaction::<UndoMarkSummary, UndoMarkError, _>(|conn| {
+        let mark: FairValue = fair_values::table
+            .filter(fair_values::date.eq(mark_date))
+            .order(fair_values::id.desc())
+            .select(FairValue::as_select())
+            .first(conn)
+            .optional()
+            .map_err(|e| format!("Error fetching mark to market: {}", e))?
+            .ok_or_else(|| format!("No mark to market is dated {}", mark_date.format("%Y-%m-%d")))?;
+
+        // Lots marked later carry the later mark, so only the latest can be rolled back
+        let later: Option<FairValue> = fair_values::table
+            .filter(fair_values::date.gt(mark.date)
+                .or(fair_values::date.eq(mark.date).and(fair_values::id.gt(mark.id))))
+            .order((fair_values::date.desc(), fair_values::id.desc()))
+            .select(FairValue::as_select())
+            .first(conn)
+            .optional()
+            .map_err(|e| format!("Error fetching later marks to market: {}", e))?;
+        if let Some(later) = later {
+            return Err(UndoMarkError::Custom(format!(
+                "The mark to market dated {} was run after this one. Undo it first.",
+                later.date.format("%Y-%m-%d")
+            )));
+        }
+        ensure_open(conn, mark.date, "The mark to market")?;
+
+        let links: Vec<AcquisitionFairValue> = acquisition_fair_values::table
+            .filter(acquisition_fair_values::fair_value_id.eq(mark.id))
+            .select(AcquisitionFairValue::as_select())
+            .load(conn)
+            .map_err(|e| format!("Error fetching marked lots: {}", e))?;
+        if links.iter().any(|l| l.prior_usd_cents_btc_fair_value.is_none()) {
+            return Err(UndoMarkError::Custom(format!(
+                "The mark to market dated {} was recorded before prior fair values were kept and cannot be undone",
+                mark.date.format("%Y-%m-%d")
+            )));
+        }
+
+        let lot_ids: Vec<i32> = links.iter().map(|l| l.acquisition_id).collect();
+        let relieved: Vec<i32> = acquisition_dispositions::table
+            .inner_join(dispositions::table)
+            .filter(acquisition_dispositions::match_type.eq("gaap"))
+            .filter(acquisition_dispositions::acquisition_id.eq_any(&lot_ids))
+            .filter(dispositions::disposition_date.gt(mark.date))
+            .select(dispositions::id)
+            .distinct()
+            .order(dispositions::id.asc())
+            .load(conn)
+            .map_err(|e| format!("Error fetching dispositions matched after the mark: {}", e))?;
+        if !relieved.is_empty() && !rematch {
+            let ids: Vec<String> = relieved.iter().map(|id| id.to_string()).collect();
+            return Err(UndoMarkError::Custom(format!(
+                "GAAP dispositions {} were matched against the mark to market dated {}. \
+                 Pass --rematch to rematch them without it.",
+                ids.join(", "), mark.date.format("%Y-%m-%d")
+            )));
+        }
+
+        for link in &links {
+            diesel::update(acquisitions::table.find(link.acquisition_id))
+                .set(acquisitions::usd_cents_btc_fair_value.eq(link.prior_usd_cents_btc_fair_value.unwrap()))
+                .execute(conn)
+                .map_err(|e| format!("Error restoring lot carrying value: {}", e))?;
+        }
+        diesel::delete(acquisition_fair_values::table.filter(acquisition_fair_values::fair_value_id.eq(mark.id)))
+            .execute(conn)
+            .map_err(|e| format!("Error deleting marked lot links: {}", e))?;
+        diesel::delete(fair_values::table.find(mark.id))
+            .execute(conn)
+            .map_err(|e| format!("Error deleting mark to market: {}", e))?;
+
+        let unwound = if relieved.is_empty() { 0 } else { rematch_gaap_from(conn, config, mark.date)? };
+
+        Ok(UndoMarkSummary {
+            fair_value_cents: mark.fair_value_cents,
+            lots_restored: links.len(),
+            unwound,
+        })
+    }).map_err(|e| e.to_string())).map(|(summary, _)| summary)
+}
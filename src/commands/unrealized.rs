@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use serde::de::value::{Error as ValueError, StrDeserializer};
+
+use crate::{LotConfig, rounding_div};
+use crate::commands::holdings::carrying_values_on;
+use crate::commands::import::lot_term;
+use crate::models::{Acquisition, HoldingsDate, UnrealizedHolding, deserialize_price};
+use crate::schema::{acquisitions, dispositions, acquisition_dispositions};
+
+fn usd(cents: i64) -> Decimal {
+    Decimal::new(cents, 2)
+}
+
+/// Writes each lot open at the end of `date` with its tax basis, GAAP carrying value,
+/// and market value at `price`. The unrealized gain over tax basis is split into short
+/// and long term by the lot's holding period on `date`. `view` picks the tax or GAAP
+/// undisposed tracker, and disposals dated after `date` are added back as in `holdings`.
+pub fn unrealized(date: &String, price: &str, view: &str, conn: &mut SqliteConnection, config: &LotConfig) -> Result<(), anyhow::Error> {
+    if !["tax", "gaap"].contains(&view) {
+        return Err(anyhow::anyhow!("Invalid view '{}'. Must be 'tax' or 'gaap'.", view));
+    }
+    let price_cents = deserialize_price(StrDeserializer::<ValueError>::new(price))?;
+
+    let mut report_date: HoldingsDate =
+        serde_json::from_str(&format!(r#"{{ "date": "{}" }}"#, date))
+            .expect("Failed to deserialize unrealized report date");
+    report_date.date = report_date.date.date().and_hms_opt(23, 59, 59).unwrap();
+
+    let lots: Vec<Acquisition> = acquisitions::table
+        .filter(acquisitions::acquisition_date.le(report_date.date))
+        .order((acquisitions::acquisition_date.asc(), acquisitions::id.asc()))
+        .select(Acquisition::as_select())
+        .load(conn)?;
+
+    let subsequent: Vec<(i32, i64)> = acquisition_dispositions::table
+        .inner_join(dispositions::table)
+        .filter(acquisition_dispositions::match_type.eq(view))
+        .filter(dispositions::disposition_date.gt(report_date.date))
+        .select((acquisition_dispositions::acquisition_id, acquisition_dispositions::satoshis))
+        .load(conn)?;
+    let mut open: HashMap<i32, i64> = lots.iter()
+        .map(|l| (l.id, if view == "tax" { l.tax_undisposed_satoshis } else { l.undisposed_satoshis }))
+        .collect();
+    for (lot_id, sats) in subsequent {
+        if let Some(undisposed) = open.get_mut(&lot_id) {
+            *undisposed += sats;
+        }
+    }
+    let lots: Vec<Acquisition> = lots.into_iter().filter(|l| open[&l.id] > 0).collect();
+    let carrying = carrying_values_on(conn, config, &lots, report_date.date)?;
+
+    let file_path: PathBuf = PathBuf::from(format!("./reports/unrealized_{}.csv", report_date.date.date()));
+    let mut wtr = csv::Writer::from_path(file_path)?;
+
+    let mut total_undisposed_btc = dec!(0);
+    let mut total_tax_basis = dec!(0);
+    let mut total_carrying_value = dec!(0);
+    let mut total_market_value = dec!(0);
+    let mut total_short_term_gain = dec!(0);
+    let mut total_long_term_gain = dec!(0);
+    let mut total_gaap_unrealized_gain = dec!(0);
+
+    for lot in &lots {
+        let sats = open[&lot.id] as i128;
+        let tax_basis = rounding_div(sats * lot.usd_cents_btc_basis as i128, 100_000_000);
+        let carrying_value = rounding_div(sats * carrying[&lot.id] as i128, 100_000_000);
+        let market_value = rounding_div(sats * price_cents as i128, 100_000_000);
+        let gain = market_value - tax_basis;
+        let long_term = lot_term(lot.acquisition_date, report_date.date) == "long";
+        // `lot_term` counts a lot as long-term once it has been held more than 365 days
+        let days_held = (report_date.date - lot.acquisition_date).num_days();
+
+        let holding = UnrealizedHolding {
+            lot_id: lot.id,
+            wallet: lot.wallet.clone(),
+            acquisition_date: lot.acquisition_date,
+            undisposed_btc: Decimal::new(open[&lot.id], 8),
+            tax_basis: usd(tax_basis),
+            gaap_carrying_value: usd(carrying_value),
+            market_value: usd(market_value),
+            short_term_gain: usd(if long_term { 0 } else { gain }),
+            long_term_gain: usd(if long_term { gain } else { 0 }),
+            gaap_unrealized_gain: usd(market_value - carrying_value),
+            days_to_long_term: if long_term { 0 } else { 366 - days_held },
+        };
+        total_undisposed_btc += holding.undisposed_btc;
+        total_tax_basis += holding.tax_basis;
+        total_carrying_value += holding.gaap_carrying_value;
+        total_market_value += holding.market_value;
+        total_short_term_gain += holding.short_term_gain;
+        total_long_term_gain += holding.long_term_gain;
+        total_gaap_unrealized_gain += holding.gaap_unrealized_gain;
+
+        wtr.serialize(holding)?;
+    }
+
+    wtr.write_record(&[
+        String::from(""),
+        String::from(""),
+        String::from(""),
+        total_undisposed_btc.to_string(),
+        total_tax_basis.to_string(),
+        total_carrying_value.to_string(),
+        total_market_value.to_string(),
+        total_short_term_gain.to_string(),
+        total_long_term_gain.to_string(),
+        total_gaap_unrealized_gain.to_string(),
+        String::from(""),
+    ])?;
+    wtr.flush()?;
+
+    Ok(())
+}
@@ -49,6 +49,14 @@ pub fn rounding_div(numerator: i128, denominator: i128) -> i64 {
     }
 }
 
+/// Lot relief methods accepted by `TAX_LOT_METHOD`. `min-tax` picks lots per
+/// disposition to minimize realized gain (see `commands::import::tax_lot_category`).
+pub const LOT_METHODS: [&str; 4] = ["fifo", "lifo", "hifo", "min-tax"];
+
+/// Lot relief methods accepted by `GAAP_LOT_METHOD`. `average` relieves lots in FIFO
+/// order at the weighted-average carrying value of the pool.
+pub const GAAP_LOT_METHODS: [&str; 4] = ["fifo", "lifo", "hifo", "average"];
+
 pub fn load_lot_config() -> LotConfig {
     dotenv().ok();
     let config = LotConfig {
@@ -57,11 +65,11 @@ pub fn load_lot_config() -> LotConfig {
         gaap_lot_method: env::var("GAAP_LOT_METHOD").unwrap_or_else(|_| "fifo".to_string()),
     };
 
-    if config.tax_lot_method != "fifo" {
-        panic!("Unsupported TAX_LOT_METHOD '{}'. Only 'fifo' is currently supported.", config.tax_lot_method);
+    if !LOT_METHODS.contains(&config.tax_lot_method.as_str()) {
+        panic!("Unsupported TAX_LOT_METHOD '{}'. Must be 'fifo', 'lifo', 'hifo', or 'min-tax'.", config.tax_lot_method);
     }
-    if config.gaap_lot_method != "fifo" {
-        panic!("Unsupported GAAP_LOT_METHOD '{}'. Only 'fifo' is currently supported.", config.gaap_lot_method);
+    if !GAAP_LOT_METHODS.contains(&config.gaap_lot_method.as_str()) {
+        panic!("Unsupported GAAP_LOT_METHOD '{}'. Must be 'fifo', 'lifo', 'hifo', or 'average'.", config.gaap_lot_method);
     }
     if !["wallet", "universal"].contains(&config.tax_lot_scope.as_str()) {
         panic!("Unsupported TAX_LOT_SCOPE '{}'. Must be 'wallet' or 'universal'.", config.tax_lot_scope);
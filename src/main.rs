@@ -1,40 +1,162 @@
 use std::io::{self, Write};
 use clap::{Parser, Subcommand};
-use rust_rgl_ledger::{establish_connection, load_lot_config};
-use rust_rgl_ledger::commands::import::import_transactions;
+use rust_decimal::Decimal;
+use rust_rgl_ledger::{establish_connection, load_lot_config, LotConfig};
+use rust_rgl_ledger::commands::import::{import_transactions_with_options, ImportOptions};
 use rust_rgl_ledger::commands::report::report;
 use rust_rgl_ledger::commands::holdings::holdings;
+use rust_rgl_ledger::commands::unrealized::unrealized;
+use rust_rgl_ledger::commands::rollforward::rollforward;
+use rust_rgl_ledger::commands::journal::{journal, ChartOfAccounts};
+use rust_rgl_ledger::commands::form_8949::{form_8949, Form8949Options};
+use rust_rgl_ledger::commands::txf::txf;
 use rust_rgl_ledger::commands::mark_to_market::mark_to_market;
 use rust_rgl_ledger::commands::allocate::allocate;
 use rust_rgl_ledger::commands::transfer::transfer;
+use rust_rgl_ledger::commands::transfer_report::transfer_report;
+use rust_rgl_ledger::commands::lot_history::lot_history;
+use rust_rgl_ledger::commands::rebuild::rebuild;
+use rust_rgl_ledger::commands::undo_import::undo_import;
+use rust_rgl_ledger::commands::undo_mark_to_market::undo_mark_to_market;
+use rust_rgl_ledger::commands::audit::{latest_entry, verify_audit};
+use rust_rgl_ledger::commands::check::check_ledger;
+use rust_rgl_ledger::commands::period::{close_period, reopen_period, locked_through};
+use rust_rgl_ledger::commands::edit_transaction::{edit_transaction, delete_transaction, TransactionEdit};
 
 fn main() {
     let command = Cli::parse();
     let conn = &mut establish_connection();
     match command.subcommand {
-        Command::Import { file } => {
+        Command::Import { file, skip_existing, dry_run } => {
             let config = load_lot_config();
-            if config.tax_lot_scope == "universal" {
-                println!("WARNING: TAX_LOT_SCOPE is set to 'universal'. Universal scope is not a valid method for tax lot relief.");
-                print!("Do you want to proceed? (y/N): ");
-                io::stdout().flush().unwrap();
-                let mut input = String::new();
-                io::stdin().read_line(&mut input).unwrap();
-                if !input.trim().eq_ignore_ascii_case("y") {
-                    println!("Import cancelled.");
-                    return;
-                }
-            }
-            match import_transactions(&file, conn, &config) {
-                Ok(_) => {
-                    println!("Successfully Imported transactions from {:?}", file)
+            if !confirm_tax_lot_scope(&config) {
+                println!("Import cancelled.");
+                return;
+            }
+            let options = ImportOptions { skip_existing, dry_run };
+            match import_transactions_with_options(&file, conn, &config, &options) {
+                Ok(summary) => {
+                    if dry_run {
+                        println!("Dry run of import from {:?}; no changes were saved", file);
+                        print!("{}", summary.changes);
+                    } else {
+                        println!("Successfully Imported transactions from {:?} as import batch {}", file, summary.batch_id);
+                    }
+                    println!(
+                        "Inserted {} acquisitions and {} dispositions; skipped {} rows already imported",
+                        summary.inserted_acquisitions, summary.inserted_dispositions, summary.skipped.len()
+                    );
+                    if summary.inserted_fee_dispositions > 0 {
+                        println!("Recorded {} dispositions for fees paid in BTC", summary.inserted_fee_dispositions);
+                    }
+                    for tx_id in &summary.skipped {
+                        println!("  skipped TxId {}", tx_id);
+                    }
+                    if summary.rematched > 0 {
+                        println!("Rematched {} existing matches dated on or after the earliest imported row", summary.rematched);
+                    }
+                    if !dry_run {
+                        print_audit_head(conn);
+                    }
                 }
                 Err(e) => {
                     println!("Error importing file {:?}: {}", file, e)
                 }
             };
         },
-        Command::Report { beg, end, view } => {
+        Command::UndoImport { batch } => {
+            let config = load_lot_config();
+            if !confirm_tax_lot_scope(&config) {
+                println!("Undo cancelled.");
+                return;
+            }
+            match undo_import(batch, conn, &config) {
+                Ok(summary) => {
+                    println!("Successfully undid import batch {} from {:?}", batch, summary.file_name);
+                    println!(
+                        "Deleted {} acquisitions and {} dispositions; unwound {} matches and rematched the remaining dispositions",
+                        summary.deleted_acquisitions, summary.deleted_dispositions, summary.unwound
+                    );
+                    print_audit_head(conn);
+                }
+                Err(e) => {
+                    eprint!("Error undoing import batch {}: {}", batch, e)
+                }
+            }
+        },
+        Command::EditTransaction { kind, id, date, bitcoin, price, wallet } => {
+            let config = load_lot_config();
+            if !confirm_tax_lot_scope(&config) {
+                println!("Edit cancelled.");
+                return;
+            }
+            let edit = TransactionEdit { date, bitcoin, price, wallet };
+            match edit_transaction(&kind, id, &edit, conn, &config) {
+                Ok(summary) => {
+                    println!("Successfully edited {} {}", kind, id);
+                    print!("{}", summary);
+                    print_audit_head(conn);
+                }
+                Err(e) => {
+                    eprint!("Error editing {} {}: {}", kind, id, e)
+                }
+            }
+        },
+        Command::DeleteTransaction { kind, id } => {
+            let config = load_lot_config();
+            if !confirm_tax_lot_scope(&config) {
+                println!("Delete cancelled.");
+                return;
+            }
+            match delete_transaction(&kind, id, conn, &config) {
+                Ok(summary) => {
+                    println!("Successfully deleted {} {}", kind, id);
+                    print!("{}", summary);
+                    print_audit_head(conn);
+                }
+                Err(e) => {
+                    eprint!("Error deleting {} {}: {}", kind, id, e)
+                }
+            }
+        },
+        Command::Report { beg, end, view, format: Some(format), aggregate, broker_wallets } if format == "8949" => {
+            if view.as_deref().is_some_and(|v| v != "tax") {
+                eprintln!("--format 8949 is a tax form and only supports --view tax");
+                std::process::exit(1);
+            }
+            let options = Form8949Options { aggregate, broker_wallets };
+            match form_8949(&beg, &end, &options, conn) {
+                Ok(_) => {
+                    println!("Form 8949 and Schedule D run for the period {} - {}", beg, end)
+                }
+                Err(e) => {
+                    eprint!("Error creating Form 8949: {}", e)
+                }
+            }
+        },
+        Command::Report { format: Some(format), aggregate, broker_wallets, .. } if aggregate || !broker_wallets.is_empty() => {
+            eprintln!("--aggregate and --broker-wallets only apply to --format 8949, not --format {}", format);
+            std::process::exit(1);
+        },
+        Command::Report { beg, end, view, format: Some(format), .. } if format == "txf" => {
+            if view.as_deref().is_some_and(|v| v != "tax") {
+                eprintln!("--format txf exports tax matches and only supports --view tax");
+                std::process::exit(1);
+            }
+            match txf(&beg, &end, conn) {
+                Ok(_) => {
+                    println!("TXF export run for the period {} - {}", beg, end)
+                }
+                Err(e) => {
+                    eprint!("Error creating TXF export: {}", e)
+                }
+            }
+        },
+        Command::Report { format: Some(format), .. } if format != "csv" => {
+            eprintln!("Invalid format '{}'. Must be 'csv', '8949', or 'txf'.", format);
+            std::process::exit(1);
+        },
+        Command::Report { beg, end, view, .. } => {
             match report(&beg, &end, &view.unwrap_or_else(|| "both".to_string()), conn) {
                 Ok(_) => {
                     println!("Realized gain/loss report run for the period {} - {}", beg, end)
@@ -45,7 +167,8 @@ fn main() {
             }
         },
         Command::Holdings { date, view } => {
-            match holdings(&date, &view.unwrap_or_else(|| "gaap".to_string()), conn) {
+            let config = load_lot_config();
+            match holdings(&date, &view.unwrap_or_else(|| "gaap".to_string()), conn, &config) {
                 Ok(_) => {
                     println!("Holdings report run for the period ended {}", date)
                 }
@@ -54,47 +177,318 @@ fn main() {
                 }
             }
         },
-        Command::MarkToMarket { price, date } => {
-            match mark_to_market(&price, &date, conn) {
+        Command::Rollforward { beg, end } => {
+            let config = load_lot_config();
+            match rollforward(&beg, &end, conn, &config) {
+                Ok(summary) => {
+                    println!("Roll-forward report run for the period {} - {}", beg, end);
+                    println!(
+                        "Opening ${} + purchases ${} - disposals ${} + remeasurement ${} = closing ${}",
+                        summary.opening, summary.purchases, summary.disposals, summary.remeasurement, summary.closing
+                    );
+                    if !summary.restatement.is_zero() {
+                        println!("Includes ${} of average cost restatement", summary.restatement);
+                    }
+                    if !summary.rounding.is_zero() {
+                        println!("Includes ${} of rounding", summary.rounding);
+                    }
+                }
+                Err(e) => {
+                    eprint!("Error creating roll-forward report: {}", e)
+                }
+            }
+        },
+        Command::Journal { beg, end, accounts } => {
+            let config = load_lot_config();
+            let chart = match accounts {
+                Some(file) => match ChartOfAccounts::load(&file) {
+                    Ok(chart) => chart,
+                    Err(e) => {
+                        eprintln!("Error loading chart of accounts: {}", e);
+                        std::process::exit(1);
+                    }
+                },
+                None => ChartOfAccounts::default(),
+            };
+            match journal(&beg, &end, &chart, conn, &config) {
+                Ok(summary) => {
+                    println!("Journal run for the period {} - {}", beg, end);
+                    println!("{} entries with ${} of debits and credits", summary.entries, summary.total_debits);
+                }
+                Err(e) => {
+                    eprint!("Error creating journal: {}", e)
+                }
+            }
+        },
+        Command::Unrealized { date, price, view } => {
+            let config = load_lot_config();
+            match unrealized(&date, &price, &view.unwrap_or_else(|| "tax".to_string()), conn, &config) {
                 Ok(_) => {
+                    println!("Unrealized gain/loss report run as of {} at {}", date, price)
+                }
+                Err(e) => {
+                    eprint!("Error creating unrealized report: {}", e)
+                }
+            }
+        },
+        Command::MarkToMarket { price, date, dry_run } => {
+            let config = load_lot_config();
+            match mark_to_market(&price, &date, conn, &config, dry_run) {
+                Ok(summary) if dry_run => {
+                    println!("Dry run of marking Bitcoin holdings to {} as of {}; no changes were saved", price, &date);
+                    println!("Would mark {} lots", summary.lots_marked);
+                    print!("{}", summary.changes);
+                }
+                Ok(summary) => {
                     println!("Successfully adjusted Bitcoin holdings to {} as of {}", price, &date);
+                    if summary.rematched > 0 {
+                        println!("Rematched {} matches for dispositions after {} against the new carrying value", summary.rematched, date);
+                    }
                     println!("Mark to Market report saved to ./reports/mark-to-market-{}", date);
+                    print_audit_head(conn);
                 }
                 Err(e) => {
                     eprint!("Error marking to market bitcoin holdings: {}", e)
                 }
             }
         },
-        Command::Allocate { file } => {
-            match allocate(&file, conn) {
+        Command::UndoMarkToMarket { date, rematch } => {
+            let config = load_lot_config();
+            match undo_mark_to_market(&date, rematch, conn, &config) {
+                Ok(summary) => {
+                    println!(
+                        "Undid the mark to market of ${} as of {}; restored the prior carrying value of {} lots",
+                        cents_to_usd(summary.fair_value_cents), date, summary.lots_restored
+                    );
+                    if summary.unwound > 0 {
+                        println!("Unwound {} GAAP matches and rematched them without the mark", summary.unwound);
+                    }
+                    print_audit_head(conn);
+                }
+                Err(e) => {
+                    eprint!("Error undoing mark to market as of {}: {}", date, e)
+                }
+            }
+        },
+        Command::Allocate { file, dry_run } => {
+            match allocate(&file, conn, dry_run) {
+                Ok(changes) if dry_run => {
+                    println!("Dry run of allocation from {:?}; no changes were saved", file);
+                    print!("{}", changes);
+                }
                 Ok(_) => {
-                    println!("Successfully allocated lots from {:?}", file)
+                    println!("Successfully allocated lots from {:?}", file);
+                    print_audit_head(conn);
                 }
                 Err(e) => {
                     eprint!("Error allocating lots: {}", e)
                 }
             }
         },
-        Command::Transfer { file } => {
-            match transfer(&file, conn) {
+        Command::Transfer { file, dry_run } => {
+            let config = load_lot_config();
+            if !confirm_tax_lot_scope(&config) {
+                println!("Transfer cancelled.");
+                return;
+            }
+            match transfer(&file, conn, &config, dry_run) {
+                Ok(changes) if dry_run => {
+                    println!("Dry run of transfers from {:?}; no changes were saved", file);
+                    print!("{}", changes);
+                }
                 Ok(_) => {
-                    println!("Successfully transferred lots from {:?}", file)
+                    println!("Successfully transferred lots from {:?}", file);
+                    print_audit_head(conn);
                 }
                 Err(e) => {
                     eprint!("Error transferring lots: {}", e)
                 }
             }
         },
+        Command::Transfers { beg, end } => {
+            match transfer_report(&beg, &end, conn) {
+                Ok(_) => {
+                    println!("Transfers report run for the period {} - {}", beg, end)
+                }
+                Err(e) => {
+                    eprint!("Error creating transfers report: {}", e)
+                }
+            }
+        },
+        Command::LotHistory { id } => {
+            match lot_history(id, conn) {
+                Ok(tree) => {
+                    print!("{}", tree)
+                }
+                Err(e) => {
+                    eprint!("Error fetching lot history: {}", e)
+                }
+            }
+        },
+        Command::ClosePeriod { through } => {
+            match close_period(&through, conn) {
+                Ok(lock) => {
+                    println!("Closed the period through {}", lock.locked_through.format("%Y-%m-%d"));
+                    print_audit_head(conn);
+                }
+                Err(e) => {
+                    eprint!("Error closing period: {}", e)
+                }
+            }
+        },
+        Command::ReopenPeriod { reason } => {
+            match reopen_period(&reason, conn) {
+                Ok(lock) => {
+                    println!("Reopened the period closed through {}: {}", lock.locked_through.format("%Y-%m-%d"), reason);
+                    match locked_through(conn) {
+                        Ok(Some(date)) => println!("The period remains closed through {}", date.format("%Y-%m-%d")),
+                        Ok(None) => println!("No period is closed"),
+                        Err(e) => eprint!("{}", e),
+                    }
+                    print_audit_head(conn);
+                }
+                Err(e) => {
+                    eprint!("Error reopening period: {}", e)
+                }
+            }
+        },
+        Command::VerifyAudit => {
+            match verify_audit(conn) {
+                Ok(verification) if verification.entries == 0 => {
+                    println!("Audit log is empty; nothing to verify")
+                }
+                Ok(verification) if verification.breaks.is_empty() => {
+                    println!("Audit log verified: {} entries, hash chain intact, ledger matches the latest entry", verification.entries);
+                    print_audit_head(conn);
+                }
+                Ok(verification) => {
+                    println!("Audit log verification FAILED: {} entries, {} problems", verification.entries, verification.breaks.len());
+                    for problem in &verification.breaks {
+                        println!("  {}", problem);
+                    }
+                    std::process::exit(1);
+                }
+                Err(e) => {
+                    eprint!("Error verifying audit log: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        },
+        Command::Check => {
+            let config = load_lot_config();
+            match check_ledger(conn, &config) {
+                Ok(report) if report.violations.is_empty() => {
+                    println!("Ledger check passed: {} lots, {} dispositions, {} matches", report.lots, report.dispositions, report.matches)
+                }
+                Ok(report) => {
+                    println!("Ledger check FAILED: {} violations", report.violations.len());
+                    for violation in &report.violations {
+                        println!("  {}", violation);
+                    }
+                    std::process::exit(1);
+                }
+                Err(e) => {
+                    eprint!("Error checking ledger: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        },
+        Command::Rebuild => {
+            let config = load_lot_config();
+            if !confirm_tax_lot_scope(&config) {
+                println!("Rebuild cancelled.");
+                return;
+            }
+            match rebuild(conn, &config) {
+                Ok(summary) => {
+                    println!(
+                        "Successfully rebuilt ledger with TAX_LOT_METHOD={} TAX_LOT_SCOPE={} GAAP_LOT_METHOD={}",
+                        config.tax_lot_method, config.tax_lot_scope, config.gaap_lot_method
+                    );
+                    println!("Matches: {} removed, {} created", summary.matches_removed, summary.matches_created);
+                    println!("Tax RGL:  {} -> {}", cents_to_usd(summary.tax_rgl_before), cents_to_usd(summary.tax_rgl_after));
+                    println!("GAAP RGL: {} -> {}", cents_to_usd(summary.gaap_rgl_before), cents_to_usd(summary.gaap_rgl_after));
+                    print_audit_head(conn);
+                }
+                Err(e) => {
+                    eprint!("Error rebuilding ledger: {}", e)
+                }
+            }
+        },
+    }
+}
+
+/// Prints the latest audit log entry's hash. Kept outside the database, it shows whether
+/// the log was later rewritten, which the hash chain alone cannot.
+fn print_audit_head(conn: &mut diesel::SqliteConnection) {
+    match latest_entry(conn) {
+        Ok(Some(entry)) => println!("Audit log entry {} ({}): {}", entry.id, entry.command, entry.entry_hash),
+        Ok(None) => {}
+        Err(e) => eprintln!("{}", e),
     }
 }
 
+/// Universal scope is not a valid method for tax lot relief; ask before matching with it.
+fn confirm_tax_lot_scope(config: &LotConfig) -> bool {
+    if config.tax_lot_scope != "universal" {
+        return true;
+    }
+    println!("WARNING: TAX_LOT_SCOPE is set to 'universal'. Universal scope is not a valid method for tax lot relief.");
+    print!("Do you want to proceed? (y/N): ");
+    io::stdout().flush().unwrap();
+    let mut input = String::new();
+    io::stdin().read_line(&mut input).unwrap();
+    input.trim().eq_ignore_ascii_case("y")
+}
+
+fn cents_to_usd(cents: i64) -> Decimal {
+    Decimal::new(cents, 2)
+}
+
 #[derive(Subcommand)]
 enum Command {
     /// Import a specified CSV file at the provided path
     Import {
-        /// The file to import including columns: Date, Bitcoin, Price, [Wallet]
+        /// The file to import including columns: Date, Bitcoin, Price, [Wallet], [Lot], [TxId], [Fee]
         #[clap(long, short)]
         file: std::path::PathBuf,
+        /// Skip rows whose TxId has already been imported instead of rejecting the file
+        #[clap(long)]
+        skip_existing: bool,
+        /// Run the import and matching, print what would change, and save nothing
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// Delete the acquisitions and dispositions of an import batch and rematch the ledger without them
+    UndoImport {
+        /// The import batch id printed by `import`
+        batch: i32,
+    },
+    /// Correct the date, amount, price or wallet of an acquisition or disposition and rematch
+    EditTransaction {
+        /// "acquisition" or "disposition"
+        kind: String,
+        /// The acquisition or disposition id
+        id: i32,
+        /// The corrected date
+        #[clap(long, short)]
+        date: Option<String>,
+        /// The corrected BTC amount, negative for a disposition
+        #[clap(long, short, allow_hyphen_values = true)]
+        bitcoin: Option<String>,
+        /// The corrected USD price of one BTC
+        #[clap(long, short)]
+        price: Option<String>,
+        /// The corrected wallet
+        #[clap(long, short)]
+        wallet: Option<String>,
+    },
+    /// Delete an acquisition or disposition and rematch
+    DeleteTransaction {
+        /// "acquisition" or "disposition"
+        kind: String,
+        /// The acquisition or disposition id
+        id: i32,
     },
     /// Export a CSV report of Realized Gain/Loss activity for a specfied period to the 'reports' directory
     Report {
@@ -104,9 +498,18 @@ enum Command {
         /// The ending date for RGL report
         #[clap(long, short)]
         end: String,
-        /// View: "tax", "gaap", or "both" (default) — which report(s) to generate
+        /// View: "tax", "gaap", "both" (default), or "selection" (tax lot selection order)
         #[clap(long, short)]
         view: Option<String>,
+        /// Format: "csv" (default), "8949" (tax matches as Form 8949 rows plus Schedule D totals), or "txf" (tax matches for tax software)
+        #[clap(long, short)]
+        format: Option<String>,
+        /// With --format 8949, combine the lots in a box sold on the same day into one row
+        #[clap(long, requires = "format")]
+        aggregate: bool,
+        /// With --format 8949, wallets whose sales a broker reports on Form 1099-DA
+        #[clap(long, value_delimiter = ',', requires = "format")]
+        broker_wallets: Vec<String>,
     },
     /// Export a CSV report of Bitcoin holdings as of a specified date to the 'reports' directory
     Holdings {
@@ -117,6 +520,39 @@ enum Command {
         #[clap(long, short)]
         view: Option<String>,
     },
+    /// Export a CSV roll-forward of GAAP holdings for a specified period to the 'reports' directory
+    Rollforward {
+        /// The beginning date of the roll-forward
+        #[clap(long, short)]
+        beg: String,
+        /// The ending date of the roll-forward
+        #[clap(long, short)]
+        end: String,
+    },
+    /// Export GL journal entries for a specified period to the 'reports' directory
+    Journal {
+        /// The beginning date of the journal
+        #[clap(long, short)]
+        beg: String,
+        /// The ending date of the journal
+        #[clap(long, short)]
+        end: String,
+        /// CSV mapping account roles and wallets to GL accounts
+        #[clap(long, short)]
+        accounts: Option<std::path::PathBuf>,
+    },
+    /// Export unrealized gain/loss on open lots at a market price to 'reports' directory
+    Unrealized {
+        /// The date to report open lots as of
+        #[clap(long, short)]
+        date: String,
+        /// The USD price of one BTC to value open lots at
+        #[clap(long, short)]
+        price: String,
+        /// View: "tax" (default) or "gaap" — controls which undisposed tracker to use
+        #[clap(long, short)]
+        view: Option<String>,
+    },
     /// Mark holdings to provided market price and export mark-to-market report to 'reports' directory
     MarkToMarket {
         /// The USD price to value Bitcoin holdings
@@ -125,19 +561,69 @@ enum Command {
         /// The Date to mark holdings to Fair Value
         #[clap(long, short)]
         date: String,
+        /// Print the matches the mark would replay, and save nothing
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// Reverse the latest mark to market, restoring each lot's prior carrying value
+    UndoMarkToMarket {
+        /// The date of the mark to market to undo
+        #[clap(long, short)]
+        date: String,
+        /// Rematch GAAP dispositions that were matched against the mark instead of refusing
+        #[clap(long)]
+        rematch: bool,
     },
     /// Allocate existing lots to wallets using a bucket CSV
     Allocate {
         /// The bucket CSV file with columns: Wallet, BTC
         #[clap(long, short)]
         file: std::path::PathBuf,
+        /// Print the lots that would be reassigned or split, and save nothing
+        #[clap(long)]
+        dry_run: bool,
     },
     /// Transfer BTC between wallets using a transfer CSV
     Transfer {
-        /// The transfer CSV file with columns: Date, From, To, BTC
+        /// The transfer CSV file with columns: Date, From, To, BTC, [Fee], [Price]
         #[clap(long, short)]
         file: std::path::PathBuf,
+        /// Print the lots that would be moved or split, and save nothing
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// Export a CSV report of wallet transfers for a specified period to the 'reports' directory
+    Transfers {
+        /// The beginning date for the transfers report
+        #[clap(long, short)]
+        beg: String,
+        /// The ending date for the transfers report
+        #[clap(long, short)]
+        end: String,
+    },
+    /// Print a lot's lineage from the original purchase to its current fragments, with their matches
+    LotHistory {
+        /// The acquisition id of any lot in the lineage
+        id: i32,
+    },
+    /// Close every period through a date so its matches and fair values can no longer change
+    ClosePeriod {
+        /// The last day of the closed period
+        #[clap(long, short)]
+        through: String,
+    },
+    /// Reopen the latest closed period
+    ReopenPeriod {
+        /// Why the period is being reopened; saved with the lock
+        #[clap(long, short)]
+        reason: String,
     },
+    /// Recompute the audit log hash chain and check the ledger has not changed outside the tool
+    VerifyAudit,
+    /// Check that lots, dispositions and matches agree with each other and with the stored prices
+    Check,
+    /// Discard all matches and replay tax and GAAP matching under the current lot configuration
+    Rebuild,
 }
 
 #[derive(Parser)]
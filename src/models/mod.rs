@@ -2,9 +2,9 @@ use chrono::{NaiveDate, NaiveDateTime};
 use rust_decimal::Decimal;
 use serde::{de, Deserialize, Deserializer, Serialize};
 use diesel::prelude::*;
-use crate::schema::{acquisitions, dispositions, acquisition_dispositions, fair_values};
+use crate::schema::{acquisitions, audit_log, dispositions, acquisition_dispositions, acquisition_fair_values, fair_values, import_batches, passed_over_lots, period_locks, transfers, transfer_acquisitions};
 
-#[derive(Queryable, Selectable, Debug, PartialEq, Eq, Serialize, Identifiable)]
+#[derive(Queryable, Selectable, Debug, Clone, PartialEq, Eq, Serialize, Identifiable)]
 #[diesel(table_name = acquisitions)]
 #[diesel(check_for_backend(diesel::sqlite::Sqlite))]
 pub struct Acquisition {
@@ -16,6 +16,11 @@ pub struct Acquisition {
     pub usd_cents_btc_fair_value: i64,
     pub wallet: String,
     pub tax_undisposed_satoshis: i64,
+    pub external_id: Option<String>,
+    /// The lot this one was split from by `allocate` or `transfer`
+    pub parent_acquisition_id: Option<i32>,
+    pub split_reason: Option<String>,
+    pub import_batch_id: Option<i32>,
 }
 
 #[derive(Insertable, Debug)]
@@ -28,6 +33,11 @@ pub struct NewAcquisition {
     pub usd_cents_btc_fair_value: i64,
     pub wallet: String,
     pub tax_undisposed_satoshis: i64,
+    pub external_id: Option<String>,
+    /// The lot this one was split from by `allocate` or `transfer`
+    pub parent_acquisition_id: Option<i32>,
+    pub split_reason: Option<String>,
+    pub import_batch_id: Option<i32>,
 }
 
 fn default_wallet() -> String {
@@ -45,6 +55,105 @@ pub struct NewRecord {
     pub price: i64,
     #[serde(default = "default_wallet")]
     pub wallet: String,
+    #[serde(default)]
+    pub lot: Option<String>,
+    #[serde(rename = "TxId", default)]
+    pub tx_id: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_fee")]
+    pub fee: Option<Fee>,
+}
+
+/// An exchange fee from the `Fee` column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fee {
+    /// A USD fee in cents, e.g. `$4.99`
+    Usd(i64),
+    /// A fee paid in bitcoin, in satoshis, e.g. `0.00001 BTC`
+    Btc(i64),
+}
+
+/// Parses a `Fee` column value. Amounts ending in `BTC` are bitcoin; anything else,
+/// optionally prefixed with `$` or suffixed with `USD`, is US dollars.
+pub fn parse_fee(s: &str) -> Result<Option<Fee>, String> {
+    let trimmed = s.trim();
+    if trimmed.is_empty() {
+        return Ok(None);
+    }
+
+    let upper = trimmed.to_ascii_uppercase();
+    let (amount, is_btc) = match upper.strip_suffix("BTC") {
+        Some(amount) => (amount.trim().to_string(), true),
+        None => (upper.trim_end_matches("USD").trim().replace("$", "").replace(",", ""), false),
+    };
+    let amount = Decimal::from_str_exact(&amount)
+        .map_err(|e| format!("Invalid Fee format: {}\nError: {}", s, e))?;
+    if amount.is_sign_negative() {
+        return Err(format!("Fee must not be negative, got '{}'", s));
+    }
+
+    let scale = if is_btc { Decimal::from(100_000_000i64) } else { Decimal::from(100) };
+    let units = (amount * scale).round()
+        .to_string().parse::<i64>()
+        .map_err(|e| format!("Error converting Fee '{}': {}", s, e))?;
+    match (units, is_btc) {
+        (0, _) => Ok(None),
+        (units, true) => Ok(Some(Fee::Btc(units))),
+        (units, false) => Ok(Some(Fee::Usd(units))),
+    }
+}
+
+pub fn deserialize_fee<'de, D>(deserializer: D) -> Result<Option<Fee>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let fee_str = String::deserialize(deserializer)?;
+    parse_fee(&fee_str).map_err(de::Error::custom)
+}
+
+/// A lot named on a disposition row for specific identification.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LotDesignation {
+    /// An acquisition by its database id, e.g. `17`
+    Id(i32),
+    /// An acquisition by date, wallet and original amount, e.g. `01/15/2024|coinbase|0.5`
+    Lot {
+        date: NaiveDateTime,
+        wallet: String,
+        satoshis: i64,
+    },
+}
+
+/// Parses a `Lot` column value into its designations. Multiple lots are separated
+/// by `;` and are relieved in the order listed.
+pub fn parse_lot_designation(s: &str) -> Result<Vec<LotDesignation>, String> {
+    let mut designations = Vec::new();
+    for part in s.split(';').map(str::trim).filter(|p| !p.is_empty()) {
+        let fields: Vec<&str> = part.split('|').map(str::trim).collect();
+        match fields.as_slice() {
+            [id] => {
+                let id = id.parse::<i32>()
+                    .map_err(|_| format!("Invalid lot designation '{}': expected an acquisition id or Date|Wallet|BTC", part))?;
+                designations.push(LotDesignation::Id(id));
+            }
+            [date, wallet, btc] => {
+                let date = parse_date_str(date)?;
+                let btc = Decimal::from_str_exact(btc)
+                    .map_err(|e| format!("Invalid lot designation amount '{}': {}", btc, e))?;
+                let satoshis = (btc * Decimal::from(100_000_000i64)).round()
+                    .to_string().parse::<i64>()
+                    .map_err(|e| format!("Error converting BTC to sats: {}", e))?;
+                designations.push(LotDesignation::Lot { date, wallet: wallet.to_string(), satoshis });
+            }
+            _ => {
+                return Err(format!("Invalid lot designation '{}': expected an acquisition id or Date|Wallet|BTC", part));
+            }
+        }
+    }
+
+    if designations.is_empty() {
+        return Err(format!("Invalid lot designation '{}': no lots named", s));
+    }
+    Ok(designations)
 }
 
 pub fn parse_date_str(s: &str) -> Result<NaiveDateTime, String> {
@@ -118,6 +227,10 @@ pub struct Disposition {
     pub usd_cents_btc_basis: i64,
     pub wallet: String,
     pub tax_undisposed_satoshis: i64,
+    pub lot_designation: Option<String>,
+    pub external_id: Option<String>,
+    pub usd_cents_fee: i64,
+    pub import_batch_id: Option<i32>,
 }
 
 #[derive(Queryable, Insertable, Debug)]
@@ -130,6 +243,10 @@ pub struct NewDisposition {
     pub usd_cents_btc_basis: i64,
     pub wallet: String,
     pub tax_undisposed_satoshis: i64,
+    pub lot_designation: Option<String>,
+    pub external_id: Option<String>,
+    pub usd_cents_fee: i64,
+    pub import_batch_id: Option<i32>,
 }
 
 #[derive(Queryable, Selectable, Identifiable, Insertable, PartialEq, Debug, Associations)]
@@ -145,6 +262,25 @@ pub struct AcquisitionDisposition {
     pub basis: i64,
     pub rgl: i64,
     pub term: String,
+    pub fee: i64,
+    /// The rule that placed the lot: a lot method, "specific-id", or "fifo" for a transfer fee
+    pub lot_method: Option<String>,
+    /// The lot's 1-based place in that order among the disposition's candidate lots
+    pub selection_rank: Option<i32>,
+}
+
+/// A tax candidate lot a disposition did not need, with its place in the selection order
+/// and the sats it had available.
+#[derive(Queryable, Selectable, Identifiable, Insertable, Associations, PartialEq, Debug)]
+#[diesel(belongs_to(Disposition))]
+#[diesel(belongs_to(Acquisition))]
+#[diesel(table_name = passed_over_lots)]
+#[diesel(primary_key(disposition_id, acquisition_id))]
+pub struct PassedOverLot {
+    pub disposition_id: i32,
+    pub acquisition_id: i32,
+    pub selection_rank: i32,
+    pub satoshis: i64,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -173,6 +309,7 @@ pub struct TaxRGL {
     pub basis: Decimal,
     pub rgl: Decimal,
     pub term: String,
+    pub fee: Decimal,
 }
 
 #[derive(Debug, Serialize)]
@@ -190,6 +327,26 @@ pub struct GaapRGL {
     pub fmv_disposed: Decimal,
     pub rgl: Decimal,
     pub term: String,
+    pub fee: Decimal,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct LotSelection {
+    pub disposition_date: NaiveDateTime,
+    pub wallet: String,
+    pub rank: Option<i32>,
+    pub acquisition_id: i32,
+    pub acquisition_date: NaiveDateTime,
+    pub disposed_btc: Decimal,
+    pub cost_per_btc: Decimal,
+    pub disposal_fmv_per_btc: Decimal,
+    pub rgl: Decimal,
+    pub term: String,
+    pub reason: String,
+    pub method: String,
+    /// For a lot passed over, the BTC it had available
+    pub available_btc: Option<Decimal>,
 }
 
 #[derive(Debug, Serialize)]
@@ -203,6 +360,69 @@ pub struct Holding {
     pub usd_fair_value: Decimal,
 }
 
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Form8949Row {
+    pub part: String,
+    #[serde(rename = "Box")]
+    pub form_box: char,
+    pub description: String,
+    pub date_acquired: String,
+    pub date_sold: String,
+    pub proceeds: Decimal,
+    pub cost_basis: Decimal,
+    pub adjustment_code: String,
+    pub adjustment: Option<Decimal>,
+    pub gain_loss: Decimal,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ScheduleDLine {
+    pub line: String,
+    pub description: String,
+    pub proceeds: Option<Decimal>,
+    pub cost_basis: Option<Decimal>,
+    pub adjustments: Option<Decimal>,
+    pub gain_loss: Decimal,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct JournalLine {
+    pub entry: i32,
+    pub date: NaiveDate,
+    pub account: String,
+    pub debit: Option<Decimal>,
+    pub credit: Option<Decimal>,
+    pub memo: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct RollforwardLine {
+    pub line: String,
+    pub btc: Option<Decimal>,
+    pub usd: Decimal,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct UnrealizedHolding {
+    pub lot_id: i32,
+    pub wallet: String,
+    pub acquisition_date: NaiveDateTime,
+    pub undisposed_btc: Decimal,
+    pub tax_basis: Decimal,
+    pub gaap_carrying_value: Decimal,
+    pub market_value: Decimal,
+    pub short_term_gain: Decimal,
+    pub long_term_gain: Decimal,
+    pub gaap_unrealized_gain: Decimal,
+    /// Days until a gain on the lot is long-term; 0 once it is
+    pub days_to_long_term: i64,
+}
+
 #[derive(Queryable, Selectable, Debug, Deserialize)]
 #[diesel(table_name = fair_values)]
 #[diesel(check_for_backend(diesel::sqlite::Sqlite))]
@@ -221,6 +441,18 @@ pub struct NewFairValue {
     pub date: NaiveDateTime,
 }
 
+/// Links a lot to a mark to market it was held for.
+#[derive(Queryable, Selectable, Insertable, Debug, Clone)]
+#[diesel(table_name = acquisition_fair_values)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct AcquisitionFairValue {
+    pub acquisition_id: i32,
+    pub fair_value_id: i32,
+    /// The lot's carrying value per BTC before the mark; `None` for marks recorded
+    /// before prior values were kept
+    pub prior_usd_cents_btc_fair_value: Option<i64>,
+}
+
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct FairValueHolding {
@@ -233,3 +465,114 @@ pub struct FairValueHolding {
     pub current_usd_fair_value: Decimal,
     pub fair_value_adjustment: Decimal,
 }
+
+/// A run of `import`, with a SHA-256 of the file and the number of rows it contained.
+#[derive(Queryable, Selectable, Identifiable, Debug)]
+#[diesel(table_name = import_batches)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct ImportBatch {
+    pub id: i32,
+    pub file_name: String,
+    pub file_hash: String,
+    pub imported_at: NaiveDateTime,
+    pub row_count: i32,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = import_batches)]
+pub struct NewImportBatch {
+    pub file_name: String,
+    pub file_hash: String,
+    pub imported_at: NaiveDateTime,
+    pub row_count: i32,
+}
+
+/// A command recorded in the audit log, chained to the entry before it by `previous_hash`.
+#[derive(Queryable, Selectable, Identifiable, Insertable, Debug, Clone)]
+#[diesel(table_name = audit_log)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct AuditEntry {
+    pub id: i32,
+    pub logged_at: NaiveDateTime,
+    pub command: String,
+    /// The command's arguments as JSON
+    pub arguments: String,
+    /// SHA-256 of the input file, for commands that read one
+    pub file_hash: Option<String>,
+    pub input_rows: Option<i32>,
+    pub rows_changed: i64,
+    /// SHA-256 of the ledger tables after the command ran
+    pub ledger_hash: String,
+    pub previous_hash: String,
+    pub entry_hash: String,
+}
+
+/// A closed period. The lock applies until `reopened_at` is set.
+#[derive(Queryable, Selectable, Identifiable, Debug)]
+#[diesel(table_name = period_locks)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct PeriodLock {
+    pub id: i32,
+    pub locked_through: NaiveDateTime,
+    pub closed_at: NaiveDateTime,
+    pub reopened_at: Option<NaiveDateTime>,
+    pub reopen_reason: Option<String>,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = period_locks)]
+pub struct NewPeriodLock {
+    pub locked_through: NaiveDateTime,
+    pub closed_at: NaiveDateTime,
+}
+
+#[derive(Queryable, Selectable, Identifiable, Debug)]
+#[diesel(table_name = transfers)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct Transfer {
+    pub id: i32,
+    pub transfer_date: NaiveDateTime,
+    pub from_wallet: String,
+    pub to_wallet: String,
+    pub satoshis: i64,
+    pub fee_satoshis: i64,
+    pub fee_disposition_id: Option<i32>,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = transfers)]
+pub struct NewTransfer {
+    pub transfer_date: NaiveDateTime,
+    pub from_wallet: String,
+    pub to_wallet: String,
+    pub satoshis: i64,
+    pub fee_satoshis: i64,
+    pub fee_disposition_id: Option<i32>,
+}
+
+/// A lot moved into the destination wallet by a transfer, with the sats it received.
+#[derive(Queryable, Selectable, Identifiable, Insertable, Associations, PartialEq, Debug)]
+#[diesel(belongs_to(Transfer))]
+#[diesel(belongs_to(Acquisition))]
+#[diesel(table_name = transfer_acquisitions)]
+#[diesel(primary_key(transfer_id, acquisition_id))]
+pub struct TransferAcquisition {
+    pub transfer_id: i32,
+    pub acquisition_id: i32,
+    pub satoshis: i64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct TransferMovement {
+    pub transfer_id: i32,
+    pub transfer_date: NaiveDateTime,
+    pub kind: String,
+    pub from_wallet: String,
+    pub to_wallet: String,
+    pub acquisition_id: i32,
+    pub acquisition_date: NaiveDateTime,
+    pub btc: Decimal,
+    pub cost_per_btc: Decimal,
+    pub basis: Decimal,
+}
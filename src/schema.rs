@@ -9,6 +9,9 @@ diesel::table! {
         basis -> BigInt,
         rgl -> BigInt,
         term -> Text,
+        fee -> BigInt,
+        lot_method -> Nullable<Text>,
+        selection_rank -> Nullable<Integer>,
     }
 }
 
@@ -16,6 +19,7 @@ diesel::table! {
     acquisition_fair_values (acquisition_id, fair_value_id) {
         acquisition_id -> Integer,
         fair_value_id -> Integer,
+        prior_usd_cents_btc_fair_value -> Nullable<BigInt>,
     }
 }
 
@@ -29,6 +33,25 @@ diesel::table! {
         usd_cents_btc_fair_value -> BigInt,
         wallet -> Text,
         tax_undisposed_satoshis -> BigInt,
+        external_id -> Nullable<Text>,
+        parent_acquisition_id -> Nullable<Integer>,
+        split_reason -> Nullable<Text>,
+        import_batch_id -> Nullable<Integer>,
+    }
+}
+
+diesel::table! {
+    audit_log (id) {
+        id -> Integer,
+        logged_at -> Timestamp,
+        command -> Text,
+        arguments -> Text,
+        file_hash -> Nullable<Text>,
+        input_rows -> Nullable<Integer>,
+        rows_changed -> BigInt,
+        ledger_hash -> Text,
+        previous_hash -> Text,
+        entry_hash -> Text,
     }
 }
 
@@ -41,6 +64,10 @@ diesel::table! {
         usd_cents_btc_basis -> BigInt,
         wallet -> Text,
         tax_undisposed_satoshis -> BigInt,
+        lot_designation -> Nullable<Text>,
+        external_id -> Nullable<Text>,
+        usd_cents_fee -> BigInt,
+        import_batch_id -> Nullable<Integer>,
     }
 }
 
@@ -52,15 +79,77 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    import_batches (id) {
+        id -> Integer,
+        file_name -> Text,
+        file_hash -> Text,
+        imported_at -> Timestamp,
+        row_count -> Integer,
+    }
+}
+
+diesel::table! {
+    passed_over_lots (disposition_id, acquisition_id) {
+        disposition_id -> Integer,
+        acquisition_id -> Integer,
+        selection_rank -> Integer,
+        satoshis -> BigInt,
+    }
+}
+
+diesel::table! {
+    period_locks (id) {
+        id -> Integer,
+        locked_through -> Timestamp,
+        closed_at -> Timestamp,
+        reopened_at -> Nullable<Timestamp>,
+        reopen_reason -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    transfer_acquisitions (transfer_id, acquisition_id) {
+        transfer_id -> Integer,
+        acquisition_id -> Integer,
+        satoshis -> BigInt,
+    }
+}
+
+diesel::table! {
+    transfers (id) {
+        id -> Integer,
+        transfer_date -> Timestamp,
+        from_wallet -> Text,
+        to_wallet -> Text,
+        satoshis -> BigInt,
+        fee_satoshis -> BigInt,
+        fee_disposition_id -> Nullable<Integer>,
+    }
+}
+
 diesel::joinable!(acquisition_dispositions -> acquisitions (acquisition_id));
 diesel::joinable!(acquisition_dispositions -> dispositions (disposition_id));
 diesel::joinable!(acquisition_fair_values -> acquisitions (acquisition_id));
 diesel::joinable!(acquisition_fair_values -> fair_values (fair_value_id));
+diesel::joinable!(acquisitions -> import_batches (import_batch_id));
+diesel::joinable!(dispositions -> import_batches (import_batch_id));
+diesel::joinable!(passed_over_lots -> acquisitions (acquisition_id));
+diesel::joinable!(passed_over_lots -> dispositions (disposition_id));
+diesel::joinable!(transfer_acquisitions -> acquisitions (acquisition_id));
+diesel::joinable!(transfer_acquisitions -> transfers (transfer_id));
+diesel::joinable!(transfers -> dispositions (fee_disposition_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
     acquisition_dispositions,
     acquisition_fair_values,
     acquisitions,
+    audit_log,
     dispositions,
     fair_values,
+    import_batches,
+    passed_over_lots,
+    period_locks,
+    transfer_acquisitions,
+    transfers,
 );
@@ -28,6 +28,14 @@ pub fn universal_config() -> rust_rgl_ledger::LotConfig {
     }
 }
 
+pub fn lot_config(tax_lot_method: &str, tax_lot_scope: &str) -> rust_rgl_ledger::LotConfig {
+    rust_rgl_ledger::LotConfig {
+        tax_lot_method: tax_lot_method.to_string(),
+        tax_lot_scope: tax_lot_scope.to_string(),
+        gaap_lot_method: "fifo".to_string(),
+    }
+}
+
 pub fn create_test_csv(records: &[(&str, &str, &str)]) -> NamedTempFile {
     let mut file = tempfile::Builder::new()
         .suffix(".csv")
@@ -54,6 +62,45 @@ pub fn create_test_csv_with_wallet(records: &[(&str, &str, &str, &str)]) -> Name
     file
 }
 
+pub fn create_test_csv_with_lot(records: &[(&str, &str, &str, &str, &str)]) -> NamedTempFile {
+    let mut file = tempfile::Builder::new()
+        .suffix(".csv")
+        .tempfile()
+        .expect("Failed to create temp CSV file");
+    writeln!(file, "Date,Bitcoin,Price,Wallet,Lot").unwrap();
+    for (date, bitcoin, price, wallet, lot) in records {
+        writeln!(file, "{},\"{}\",\"{}\",{},{}", date, bitcoin, price, wallet, lot).unwrap();
+    }
+    file.flush().unwrap();
+    file
+}
+
+pub fn create_test_csv_with_tx_id(records: &[(&str, &str, &str, &str)]) -> NamedTempFile {
+    let mut file = tempfile::Builder::new()
+        .suffix(".csv")
+        .tempfile()
+        .expect("Failed to create temp CSV file");
+    writeln!(file, "Date,Bitcoin,Price,TxId").unwrap();
+    for (date, bitcoin, price, tx_id) in records {
+        writeln!(file, "{},\"{}\",\"{}\",{}", date, bitcoin, price, tx_id).unwrap();
+    }
+    file.flush().unwrap();
+    file
+}
+
+pub fn create_test_csv_with_fee(records: &[(&str, &str, &str, &str)]) -> NamedTempFile {
+    let mut file = tempfile::Builder::new()
+        .suffix(".csv")
+        .tempfile()
+        .expect("Failed to create temp CSV file");
+    writeln!(file, "Date,Bitcoin,Price,Fee").unwrap();
+    for (date, bitcoin, price, fee) in records {
+        writeln!(file, "{},\"{}\",\"{}\",\"{}\"", date, bitcoin, price, fee).unwrap();
+    }
+    file.flush().unwrap();
+    file
+}
+
 pub fn create_bucket_csv(records: &[(&str, &str)]) -> NamedTempFile {
     let mut file = tempfile::Builder::new()
         .suffix(".csv")
@@ -80,6 +127,19 @@ pub fn create_transfer_csv(records: &[(&str, &str, &str, &str)]) -> NamedTempFil
     file
 }
 
+pub fn create_transfer_csv_with_fee(records: &[(&str, &str, &str, &str, &str, &str)]) -> NamedTempFile {
+    let mut file = tempfile::Builder::new()
+        .suffix(".csv")
+        .tempfile()
+        .expect("Failed to create temp CSV file");
+    writeln!(file, "Date,From,To,BTC,Fee,Price").unwrap();
+    for (date, from, to, btc, fee, price) in records {
+        writeln!(file, "{},{},{},{},{},\"{}\"", date, from, to, btc, fee, price).unwrap();
+    }
+    file.flush().unwrap();
+    file
+}
+
 use rust_rgl_ledger::schema::{acquisitions, dispositions, acquisition_dispositions, fair_values, acquisition_fair_values};
 use rust_rgl_ledger::models::{Acquisition, Disposition, AcquisitionDisposition};
 
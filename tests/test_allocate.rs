@@ -21,7 +21,7 @@ fn test_allocate_simple() {
         ("coinbase", "1.00000000"),
         ("ledger", "0.50000000"),
     ]);
-    allocate(&bucket_csv.path().to_path_buf(), &mut conn).unwrap();
+    allocate(&bucket_csv.path().to_path_buf(), &mut conn, false).unwrap();
 
     let acqs = get_acquisitions(&mut conn);
     assert_eq!(acqs[0].wallet, "coinbase");
@@ -44,7 +44,7 @@ fn test_allocate_with_split() {
         ("coinbase", "1.50000000"),
         ("ledger", "0.50000000"),
     ]);
-    allocate(&bucket_csv.path().to_path_buf(), &mut conn).unwrap();
+    allocate(&bucket_csv.path().to_path_buf(), &mut conn, false).unwrap();
 
     let acqs = get_acquisitions(&mut conn);
     assert_eq!(acqs.len(), 2, "Original lot should be split into 2");
@@ -81,7 +81,7 @@ fn test_allocate_partial_lot() {
         ("coinbase", "1.00000000"),
         ("ledger", "0.50000000"),
     ]);
-    allocate(&bucket_csv.path().to_path_buf(), &mut conn).unwrap();
+    allocate(&bucket_csv.path().to_path_buf(), &mut conn, false).unwrap();
 
     let acqs = get_acquisitions(&mut conn);
     // The lot should be split: original has 1.0 BTC undisposed (coinbase), new has 0.5 BTC (ledger)
@@ -108,7 +108,7 @@ fn test_allocate_sets_disposition_wallet_to_legacy() {
     let bucket_csv = create_bucket_csv(&[
         ("coinbase", "1.50000000"),
     ]);
-    allocate(&bucket_csv.path().to_path_buf(), &mut conn).unwrap();
+    allocate(&bucket_csv.path().to_path_buf(), &mut conn, false).unwrap();
 
     let disps = get_dispositions(&mut conn);
     for d in &disps {
@@ -130,6 +130,6 @@ fn test_allocate_sum_mismatch_errors() {
     let bucket_csv = create_bucket_csv(&[
         ("coinbase", "2.00000000"),
     ]);
-    let result = allocate(&bucket_csv.path().to_path_buf(), &mut conn);
+    let result = allocate(&bucket_csv.path().to_path_buf(), &mut conn, false);
     assert!(result.is_err(), "Should error when bucket total exceeds undisposed total");
 }
@@ -0,0 +1,160 @@
+mod common;
+
+use common::{setup_test_db, create_test_csv, create_test_csv_with_wallet, create_transfer_csv, default_config};
+use diesel::prelude::*;
+use rust_rgl_ledger::commands::audit::{file_sha256, latest_entry, verify_audit};
+use rust_rgl_ledger::commands::import::{import_transactions, import_transactions_with_options, ImportOptions};
+use rust_rgl_ledger::commands::period::close_period;
+use rust_rgl_ledger::commands::transfer::transfer;
+use rust_rgl_ledger::models::AuditEntry;
+use rust_rgl_ledger::schema::{acquisitions, audit_log, import_batches, period_locks, transfer_acquisitions};
+
+fn load_entries(conn: &mut SqliteConnection) -> Vec<AuditEntry> {
+    audit_log::table.order(audit_log::id.asc()).select(AuditEntry::as_select()).load(conn).unwrap()
+}
+
+fn setup_logged_ledger(conn: &mut SqliteConnection) {
+    let config = default_config();
+    let csv = create_test_csv_with_wallet(&[
+        ("2024-01-01", "1.00000000", "$40,000.00", "cold-storage"),
+        ("2024-02-01", "1.00000000", "$45,000.00", "cold-storage"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), conn, &config).unwrap();
+    let transfer_csv = create_transfer_csv(&[("2024-03-01", "cold-storage", "exchange", "0.50000000")]);
+    transfer(&transfer_csv.path().to_path_buf(), conn, &config, false).unwrap();
+    close_period("2024-03-31", conn).unwrap();
+}
+
+#[test]
+fn test_mutating_commands_are_chained() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv(&[
+        ("01/01/2024", "1.00000000", "$40,000.00"),
+        ("02/01/2024", "-0.40000000", "$50,000.00"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+    close_period("03/31/2024", &mut conn).unwrap();
+
+    let entries = load_entries(&mut conn);
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].command, "import");
+    assert!(entries[0].arguments.contains(r#""skip_existing":false"#), "got: {}", entries[0].arguments);
+    assert_eq!(entries[0].file_hash, Some(file_sha256(&std::fs::read(csv.path()).unwrap())));
+    assert_eq!(entries[0].input_rows, Some(2));
+    // One lot, one disposition, and a tax and a GAAP match
+    assert_eq!(entries[0].rows_changed, 4);
+    assert_eq!(entries[0].previous_hash, "0".repeat(64));
+
+    assert_eq!(entries[1].command, "close-period");
+    assert_eq!(entries[1].file_hash, None);
+    assert_eq!(entries[1].previous_hash, entries[0].entry_hash);
+    assert_ne!(entries[1].ledger_hash, entries[0].ledger_hash);
+
+    let verification = verify_audit(&mut conn).unwrap();
+    assert_eq!(verification.entries, 2);
+    assert!(verification.breaks.is_empty(), "got: {:?}", verification.breaks);
+}
+
+#[test]
+fn test_dry_runs_and_failures_are_not_logged() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv(&[("01/01/2024", "1.00000000", "$40,000.00")]);
+    let options = ImportOptions { dry_run: true, ..ImportOptions::default() };
+    import_transactions_with_options(&csv.path().to_path_buf(), &mut conn, &config, &options).unwrap();
+
+    let bad = create_test_csv(&[("01/01/2024", "-1.00000000", "$40,000.00")]);
+    assert!(import_transactions(&bad.path().to_path_buf(), &mut conn, &config).is_err());
+
+    assert!(load_entries(&mut conn).is_empty());
+}
+
+#[test]
+fn test_verify_detects_ledger_changes_outside_the_tool() {
+    let mut conn = setup_test_db();
+    setup_logged_ledger(&mut conn);
+    assert_eq!(load_entries(&mut conn).len(), 3);
+    assert!(verify_audit(&mut conn).unwrap().breaks.is_empty());
+
+    diesel::update(acquisitions::table.find(1))
+        .set(acquisitions::usd_cents_btc_basis.eq(1))
+        .execute(&mut conn)
+        .unwrap();
+
+    let breaks = verify_audit(&mut conn).unwrap().breaks;
+    assert_eq!(breaks, vec!["The ledger has changed since entry 3 (close-period) without an audit log entry".to_string()]);
+}
+
+#[test]
+fn test_verify_detects_altered_and_deleted_entries() {
+    let mut conn = setup_test_db();
+    setup_logged_ledger(&mut conn);
+
+    // The table refuses changes made through SQLite
+    assert!(diesel::update(audit_log::table.find(2)).set(audit_log::rows_changed.eq(0)).execute(&mut conn).is_err());
+    assert!(diesel::delete(audit_log::table.find(1)).execute(&mut conn).is_err());
+
+    diesel::sql_query("DROP TRIGGER audit_log_no_update").execute(&mut conn).unwrap();
+    diesel::sql_query("DROP TRIGGER audit_log_no_delete").execute(&mut conn).unwrap();
+
+    diesel::update(audit_log::table.find(2)).set(audit_log::rows_changed.eq(0)).execute(&mut conn).unwrap();
+    let breaks = verify_audit(&mut conn).unwrap().breaks;
+    assert_eq!(breaks, vec!["Entry 2 (transfer) has been altered since it was logged".to_string()]);
+
+    diesel::delete(audit_log::table.find(1)).execute(&mut conn).unwrap();
+    let breaks = verify_audit(&mut conn).unwrap().breaks;
+    assert_eq!(breaks, vec![
+        "Entry 1 is missing".to_string(),
+        "Entry 2 (transfer) does not follow the entry before it".to_string(),
+        "Entry 2 (transfer) has been altered since it was logged".to_string(),
+    ]);
+}
+
+#[test]
+fn test_verify_detects_changes_to_links_and_batches() {
+    let tamper: [fn(&mut SqliteConnection); 4] = [
+        |conn| {
+            diesel::update(transfer_acquisitions::table)
+                .set(transfer_acquisitions::satoshis.eq(1))
+                .execute(conn)
+                .unwrap();
+        },
+        |conn| {
+            diesel::update(import_batches::table)
+                .set(import_batches::file_hash.eq("0"))
+                .execute(conn)
+                .unwrap();
+        },
+        |conn| {
+            diesel::update(acquisitions::table)
+                .set(acquisitions::import_batch_id.eq(None::<i32>))
+                .execute(conn)
+                .unwrap();
+        },
+        |conn| {
+            let closed_at = chrono::NaiveDate::from_ymd_opt(2024, 4, 1).unwrap().and_hms_opt(0, 0, 0).unwrap();
+            diesel::update(period_locks::table)
+                .set(period_locks::closed_at.eq(closed_at))
+                .execute(conn)
+                .unwrap();
+        },
+    ];
+    for change in tamper {
+        let mut conn = setup_test_db();
+        setup_logged_ledger(&mut conn);
+        change(&mut conn);
+        assert_eq!(verify_audit(&mut conn).unwrap().breaks.len(), 1);
+    }
+}
+
+#[test]
+fn test_latest_entry_is_the_chain_head() {
+    let mut conn = setup_test_db();
+    assert!(latest_entry(&mut conn).unwrap().is_none());
+    setup_logged_ledger(&mut conn);
+
+    let head = latest_entry(&mut conn).unwrap().unwrap();
+    assert_eq!((head.id, head.command.as_str()), (3, "close-period"));
+    assert_eq!(head.entry_hash, load_entries(&mut conn).last().unwrap().entry_hash);
+}
@@ -0,0 +1,252 @@
+mod common;
+
+use common::{setup_test_db, create_test_csv, get_acquisitions, get_tax_acq_disps, get_gaap_acq_disps};
+use rust_rgl_ledger::commands::import::import_transactions;
+use rust_rgl_ledger::commands::holdings::holdings;
+use rust_rgl_ledger::commands::mark_to_market::mark_to_market;
+use rust_rgl_ledger::commands::report::report;
+use rust_rgl_ledger::LotConfig;
+use rust_decimal::Decimal;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+static CWD_LOCK: Mutex<()> = Mutex::new(());
+
+fn average_config() -> LotConfig {
+    LotConfig {
+        gaap_lot_method: "average".to_string(),
+        ..LotConfig::default()
+    }
+}
+
+/// Runs `f` inside a temp dir containing a `reports` directory and returns the
+/// contents of the first report whose file name starts with `prefix`.
+fn run_in_reports_dir<F: FnOnce()>(prefix: &str, f: F) -> String {
+    let tmp = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir_all(tmp.path().join("reports")).unwrap();
+
+    let original_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(tmp.path()).unwrap();
+    f();
+    let content = std::fs::read_dir("./reports")
+        .unwrap()
+        .filter_map(|e| e.ok())
+        .find(|e| e.file_name().to_string_lossy().starts_with(prefix))
+        .map(|e| std::fs::read_to_string(e.path()).unwrap())
+        .unwrap_or_default();
+    std::env::set_current_dir(original_dir).unwrap();
+    content
+}
+
+#[test]
+fn test_average_cost_basis_uses_pool_average() {
+    let mut conn = setup_test_db();
+    let csv = create_test_csv(&[
+        ("01/01/2025", "1.00000000", "$30,000.00"),
+        ("02/01/2025", "1.00000000", "$50,000.00"),
+        ("06/01/2025", "-0.50000000", "$60,000.00"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &average_config()).unwrap();
+
+    let gaap_ads = get_gaap_acq_disps(&mut conn);
+    assert_eq!(gaap_ads.len(), 1);
+    assert_eq!(gaap_ads[0].acquisition_id, 1, "Relieved lot is still recorded in FIFO order");
+    assert_eq!(gaap_ads[0].basis, 2_000_000, "0.5 BTC at the $40k pool average");
+    assert_eq!(gaap_ads[0].rgl, 1_000_000);
+
+    // Tax stays on cost-basis FIFO
+    let tax_ads = get_tax_acq_disps(&mut conn);
+    assert_eq!(tax_ads[0].basis, 1_500_000);
+
+    let acqs = get_acquisitions(&mut conn);
+    assert_eq!(acqs[0].usd_cents_btc_fair_value, 4_000_000, "Pool lots carry the average value");
+    assert_eq!(acqs[1].usd_cents_btc_fair_value, 4_000_000, "Pool lots carry the average value");
+    assert_eq!(acqs[0].usd_cents_btc_basis, 3_000_000, "Cost basis is untouched");
+}
+
+#[test]
+fn test_average_cost_moving_average() {
+    // Lots acquired after a disposition only join the pool for later dispositions
+    let mut conn = setup_test_db();
+    let csv = create_test_csv(&[
+        ("01/01/2025", "1.00000000", "$30,000.00"),
+        ("02/01/2025", "1.00000000", "$50,000.00"),
+        ("03/01/2025", "-1.00000000", "$60,000.00"),
+        ("04/01/2025", "1.00000000", "$70,000.00"),
+        ("05/01/2025", "-1.00000000", "$80,000.00"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &average_config()).unwrap();
+
+    let gaap_ads = get_gaap_acq_disps(&mut conn);
+    let basis_for = |disp_id: i32| -> i64 {
+        gaap_ads.iter().filter(|ad| ad.disposition_id == disp_id).map(|ad| ad.basis).sum()
+    };
+    assert_eq!(basis_for(1), 4_000_000, "First sale at the $40k average of the first two lots");
+    assert_eq!(basis_for(2), 5_500_000, "Second sale at the $55k average of $40k and $70k");
+
+    // Remaining 1 BTC carries the rest of the $150k total cost
+    let acqs = get_acquisitions(&mut conn);
+    let carrying: i64 = acqs.iter()
+        .map(|a| a.undisposed_satoshis * a.usd_cents_btc_fair_value / 100_000_000)
+        .sum();
+    assert_eq!(carrying + basis_for(1) + basis_for(2), 15_000_000);
+}
+
+#[test]
+fn test_average_cost_after_mark_to_market() {
+    let _lock = CWD_LOCK.lock().unwrap();
+    let mut conn = setup_test_db();
+    let config = average_config();
+    let csv = create_test_csv(&[
+        ("01/01/2025", "1.00000000", "$30,000.00"),
+        ("02/01/2025", "1.00000000", "$50,000.00"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    run_in_reports_dir("mark-to-market-", || {
+        mark_to_market(&"$45,000.00".to_string(), &"03/31/2025".to_string(), &mut conn, &average_config(), false).unwrap();
+    });
+
+    let csv2 = create_test_csv(&[
+        ("04/01/2025", "1.00000000", "$60,000.00"),
+        ("05/01/2025", "-1.00000000", "$65,000.00"),
+    ]);
+    import_transactions(&csv2.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    let gaap_ads = get_gaap_acq_disps(&mut conn);
+    let basis: i64 = gaap_ads.iter().map(|ad| ad.basis).sum();
+    assert_eq!(basis, 5_000_000, "Pool of 2 BTC at $45k and 1 BTC at $60k averages $50k");
+}
+
+#[test]
+fn test_average_cost_reports_use_pooled_value() {
+    let _lock = CWD_LOCK.lock().unwrap();
+    let mut conn = setup_test_db();
+    let csv = create_test_csv(&[
+        ("01/01/2025", "1.00000000", "$30,000.00"),
+        ("02/01/2025", "1.00000000", "$50,000.00"),
+        ("06/01/2025", "-0.50000000", "$60,000.00"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &average_config()).unwrap();
+
+    // GAAP report: GaapPerBtc(5) reflects the pooled carrying value
+    let gaap = run_in_reports_dir("rgl_gaap_", || {
+        report(&"01/01/2025".to_string(), &"12/31/2025".to_string(), "gaap", &mut conn).unwrap();
+    });
+    let mut rdr = csv::Reader::from_reader(gaap.as_bytes());
+    let detail: Vec<csv::StringRecord> = rdr.records()
+        .filter_map(|r| r.ok())
+        .filter(|r| r.get(0).is_some_and(|s| !s.is_empty()))
+        .collect();
+    assert_eq!(detail.len(), 1);
+    assert_eq!(Decimal::from_str(&detail[0][5]).unwrap(), Decimal::from(40_000));
+    assert_eq!(Decimal::from_str(&detail[0][8]).unwrap(), Decimal::from(20_000));
+
+    // Holdings: 1.5 BTC remaining at the $40k average = $60k carrying value
+    let content = run_in_reports_dir("holdings_", || {
+        holdings(&"12/31/2025".to_string(), "gaap", &mut conn, &average_config()).unwrap();
+    });
+    let mut rdr = csv::Reader::from_reader(content.as_bytes());
+    let rows: Vec<csv::StringRecord> = rdr.records().filter_map(|r| r.ok()).collect();
+    let totals = rows.last().unwrap();
+    assert_eq!(Decimal::from_str(&totals[5]).unwrap(), Decimal::from(60_000));
+}
+
+#[test]
+fn test_average_cost_historical_holdings_replay_pool() {
+    let _lock = CWD_LOCK.lock().unwrap();
+    let mut conn = setup_test_db();
+    let csv = create_test_csv(&[
+        ("01/01/2025", "1.00000000", "$30,000.00"),
+        ("02/01/2025", "1.00000000", "$50,000.00"),
+        ("06/01/2025", "-0.50000000", "$60,000.00"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &average_config()).unwrap();
+    run_in_reports_dir("mark-to-market-", || {
+        mark_to_market(&"$70,000.00".to_string(), &"09/30/2025".to_string(), &mut conn, &average_config(), false).unwrap();
+    });
+
+    let total_fair_value = |date: &str, conn: &mut diesel::SqliteConnection| {
+        let content = run_in_reports_dir("holdings_", || {
+            holdings(&date.to_string(), "gaap", conn, &average_config()).unwrap();
+        });
+        let mut rdr = csv::Reader::from_reader(content.as_bytes());
+        let rows: Vec<csv::StringRecord> = rdr.records().filter_map(|r| r.ok()).collect();
+        Decimal::from_str(&rows.last().unwrap()[5]).unwrap()
+    };
+
+    // Before the sale each lot is at cost, after it the 1.5 BTC left are at the $40k average
+    assert_eq!(total_fair_value("03/01/2025", &mut conn), Decimal::from(80_000));
+    assert_eq!(total_fair_value("07/01/2025", &mut conn), Decimal::from(60_000));
+    assert_eq!(total_fair_value("12/31/2025", &mut conn), Decimal::from(105_000));
+}
+
+#[test]
+fn test_average_cost_backdated_mark_replays_pool() {
+    let _lock = CWD_LOCK.lock().unwrap();
+    let mut conn = setup_test_db();
+    let csv = create_test_csv(&[
+        ("01/01/2025", "1.00000000", "$30,000.00"),
+        ("02/01/2025", "1.00000000", "$50,000.00"),
+        ("06/01/2025", "-0.50000000", "$60,000.00"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &average_config()).unwrap();
+    assert_eq!(get_gaap_acq_disps(&mut conn)[0].basis, 2_000_000);
+
+    run_in_reports_dir("mark-to-market-", || {
+        mark_to_market(&"$45,000.00".to_string(), &"03/31/2025".to_string(), &mut conn, &average_config(), false).unwrap();
+    });
+
+    // Both lots were marked to $45k before the sale, so the pool relieves at $45k
+    assert_eq!(get_gaap_acq_disps(&mut conn)[0].basis, 2_250_000);
+    assert!(get_acquisitions(&mut conn).iter().all(|l| l.usd_cents_btc_fair_value == 4_500_000));
+}
+
+#[test]
+fn test_average_cost_mark_prior_value_includes_relieved_lots() {
+    let _lock = CWD_LOCK.lock().unwrap();
+    let mut conn = setup_test_db();
+    let csv = create_test_csv(&[
+        ("01/01/2025", "1.00000000", "$30,000.00"),
+        ("02/01/2025", "1.00000000", "$50,000.00"),
+        ("06/01/2025", "-1.00000000", "$60,000.00"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &average_config()).unwrap();
+
+    let content = run_in_reports_dir("mark-to-market-", || {
+        mark_to_market(&"$70,000.00".to_string(), &"09/30/2025".to_string(), &mut conn, &average_config(), false).unwrap();
+    });
+    let mut rdr = csv::Reader::from_reader(content.as_bytes());
+    let rows: Vec<csv::StringRecord> = rdr.records().filter_map(|r| r.ok()).collect();
+
+    // The BTC left after the sale was restated to the $40k average, not its $50k cost
+    assert_eq!(Decimal::from_str(&rows.last().unwrap()[5]).unwrap(), Decimal::from(40_000));
+}
+
+#[test]
+fn test_average_cost_backdated_sale_before_later_mark_replays_pool() {
+    let _lock = CWD_LOCK.lock().unwrap();
+    let mut conn = setup_test_db();
+    let config = average_config();
+    let csv = create_test_csv(&[
+        ("01/01/2025", "1.00000000", "$30,000.00"),
+        ("02/01/2025", "1.00000000", "$50,000.00"),
+        ("03/01/2025", "-0.50000000", "$60,000.00"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    run_in_reports_dir("mark-to-market-", || {
+        mark_to_market(&"$100,000.00".to_string(), &"06/30/2025".to_string(), &mut conn, &average_config(), false).unwrap();
+    });
+
+    // No GAAP match is dated after the May sale, but the June mark already restated the pool
+    let csv2 = create_test_csv(&[("05/01/2025", "-0.50000000", "$70,000.00")]);
+    import_transactions(&csv2.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    let gaap_ads = get_gaap_acq_disps(&mut conn);
+    let may_sale: i64 = gaap_ads.iter().filter(|ad| ad.disposition_id == 2).map(|ad| ad.basis).sum();
+    assert_eq!(may_sale, 2_000_000, "0.5 BTC at the $40k pool average before the June mark");
+    assert!(get_acquisitions(&mut conn).iter()
+        .filter(|l| l.undisposed_satoshis > 0)
+        .all(|l| l.usd_cents_btc_fair_value == 10_000_000));
+}
@@ -0,0 +1,101 @@
+mod common;
+
+use common::{
+    setup_test_db, create_test_csv_with_fee, create_test_csv_with_wallet, create_transfer_csv, default_config,
+};
+use diesel::prelude::*;
+use rust_rgl_ledger::LotConfig;
+use rust_rgl_ledger::commands::check::check_ledger;
+use rust_rgl_ledger::commands::import::import_transactions;
+use rust_rgl_ledger::commands::mark_to_market::mark_to_market;
+use rust_rgl_ledger::commands::transfer::transfer;
+use rust_rgl_ledger::schema::{acquisitions, acquisition_dispositions, dispositions};
+use std::sync::Mutex;
+
+static CWD_LOCK: Mutex<()> = Mutex::new(());
+
+fn run_mtm_in_dir(price: &str, date: &str, conn: &mut SqliteConnection) {
+    let _lock = CWD_LOCK.lock().unwrap();
+    let tmp = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir_all(tmp.path().join("reports")).unwrap();
+
+    let original_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(tmp.path()).unwrap();
+    mark_to_market(&price.to_string(), &date.to_string(), conn, &default_config(), false).unwrap();
+    std::env::set_current_dir(original_dir).unwrap();
+}
+
+fn setup_transferred_ledger(conn: &mut SqliteConnection) {
+    let config = default_config();
+    let csv = create_test_csv_with_wallet(&[
+        ("2024-01-01", "1.00000000", "$40,000.00", "cold-storage"),
+        ("2024-02-01", "1.00000000", "$45,000.00", "cold-storage"),
+        ("2024-03-01", "-0.40000000", "$50,000.00", "cold-storage"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), conn, &config).unwrap();
+    // Splits the partly sold first lot, leaving its match on the lot that stays behind
+    let transfer_csv = create_transfer_csv(&[("2024-04-01", "cold-storage", "exchange", "0.30000000")]);
+    transfer(&transfer_csv.path().to_path_buf(), conn, &config, false).unwrap();
+    run_mtm_in_dir("$60,000.00", "2024-06-30", conn);
+    let sale = create_test_csv_with_wallet(&[("2025-03-01", "-0.30000000", "$70,000.00", "exchange")]);
+    import_transactions(&sale.path().to_path_buf(), conn, &config).unwrap();
+}
+
+#[test]
+fn test_check_passes_on_transferred_and_marked_ledger() {
+    let mut conn = setup_test_db();
+    setup_transferred_ledger(&mut conn);
+
+    let report = check_ledger(&mut conn, &default_config()).unwrap();
+    assert_eq!((report.lots, report.dispositions, report.matches), (3, 2, 4));
+    assert!(report.violations.is_empty(), "got: {:?}", report.violations);
+}
+
+#[test]
+fn test_check_passes_with_fees_and_average_cost() {
+    let mut conn = setup_test_db();
+    let config = LotConfig { gaap_lot_method: "average".to_string(), ..default_config() };
+    let csv = create_test_csv_with_fee(&[
+        ("01/01/2024", "0.30000000", "$40,000.00", "$10.00"),
+        ("01/02/2024", "0.70000000", "$41,000.00", ""),
+        ("06/01/2024", "-0.50000000", "$50,000.00", "$10.01"),
+        ("07/01/2024", "-0.20000000", "$55,000.00", "0.0001 BTC"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    let report = check_ledger(&mut conn, &config).unwrap();
+    assert!(report.violations.is_empty(), "got: {:?}", report.violations);
+}
+
+#[test]
+fn test_check_reports_every_violation() {
+    let mut conn = setup_test_db();
+    setup_transferred_ledger(&mut conn);
+
+    diesel::update(acquisitions::table.find(2))
+        .set(acquisitions::tax_undisposed_satoshis.eq(90_000_000))
+        .execute(&mut conn)
+        .unwrap();
+    diesel::update(dispositions::table.find(1))
+        .set(dispositions::undisposed_satoshis.eq(-1))
+        .execute(&mut conn)
+        .unwrap();
+    diesel::update(acquisition_dispositions::table.find((1, 1, "tax")))
+        .set((acquisition_dispositions::basis.eq(1_500_000), acquisition_dispositions::term.eq("long")))
+        .execute(&mut conn)
+        .unwrap();
+    diesel::update(acquisition_dispositions::table.find((1, 2, "gaap")))
+        .set(acquisition_dispositions::rgl.eq(0))
+        .execute(&mut conn)
+        .unwrap();
+
+    let report = check_ledger(&mut conn, &default_config()).unwrap();
+    assert_eq!(report.violations, vec![
+        "Lot 2: satoshis less tax_undisposed_satoshis is 10000000, but its tax matches relieve 0 sats",
+        "Disposition 1 has 1 undisposed sats for GAAP matching, but its matches leave 0 unmatched",
+        "Tax match of lot 1 to disposition 1: term is 'long', but the lot was held 60 days, which is 'short'",
+        "Tax match of lot 1 to disposition 1: basis is $15000.00, but 40000000 sats at $40000.00 per BTC is $16000.00",
+        "Tax match of lot 1 to disposition 1: RGL is $4000.00, but proceeds of $20000.00 less fee of $0.00 and basis of $15000.00 is $5000.00",
+        "GAAP match of lot 1 to disposition 2: RGL is $0.00, but proceeds of $21000.00 less fee of $0.00 and basis of $18000.00 is $3000.00",
+    ]);
+}
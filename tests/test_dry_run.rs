@@ -0,0 +1,126 @@
+mod common;
+
+use common::{
+    setup_test_db, create_test_csv, create_test_csv_with_wallet, create_bucket_csv, create_transfer_csv,
+    default_config, get_acquisitions, get_dispositions, get_acq_disps,
+};
+use rust_rgl_ledger::commands::import::{import_transactions, import_transactions_with_options, ImportOptions};
+use rust_rgl_ledger::commands::allocate::allocate;
+use rust_rgl_ledger::commands::transfer::transfer;
+
+fn dry_run() -> ImportOptions {
+    ImportOptions { dry_run: true, ..ImportOptions::default() }
+}
+
+#[test]
+fn test_import_dry_run_saves_nothing() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv(&[
+        ("01/01/2024", "1.00000000", "$40,000.00"),
+        ("02/01/2024", "-0.40000000", "$50,000.00"),
+    ]);
+
+    let summary = import_transactions_with_options(&csv.path().to_path_buf(), &mut conn, &config, &dry_run()).unwrap();
+    assert_eq!(summary.inserted_acquisitions, 1);
+    assert_eq!(summary.inserted_dispositions, 1);
+    assert_eq!(summary.changes.lots_created.len(), 1);
+    assert_eq!(summary.changes.dispositions_created.len(), 1);
+    assert_eq!(summary.changes.matches_created.len(), 2, "One tax and one GAAP match");
+
+    let tax = summary.changes.matches_created.iter().find(|m| m.match_type == "tax").unwrap();
+    assert_eq!(tax.satoshis, 40_000_000);
+    assert_eq!(tax.basis, 1_600_000);
+    assert_eq!(tax.rgl, 400_000);
+
+    assert!(get_acquisitions(&mut conn).is_empty());
+    assert!(get_dispositions(&mut conn).is_empty());
+    assert!(get_acq_disps(&mut conn).is_empty());
+}
+
+#[test]
+fn test_import_dry_run_reports_rematch() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv(&[
+        ("03/01/2024", "1.00000000", "$40,000.00"),
+        ("04/01/2024", "-0.50000000", "$50,000.00"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    // An earlier, cheaper lot takes over the FIFO match
+    let backdated = create_test_csv(&[("01/01/2024", "1.00000000", "$30,000.00")]);
+    let summary = import_transactions_with_options(&backdated.path().to_path_buf(), &mut conn, &config, &dry_run()).unwrap();
+    assert_eq!(summary.changes.matches_removed.len(), 2);
+    assert_eq!(summary.changes.matches_created.len(), 2);
+    assert!(summary.changes.matches_created.iter().all(|m| m.rgl == 1_000_000));
+
+    let output = summary.changes.to_string();
+    assert!(output.contains("Matches removed: 2"), "got: {}", output);
+    assert!(output.contains("rgl $10000.00"), "got: {}", output);
+
+    assert_eq!(get_acquisitions(&mut conn).len(), 1);
+    assert!(get_acq_disps(&mut conn).iter().all(|m| m.rgl == 500_000), "Existing matches are untouched");
+}
+
+#[test]
+fn test_import_dry_run_reports_errors() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv(&[("01/01/2024", "-1.00000000", "$40,000.00")]);
+
+    let err = import_transactions_with_options(&csv.path().to_path_buf(), &mut conn, &config, &dry_run()).unwrap_err();
+    assert!(err.contains("No undisposed acquisition lots available"), "got: {}", err);
+    assert!(get_dispositions(&mut conn).is_empty());
+}
+
+#[test]
+fn test_transfer_dry_run_reports_split() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv_with_wallet(&[
+        ("2024-01-01", "1.00000000", "$40,000.00", "cold-storage"),
+        ("2024-02-01", "2.00000000", "$45,000.00", "cold-storage"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    let transfer_csv = create_transfer_csv(&[("2024-06-15", "cold-storage", "exchange", "1.50000000")]);
+    let changes = transfer(&transfer_csv.path().to_path_buf(), &mut conn, &config, true).unwrap();
+
+    assert_eq!(changes.wallet_reassignments, vec![(1, "cold-storage".to_string(), "exchange".to_string())]);
+    assert_eq!(changes.lots_split.len(), 1);
+    assert_eq!(changes.lots_split[0].0.satoshis, 200_000_000);
+    assert_eq!(changes.lots_split[0].1.satoshis, 150_000_000);
+    assert_eq!(changes.lots_created.len(), 1);
+    assert_eq!(changes.lots_created[0].wallet, "exchange");
+    assert_eq!(changes.lots_created[0].satoshis, 50_000_000);
+
+    let acqs = get_acquisitions(&mut conn);
+    assert_eq!(acqs.len(), 2);
+    assert!(acqs.iter().all(|a| a.wallet == "cold-storage"));
+    assert_eq!(acqs[1].satoshis, 200_000_000);
+}
+
+#[test]
+fn test_allocate_dry_run_saves_nothing() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv(&[
+        ("01/01/2024", "1.00000000", "$40,000.00"),
+        ("02/01/2024", "-0.25000000", "$45,000.00"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    let bucket_csv = create_bucket_csv(&[("coinbase", "0.50000000"), ("ledger", "0.25000000")]);
+    let changes = allocate(&bucket_csv.path().to_path_buf(), &mut conn, true).unwrap();
+    assert_eq!(changes.lots_split.len(), 1);
+    assert_eq!(changes.lots_created.len(), 1);
+    assert_eq!(changes.lots_created[0].wallet, "ledger");
+    assert_eq!(changes.disposition_reassignments, vec![(1, "default".to_string(), "legacy".to_string())]);
+    assert!(changes.matches_created.is_empty());
+
+    let acqs = get_acquisitions(&mut conn);
+    assert_eq!(acqs.len(), 1);
+    assert_eq!(acqs[0].wallet, "default");
+    assert_eq!(get_dispositions(&mut conn)[0].wallet, "default");
+}
@@ -0,0 +1,150 @@
+mod common;
+
+use common::{
+    setup_test_db, create_test_csv, create_test_csv_with_wallet, create_transfer_csv, default_config,
+    get_acquisitions, get_dispositions, get_tax_acq_disps, get_gaap_acq_disps,
+};
+use rust_rgl_ledger::commands::edit_transaction::{edit_transaction, delete_transaction, RglChange, TransactionEdit};
+use rust_rgl_ledger::commands::import::import_transactions;
+use rust_rgl_ledger::commands::transfer::transfer;
+
+#[test]
+fn test_edit_acquisition_price_rematches() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv(&[
+        ("01/01/2024", "1.00000000", "$40,000.00"),
+        ("02/01/2024", "-0.50000000", "$50,000.00"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    let edit = TransactionEdit { price: Some("$45,000.00".to_string()), ..TransactionEdit::default() };
+    let summary = edit_transaction("acquisition", 1, &edit, &mut conn, &config).unwrap();
+    assert_eq!((summary.matches_removed, summary.matches_created), (2, 2));
+    assert_eq!((summary.tax_rgl_before, summary.tax_rgl_after), (500_000, 250_000));
+    assert_eq!(summary.rgl_changes, vec![
+        RglChange { disposition_id: 1, match_type: "gaap".to_string(), before: 500_000, after: 250_000 },
+        RglChange { disposition_id: 1, match_type: "tax".to_string(), before: 500_000, after: 250_000 },
+    ]);
+    assert!(summary.to_string().contains("Tax RGL:  $5000.00 -> $2500.00"), "got: {}", summary);
+
+    let acq = &get_acquisitions(&mut conn)[0];
+    assert_eq!(acq.usd_cents_btc_basis, 4_500_000);
+    assert_eq!(acq.undisposed_satoshis, 50_000_000);
+    assert_eq!(acq.tax_undisposed_satoshis, 50_000_000);
+    assert_eq!(get_tax_acq_disps(&mut conn)[0].basis, 2_250_000);
+}
+
+#[test]
+fn test_edit_disposition_date_and_amount_moves_match() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv(&[
+        ("01/01/2024", "1.00000000", "$40,000.00"),
+        ("03/01/2024", "1.00000000", "$30,000.00"),
+        ("04/01/2024", "-0.50000000", "$50,000.00"),
+        ("05/01/2024", "-0.50000000", "$50,000.00"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    // The May sale was really 0.75 BTC in February, before the second lot was bought
+    let edit = TransactionEdit {
+        date: Some("02/01/2024".to_string()),
+        bitcoin: Some("-0.75000000".to_string()),
+        ..TransactionEdit::default()
+    };
+    let summary = edit_transaction("disposition", 2, &edit, &mut conn, &config).unwrap();
+    assert_eq!(summary.matches_removed, 4);
+
+    let disp = get_dispositions(&mut conn).into_iter().find(|d| d.id == 2).unwrap();
+    assert_eq!(disp.disposition_date.format("%Y-%m-%d").to_string(), "2024-02-01");
+    assert_eq!((disp.satoshis, disp.undisposed_satoshis, disp.tax_undisposed_satoshis), (-75_000_000, 0, 0));
+
+    // February takes 0.75 of lot 1; April takes the last 0.25 of lot 1 and 0.25 of lot 2
+    let mut tax: Vec<(i32, i32, i64)> = get_tax_acq_disps(&mut conn).iter()
+        .map(|m| (m.acquisition_id, m.disposition_id, m.satoshis)).collect();
+    tax.sort();
+    assert_eq!(tax, vec![(1, 1, 25_000_000), (1, 2, 75_000_000), (2, 1, 25_000_000)]);
+    assert_eq!(get_gaap_acq_disps(&mut conn).len(), 3);
+}
+
+#[test]
+fn test_delete_acquisition_rematches_later_sale() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv(&[
+        ("01/01/2024", "1.00000000", "$30,000.00"),
+        ("02/01/2024", "1.00000000", "$40,000.00"),
+        ("03/01/2024", "-0.50000000", "$50,000.00"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    let summary = delete_transaction("acquisition", 1, &mut conn, &config).unwrap();
+    assert_eq!((summary.tax_rgl_before, summary.tax_rgl_after), (1_000_000, 500_000));
+    assert_eq!((summary.gaap_rgl_before, summary.gaap_rgl_after), (1_000_000, 500_000));
+
+    let acqs = get_acquisitions(&mut conn);
+    assert_eq!(acqs.len(), 1);
+    assert_eq!(acqs[0].tax_undisposed_satoshis, 50_000_000);
+    assert!(get_tax_acq_disps(&mut conn).iter().all(|m| m.acquisition_id == 2));
+}
+
+#[test]
+fn test_delete_disposition_restores_lot() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv(&[
+        ("01/01/2024", "1.00000000", "$40,000.00"),
+        ("02/01/2024", "-0.50000000", "$50,000.00"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    let summary = delete_transaction("disposition", 1, &mut conn, &config).unwrap();
+    assert_eq!((summary.matches_removed, summary.matches_created), (2, 0));
+    assert_eq!(summary.rgl_changes.len(), 2);
+    assert!(summary.rgl_changes.iter().all(|c| c.after == 0));
+
+    assert!(get_dispositions(&mut conn).is_empty());
+    assert_eq!(get_acquisitions(&mut conn)[0].undisposed_satoshis, 100_000_000);
+}
+
+#[test]
+fn test_edit_rolls_back_when_rematch_fails() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv(&[
+        ("01/01/2024", "1.00000000", "$40,000.00"),
+        ("02/01/2024", "-0.50000000", "$50,000.00"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    let edit = TransactionEdit { bitcoin: Some("0.25".to_string()), ..TransactionEdit::default() };
+    let err = edit_transaction("acquisition", 1, &edit, &mut conn, &config).unwrap_err();
+    assert!(err.contains("No undisposed acquisition lots available"), "got: {}", err);
+    assert_eq!(get_acquisitions(&mut conn)[0].satoshis, 100_000_000);
+    assert_eq!(get_tax_acq_disps(&mut conn).len(), 1);
+
+    let edit = TransactionEdit { bitcoin: Some("0.25".to_string()), ..TransactionEdit::default() };
+    let err = edit_transaction("disposition", 1, &edit, &mut conn, &config).unwrap_err();
+    assert_eq!(err, "Disposition 1 must keep a negative Bitcoin amount");
+
+    let err = edit_transaction("acquisition", 1, &TransactionEdit::default(), &mut conn, &config).unwrap_err();
+    assert!(err.starts_with("Nothing to edit"), "got: {}", err);
+}
+
+#[test]
+fn test_edit_rejects_transferred_lot() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv_with_wallet(&[("2024-01-01", "1.00000000", "$40,000.00", "cold-storage")]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+    let transfer_csv = create_transfer_csv(&[("2024-06-01", "cold-storage", "exchange", "0.50000000")]);
+    transfer(&transfer_csv.path().to_path_buf(), &mut conn, &config, false).unwrap();
+
+    let edit = TransactionEdit { price: Some("$1.00".to_string()), ..TransactionEdit::default() };
+    for id in [1, 2] {
+        let err = edit_transaction("acquisition", id, &edit, &mut conn, &config).unwrap_err();
+        assert!(err.contains("has been split or transferred"), "got: {}", err);
+    }
+    assert_eq!(delete_transaction("disposition", 7, &mut conn, &config).unwrap_err(), "Disposition 7 does not exist");
+}
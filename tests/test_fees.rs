@@ -0,0 +1,137 @@
+mod common;
+
+use common::{
+    setup_test_db, create_test_csv_with_fee, default_config, get_acquisitions, get_dispositions,
+    get_tax_acq_disps, get_gaap_acq_disps,
+};
+use rust_rgl_ledger::commands::import::import_transactions;
+use rust_rgl_ledger::commands::report::report;
+use rust_rgl_ledger::models::{parse_fee, Fee};
+use rust_decimal::Decimal;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+static CWD_LOCK: Mutex<()> = Mutex::new(());
+
+fn run_tax_report_in_dir(beg: &str, end: &str, conn: &mut diesel::SqliteConnection) -> String {
+    let _lock = CWD_LOCK.lock().unwrap();
+    let tmp = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir_all(tmp.path().join("reports")).unwrap();
+
+    let original_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(tmp.path()).unwrap();
+    report(&beg.to_string(), &end.to_string(), "tax", conn).unwrap();
+
+    let content = std::fs::read_dir("./reports")
+        .unwrap()
+        .filter_map(|e| e.ok())
+        .find(|e| e.file_name().to_string_lossy().starts_with("rgl_tax_"))
+        .map(|e| std::fs::read_to_string(e.path()).unwrap())
+        .unwrap_or_default();
+
+    std::env::set_current_dir(original_dir).unwrap();
+    content
+}
+
+#[test]
+fn test_parse_fee() {
+    assert_eq!(parse_fee("$4.99").unwrap(), Some(Fee::Usd(499)));
+    assert_eq!(parse_fee("1,000.50 USD").unwrap(), Some(Fee::Usd(100_050)));
+    assert_eq!(parse_fee("0.0001 BTC").unwrap(), Some(Fee::Btc(10_000)));
+    assert_eq!(parse_fee("0.00002btc").unwrap(), Some(Fee::Btc(2_000)));
+    assert_eq!(parse_fee("").unwrap(), None);
+    assert_eq!(parse_fee("0").unwrap(), None);
+    assert!(parse_fee("-1.00").is_err());
+    assert!(parse_fee("ten dollars").is_err());
+}
+
+#[test]
+fn test_usd_fee_on_buy_is_capitalized() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv_with_fee(&[("01/01/2024", "0.50000000", "$40,000.00", "$10.00")]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    let acqs = get_acquisitions(&mut conn);
+    // $10 over 0.5 BTC adds $20 per BTC
+    assert_eq!(acqs[0].usd_cents_btc_basis, 4_002_000);
+    assert_eq!(acqs[0].usd_cents_btc_fair_value, 4_002_000);
+    assert!(get_dispositions(&mut conn).is_empty());
+}
+
+#[test]
+fn test_usd_fee_on_sell_reduces_proceeds() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv_with_fee(&[
+        ("01/01/2024", "0.30000000", "$40,000.00", ""),
+        ("01/02/2024", "0.70000000", "$40,000.00", ""),
+        ("06/01/2024", "-1.00000000", "$50,000.00", "$10.01"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    assert_eq!(get_dispositions(&mut conn)[0].usd_cents_fee, 1001);
+
+    for matches in [get_tax_acq_disps(&mut conn), get_gaap_acq_disps(&mut conn)] {
+        assert_eq!(matches.len(), 2);
+        let fees: i64 = matches.iter().map(|m| m.fee).sum();
+        assert_eq!(fees, 1001, "Fee shares add up to the whole fee");
+        let rgl: i64 = matches.iter().map(|m| m.rgl).sum();
+        assert_eq!(rgl, 5_000_000 - 4_000_000 - 1001);
+    }
+}
+
+#[test]
+fn test_btc_fee_is_a_separate_disposition() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv_with_fee(&[
+        ("01/01/2024", "1.00000000", "$40,000.00", "0.0001 BTC"),
+        ("06/01/2024", "-0.50000000", "$50,000.00", "0.0002 BTC"),
+    ]);
+    let summary = import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+    assert_eq!(summary.inserted_acquisitions, 1);
+    assert_eq!(summary.inserted_dispositions, 1);
+    assert_eq!(summary.inserted_fee_dispositions, 2);
+
+    let disps = get_dispositions(&mut conn);
+    let amounts: Vec<(i64, i64)> = disps.iter().map(|d| (d.satoshis, d.usd_cents_btc_basis)).collect();
+    assert_eq!(amounts, vec![(-10_000, 4_000_000), (-50_000_000, 5_000_000), (-20_000, 5_000_000)]);
+    assert!(disps.iter().all(|d| d.usd_cents_fee == 0));
+
+    let acqs = get_acquisitions(&mut conn);
+    assert_eq!(acqs[0].usd_cents_btc_basis, 4_000_000, "BTC fees are not capitalized");
+    assert_eq!(acqs[0].tax_undisposed_satoshis, 100_000_000 - 10_000 - 50_000_000 - 20_000);
+}
+
+#[test]
+fn test_report_fee_column_ties_to_fees() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv_with_fee(&[
+        ("01/01/2024", "0.30000000", "$40,000.00", ""),
+        ("01/02/2024", "0.70000000", "$40,000.00", ""),
+        ("06/01/2024", "-1.00000000", "$50,000.00", "$10.01"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    let tax = run_tax_report_in_dir("01/01/2024", "12/31/2024", &mut conn);
+    let mut rdr = csv::Reader::from_reader(tax.as_bytes());
+    assert_eq!(rdr.headers().unwrap().get(9).unwrap(), "Fee");
+    let rows: Vec<csv::StringRecord> = rdr.records().filter_map(|r| r.ok()).collect();
+
+    // Tax columns: DisposalFmv(5), Basis(6), Rgl(7), Term(8), Fee(9)
+    let detail: Vec<_> = rows.iter().filter(|r| r.get(0).is_some_and(|s| !s.is_empty())).collect();
+    for row in &detail {
+        let disposal_fmv = Decimal::from_str(row.get(5).unwrap()).unwrap();
+        let basis = Decimal::from_str(row.get(6).unwrap()).unwrap();
+        let rgl = Decimal::from_str(row.get(7).unwrap()).unwrap();
+        let fee = Decimal::from_str(row.get(9).unwrap()).unwrap();
+        assert_eq!(disposal_fmv - fee - basis, rgl);
+    }
+
+    let totals = rows.iter()
+        .find(|r| r.get(0).is_some_and(|s| s.is_empty()) && r.get(2).is_some_and(|s| !s.is_empty()))
+        .unwrap();
+    assert_eq!(Decimal::from_str(totals.get(9).unwrap()).unwrap(), Decimal::from_str("10.01").unwrap());
+}
@@ -0,0 +1,149 @@
+mod common;
+
+use common::{setup_test_db, create_test_csv_with_wallet, default_config};
+use rust_rgl_ledger::commands::form_8949::{form_8949, Form8949Options};
+use rust_rgl_ledger::commands::import::import_transactions;
+use rust_decimal::Decimal;
+use std::io::Write;
+use std::sync::Mutex;
+
+static CWD_LOCK: Mutex<()> = Mutex::new(());
+
+/// Runs `form_8949` in a temp dir and returns the Form 8949 and Schedule D rows.
+fn run_form_8949(
+    beg: &str,
+    end: &str,
+    options: &Form8949Options,
+    conn: &mut diesel::SqliteConnection,
+) -> (Vec<csv::StringRecord>, Vec<csv::StringRecord>) {
+    let _lock = CWD_LOCK.lock().unwrap();
+    let tmp = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir_all(tmp.path().join("reports")).unwrap();
+
+    let original_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(tmp.path()).unwrap();
+    form_8949(&beg.to_string(), &end.to_string(), options, conn).unwrap();
+    let read = |prefix: &str| -> Vec<csv::StringRecord> {
+        let path = std::fs::read_dir("./reports")
+            .unwrap()
+            .filter_map(|e| e.ok())
+            .find(|e| e.file_name().to_string_lossy().starts_with(prefix))
+            .unwrap()
+            .path();
+        csv::Reader::from_path(path).unwrap().records().map(|r| r.unwrap()).collect()
+    };
+    let rows = (read("form_8949_"), read("schedule_d_"));
+    std::env::set_current_dir(original_dir).unwrap();
+    rows
+}
+
+fn usd(s: &str) -> Decimal {
+    s.parse::<Decimal>().unwrap()
+}
+
+#[test]
+fn test_form_8949_splits_by_part_and_box() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv_with_wallet(&[
+        ("2024-01-01", "1.00000000", "$40,000.00", "exchange"),
+        ("2025-03-01", "0.50000000", "$80,000.00", "exchange"),
+        ("2025-03-01", "0.50000000", "$82,000.00", "cold-storage"),
+        ("2025-07-01", "-0.50000000", "$90,000.00", "cold-storage"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+    let mut sale = tempfile::Builder::new().suffix(".csv").tempfile().unwrap();
+    writeln!(sale, "Date,Bitcoin,Price,Wallet,Fee").unwrap();
+    writeln!(sale, "06/01/2025,-1.20000000,\"$100,000.00\",exchange,$12.00").unwrap();
+    sale.flush().unwrap();
+    import_transactions(&sale.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    let options = Form8949Options { broker_wallets: vec!["exchange".to_string()], ..Form8949Options::default() };
+    let (form, schedule_d) = run_form_8949("01/01/2025", "12/31/2025", &options, &mut conn);
+    assert_eq!(form.len(), 6);
+
+    // Part I, Box H: broker-reported gross proceeds with the fee as a code E adjustment
+    assert_eq!((&form[0][0], &form[0][1], &form[0][2]), ("I", "H", "0.2 BTC"));
+    assert_eq!((&form[0][3], &form[0][4]), ("03/01/2025", "06/01/2025"));
+    assert_eq!(usd(&form[0][5]), usd("20000"));
+    assert_eq!(usd(&form[0][6]), usd("16000"));
+    assert_eq!((&form[0][7], usd(&form[0][8])), ("E", usd("-2")));
+    assert_eq!(usd(&form[0][9]), usd("3998"));
+    assert_eq!(&form[1][2], "Totals");
+
+    // Part I, Box I: not broker-reported, so no adjustment
+    assert_eq!((&form[2][1], &form[2][2]), ("I", "0.5 BTC"));
+    assert_eq!(usd(&form[2][5]), usd("45000"));
+    assert_eq!((&form[2][7], &form[2][8]), ("", ""));
+    assert_eq!(usd(&form[2][9]), usd("4000"));
+
+    // Part II, Box K: held more than a year
+    assert_eq!((&form[4][0], &form[4][1], &form[4][3]), ("II", "K", "01/01/2024"));
+    assert_eq!(usd(&form[4][5]), usd("100000"));
+    assert_eq!(usd(&form[4][8]), usd("-10"));
+    assert_eq!(usd(&form[4][9]), usd("59990"));
+
+    let lines: Vec<(&str, Decimal)> = schedule_d.iter().map(|r| (&r[0], usd(&r[5]))).collect();
+    assert_eq!(lines, vec![
+        ("2", usd("3998")),
+        ("3", usd("4000")),
+        ("9", usd("59990")),
+        ("7", usd("7998")),
+        ("15", usd("59990")),
+        ("16", usd("67988")),
+    ]);
+    assert_eq!(usd(&schedule_d[0][4]), usd("-2"));
+}
+
+#[test]
+fn test_form_8949_aggregates_same_day_sales() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv_with_wallet(&[
+        ("2024-02-01", "0.30000000", "$40,000.00", "default"),
+        ("2024-02-01", "0.20000000", "$41,000.00", "default"),
+        ("2024-03-01", "0.50000000", "$45,000.00", "default"),
+        ("2024-06-01", "-0.60000000", "$60,000.00", "default"),
+        ("2024-06-01", "-0.40000000", "$60,000.00", "default"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    let (form, _) = run_form_8949("01/01/2024", "12/31/2024", &Form8949Options::default(), &mut conn);
+    assert_eq!(form.iter().filter(|r| &r[2] != "Totals").count(), 4);
+    assert!(form.iter().all(|r| &r[1] == "C"));
+
+    let options = Form8949Options { aggregate: true, ..Form8949Options::default() };
+    let (form, schedule_d) = run_form_8949("01/01/2024", "12/31/2024", &options, &mut conn);
+    assert_eq!(form.len(), 2);
+    assert_eq!((&form[0][2], &form[0][3], &form[0][4]), ("1 BTC", "VARIOUS", "06/01/2024"));
+    assert_eq!(usd(&form[0][5]), usd("60000"));
+    assert_eq!(usd(&form[0][6]), usd("42700"));
+    assert_eq!(usd(&form[0][9]), usd("17300"));
+    assert_eq!((&schedule_d[0][0], usd(&schedule_d[0][5])), ("3", usd("17300")));
+}
+
+#[test]
+fn test_form_8949_rejects_periods_spanning_tax_years() {
+    let mut conn = setup_test_db();
+    let err = form_8949(&"07/01/2024".to_string(), &"06/30/2025".to_string(), &Form8949Options::default(), &mut conn).unwrap_err();
+    assert!(err.to_string().contains("spans 2024 through 2025"), "got: {}", err);
+}
+
+#[test]
+fn test_form_8949_basis_reported_for_lots_bought_at_the_broker_from_2026() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv_with_wallet(&[
+        ("2025-12-01", "0.50000000", "$90,000.00", "exchange"),
+        ("2026-01-02", "0.50000000", "$95,000.00", "exchange"),
+        ("2026-03-01", "-1.00000000", "$100,000.00", "exchange"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    let options = Form8949Options { broker_wallets: vec!["exchange".to_string()], ..Form8949Options::default() };
+    let (form, schedule_d) = run_form_8949("01/01/2026", "12/31/2026", &options, &mut conn);
+    let boxes: Vec<(&str, &str)> = form.iter().map(|r| (&r[1], &r[2])).collect();
+    assert_eq!(boxes, vec![("G", "0.5 BTC"), ("G", "Totals"), ("H", "0.5 BTC"), ("H", "Totals")]);
+    assert_eq!(&form[0][3], "01/02/2026");
+    assert_eq!((&schedule_d[0][0], &schedule_d[1][0]), ("1b", "2"));
+}
@@ -16,7 +16,7 @@ fn run_mtm_in_dir(price: &str, date: &str, conn: &mut diesel::SqliteConnection)
 
     let original_dir = std::env::current_dir().unwrap();
     std::env::set_current_dir(tmp.path()).unwrap();
-    mark_to_market(&price.to_string(), &date.to_string(), conn).unwrap();
+    mark_to_market(&price.to_string(), &date.to_string(), conn, &default_config(), false).unwrap();
     std::env::set_current_dir(original_dir).unwrap();
 }
 
@@ -104,8 +104,8 @@ fn test_report_tax_gaap_separate_files_after_mtm() {
     let mut tax_rdr = csv::Reader::from_reader(tax_content.as_bytes());
     let tax_rows: Vec<csv::StringRecord> = tax_rdr.records().filter_map(|r| r.ok()).collect();
     let tax_detail: Vec<_> = tax_rows.iter().filter(|r| {
-        r.get(0).map_or(false, |s| !s.is_empty()) &&
-        r.get(8).map_or(false, |s| s == "short" || s == "long")
+        r.get(0).is_some_and(|s| !s.is_empty()) &&
+        r.get(8).is_some_and(|s| s == "short" || s == "long")
     }).collect();
     assert_eq!(tax_detail.len(), 1, "Should have 1 tax detail row");
     let tax_basis = Decimal::from_str(tax_detail[0].get(6).unwrap()).unwrap();
@@ -117,8 +117,8 @@ fn test_report_tax_gaap_separate_files_after_mtm() {
     let mut gaap_rdr = csv::Reader::from_reader(gaap_content.as_bytes());
     let gaap_rows: Vec<csv::StringRecord> = gaap_rdr.records().filter_map(|r| r.ok()).collect();
     let gaap_detail: Vec<_> = gaap_rows.iter().filter(|r| {
-        r.get(0).map_or(false, |s| !s.is_empty()) &&
-        r.get(11).map_or(false, |s| s == "short" || s == "long")
+        r.get(0).is_some_and(|s| !s.is_empty()) &&
+        r.get(11).is_some_and(|s| s == "short" || s == "long")
     }).collect();
     assert_eq!(gaap_detail.len(), 1, "Should have 1 gaap detail row");
 
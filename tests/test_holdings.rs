@@ -3,6 +3,8 @@ mod common;
 use common::{setup_test_db, create_test_csv, default_config};
 use rust_rgl_ledger::commands::import::import_transactions;
 use rust_rgl_ledger::commands::holdings::holdings;
+use rust_rgl_ledger::commands::mark_to_market::mark_to_market;
+use rust_decimal::Decimal;
 use std::sync::Mutex;
 
 static CWD_LOCK: Mutex<()> = Mutex::new(());
@@ -15,7 +17,7 @@ fn run_holdings_in_dir(date: &str, conn: &mut diesel::SqliteConnection) -> Strin
     let original_dir = std::env::current_dir().unwrap();
     std::env::set_current_dir(report_dir.path()).unwrap();
 
-    holdings(&date.to_string(), "gaap", conn).unwrap();
+    holdings(&date.to_string(), "gaap", conn, &default_config()).unwrap();
 
     let report_files: Vec<_> = std::fs::read_dir("./reports")
         .unwrap()
@@ -33,6 +35,17 @@ fn run_holdings_in_dir(date: &str, conn: &mut diesel::SqliteConnection) -> Strin
     content
 }
 
+fn run_mtm_in_dir(price: &str, date: &str, conn: &mut diesel::SqliteConnection) {
+    let _lock = CWD_LOCK.lock().unwrap();
+    let tmp = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir_all(tmp.path().join("reports")).unwrap();
+
+    let original_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(tmp.path()).unwrap();
+    mark_to_market(&price.to_string(), &date.to_string(), conn, &default_config(), false).unwrap();
+    std::env::set_current_dir(original_dir).unwrap();
+}
+
 /// Parse CSV into rows. Returns data rows (header excluded).
 fn parse_holdings_csv(content: &str) -> Vec<Vec<String>> {
     let mut rdr = csv::ReaderBuilder::new().has_headers(true).from_reader(content.as_bytes());
@@ -131,3 +144,24 @@ fn test_holdings_fully_disposed_lot_excluded() {
     assert_eq!(rows[0].get(2).unwrap(), "0", "Total BTC should be 0");
     assert_eq!(rows[0].get(3).unwrap(), "0", "Total undisposed BTC should be 0");
 }
+
+#[test]
+fn test_holdings_valued_at_mark_as_of_date() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv(&[("01/01/2024", "1.00000000", "$40,000.00")]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+    run_mtm_in_dir("$50,000.00", "03/31/2024", &mut conn);
+    let sale = create_test_csv(&[("05/01/2024", "-0.50000000", "$55,000.00")]);
+    import_transactions(&sale.path().to_path_buf(), &mut conn, &config).unwrap();
+    run_mtm_in_dir("$60,000.00", "06/30/2024", &mut conn);
+
+    // usd_fair_value (index 5) uses the latest mark on or before the report date, or cost
+    let rows = parse_holdings_csv(&run_holdings_in_dir("02/01/2024", &mut conn));
+    assert_eq!(rows[0][5].parse::<Decimal>().unwrap(), Decimal::from(40000));
+    let rows = parse_holdings_csv(&run_holdings_in_dir("04/30/2024", &mut conn));
+    assert_eq!(rows[0][5].parse::<Decimal>().unwrap(), Decimal::from(50000));
+    let rows = parse_holdings_csv(&run_holdings_in_dir("07/01/2024", &mut conn));
+    assert_eq!(rows[0][5].parse::<Decimal>().unwrap(), Decimal::from(30000));
+    assert_eq!(rows[0][4].parse::<Decimal>().unwrap(), Decimal::from(20000));
+}
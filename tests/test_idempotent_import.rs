@@ -0,0 +1,105 @@
+mod common;
+
+use common::{setup_test_db, create_test_csv_with_tx_id, default_config,
+             get_acquisitions, get_dispositions, get_tax_acq_disps};
+use rust_rgl_ledger::commands::import::{import_transactions, import_transactions_with_options, ImportOptions};
+
+fn skip_existing() -> ImportOptions {
+    ImportOptions { skip_existing: true, ..ImportOptions::default() }
+}
+
+#[test]
+fn test_tx_id_stored_on_import() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv_with_tx_id(&[
+        ("01/01/2025", "1.00000000", "$40,000.00", "cb-1"),
+        ("02/01/2025", "-0.50000000", "$45,000.00", "cb-2"),
+    ]);
+    let summary = import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+    assert_eq!(summary.inserted_acquisitions, 1);
+    assert_eq!(summary.inserted_dispositions, 1);
+    assert_eq!(summary.inserted, vec!["cb-1", "cb-2"]);
+    assert!(summary.skipped.is_empty());
+
+    assert_eq!(get_acquisitions(&mut conn)[0].external_id.as_deref(), Some("cb-1"));
+    assert_eq!(get_dispositions(&mut conn)[0].external_id.as_deref(), Some("cb-2"));
+}
+
+#[test]
+fn test_overlapping_import_skips_existing_rows() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let first = create_test_csv_with_tx_id(&[
+        ("01/01/2025", "1.00000000", "$40,000.00", "cb-1"),
+        ("01/02/2025", "1.00000000", "$41,000.00", "cb-2"),
+        ("01/03/2025", "-0.50000000", "$42,000.00", "cb-3"),
+    ]);
+    import_transactions(&first.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    // Nightly export overlaps the previous one by two days
+    let second = create_test_csv_with_tx_id(&[
+        ("01/02/2025", "1.00000000", "$41,000.00", "cb-2"),
+        ("01/03/2025", "-0.50000000", "$42,000.00", "cb-3"),
+        ("01/04/2025", "-0.25000000", "$43,000.00", "cb-4"),
+    ]);
+    let summary = import_transactions_with_options(&second.path().to_path_buf(), &mut conn, &config, &skip_existing()).unwrap();
+    assert_eq!(summary.skipped, vec!["cb-2", "cb-3"]);
+    assert_eq!(summary.inserted, vec!["cb-4"]);
+    assert_eq!(summary.inserted_acquisitions, 0);
+    assert_eq!(summary.inserted_dispositions, 1);
+
+    assert_eq!(get_acquisitions(&mut conn).len(), 2);
+    assert_eq!(get_dispositions(&mut conn).len(), 2);
+    let matched: i64 = get_tax_acq_disps(&mut conn).iter().map(|ad| ad.satoshis).sum();
+    assert_eq!(matched, 75_000_000, "Only the two distinct sales are matched");
+}
+
+#[test]
+fn test_reimport_without_skip_is_rejected() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv_with_tx_id(&[("01/01/2025", "1.00000000", "$40,000.00", "cb-1")]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    let again = create_test_csv_with_tx_id(&[
+        ("01/05/2025", "2.00000000", "$40,000.00", "cb-9"),
+        ("01/01/2025", "1.00000000", "$40,000.00", "cb-1"),
+    ]);
+    let err = import_transactions(&again.path().to_path_buf(), &mut conn, &config).unwrap_err();
+    assert!(err.contains("TxId 'cb-1' has already been imported"), "got: {}", err);
+    assert!(err.contains("--skip-existing"), "got: {}", err);
+    assert_eq!(get_acquisitions(&mut conn).len(), 1, "Whole file is rolled back");
+}
+
+#[test]
+fn test_duplicate_tx_id_within_file() {
+    let config = default_config();
+    let records = [
+        ("01/01/2025", "1.00000000", "$40,000.00", "cb-1"),
+        ("01/01/2025", "1.00000000", "$40,000.00", "cb-1"),
+    ];
+
+    let mut conn = setup_test_db();
+    let csv = create_test_csv_with_tx_id(&records);
+    assert!(import_transactions(&csv.path().to_path_buf(), &mut conn, &config).is_err());
+
+    let mut conn2 = setup_test_db();
+    let summary = import_transactions_with_options(&csv.path().to_path_buf(), &mut conn2, &config, &skip_existing()).unwrap();
+    assert_eq!(summary.inserted, vec!["cb-1"]);
+    assert_eq!(summary.skipped, vec!["cb-1"]);
+    assert_eq!(get_acquisitions(&mut conn2).len(), 1);
+}
+
+#[test]
+fn test_rows_without_tx_id_always_inserted() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv_with_tx_id(&[("01/01/2025", "1.00000000", "$40,000.00", "")]);
+    import_transactions_with_options(&csv.path().to_path_buf(), &mut conn, &config, &skip_existing()).unwrap();
+    import_transactions_with_options(&csv.path().to_path_buf(), &mut conn, &config, &skip_existing()).unwrap();
+
+    let acqs = get_acquisitions(&mut conn);
+    assert_eq!(acqs.len(), 2);
+    assert!(acqs.iter().all(|a| a.external_id.is_none()));
+}
@@ -0,0 +1,225 @@
+mod common;
+
+use common::{setup_test_db, create_test_csv_with_wallet, create_transfer_csv_with_fee, default_config};
+use rust_rgl_ledger::LotConfig;
+use rust_rgl_ledger::commands::import::import_transactions;
+use rust_rgl_ledger::commands::journal::{journal, ensure_balanced, ChartOfAccounts, JournalSummary};
+use rust_rgl_ledger::commands::mark_to_market::mark_to_market;
+use rust_rgl_ledger::commands::rollforward::rollforward;
+use rust_rgl_ledger::commands::transfer::transfer;
+use rust_rgl_ledger::models::JournalLine;
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::Mutex;
+
+static CWD_LOCK: Mutex<()> = Mutex::new(());
+
+struct Line {
+    entry: i32,
+    account: String,
+    /// Debit less credit
+    amount: Decimal,
+    memo: String,
+}
+
+fn in_reports_dir<T, F: FnOnce() -> T>(f: F) -> (T, String) {
+    let _lock = CWD_LOCK.lock().unwrap();
+    let tmp = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir_all(tmp.path().join("reports")).unwrap();
+
+    let original_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(tmp.path()).unwrap();
+    let result = f();
+    let content = std::fs::read_dir("./reports")
+        .unwrap()
+        .filter_map(|e| e.ok())
+        .find(|e| e.file_name().to_string_lossy().starts_with("journal_"))
+        .map(|e| std::fs::read_to_string(e.path()).unwrap())
+        .unwrap_or_default();
+    std::env::set_current_dir(original_dir).unwrap();
+    (result, content)
+}
+
+fn run_journal(chart: &ChartOfAccounts, conn: &mut diesel::SqliteConnection, config: &LotConfig) -> (JournalSummary, Vec<Line>) {
+    let (summary, content) = in_reports_dir(|| {
+        journal(&"01/01/2024".to_string(), &"12/31/2024".to_string(), chart, conn, config).unwrap()
+    });
+    let mut rdr = csv::Reader::from_reader(content.as_bytes());
+    assert_eq!(rdr.headers().unwrap(), vec!["Entry", "Date", "Account", "Debit", "Credit", "Memo"]);
+    let amount = |s: &str| if s.is_empty() { Decimal::ZERO } else { s.parse::<Decimal>().unwrap() };
+    let lines = rdr.records()
+        .map(|r| r.unwrap())
+        .map(|r| Line {
+            entry: r[0].parse().unwrap(),
+            account: r[2].to_string(),
+            amount: amount(&r[3]) - amount(&r[4]),
+            memo: r[5].to_string(),
+        })
+        .collect();
+    (summary, lines)
+}
+
+fn setup_ledger(conn: &mut diesel::SqliteConnection, config: &LotConfig) {
+    let csv = create_test_csv_with_wallet(&[
+        ("2024-01-01", "1.00000000", "$40,000.00", "cold-storage"),
+        ("2024-02-01", "1.00000000", "$50,000.00", "cold-storage"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), conn, config).unwrap();
+    let transfer_csv = create_transfer_csv_with_fee(&[
+        ("2024-03-01", "cold-storage", "exchange", "0.60010000", "0.00010000", "$55,000.00"),
+    ]);
+    transfer(&transfer_csv.path().to_path_buf(), conn, config, false).unwrap();
+    in_reports_dir(|| mark_to_market(&"$60,000.00".to_string(), &"2024-03-31".to_string(), conn, config, false).unwrap());
+    let sale = create_test_csv_with_wallet(&[("2024-05-01", "-0.50000000", "$70,000.00", "exchange")]);
+    import_transactions(&sale.path().to_path_buf(), conn, config).unwrap();
+}
+
+fn entry_lines<'a>(lines: &'a [Line], memo: &str) -> HashMap<&'a str, Decimal> {
+    lines.iter()
+        .filter(|l| l.memo.starts_with(memo))
+        .map(|l| (l.account.as_str(), l.amount))
+        .collect()
+}
+
+#[test]
+fn test_journal_entries_balance_and_tie_to_rollforward() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    setup_ledger(&mut conn, &config);
+
+    let (summary, lines) = run_journal(&ChartOfAccounts::default(), &mut conn, &config);
+    assert_eq!(summary.entries, 6);
+    for entry in 1..=summary.entries as i32 {
+        let total: Decimal = lines.iter().filter(|l| l.entry == entry).map(|l| l.amount).sum();
+        assert_eq!(total, Decimal::ZERO, "entry {} does not balance", entry);
+    }
+
+    let purchase = entry_lines(&lines, "Purchase of 1 BTC (lot 1)");
+    assert_eq!(purchase["Digital Assets:cold-storage"], Decimal::from(40_000));
+    assert_eq!(purchase["Cash"], Decimal::from(-40_000));
+
+    let moved = entry_lines(&lines, "Transfer of 0.6 BTC from cold-storage to exchange");
+    assert_eq!(moved["Digital Assets:exchange"], Decimal::from(24_000));
+    assert_eq!(moved["Digital Assets:cold-storage"], Decimal::from(-24_000));
+
+    let fee = entry_lines(&lines, "Network fee of 0.0001 BTC for transfer 1");
+    assert_eq!(fee["Network Fees"], Decimal::new(550, 2));
+    assert_eq!(fee["Digital Assets:cold-storage"], Decimal::new(-400, 2));
+    assert_eq!(fee["Realized Gain/Loss on Digital Assets"], Decimal::new(-150, 2));
+
+    // GAAP relief is universal: FIFO takes the 0.3999 BTC left in cold storage first
+    let sale = entry_lines(&lines, "Sale of 0.5 BTC");
+    assert_eq!(sale["Cash"], Decimal::from(35_000));
+    assert_eq!(sale["Digital Assets:cold-storage"], Decimal::from(-23_994));
+    assert_eq!(sale["Digital Assets:exchange"], Decimal::from(-6_006));
+    assert_eq!(sale["Realized Gain/Loss on Digital Assets"], Decimal::from(-5_000));
+
+    // The mark lands in the wallet each lot was in on the mark date
+    let mark = entry_lines(&lines, "Mark to market at $60000.00");
+    assert_eq!(mark["Digital Assets:exchange"], Decimal::from(12_000));
+    assert_eq!(mark["Digital Assets:cold-storage"], Decimal::from(17_998));
+
+    let (rollforward, _) = in_reports_dir(|| {
+        rollforward(&"01/01/2024".to_string(), &"12/31/2024".to_string(), &mut conn, &config).unwrap()
+    });
+    let account_total = |account: &str| -> Decimal {
+        lines.iter().filter(|l| l.account == account).map(|l| l.amount).sum()
+    };
+    assert_eq!(-account_total("Unrealized Gain/Loss on Digital Assets"), rollforward.remeasurement);
+    assert_eq!(-account_total("Realized Gain/Loss on Digital Assets"), rollforward.realized_gain);
+    let digital_assets: Decimal = lines.iter().filter(|l| l.account.starts_with("Digital Assets:")).map(|l| l.amount).sum();
+    assert_eq!(digital_assets, rollforward.closing - rollforward.opening);
+
+    // Each wallet's sub-account ends at what the wallet holds at $60k: the second lot in
+    // cold storage, and the 0.4999 BTC of the transferred lot the sale left on the exchange
+    assert_eq!(account_total("Digital Assets:cold-storage"), Decimal::from(60_000));
+    assert_eq!(account_total("Digital Assets:exchange"), Decimal::from(29_994));
+}
+
+#[test]
+fn test_journal_fails_when_it_does_not_tie_to_rollforward() {
+    use diesel::prelude::*;
+    use rust_rgl_ledger::schema::acquisition_dispositions;
+
+    let mut conn = setup_test_db();
+    let config = default_config();
+    setup_ledger(&mut conn, &config);
+    diesel::update(acquisition_dispositions::table.filter(acquisition_dispositions::match_type.eq("gaap")))
+        .set(acquisition_dispositions::basis.eq(1))
+        .execute(&mut conn)
+        .unwrap();
+
+    let (result, content) = in_reports_dir(|| {
+        journal(&"01/01/2024".to_string(), &"12/31/2024".to_string(), &ChartOfAccounts::default(), &mut conn, &config)
+    });
+    assert!(result.unwrap_err().to_string().contains("does not tie"));
+    assert!(content.is_empty());
+}
+
+#[test]
+fn test_unbalanced_journal_entries_are_rejected() {
+    let line = |entry: i32, account: &str, cents: i64, memo: &str| JournalLine {
+        entry,
+        date: chrono::NaiveDate::from_ymd_opt(2024, 5, 1).unwrap(),
+        account: account.to_string(),
+        debit: (cents > 0).then(|| Decimal::new(cents, 2)),
+        credit: (cents < 0).then(|| Decimal::new(-cents, 2)),
+        memo: memo.to_string(),
+    };
+    let mut rows = vec![
+        line(1, "Digital Assets", 4_000_000, "Purchase of 1 BTC (lot 1)"),
+        line(1, "Cash", -4_000_000, "Purchase of 1 BTC (lot 1)"),
+        line(2, "Cash", 3_500_000, "Sale of 0.5 BTC"),
+        line(2, "Digital Assets", -2_000_000, "Sale of 0.5 BTC"),
+        line(2, "Realized Gain/Loss on Digital Assets", -1_500_000, "Sale of 0.5 BTC"),
+    ];
+    assert!(ensure_balanced(&rows).is_ok());
+
+    rows[4] = line(2, "Realized Gain/Loss on Digital Assets", -1_499_999, "Sale of 0.5 BTC");
+    let err = ensure_balanced(&rows).unwrap_err();
+    assert_eq!(err, "Journal entry 2 on 2024-05-01 (Sale of 0.5 BTC) does not balance: debits $35000.00, credits $34999.99");
+}
+
+#[test]
+fn test_journal_uses_chart_of_accounts_mapping() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    setup_ledger(&mut conn, &config);
+
+    let mut file = tempfile::Builder::new().suffix(".csv").tempfile().unwrap();
+    writeln!(file, "Role,Wallet,Account").unwrap();
+    writeln!(file, "cash,,1000 Operating Cash").unwrap();
+    writeln!(file, "digital_assets,,1500 Digital Assets").unwrap();
+    writeln!(file, "digital_assets,cold-storage,1510 Cold Storage").unwrap();
+    writeln!(file, "realized_gain_loss,,4100 Realized Gain/Loss").unwrap();
+    file.flush().unwrap();
+    let chart = ChartOfAccounts::load(&file.path().to_path_buf()).unwrap();
+
+    let (_, lines) = run_journal(&chart, &mut conn, &config);
+    let purchase = entry_lines(&lines, "Purchase of 1 BTC (lot 1)");
+    assert_eq!(purchase["1510 Cold Storage"], Decimal::from(40_000));
+    assert_eq!(purchase["1000 Operating Cash"], Decimal::from(-40_000));
+    let sale = entry_lines(&lines, "Sale of 0.5 BTC");
+    assert_eq!(sale["1510 Cold Storage"], Decimal::from(-23_994));
+    assert_eq!(sale["1500 Digital Assets:exchange"], Decimal::from(-6_006));
+    assert_eq!(sale["4100 Realized Gain/Loss"], Decimal::from(-5_000));
+    assert!(lines.iter().any(|l| l.account == "Unrealized Gain/Loss on Digital Assets"));
+}
+
+#[test]
+fn test_chart_of_accounts_rejects_unknown_roles() {
+    let mut file = tempfile::Builder::new().suffix(".csv").tempfile().unwrap();
+    writeln!(file, "Role,Wallet,Account").unwrap();
+    writeln!(file, "inventory,,1200 Inventory").unwrap();
+    file.flush().unwrap();
+    let err = ChartOfAccounts::load(&file.path().to_path_buf()).unwrap_err();
+    assert!(err.starts_with("Unknown account role 'inventory'"), "got: {}", err);
+
+    let mut file = tempfile::Builder::new().suffix(".csv").tempfile().unwrap();
+    writeln!(file, "Role,Wallet,Account").unwrap();
+    writeln!(file, "cash,exchange,1010 Exchange Cash").unwrap();
+    file.flush().unwrap();
+    let err = ChartOfAccounts::load(&file.path().to_path_buf()).unwrap_err();
+    assert!(err.contains("only digital_assets has wallet sub-accounts"), "got: {}", err);
+}
@@ -0,0 +1,75 @@
+mod common;
+
+use common::{
+    setup_test_db, create_test_csv, create_test_csv_with_wallet, create_bucket_csv, create_transfer_csv,
+    default_config, get_acquisitions,
+};
+use rust_rgl_ledger::commands::allocate::allocate;
+use rust_rgl_ledger::commands::import::import_transactions;
+use rust_rgl_ledger::commands::lot_history::lot_history;
+use rust_rgl_ledger::commands::transfer::transfer;
+
+#[test]
+fn test_split_paths_record_parent() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv(&[("01/01/2024", "1.00000000", "$40,000.00")]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    let bucket_csv = create_bucket_csv(&[("coinbase", "0.60000000"), ("ledger", "0.40000000")]);
+    allocate(&bucket_csv.path().to_path_buf(), &mut conn, false).unwrap();
+
+    let transfer_csv = create_transfer_csv(&[("2024-06-01", "ledger", "exchange", "0.10000000")]);
+    transfer(&transfer_csv.path().to_path_buf(), &mut conn, &config, false).unwrap();
+
+    let lineage: Vec<(i32, Option<i32>, Option<String>)> = get_acquisitions(&mut conn)
+        .into_iter()
+        .map(|a| (a.id, a.parent_acquisition_id, a.split_reason))
+        .collect();
+    assert_eq!(lineage, vec![
+        (1, None, None),
+        (2, Some(1), Some("allocate".to_string())),
+        (3, Some(2), Some("transfer".to_string())),
+    ]);
+}
+
+#[test]
+fn test_lot_history_prints_tree_from_original_buy() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv_with_wallet(&[
+        ("2024-01-01", "2.00000000", "$40,000.00", "cold-storage"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    let to_exchange = create_transfer_csv(&[("2024-03-01", "cold-storage", "exchange", "0.50000000")]);
+    transfer(&to_exchange.path().to_path_buf(), &mut conn, &config, false).unwrap();
+
+    let sale = create_test_csv_with_wallet(&[("2024-04-01", "-0.25000000", "$50,000.00", "exchange")]);
+    import_transactions(&sale.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    let to_hot = create_transfer_csv(&[("2024-05-01", "exchange", "hot", "0.10000000")]);
+    transfer(&to_hot.path().to_path_buf(), &mut conn, &config, false).unwrap();
+
+    let tree = lot_history(3, &mut conn).unwrap();
+    let lines: Vec<&str> = tree.lines().collect();
+    assert!(lines[0].starts_with("lot 1 2024-01-01 wallet 'cold-storage' 1.50000000 BTC at $40000.00 (original purchase)"), "got:\n{}", tree);
+    // GAAP matching is universal, so the sale relieves the oldest lot for GAAP
+    assert!(lines[1].starts_with("  gaap disposition 1 2024-04-01 wallet 'exchange' 0.25000000 BTC"), "got:\n{}", tree);
+    assert!(lines[2].starts_with("    lot 2 2024-01-01 wallet 'exchange' 0.30000000 BTC at $40000.00 (split from lot 1 by transfer)"), "got:\n{}", tree);
+    assert!(lines[3].starts_with("      tax disposition 1 2024-04-01 wallet 'exchange' 0.25000000 BTC basis $10000.00 rgl $2500.00 short"), "got:\n{}", tree);
+    assert!(lines[4].starts_with("        * lot 3 2024-01-01 wallet 'hot' 0.20000000 BTC at $40000.00 (split from lot 2 by transfer); tax undisposed 0.10000000 BTC"), "got:\n{}", tree);
+    assert_eq!(lines.len(), 5);
+
+    // Any lot in the lineage prints the same tree, marked at that lot
+    let from_root = lot_history(1, &mut conn).unwrap();
+    assert!(from_root.starts_with("* lot 1 "));
+    assert_eq!(from_root.replace("* ", ""), tree.replace("* ", ""));
+}
+
+#[test]
+fn test_lot_history_unknown_lot() {
+    let mut conn = setup_test_db();
+    let err = lot_history(42, &mut conn).unwrap_err();
+    assert_eq!(err, "Acquisition 42 does not exist");
+}
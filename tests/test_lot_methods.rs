@@ -0,0 +1,295 @@
+mod common;
+
+use common::{setup_test_db, create_test_csv_with_wallet, create_test_csv_with_lot, create_transfer_csv_with_fee, lot_config,
+             get_acquisitions, get_tax_acq_disps, get_gaap_acq_disps};
+use rust_rgl_ledger::commands::import::import_transactions;
+use rust_rgl_ledger::commands::report::report;
+use rust_rgl_ledger::commands::transfer::transfer;
+use rust_rgl_ledger::LotConfig;
+use std::sync::Mutex;
+
+static CWD_LOCK: Mutex<()> = Mutex::new(());
+
+// Three lots in two wallets, then a sale from coinbase:
+//   ledger   01/01/2025  1.0 BTC @ $30k
+//   coinbase 02/01/2025  1.0 BTC @ $50k
+//   coinbase 03/01/2025  1.0 BTC @ $40k
+//   ledger   04/01/2025  1.0 BTC @ $60k
+//   coinbase 06/01/2025 -0.5 BTC @ $55k
+fn import_three_wallet_lots(conn: &mut diesel::SqliteConnection, config: &LotConfig) {
+    let csv = create_test_csv_with_wallet(&[
+        ("01/01/2025", "1.00000000", "$30,000.00", "ledger"),
+        ("02/01/2025", "1.00000000", "$50,000.00", "coinbase"),
+        ("03/01/2025", "1.00000000", "$40,000.00", "coinbase"),
+        ("04/01/2025", "1.00000000", "$60,000.00", "ledger"),
+        ("06/01/2025", "-0.50000000", "$55,000.00", "coinbase"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), conn, config).unwrap();
+}
+
+fn tax_matched_basis_price(conn: &mut diesel::SqliteConnection) -> i64 {
+    let tax_ads = get_tax_acq_disps(conn);
+    assert_eq!(tax_ads.len(), 1, "Sale should match a single lot");
+    let acqs = get_acquisitions(conn);
+    acqs.iter().find(|a| a.id == tax_ads[0].acquisition_id).unwrap().usd_cents_btc_basis
+}
+
+#[test]
+fn test_fifo_wallet_scope() {
+    let mut conn = setup_test_db();
+    import_three_wallet_lots(&mut conn, &lot_config("fifo", "wallet"));
+    assert_eq!(tax_matched_basis_price(&mut conn), 5_000_000, "Earliest coinbase lot is $50k");
+}
+
+#[test]
+fn test_fifo_universal_scope() {
+    let mut conn = setup_test_db();
+    import_three_wallet_lots(&mut conn, &lot_config("fifo", "universal"));
+    assert_eq!(tax_matched_basis_price(&mut conn), 3_000_000, "Earliest lot overall is $30k");
+}
+
+#[test]
+fn test_lifo_wallet_scope() {
+    let mut conn = setup_test_db();
+    import_three_wallet_lots(&mut conn, &lot_config("lifo", "wallet"));
+    assert_eq!(tax_matched_basis_price(&mut conn), 4_000_000, "Latest coinbase lot is $40k");
+}
+
+#[test]
+fn test_lifo_universal_scope() {
+    let mut conn = setup_test_db();
+    import_three_wallet_lots(&mut conn, &lot_config("lifo", "universal"));
+    assert_eq!(tax_matched_basis_price(&mut conn), 6_000_000, "Latest lot overall is $60k");
+}
+
+#[test]
+fn test_hifo_wallet_scope() {
+    let mut conn = setup_test_db();
+    import_three_wallet_lots(&mut conn, &lot_config("hifo", "wallet"));
+    assert_eq!(tax_matched_basis_price(&mut conn), 5_000_000, "Highest coinbase basis is $50k");
+}
+
+#[test]
+fn test_hifo_universal_scope() {
+    let mut conn = setup_test_db();
+    import_three_wallet_lots(&mut conn, &lot_config("hifo", "universal"));
+    assert_eq!(tax_matched_basis_price(&mut conn), 6_000_000, "Highest basis overall is $60k");
+}
+
+#[test]
+fn test_lifo_ignores_lots_acquired_after_disposition() {
+    // LIFO must not reach forward in time to a lot bought after the sale
+    let mut conn = setup_test_db();
+    let config = lot_config("lifo", "universal");
+    let csv = create_test_csv_with_wallet(&[
+        ("01/01/2025", "1.00000000", "$30,000.00", "default"),
+        ("02/01/2025", "1.00000000", "$40,000.00", "default"),
+        ("03/01/2025", "-1.50000000", "$45,000.00", "default"),
+        ("04/01/2025", "1.00000000", "$50,000.00", "default"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    let tax_ads = get_tax_acq_disps(&mut conn);
+    assert_eq!(tax_ads.len(), 2);
+    assert_eq!(tax_ads[0].satoshis, 100_000_000, "Feb lot is relieved first");
+    assert_eq!(tax_ads[0].basis, 4_000_000);
+    assert_eq!(tax_ads[1].satoshis, 50_000_000, "Remainder comes from the Jan lot");
+    assert_eq!(tax_ads[1].basis, 1_500_000);
+
+    let acqs = get_acquisitions(&mut conn);
+    assert_eq!(acqs[2].tax_undisposed_satoshis, 100_000_000, "April lot is untouched");
+}
+
+#[test]
+fn test_hifo_spans_multiple_lots() {
+    let mut conn = setup_test_db();
+    let config = lot_config("hifo", "wallet");
+    let csv = create_test_csv_with_wallet(&[
+        ("01/01/2025", "1.00000000", "$30,000.00", "default"),
+        ("02/01/2025", "1.00000000", "$50,000.00", "default"),
+        ("03/01/2025", "1.00000000", "$40,000.00", "default"),
+        ("06/01/2025", "-1.50000000", "$55,000.00", "default"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    let acqs = get_acquisitions(&mut conn);
+    assert_eq!(acqs[0].tax_undisposed_satoshis, 100_000_000, "$30k lot is relieved last");
+    assert_eq!(acqs[1].tax_undisposed_satoshis, 0, "$50k lot is relieved first");
+    assert_eq!(acqs[2].tax_undisposed_satoshis, 50_000_000, "$40k lot covers the remainder");
+}
+
+#[test]
+fn test_tax_method_does_not_change_gaap_fifo() {
+    let mut conn = setup_test_db();
+    import_three_wallet_lots(&mut conn, &lot_config("hifo", "universal"));
+
+    let gaap_ads = get_gaap_acq_disps(&mut conn);
+    assert_eq!(gaap_ads.len(), 1);
+    let acqs = get_acquisitions(&mut conn);
+    let gaap_lot = acqs.iter().find(|a| a.id == gaap_ads[0].acquisition_id).unwrap();
+    assert_eq!(gaap_lot.usd_cents_btc_basis, 3_000_000, "GAAP stays FIFO on the earliest lot");
+}
+
+#[test]
+fn test_gaap_lifo() {
+    let mut conn = setup_test_db();
+    let config = LotConfig {
+        gaap_lot_method: "lifo".to_string(),
+        ..LotConfig::default()
+    };
+    import_three_wallet_lots(&mut conn, &config);
+
+    let gaap_ads = get_gaap_acq_disps(&mut conn);
+    assert_eq!(gaap_ads.len(), 1);
+    let acqs = get_acquisitions(&mut conn);
+    let gaap_lot = acqs.iter().find(|a| a.id == gaap_ads[0].acquisition_id).unwrap();
+    assert_eq!(gaap_lot.usd_cents_btc_basis, 6_000_000, "GAAP LIFO relieves the latest lot in any wallet");
+}
+
+// Lots held on 06/01/2025 when 3.5 BTC is sold at $60k:
+//   1: 01/01/2023 @ $70k  long-term loss
+//   2: 01/01/2025 @ $65k  short-term loss
+//   3: 01/01/2024 @ $20k  long-term gain, low basis
+//   4: 02/01/2024 @ $40k  long-term gain, high basis
+//   5: 03/01/2025 @ $50k  short-term gain
+fn import_min_tax_lots(conn: &mut diesel::SqliteConnection, sale_wallet: &str) {
+    let csv = create_test_csv_with_wallet(&[
+        ("01/01/2023", "1.00000000", "$70,000.00", "default"),
+        ("01/01/2025", "1.00000000", "$65,000.00", "default"),
+        ("01/01/2024", "1.00000000", "$20,000.00", "default"),
+        ("02/01/2024", "1.00000000", "$40,000.00", "default"),
+        ("03/01/2025", "1.00000000", "$50,000.00", "default"),
+        ("04/01/2025", "1.00000000", "$90,000.00", "other"),
+        ("06/01/2025", "-3.50000000", "$60,000.00", sale_wallet),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), conn, &lot_config("min-tax", "wallet")).unwrap();
+}
+
+#[test]
+fn test_min_tax_prefers_losses_then_high_basis_long_term_gains() {
+    let mut conn = setup_test_db();
+    import_min_tax_lots(&mut conn, "default");
+
+    let acqs = get_acquisitions(&mut conn);
+    let undisposed = |price: i64| {
+        acqs.iter().find(|a| a.usd_cents_btc_basis == price).unwrap().tax_undisposed_satoshis
+    };
+    assert_eq!(undisposed(7_000_000), 0, "Long-term loss is relieved first");
+    assert_eq!(undisposed(6_500_000), 0, "Short-term loss is relieved second");
+    assert_eq!(undisposed(4_000_000), 0, "Higher-basis long-term gain is relieved next");
+    assert_eq!(undisposed(2_000_000), 50_000_000, "Lower-basis long-term gain covers the remainder");
+    assert_eq!(undisposed(5_000_000), 100_000_000, "Short-term gain is relieved last");
+    assert_eq!(undisposed(9_000_000), 100_000_000, "Lot in another wallet is out of scope");
+
+    let tax_ads = get_tax_acq_disps(&mut conn);
+    let total_rgl: i64 = tax_ads.iter().map(|ad| ad.rgl).sum();
+    // -10k - 5k + 20k + 20k = 25k
+    assert_eq!(total_rgl, 2_500_000);
+}
+
+fn run_selection_report(conn: &mut diesel::SqliteConnection) -> Vec<csv::StringRecord> {
+    let tmp = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir_all(tmp.path().join("reports")).unwrap();
+    let original_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(tmp.path()).unwrap();
+    report(&"01/01/2025".to_string(), &"12/31/2025".to_string(), "selection", conn).unwrap();
+    let content = std::fs::read_to_string("./reports/lot_selection_2025-01-01_2025-12-31.csv").unwrap();
+    std::env::set_current_dir(original_dir).unwrap();
+
+    let mut rdr = csv::Reader::from_reader(content.as_bytes());
+    rdr.records().filter_map(|r| r.ok()).collect()
+}
+
+// Selection columns: DispositionDate(0), Wallet(1), Rank(2), AcquisitionId(3), AcquisitionDate(4),
+//                    DisposedBtc(5), CostPerBtc(6), DisposalFmvPerBtc(7), Rgl(8), Term(9), Reason(10), Method(11),
+//                    AvailableBtc(12)
+
+#[test]
+fn test_min_tax_lot_selection_report() {
+    let _lock = CWD_LOCK.lock().unwrap();
+    let mut conn = setup_test_db();
+    import_min_tax_lots(&mut conn, "default");
+
+    let rows = run_selection_report(&mut conn);
+    let reasons: Vec<&str> = rows.iter().map(|r| r.get(10).unwrap()).collect();
+    assert_eq!(reasons, vec!["long-term loss", "short-term loss", "long-term gain", "long-term gain", "passed over; short-term gain"]);
+    let ranks: Vec<&str> = rows.iter().map(|r| r.get(2).unwrap()).collect();
+    assert_eq!(ranks, vec!["1", "2", "3", "4", "5"]);
+    assert_eq!(rows[2].get(6).unwrap(), "40000", "Higher-basis long-term gain ranks first");
+    // The short-term gain lot was not needed; it is listed with what it had available
+    assert_eq!((rows[4].get(5).unwrap(), rows[4].get(6).unwrap(), rows[4].get(12).unwrap()), ("0", "50000", "1"));
+    assert!(rows[..4].iter().all(|r| r.get(12).unwrap().is_empty()));
+    assert!(rows.iter().all(|r| r.get(11).unwrap() == "min-tax"));
+}
+
+#[test]
+fn test_lot_selection_report_follows_configured_method() {
+    let _lock = CWD_LOCK.lock().unwrap();
+    let mut conn = setup_test_db();
+    let config = lot_config("hifo", "wallet");
+    let csv = create_test_csv_with_wallet(&[
+        ("01/01/2025", "1.00000000", "$30,000.00", "default"),
+        ("02/01/2025", "1.00000000", "$50,000.00", "default"),
+        ("03/01/2025", "1.00000000", "$40,000.00", "default"),
+        ("06/01/2025", "-1.50000000", "$55,000.00", "default"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    let rows = run_selection_report(&mut conn);
+    let order: Vec<(&str, &str)> = rows.iter().map(|r| (r.get(2).unwrap(), r.get(6).unwrap())).collect();
+    assert_eq!(order, vec![("1", "50000"), ("2", "40000"), ("3", "30000")], "Ranked in HIFO order, not by min-tax category");
+    assert_eq!(rows[2].get(10).unwrap(), "passed over; highest cost first");
+    assert!(rows.iter().all(|r| r.get(11).unwrap() == "hifo"));
+}
+
+#[test]
+fn test_lot_selection_report_specific_identification() {
+    let _lock = CWD_LOCK.lock().unwrap();
+    let mut conn = setup_test_db();
+    let config = lot_config("fifo", "wallet");
+    let csv = create_test_csv_with_lot(&[
+        ("01/01/2025", "1.00000000", "$30,000.00", "coinbase", ""),
+        ("02/01/2025", "1.00000000", "$50,000.00", "coinbase", ""),
+        ("03/01/2025", "1.00000000", "$40,000.00", "coinbase", ""),
+        ("06/01/2025", "-1.50000000", "$55,000.00", "coinbase", "3;1"),
+        ("07/01/2025", "-0.50000000", "$55,000.00", "coinbase", ""),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    let rows = run_selection_report(&mut conn);
+    let selections: Vec<(&str, &str, &str, &str)> = rows.iter()
+        .map(|r| (r.get(2).unwrap(), r.get(3).unwrap(), r.get(10).unwrap(), r.get(11).unwrap()))
+        .collect();
+    assert_eq!(selections, vec![
+        ("1", "3", "named on the disposition", "specific-id"),
+        ("2", "1", "named on the disposition", "specific-id"),
+        ("1", "1", "first in, first out", "fifo"),
+        ("2", "2", "passed over; first in, first out", "fifo"),
+    ]);
+}
+
+#[test]
+fn test_lot_selection_report_labels_transfer_fees_fifo() {
+    let _lock = CWD_LOCK.lock().unwrap();
+    let mut conn = setup_test_db();
+    let config = lot_config("hifo", "wallet");
+    let csv = create_test_csv_with_wallet(&[
+        ("01/01/2025", "1.00000000", "$30,000.00", "cold-storage"),
+        ("02/01/2025", "1.00000000", "$50,000.00", "cold-storage"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+    let transfer_csv = create_transfer_csv_with_fee(&[
+        ("2025-06-15", "cold-storage", "exchange", "0.50000000", "0.0001 BTC", "$60,000.00"),
+    ]);
+    transfer(&transfer_csv.path().to_path_buf(), &mut conn, &config, false).unwrap();
+
+    // A transfer fee is paid from the wallet's earliest lot whatever TAX_LOT_METHOD says
+    let rows = run_selection_report(&mut conn);
+    let selections: Vec<(&str, &str, &str, &str)> = rows.iter()
+        .map(|r| (r.get(2).unwrap(), r.get(6).unwrap(), r.get(10).unwrap(), r.get(11).unwrap()))
+        .collect();
+    assert_eq!(selections, vec![
+        ("1", "30000", "first in, first out", "fifo"),
+        ("2", "50000", "passed over; first in, first out", "fifo"),
+    ]);
+}
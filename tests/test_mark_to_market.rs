@@ -1,8 +1,12 @@
 mod common;
 
-use common::{setup_test_db, create_test_csv, default_config, get_acquisitions, get_fair_value_count, get_acq_fair_value_count};
+use common::{
+    setup_test_db, create_test_csv, default_config, get_acquisitions, get_fair_value_count, get_acq_fair_value_count,
+    get_gaap_acq_disps, get_tax_acq_disps,
+};
 use rust_rgl_ledger::commands::import::import_transactions;
 use rust_rgl_ledger::commands::mark_to_market::mark_to_market;
+use rust_decimal::Decimal;
 use std::sync::Mutex;
 
 static CWD_LOCK: Mutex<()> = Mutex::new(());
@@ -13,7 +17,7 @@ fn run_mtm_in_dir(price: &str, date: &str, conn: &mut diesel::SqliteConnection)
 
     let original_dir = std::env::current_dir().unwrap();
     std::env::set_current_dir(tmp.path()).unwrap();
-    mark_to_market(&price.to_string(), &date.to_string(), conn).unwrap();
+    mark_to_market(&price.to_string(), &date.to_string(), conn, &default_config(), false).unwrap();
     std::env::set_current_dir(original_dir).unwrap();
 }
 
@@ -123,3 +127,106 @@ fn test_mtm_successive_adjustments() {
     assert_eq!(get_fair_value_count(&mut conn), 2);
     assert_eq!(get_acq_fair_value_count(&mut conn), 2);
 }
+
+#[test]
+fn test_backdated_mtm_rematches_later_dispositions() {
+    let _lock = CWD_LOCK.lock().unwrap();
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv(&[
+        ("01/01/2024", "1.00000000", "$40,000.00"),
+        ("08/01/2024", "-0.40000000", "$60,000.00"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+    assert_eq!(get_gaap_acq_disps(&mut conn)[0].basis, 1_600_000);
+
+    let tmp = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir_all(tmp.path().join("reports")).unwrap();
+    let original_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(tmp.path()).unwrap();
+    let summary = mark_to_market(&"$50,000.00".to_string(), &"06/30/2024".to_string(), &mut conn, &config, false).unwrap();
+    let report = std::fs::read_to_string("./reports/mark-to-market-2024-06-30.csv").unwrap();
+    std::env::set_current_dir(original_dir).unwrap();
+
+    assert_eq!(summary.lots_marked, 1);
+    assert_eq!(summary.rematched, 1);
+    // The whole lot was held on the mark date, so the report shows 1 BTC going from $40k to $50k
+    let mut rdr = csv::Reader::from_reader(report.as_bytes());
+    let row = rdr.records().next().unwrap().unwrap();
+    assert_eq!(row[3].parse::<Decimal>().unwrap(), Decimal::from(1));
+    assert_eq!(row[7].parse::<Decimal>().unwrap(), Decimal::from(10_000));
+
+    let gaap = get_gaap_acq_disps(&mut conn);
+    assert_eq!(gaap[0].basis, 2_000_000);
+    assert_eq!(gaap[0].rgl, 400_000);
+    assert_eq!(get_tax_acq_disps(&mut conn)[0].basis, 1_600_000);
+    assert_eq!(get_acquisitions(&mut conn)[0].usd_cents_btc_fair_value, 5_000_000);
+}
+
+#[test]
+fn test_mtm_before_a_later_mark_is_refused() {
+    let _lock = CWD_LOCK.lock().unwrap();
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv(&[("01/01/2024", "1.00000000", "$40,000.00")]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+    run_mtm_in_dir("$50,000.00", "06/30/2024", &mut conn);
+
+    let err = mark_to_market(&"$45,000.00".to_string(), &"03/31/2024".to_string(), &mut conn, &config, false).unwrap_err();
+    assert!(err.to_string().starts_with("A mark to market dated 2024-06-30 already exists"), "got: {}", err);
+    assert_eq!(get_fair_value_count(&mut conn), 1);
+    assert_eq!(get_acquisitions(&mut conn)[0].usd_cents_btc_fair_value, 5_000_000);
+}
+
+#[test]
+fn test_mtm_rematches_gaap_but_leaves_tax_matches_alone() {
+    let _lock = CWD_LOCK.lock().unwrap();
+    let mut conn = setup_test_db();
+    let csv = create_test_csv(&[
+        ("01/01/2024", "1.00000000", "$40,000.00"),
+        ("02/01/2024", "1.00000000", "$42,000.00"),
+        ("09/01/2024", "-0.50000000", "$60,000.00"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &default_config()).unwrap();
+    let tax_before = get_tax_acq_disps(&mut conn);
+
+    // The tax method changed since the sale was matched; a GAAP mark must not apply it
+    let config = rust_rgl_ledger::LotConfig { tax_lot_method: "lifo".to_string(), ..default_config() };
+    let tmp = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir_all(tmp.path().join("reports")).unwrap();
+    let original_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(tmp.path()).unwrap();
+    let summary = mark_to_market(&"$50,000.00".to_string(), &"06/30/2024".to_string(), &mut conn, &config, false).unwrap();
+    std::env::set_current_dir(original_dir).unwrap();
+
+    assert_eq!(summary.rematched, 1);
+    assert_eq!(get_gaap_acq_disps(&mut conn)[0].basis, 2_500_000);
+    assert_eq!(get_tax_acq_disps(&mut conn), tax_before);
+}
+
+#[test]
+fn test_mtm_dry_run_saves_nothing() {
+    let _lock = CWD_LOCK.lock().unwrap();
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv(&[
+        ("01/01/2024", "1.00000000", "$40,000.00"),
+        ("09/01/2024", "-0.50000000", "$60,000.00"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    let tmp = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir_all(tmp.path().join("reports")).unwrap();
+    let original_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(tmp.path()).unwrap();
+    let summary = mark_to_market(&"$50,000.00".to_string(), &"06/30/2024".to_string(), &mut conn, &config, true).unwrap();
+    let report_written = std::path::Path::new("./reports/mark-to-market-2024-06-30.csv").exists();
+    std::env::set_current_dir(original_dir).unwrap();
+
+    assert_eq!(summary.lots_marked, 1);
+    assert_eq!(summary.changes.matches_created.len(), 1);
+    assert_eq!(summary.changes.matches_created[0].basis, 2_500_000);
+    assert!(!report_written);
+    assert_eq!(get_fair_value_count(&mut conn), 0);
+    assert_eq!(get_gaap_acq_disps(&mut conn)[0].basis, 2_000_000);
+}
@@ -0,0 +1,115 @@
+mod common;
+
+use common::{
+    setup_test_db, create_test_csv, create_test_csv_with_wallet, create_transfer_csv_with_fee, default_config,
+    get_acquisitions, get_dispositions, get_tax_acq_disps, get_fair_value_count,
+};
+use diesel::prelude::*;
+use rust_rgl_ledger::commands::edit_transaction::{edit_transaction, TransactionEdit};
+use rust_rgl_ledger::commands::import::import_transactions;
+use rust_rgl_ledger::commands::mark_to_market::mark_to_market;
+use rust_rgl_ledger::commands::period::{close_period, reopen_period, locked_through};
+use rust_rgl_ledger::commands::transfer::transfer;
+use rust_rgl_ledger::models::PeriodLock;
+use rust_rgl_ledger::schema::period_locks;
+
+fn setup_closed_quarter(conn: &mut SqliteConnection) {
+    let csv = create_test_csv(&[
+        ("01/01/2024", "1.00000000", "$40,000.00"),
+        ("02/01/2024", "-0.50000000", "$50,000.00"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), conn, &default_config()).unwrap();
+    close_period("03/31/2024", conn).unwrap();
+}
+
+#[test]
+fn test_import_into_closed_period_is_rejected() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    setup_closed_quarter(&mut conn);
+
+    let sale = create_test_csv(&[("03/15/2024", "-0.10000000", "$60,000.00")]);
+    let err = import_transactions(&sale.path().to_path_buf(), &mut conn, &config).unwrap_err();
+    assert!(err.contains("closed through 2024-03-31"), "got: {}", err);
+    assert_eq!(get_dispositions(&mut conn).len(), 1);
+
+    // A cheaper lot dated before the sale would take over its FIFO match
+    let backdated = create_test_csv(&[("12/01/2023", "1.00000000", "$30,000.00")]);
+    let err = import_transactions(&backdated.path().to_path_buf(), &mut conn, &config).unwrap_err();
+    assert!(err.contains("match for disposition 1"), "got: {}", err);
+    assert_eq!(get_acquisitions(&mut conn).len(), 1);
+    assert!(get_tax_acq_disps(&mut conn).iter().all(|m| m.rgl == 500_000));
+}
+
+#[test]
+fn test_changes_that_leave_closed_matches_alone_are_allowed() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    setup_closed_quarter(&mut conn);
+
+    // A lot bought after the first one is replayed behind it, so the closed match is unchanged
+    let csv = create_test_csv(&[
+        ("01/15/2024", "1.00000000", "$30,000.00"),
+        ("04/15/2024", "-0.75000000", "$60,000.00"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+    assert_eq!(get_tax_acq_disps(&mut conn).len(), 3);
+}
+
+#[test]
+fn test_edit_and_mark_to_market_respect_lock() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    setup_closed_quarter(&mut conn);
+
+    let edit = TransactionEdit { price: Some("$45,000.00".to_string()), ..TransactionEdit::default() };
+    let err = edit_transaction("acquisition", 1, &edit, &mut conn, &config).unwrap_err();
+    assert!(err.contains("closed through 2024-03-31"), "got: {}", err);
+    assert_eq!(get_acquisitions(&mut conn)[0].usd_cents_btc_basis, 4_000_000);
+
+    let err = mark_to_market(&"$45,000.00".to_string(), &"03/31/2024".to_string(), &mut conn, &config, false).unwrap_err();
+    assert!(err.to_string().starts_with("The mark to market is dated 2024-03-31"), "got: {}", err);
+    assert_eq!(get_fair_value_count(&mut conn), 0);
+}
+
+#[test]
+fn test_transfer_fee_in_closed_period_is_rejected() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv_with_wallet(&[("2024-01-01", "1.00000000", "$40,000.00", "cold-storage")]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+    close_period("2024-03-31", &mut conn).unwrap();
+
+    let transfer_csv = create_transfer_csv_with_fee(&[
+        ("2024-02-15", "cold-storage", "exchange", "0.50000000", "0.0001", "$50,000.00"),
+    ]);
+    let err = transfer(&transfer_csv.path().to_path_buf(), &mut conn, &config, false).unwrap_err();
+    assert!(err.contains("closed through 2024-03-31"), "got: {}", err);
+    assert!(get_dispositions(&mut conn).is_empty());
+}
+
+#[test]
+fn test_reopen_period_logs_reason() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    setup_closed_quarter(&mut conn);
+    close_period("06/30/2024", &mut conn).unwrap();
+    assert!(close_period("05/31/2024", &mut conn).unwrap_err().contains("already closed through 2024-06-30"));
+
+    assert!(reopen_period("  ", &mut conn).is_err());
+    let lock = reopen_period("Late exchange statement for June", &mut conn).unwrap();
+    assert_eq!(lock.locked_through.format("%Y-%m-%d").to_string(), "2024-06-30");
+    assert_eq!(locked_through(&mut conn).unwrap().unwrap().format("%Y-%m-%d").to_string(), "2024-03-31");
+
+    let locks: Vec<PeriodLock> = period_locks::table.order(period_locks::id.asc()).select(PeriodLock::as_select()).load(&mut conn).unwrap();
+    assert!(locks[0].reopened_at.is_none());
+    assert!(locks[1].reopened_at.is_some());
+    assert_eq!(locks[1].reopen_reason.as_deref(), Some("Late exchange statement for June"));
+
+    reopen_period("Restating Q1", &mut conn).unwrap();
+    assert!(locked_through(&mut conn).unwrap().is_none());
+    assert_eq!(reopen_period("Again", &mut conn).unwrap_err(), "No period is closed");
+
+    let sale = create_test_csv(&[("03/15/2024", "-0.10000000", "$60,000.00")]);
+    import_transactions(&sale.path().to_path_buf(), &mut conn, &config).unwrap();
+}
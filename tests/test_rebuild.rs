@@ -0,0 +1,220 @@
+mod common;
+
+use common::{setup_test_db, create_test_csv, create_test_csv_with_wallet, create_transfer_csv, default_config, lot_config,
+             get_acquisitions, get_dispositions, get_acq_disps, get_tax_acq_disps, get_gaap_acq_disps};
+use diesel::prelude::*;
+use rust_rgl_ledger::commands::check::check_ledger;
+use rust_rgl_ledger::commands::import::import_transactions;
+use rust_rgl_ledger::commands::mark_to_market::mark_to_market;
+use rust_rgl_ledger::commands::rebuild::rebuild;
+use rust_rgl_ledger::commands::transfer::transfer;
+use rust_rgl_ledger::schema::acquisitions;
+use rust_rgl_ledger::LotConfig;
+use std::sync::Mutex;
+
+static CWD_LOCK: Mutex<()> = Mutex::new(());
+
+fn run_mtm_in_dir(price: &str, date: &str, conn: &mut diesel::SqliteConnection) {
+    let tmp = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir_all(tmp.path().join("reports")).unwrap();
+
+    let original_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(tmp.path()).unwrap();
+    mark_to_market(&price.to_string(), &date.to_string(), conn, &default_config(), false).unwrap();
+    std::env::set_current_dir(original_dir).unwrap();
+}
+
+#[test]
+fn test_rebuild_with_same_config_is_stable() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv(&[
+        ("01/01/2025", "1.00000000", "$30,000.00"),
+        ("02/01/2025", "1.00000000", "$50,000.00"),
+        ("06/01/2025", "-1.50000000", "$60,000.00"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+    let before = get_acq_disps(&mut conn);
+
+    let summary = rebuild(&mut conn, &config).unwrap();
+    assert_eq!(summary.matches_removed, 4);
+    assert_eq!(summary.matches_created, 4);
+    assert_eq!(summary.tax_rgl_before, summary.tax_rgl_after);
+    assert_eq!(summary.gaap_rgl_before, summary.gaap_rgl_after);
+    assert_eq!(get_acq_disps(&mut conn), before);
+}
+
+#[test]
+fn test_rebuild_switches_tax_method() {
+    let mut conn = setup_test_db();
+    let csv = create_test_csv(&[
+        ("01/01/2025", "1.00000000", "$30,000.00"),
+        ("02/01/2025", "1.00000000", "$50,000.00"),
+        ("06/01/2025", "-1.00000000", "$60,000.00"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &default_config()).unwrap();
+
+    let summary = rebuild(&mut conn, &lot_config("hifo", "wallet")).unwrap();
+    assert_eq!(summary.tax_rgl_before, 3_000_000);
+    assert_eq!(summary.tax_rgl_after, 1_000_000, "HIFO relieves the $50k lot");
+    assert_eq!(summary.gaap_rgl_before, summary.gaap_rgl_after, "GAAP stays FIFO");
+
+    let acqs = get_acquisitions(&mut conn);
+    assert_eq!(acqs[0].tax_undisposed_satoshis, 100_000_000);
+    assert_eq!(acqs[1].tax_undisposed_satoshis, 0);
+    assert_eq!(acqs[0].undisposed_satoshis, 0);
+}
+
+#[test]
+fn test_rebuild_switches_tax_scope() {
+    let mut conn = setup_test_db();
+    let csv = create_test_csv_with_wallet(&[
+        ("01/01/2025", "1.00000000", "$30,000.00", "ledger"),
+        ("02/01/2025", "1.00000000", "$50,000.00", "coinbase"),
+        ("06/01/2025", "-0.50000000", "$60,000.00", "coinbase"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &default_config()).unwrap();
+    let acqs = get_acquisitions(&mut conn);
+    let coinbase = acqs.iter().find(|a| a.wallet == "coinbase").unwrap().id;
+    let ledger = acqs.iter().find(|a| a.wallet == "ledger").unwrap().id;
+    assert_eq!(get_tax_acq_disps(&mut conn)[0].acquisition_id, coinbase);
+
+    rebuild(&mut conn, &lot_config("fifo", "universal")).unwrap();
+    assert_eq!(get_tax_acq_disps(&mut conn)[0].acquisition_id, ledger);
+}
+
+#[test]
+fn test_rebuild_reapplies_marks_in_date_order() {
+    let _lock = CWD_LOCK.lock().unwrap();
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv(&[
+        ("01/01/2025", "2.00000000", "$30,000.00"),
+        ("02/01/2025", "-0.50000000", "$35,000.00"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+    run_mtm_in_dir("$45,000.00", "03/31/2025", &mut conn);
+    let csv2 = create_test_csv(&[("06/01/2025", "-0.50000000", "$60,000.00")]);
+    import_transactions(&csv2.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    rebuild(&mut conn, &config).unwrap();
+
+    let gaap_ads = get_gaap_acq_disps(&mut conn);
+    let disps = get_dispositions(&mut conn);
+    let basis_for = |disp_id: i32| gaap_ads.iter().find(|ad| ad.disposition_id == disp_id).unwrap().basis;
+    assert_eq!(basis_for(disps[0].id), 1_500_000, "February sale precedes the mark and is relieved at cost");
+    assert_eq!(basis_for(disps[1].id), 2_250_000, "June sale is relieved at the March mark");
+    assert_eq!(get_acquisitions(&mut conn)[0].usd_cents_btc_fair_value, 4_500_000);
+}
+
+#[test]
+fn test_rebuild_to_average_cost_with_marks() {
+    let _lock = CWD_LOCK.lock().unwrap();
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv(&[
+        ("01/01/2025", "1.00000000", "$30,000.00"),
+        ("02/01/2025", "1.00000000", "$50,000.00"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+    run_mtm_in_dir("$45,000.00", "03/31/2025", &mut conn);
+    let csv2 = create_test_csv(&[
+        ("04/01/2025", "1.00000000", "$60,000.00"),
+        ("05/01/2025", "-1.00000000", "$65,000.00"),
+    ]);
+    import_transactions(&csv2.path().to_path_buf(), &mut conn, &config).unwrap();
+    assert_eq!(get_gaap_acq_disps(&mut conn)[0].basis, 4_500_000, "FIFO relieves the marked January lot");
+
+    let average = LotConfig { gaap_lot_method: "average".to_string(), ..LotConfig::default() };
+    rebuild(&mut conn, &average).unwrap();
+
+    // Pool on May 1: 2 BTC at the $45k mark + 1 BTC at $60k cost = $50k average
+    let basis: i64 = get_gaap_acq_disps(&mut conn).iter().map(|ad| ad.basis).sum();
+    assert_eq!(basis, 5_000_000);
+}
+
+#[test]
+fn test_rebuild_repairs_corrupted_trackers() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv(&[
+        ("01/01/2025", "1.00000000", "$30,000.00"),
+        ("06/01/2025", "-0.25000000", "$60,000.00"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    diesel::update(acquisitions::table)
+        .set(acquisitions::tax_undisposed_satoshis.eq(1))
+        .execute(&mut conn)
+        .unwrap();
+
+    rebuild(&mut conn, &config).unwrap();
+    let acqs = get_acquisitions(&mut conn);
+    assert_eq!(acqs[0].tax_undisposed_satoshis, 75_000_000);
+    assert_eq!(acqs[0].undisposed_satoshis, 75_000_000);
+}
+
+#[test]
+fn test_rebuild_failure_rolls_back() {
+    let mut conn = setup_test_db();
+    let csv = create_test_csv_with_wallet(&[
+        ("01/01/2025", "1.00000000", "$30,000.00", "ledger"),
+        ("06/01/2025", "-0.50000000", "$60,000.00", "coinbase"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &lot_config("fifo", "universal")).unwrap();
+
+    // Wallet scope leaves the coinbase sale without lots
+    let err = rebuild(&mut conn, &default_config()).unwrap_err();
+    assert!(err.contains("in wallet 'coinbase'"), "got: {}", err);
+    assert_eq!(get_tax_acq_disps(&mut conn).len(), 1, "Existing matches are kept");
+}
+
+/// Buys 1 BTC in wallet x, sells 0.5 from x, then moves `moved` BTC from x to y.
+fn setup_sale_then_transfer(conn: &mut diesel::SqliteConnection, moved: &str) {
+    let config = default_config();
+    let csv = create_test_csv_with_wallet(&[
+        ("01/01/2025", "1.00000000", "$30,000.00", "x"),
+        ("02/01/2025", "-0.50000000", "$40,000.00", "x"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), conn, &config).unwrap();
+    let transfer_csv = create_transfer_csv(&[("03/01/2025", "x", "y", moved)]);
+    transfer(&transfer_csv.path().to_path_buf(), conn, &config, false).unwrap();
+}
+
+#[test]
+fn test_rebuild_after_whole_lot_transfer() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    setup_sale_then_transfer(&mut conn, "0.5");
+    let before = get_acq_disps(&mut conn);
+
+    rebuild(&mut conn, &config).unwrap();
+
+    // The February sale drew on the lot while it was still in x
+    assert_eq!(get_acq_disps(&mut conn), before);
+    let acqs = get_acquisitions(&mut conn);
+    assert_eq!(acqs.len(), 1);
+    assert_eq!((acqs[0].wallet.as_str(), acqs[0].tax_undisposed_satoshis), ("y", 50_000_000));
+    let report = check_ledger(&mut conn, &config).unwrap();
+    assert!(report.violations.is_empty(), "got: {:?}", report.violations);
+}
+
+#[test]
+fn test_rebuild_after_split_transfer() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    setup_sale_then_transfer(&mut conn, "0.25");
+
+    rebuild(&mut conn, &config).unwrap();
+
+    let acqs = get_acquisitions(&mut conn);
+    let x = acqs.iter().find(|a| a.wallet == "x").unwrap();
+    let y = acqs.iter().find(|a| a.wallet == "y").unwrap();
+    assert_eq!(x.tax_undisposed_satoshis, 25_000_000);
+    assert_eq!(y.tax_undisposed_satoshis, 25_000_000, "The moved quarter was never sold");
+    let tax_ads = get_tax_acq_disps(&mut conn);
+    assert_eq!(tax_ads.len(), 1);
+    assert_eq!((tax_ads[0].acquisition_id, tax_ads[0].satoshis), (x.id, 50_000_000));
+    let report = check_ledger(&mut conn, &config).unwrap();
+    assert!(report.violations.is_empty(), "got: {:?}", report.violations);
+}
@@ -0,0 +1,235 @@
+mod common;
+
+use common::{setup_test_db, create_test_csv, create_test_csv_with_wallet, create_transfer_csv,
+             default_config, lot_config,
+             get_acquisitions, get_dispositions, get_tax_acq_disps, get_gaap_acq_disps};
+use rust_rgl_ledger::commands::import::import_transactions;
+use rust_rgl_ledger::commands::mark_to_market::mark_to_market;
+use rust_rgl_ledger::commands::transfer::transfer;
+use rust_rgl_ledger::commands::undo_import::undo_import;
+use rust_rgl_ledger::LotConfig;
+use std::sync::Mutex;
+
+static CWD_LOCK: Mutex<()> = Mutex::new(());
+
+fn run_mtm_in_dir(price: &str, date: &str, conn: &mut diesel::SqliteConnection) {
+    let tmp = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir_all(tmp.path().join("reports")).unwrap();
+
+    let original_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(tmp.path()).unwrap();
+    mark_to_market(&price.to_string(), &date.to_string(), conn, &default_config(), false).unwrap();
+    std::env::set_current_dir(original_dir).unwrap();
+}
+
+#[test]
+fn test_later_rows_do_not_rematch() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv(&[
+        ("01/01/2025", "1.00000000", "$30,000.00"),
+        ("02/01/2025", "-0.50000000", "$40,000.00"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    let csv2 = create_test_csv(&[("03/01/2025", "-0.25000000", "$45,000.00")]);
+    let summary = import_transactions(&csv2.path().to_path_buf(), &mut conn, &config).unwrap();
+    assert_eq!(summary.rematched, 0);
+    assert_eq!(get_tax_acq_disps(&mut conn).len(), 2);
+}
+
+#[test]
+fn test_backdated_acquisition_rematches_fifo() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv(&[
+        ("02/01/2025", "1.00000000", "$50,000.00"),
+        ("06/01/2025", "-0.50000000", "$60,000.00"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    // A January purchase turns up later; FIFO says it should have been sold first
+    let csv2 = create_test_csv(&[("01/01/2025", "1.00000000", "$30,000.00")]);
+    let summary = import_transactions(&csv2.path().to_path_buf(), &mut conn, &config).unwrap();
+    assert_eq!(summary.rematched, 2, "One tax and one GAAP match are replayed");
+
+    let acqs = get_acquisitions(&mut conn);
+    let jan = acqs.iter().find(|a| a.usd_cents_btc_basis == 3_000_000).unwrap();
+    let feb = acqs.iter().find(|a| a.usd_cents_btc_basis == 5_000_000).unwrap();
+    assert_eq!(jan.tax_undisposed_satoshis, 50_000_000);
+    assert_eq!(jan.undisposed_satoshis, 50_000_000);
+    assert_eq!(feb.tax_undisposed_satoshis, 100_000_000, "Feb lot is restored");
+    assert_eq!(feb.undisposed_satoshis, 100_000_000, "Feb lot is restored");
+
+    for ads in [get_tax_acq_disps(&mut conn), get_gaap_acq_disps(&mut conn)] {
+        assert_eq!(ads.len(), 1);
+        assert_eq!(ads[0].acquisition_id, jan.id);
+        assert_eq!(ads[0].basis, 1_500_000);
+        assert_eq!(ads[0].rgl, 1_500_000);
+    }
+}
+
+#[test]
+fn test_backdated_disposition_rematches_later_dispositions() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv(&[
+        ("01/01/2025", "1.00000000", "$30,000.00"),
+        ("02/01/2025", "1.00000000", "$50,000.00"),
+        ("06/01/2025", "-1.00000000", "$60,000.00"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    let csv2 = create_test_csv(&[("03/01/2025", "-0.50000000", "$55,000.00")]);
+    let summary = import_transactions(&csv2.path().to_path_buf(), &mut conn, &config).unwrap();
+    assert_eq!(summary.rematched, 2);
+
+    let disps = get_dispositions(&mut conn);
+    let march = disps.iter().find(|d| d.satoshis == -50_000_000).unwrap();
+    let june = disps.iter().find(|d| d.satoshis == -100_000_000).unwrap();
+
+    let tax_ads = get_tax_acq_disps(&mut conn);
+    let march_ads: Vec<_> = tax_ads.iter().filter(|ad| ad.disposition_id == march.id).collect();
+    let june_ads: Vec<_> = tax_ads.iter().filter(|ad| ad.disposition_id == june.id).collect();
+    assert_eq!(march_ads.len(), 1);
+    assert_eq!(march_ads[0].basis, 1_500_000, "March sale takes the first half of the Jan lot");
+    assert_eq!(june_ads.len(), 2, "June sale now spans both lots");
+    assert_eq!(june_ads.iter().map(|ad| ad.basis).sum::<i64>(), 1_500_000 + 2_500_000);
+
+    for d in get_dispositions(&mut conn) {
+        assert_eq!(d.undisposed_satoshis, 0);
+        assert_eq!(d.tax_undisposed_satoshis, 0);
+    }
+}
+
+#[test]
+fn test_backdated_disposition_uses_carrying_value_as_of_date() {
+    let _lock = CWD_LOCK.lock().unwrap();
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv(&[
+        ("01/01/2025", "1.00000000", "$30,000.00"),
+        ("05/01/2025", "-0.50000000", "$50,000.00"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+    run_mtm_in_dir("$60,000.00", "06/30/2025", &mut conn);
+
+    let csv2 = create_test_csv(&[("04/01/2025", "-0.25000000", "$40,000.00")]);
+    import_transactions(&csv2.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    // Both sales precede the June mark, so GAAP relieves them at cost
+    let gaap_ads = get_gaap_acq_disps(&mut conn);
+    assert_eq!(gaap_ads.len(), 2);
+    let total_basis: i64 = gaap_ads.iter().map(|ad| ad.basis).sum();
+    assert_eq!(total_basis, 750_000 + 1_500_000);
+
+    let acqs = get_acquisitions(&mut conn);
+    assert_eq!(acqs[0].undisposed_satoshis, 25_000_000);
+    assert_eq!(acqs[0].usd_cents_btc_fair_value, 6_000_000, "Remaining sats keep the June mark");
+}
+
+#[test]
+fn test_backdated_acquisition_under_average_cost() {
+    let mut conn = setup_test_db();
+    let config = LotConfig {
+        gaap_lot_method: "average".to_string(),
+        ..LotConfig::default()
+    };
+    let csv = create_test_csv(&[
+        ("01/01/2025", "1.00000000", "$30,000.00"),
+        ("02/01/2025", "1.00000000", "$50,000.00"),
+        ("06/01/2025", "-1.00000000", "$60,000.00"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+    assert_eq!(get_gaap_acq_disps(&mut conn).iter().map(|ad| ad.basis).sum::<i64>(), 4_000_000);
+
+    let csv2 = create_test_csv(&[("03/01/2025", "1.00000000", "$70,000.00")]);
+    import_transactions(&csv2.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    // Pool on June 1 is now $30k + $50k + $70k = $50k average
+    assert_eq!(get_gaap_acq_disps(&mut conn).iter().map(|ad| ad.basis).sum::<i64>(), 5_000_000);
+    let acqs = get_acquisitions(&mut conn);
+    assert!(acqs.iter().all(|a| a.usd_cents_btc_fair_value == 5_000_000));
+}
+
+#[test]
+fn test_failed_rematch_rolls_back() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv(&[
+        ("01/01/2025", "1.00000000", "$30,000.00"),
+        ("06/01/2025", "-1.00000000", "$60,000.00"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    // Backdated sale leaves the June sale short of lots
+    let csv2 = create_test_csv(&[("03/01/2025", "-0.50000000", "$55,000.00")]);
+    let err = import_transactions(&csv2.path().to_path_buf(), &mut conn, &config).unwrap_err();
+    assert!(err.contains("rolled back"), "got: {}", err);
+
+    assert_eq!(get_dispositions(&mut conn).len(), 1);
+    let tax_ads = get_tax_acq_disps(&mut conn);
+    assert_eq!(tax_ads.len(), 1);
+    assert_eq!(tax_ads[0].satoshis, 100_000_000, "Original match is intact");
+    assert_eq!(get_acquisitions(&mut conn)[0].tax_undisposed_satoshis, 0);
+}
+
+/// Buys 1 BTC in wallet x, sells 0.5 from x, then moves `moved` BTC from x to y.
+fn setup_sale_then_transfer(conn: &mut diesel::SqliteConnection, config: &LotConfig, moved: &str) {
+    let csv = create_test_csv_with_wallet(&[
+        ("01/01/2025", "1.00000000", "$30,000.00", "x"),
+        ("02/01/2025", "-0.50000000", "$40,000.00", "x"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), conn, config).unwrap();
+    let transfer_csv = create_transfer_csv(&[("03/01/2025", "x", "y", moved)]);
+    transfer(&transfer_csv.path().to_path_buf(), conn, config, false).unwrap();
+}
+
+#[test]
+fn test_replay_after_whole_lot_transfer_uses_wallet_on_disposition_date() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    setup_sale_then_transfer(&mut conn, &config, "0.5");
+    assert_eq!(get_acquisitions(&mut conn)[0].wallet, "y", "Whole remainder moved, no split");
+
+    // Unrelated backdated buy replays the February sale, which drew on the lot while it was in x
+    let csv = create_test_csv_with_wallet(&[("01/15/2025", "0.20000000", "$35,000.00", "z")]);
+    let summary = import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    let acqs = get_acquisitions(&mut conn);
+    assert_eq!(acqs[0].tax_undisposed_satoshis, 50_000_000);
+    assert_eq!(acqs[1].tax_undisposed_satoshis, 20_000_000, "Wallet z lot is untouched");
+    let tax_ads = get_tax_acq_disps(&mut conn);
+    assert_eq!(tax_ads.len(), 1);
+    assert_eq!(tax_ads[0].acquisition_id, acqs[0].id);
+
+    undo_import(summary.batch_id, &mut conn, &config).unwrap();
+    assert_eq!(get_tax_acq_disps(&mut conn).len(), 1);
+    assert_eq!(get_acquisitions(&mut conn)[0].tax_undisposed_satoshis, 50_000_000);
+}
+
+#[test]
+fn test_replay_after_split_transfer_does_not_draw_moved_satoshis() {
+    let mut conn = setup_test_db();
+    // LIFO would take the transfer child first if it were treated as sitting in x in February
+    let config = lot_config("lifo", "wallet");
+    setup_sale_then_transfer(&mut conn, &config, "0.25");
+
+    let csv = create_test_csv_with_wallet(&[("01/15/2025", "0.20000000", "$35,000.00", "z")]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    let acqs = get_acquisitions(&mut conn);
+    let parent = acqs.iter().find(|a| a.wallet == "x").unwrap();
+    let child = acqs.iter().find(|a| a.wallet == "y").unwrap();
+    assert_eq!(parent.tax_undisposed_satoshis, 25_000_000);
+    assert_eq!(child.tax_undisposed_satoshis, 25_000_000);
+    let tax_ads = get_tax_acq_disps(&mut conn);
+    assert_eq!(tax_ads.len(), 1);
+    assert_eq!(tax_ads[0].acquisition_id, parent.id);
+    assert_eq!(tax_ads[0].satoshis, 50_000_000);
+
+    // The moved quarter only reached y in March, so a February sale from y has nothing to draw on
+    let csv = create_test_csv_with_wallet(&[("02/15/2025", "-0.10000000", "$40,000.00", "y")]);
+    let err = import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap_err();
+    assert!(err.contains("rolled back"), "got: {}", err);
+}
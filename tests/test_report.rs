@@ -189,8 +189,8 @@ fn test_report_date_filtering() {
 
     // Detail rows: non-empty first column and term in column 8
     let detail_rows: Vec<_> = rows.iter().filter(|r| {
-        r.get(0).map_or(false, |s| !s.is_empty()) &&
-        r.get(8).map_or(false, |s| s == "short" || s == "long")
+        r.get(0).is_some_and(|s| !s.is_empty()) &&
+        r.get(8).is_some_and(|s| s == "short" || s == "long")
     }).collect();
 
     assert_eq!(detail_rows.len(), 1, "Tax report should have 1 detail row for Q1");
@@ -213,14 +213,14 @@ fn test_report_totals_match_sum() {
 
     // Tax: columns are AcquisitionDate(0), DispositionDate(1), DisposedBtc(2), CostPerBtc(3), DisposalFmvPerBtc(4), DisposalFmv(5), Basis(6), Rgl(7), Term(8)
     let detail_rows: Vec<_> = rows.iter().filter(|r| {
-        r.get(0).map_or(false, |s| !s.is_empty()) &&
-        r.get(8).map_or(false, |s| s == "short")
+        r.get(0).is_some_and(|s| !s.is_empty()) &&
+        r.get(8).is_some_and(|s| s == "short")
     }).collect();
 
     let total_rows: Vec<_> = rows.iter().filter(|r| {
-        r.get(0).map_or(false, |s| s.is_empty()) &&
-        r.get(2).map_or(false, |s| !s.is_empty()) &&
-        r.get(8).map_or(false, |s| s == "short")
+        r.get(0).is_some_and(|s| s.is_empty()) &&
+        r.get(2).is_some_and(|s| !s.is_empty()) &&
+        r.get(8).is_some_and(|s| s == "short")
     }).collect();
 
     assert!(!detail_rows.is_empty(), "Should have detail rows");
@@ -250,8 +250,8 @@ fn test_report_disposal_fmv_minus_basis_equals_rgl() {
     let rows = parse_report_rows(&tax);
 
     let detail_rows: Vec<_> = rows.iter().filter(|r| {
-        r.get(0).map_or(false, |s| !s.is_empty()) &&
-        r.get(8).map_or(false, |s| s == "short" || s == "long")
+        r.get(0).is_some_and(|s| !s.is_empty()) &&
+        r.get(8).is_some_and(|s| s == "short" || s == "long")
     }).collect();
 
     for row in &detail_rows {
@@ -292,8 +292,8 @@ fn test_gaap_report_has_fmv_disposed_column() {
     // Without MTM, fmv_disposed should be 0 and cost_basis should equal basis
     let rows: Vec<csv::StringRecord> = rdr.records().filter_map(|r| r.ok()).collect();
     let detail_rows: Vec<_> = rows.iter().filter(|r| {
-        r.get(0).map_or(false, |s| !s.is_empty()) &&
-        r.get(11).map_or(false, |s| s == "short" || s == "long")
+        r.get(0).is_some_and(|s| !s.is_empty()) &&
+        r.get(11).is_some_and(|s| s == "short" || s == "long")
     }).collect();
 
     for row in &detail_rows {
@@ -321,8 +321,8 @@ fn test_tax_report_has_no_fmv_disposed_column() {
     let mut rdr = csv::Reader::from_reader(tax.as_bytes());
     let headers = rdr.headers().unwrap().clone();
 
-    // Tax report: 9 columns, no FmvDisposed
-    assert_eq!(headers.len(), 9, "Tax report should have 9 columns");
+    // Tax report: 10 columns ending in Fee, no FmvDisposed
+    assert_eq!(headers.len(), 10, "Tax report should have 10 columns");
     let header_names: Vec<&str> = (0..headers.len()).map(|i| headers.get(i).unwrap()).collect();
     assert!(!header_names.contains(&"FmvDisposed"), "Tax report should NOT have FmvDisposed column");
 }
@@ -0,0 +1,180 @@
+mod common;
+
+use common::{setup_test_db, create_test_csv, create_test_csv_with_wallet, create_transfer_csv, default_config};
+use rust_rgl_ledger::LotConfig;
+use rust_rgl_ledger::commands::import::import_transactions;
+use rust_rgl_ledger::commands::mark_to_market::mark_to_market;
+use rust_rgl_ledger::commands::rollforward::{rollforward, Rollforward};
+use rust_rgl_ledger::commands::transfer::transfer;
+use rust_decimal::Decimal;
+use std::sync::Mutex;
+
+static CWD_LOCK: Mutex<()> = Mutex::new(());
+
+fn run_in_reports_dir<T, F: FnOnce() -> T>(f: F) -> (T, String) {
+    let _lock = CWD_LOCK.lock().unwrap();
+    let tmp = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir_all(tmp.path().join("reports")).unwrap();
+
+    let original_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(tmp.path()).unwrap();
+    let result = f();
+    let content = std::fs::read_dir("./reports")
+        .unwrap()
+        .filter_map(|e| e.ok())
+        .find(|e| e.file_name().to_string_lossy().starts_with("rollforward_"))
+        .map(|e| std::fs::read_to_string(e.path()).unwrap())
+        .unwrap_or_default();
+    std::env::set_current_dir(original_dir).unwrap();
+    (result, content)
+}
+
+fn mtm(price: &str, date: &str, conn: &mut diesel::SqliteConnection, config: &LotConfig) {
+    run_in_reports_dir(|| mark_to_market(&price.to_string(), &date.to_string(), conn, config, false).unwrap());
+}
+
+fn run_rollforward(beg: &str, end: &str, conn: &mut diesel::SqliteConnection, config: &LotConfig) -> (Rollforward, String) {
+    run_in_reports_dir(|| rollforward(&beg.to_string(), &end.to_string(), conn, config).unwrap())
+}
+
+fn usd(dollars: i64) -> Decimal {
+    Decimal::from(dollars)
+}
+
+#[test]
+fn test_rollforward_reconciles_purchases_disposals_and_marks() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv(&[("12/01/2023", "1.00000000", "$40,000.00")]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+    mtm("$45,000.00", "12/31/2023", &mut conn, &config);
+    let csv = create_test_csv(&[("02/01/2024", "1.00000000", "$50,000.00")]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+    mtm("$60,000.00", "03/31/2024", &mut conn, &config);
+    let csv = create_test_csv(&[("05/01/2024", "-0.50000000", "$70,000.00")]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+    mtm("$80,000.00", "12/31/2024", &mut conn, &config);
+
+    let (summary, content) = run_rollforward("01/01/2024", "12/31/2024", &mut conn, &config);
+    assert_eq!(summary.opening_btc, Decimal::from(1));
+    assert_eq!(summary.opening, usd(45_000));
+    assert_eq!(summary.purchases, usd(50_000));
+    assert_eq!(summary.disposals_btc, Decimal::new(5, 1));
+    assert_eq!(summary.disposals, usd(30_000));
+    assert_eq!(summary.realized_gain, usd(5_000));
+    // $15k + $10k at the March mark, then $30k on the 1.5 BTC left at year end
+    assert_eq!(summary.remeasurement, usd(55_000));
+    assert_eq!(summary.rounding, usd(0));
+    assert_eq!(summary.closing, usd(120_000));
+
+    let mut rdr = csv::Reader::from_reader(content.as_bytes());
+    let rows: Vec<csv::StringRecord> = rdr.records().filter_map(|r| r.ok()).collect();
+    assert_eq!(&rows[0][0], "Opening balance 2023-12-31");
+    assert_eq!(&rows[2][0], "Disposals at carrying value");
+    assert_eq!(rows[2][2].parse::<Decimal>().unwrap(), usd(-30_000));
+    assert_eq!(&rows[5][0], "Closing balance 2024-12-31");
+    assert_eq!(rows[5][1].parse::<Decimal>().unwrap(), Decimal::new(15, 1));
+    assert_eq!(rows[6][2].parse::<Decimal>().unwrap(), usd(5_000));
+}
+
+#[test]
+fn test_rollforward_ties_for_a_quarter_inside_the_year() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv(&[
+        ("01/01/2024", "1.00000000", "$40,000.00"),
+        ("08/01/2024", "-0.25000000", "$65,000.00"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+    mtm("$60,000.00", "06/30/2024", &mut conn, &config);
+
+    // The sale after the mark is rematched at $60k, so the quarter opens at the mark
+    let (summary, _) = run_rollforward("07/01/2024", "09/30/2024", &mut conn, &config);
+    assert_eq!(summary.opening, usd(60_000));
+    assert_eq!(summary.disposals, usd(15_000));
+    assert_eq!(summary.realized_gain, usd(1_250));
+    assert_eq!(summary.remeasurement, usd(0));
+    assert_eq!(summary.closing, usd(45_000));
+}
+
+#[test]
+fn test_rollforward_ties_under_average_cost_with_transfers() {
+    let mut conn = setup_test_db();
+    let config = LotConfig { gaap_lot_method: "average".to_string(), ..default_config() };
+    let csv = create_test_csv_with_wallet(&[
+        ("2025-01-01", "1.00000000", "$30,000.00", "cold-storage"),
+        ("2025-02-01", "1.00000000", "$50,000.00", "cold-storage"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+    let transfer_csv = create_transfer_csv(&[("2025-03-01", "cold-storage", "exchange", "0.60000000")]);
+    transfer(&transfer_csv.path().to_path_buf(), &mut conn, &config, false).unwrap();
+    let sale = create_test_csv_with_wallet(&[("2025-06-01", "-0.50000000", "$60,000.00", "exchange")]);
+    import_transactions(&sale.path().to_path_buf(), &mut conn, &config).unwrap();
+    mtm("$70,000.00", "09/30/2025", &mut conn, &config);
+
+    let (summary, _) = run_rollforward("01/01/2025", "12/31/2025", &mut conn, &config);
+    assert_eq!(summary.opening, usd(0));
+    assert_eq!(summary.purchases_btc, Decimal::from(2));
+    assert_eq!(summary.purchases, usd(80_000));
+    assert_eq!(summary.disposals, usd(20_000));
+    assert_eq!(summary.realized_gain, usd(10_000));
+    // The 1.5 BTC left were at the $40k average before the mark
+    assert_eq!(summary.remeasurement, usd(45_000));
+    assert_eq!(summary.closing, usd(105_000));
+}
+
+#[test]
+fn test_rollforward_rejects_reversed_dates() {
+    let mut conn = setup_test_db();
+    let err = rollforward(&"12/31/2024".to_string(), &"01/01/2024".to_string(), &mut conn, &default_config()).unwrap_err();
+    assert!(err.to_string().contains("The ending date 2024-01-01 is before the beginning date 2024-12-31"));
+}
+
+#[test]
+fn test_rollforward_fails_when_it_does_not_tie() {
+    use diesel::prelude::*;
+    use rust_rgl_ledger::schema::acquisition_dispositions;
+
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv(&[
+        ("01/01/2024", "1.00000000", "$40,000.00"),
+        ("03/01/2024", "-0.50000000", "$50,000.00"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+    diesel::update(acquisition_dispositions::table.filter(acquisition_dispositions::match_type.eq("gaap")))
+        .set(acquisition_dispositions::basis.eq(1_900_000))
+        .execute(&mut conn)
+        .unwrap();
+
+    let (result, content) = run_in_reports_dir(|| rollforward(&"01/01/2024".to_string(), &"12/31/2024".to_string(), &mut conn, &config));
+    let err = result.unwrap_err().to_string();
+    assert!(err.contains("less disposals $19000.00"), "got: {}", err);
+    assert!(err.contains("is $21000.00, but holdings on 2024-12-31 are $20000.00"), "got: {}", err);
+    assert!(content.is_empty());
+}
+
+#[test]
+fn test_rollforward_shows_average_cost_restatement() {
+    let mut conn = setup_test_db();
+    let config = LotConfig { gaap_lot_method: "average".to_string(), ..default_config() };
+    // The pool averages $30,000.005, restated to $30,000.01 on each of 1,000 BTC
+    let csv = create_test_csv(&[
+        ("01/01/2025", "500.00000000", "$30,000.01"),
+        ("02/01/2025", "500.00000000", "$30,000.00"),
+        ("03/01/2025", "-1.00000000", "$40,000.00"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    let (summary, content) = run_rollforward("01/01/2025", "12/31/2025", &mut conn, &config);
+    assert_eq!(summary.restatement, Decimal::new(500, 2));
+    assert_eq!(summary.rounding, usd(0));
+    assert_eq!(summary.disposals, Decimal::new(3_000_001, 2));
+    assert_eq!(summary.closing, Decimal::new(2_997_000_999, 2));
+
+    let mut rdr = csv::Reader::from_reader(content.as_bytes());
+    let rows: Vec<csv::StringRecord> = rdr.records().filter_map(|r| r.ok()).collect();
+    assert_eq!(&rows[4][0], "Average cost restatement");
+    assert_eq!(rows[4][2].parse::<Decimal>().unwrap(), Decimal::new(500, 2));
+    assert_eq!(&rows[5][0], "Rounding");
+}
@@ -0,0 +1,141 @@
+mod common;
+
+use common::{setup_test_db, create_test_csv_with_lot, default_config,
+             get_acquisitions, get_dispositions, get_tax_acq_disps, get_gaap_acq_disps};
+use rust_rgl_ledger::commands::import::import_transactions;
+use rust_rgl_ledger::models::{parse_lot_designation, LotDesignation};
+
+#[test]
+fn test_designation_by_id_overrides_fifo() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv_with_lot(&[
+        ("01/01/2025", "1.00000000", "$30,000.00", "coinbase", ""),
+        ("02/01/2025", "1.00000000", "$50,000.00", "coinbase", ""),
+        ("06/01/2025", "-0.50000000", "$55,000.00", "coinbase", "2"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    let tax_ads = get_tax_acq_disps(&mut conn);
+    assert_eq!(tax_ads.len(), 1);
+    assert_eq!(tax_ads[0].acquisition_id, 2, "Tax should relieve the designated lot");
+    assert_eq!(tax_ads[0].basis, 2_500_000);
+
+    let gaap_ads = get_gaap_acq_disps(&mut conn);
+    assert_eq!(gaap_ads[0].acquisition_id, 1, "GAAP is unaffected by the designation");
+
+    let disps = get_dispositions(&mut conn);
+    assert_eq!(disps[0].lot_designation.as_deref(), Some("2"), "Designation is recorded on the disposition");
+}
+
+#[test]
+fn test_designation_by_date_wallet_amount() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv_with_lot(&[
+        ("01/01/2025", "1.00000000", "$30,000.00", "coinbase", ""),
+        ("02/01/2025", "0.75000000", "$50,000.00", "coinbase", ""),
+        ("06/01/2025", "-0.50000000", "$55,000.00", "coinbase", "02/01/2025|coinbase|0.75"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    let tax_ads = get_tax_acq_disps(&mut conn);
+    assert_eq!(tax_ads.len(), 1);
+    assert_eq!(tax_ads[0].acquisition_id, 2);
+
+    let acqs = get_acquisitions(&mut conn);
+    assert_eq!(acqs[0].tax_undisposed_satoshis, 100_000_000);
+    assert_eq!(acqs[1].tax_undisposed_satoshis, 25_000_000);
+}
+
+#[test]
+fn test_designation_multiple_lots_in_order() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv_with_lot(&[
+        ("01/01/2025", "1.00000000", "$30,000.00", "coinbase", ""),
+        ("02/01/2025", "1.00000000", "$50,000.00", "coinbase", ""),
+        ("03/01/2025", "1.00000000", "$40,000.00", "coinbase", ""),
+        ("06/01/2025", "-1.50000000", "$55,000.00", "coinbase", "3;1"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    let acqs = get_acquisitions(&mut conn);
+    assert_eq!(acqs[2].tax_undisposed_satoshis, 0, "Lot 3 is relieved first");
+    assert_eq!(acqs[0].tax_undisposed_satoshis, 50_000_000, "Lot 1 covers the remainder");
+    assert_eq!(acqs[1].tax_undisposed_satoshis, 100_000_000, "Lot 2 was not named");
+}
+
+#[test]
+fn test_designation_insufficient_sats_rejected() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv_with_lot(&[
+        ("01/01/2025", "1.00000000", "$30,000.00", "coinbase", ""),
+        ("02/01/2025", "0.25000000", "$50,000.00", "coinbase", ""),
+        ("06/01/2025", "-0.50000000", "$55,000.00", "coinbase", "2"),
+    ]);
+    let result = import_transactions(&csv.path().to_path_buf(), &mut conn, &config);
+    let err = result.unwrap_err();
+    assert!(err.contains("tax undisposed sats"), "got: {}", err);
+    assert!(err.contains("rolled back"), "got: {}", err);
+
+    assert!(get_acquisitions(&mut conn).is_empty(), "Import should be rolled back");
+}
+
+#[test]
+fn test_designation_other_wallet_rejected() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv_with_lot(&[
+        ("01/01/2025", "1.00000000", "$30,000.00", "ledger", ""),
+        ("02/01/2025", "1.00000000", "$50,000.00", "coinbase", ""),
+        ("06/01/2025", "-0.50000000", "$55,000.00", "coinbase", "1"),
+    ]);
+    let err = import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap_err();
+    assert!(err.contains("is in wallet 'ledger'"), "got: {}", err);
+    assert!(get_dispositions(&mut conn).is_empty(), "Import should be rolled back");
+}
+
+#[test]
+fn test_designation_unknown_lot_rejected() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv_with_lot(&[
+        ("01/01/2025", "1.00000000", "$30,000.00", "coinbase", ""),
+        ("06/01/2025", "-0.50000000", "$55,000.00", "coinbase", "01/02/2025|coinbase|1.0"),
+    ]);
+    let err = import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap_err();
+    assert!(err.contains("does not match any acquisition"), "got: {}", err);
+}
+
+#[test]
+fn test_designation_on_acquisition_rejected() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv_with_lot(&[
+        ("01/01/2025", "1.00000000", "$30,000.00", "coinbase", "1"),
+    ]);
+    let err = import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap_err();
+    assert!(err.contains("only dispositions can name lots"), "got: {}", err);
+}
+
+#[test]
+fn test_parse_lot_designation() {
+    assert_eq!(parse_lot_designation("17").unwrap(), vec![LotDesignation::Id(17)]);
+    assert_eq!(parse_lot_designation("3; 1").unwrap(), vec![LotDesignation::Id(3), LotDesignation::Id(1)]);
+
+    let parsed = parse_lot_designation("2025-02-01|coinbase|0.75").unwrap();
+    match &parsed[0] {
+        LotDesignation::Lot { date, wallet, satoshis } => {
+            assert_eq!(date.format("%Y-%m-%d").to_string(), "2025-02-01");
+            assert_eq!(wallet, "coinbase");
+            assert_eq!(*satoshis, 75_000_000);
+        }
+        other => panic!("Expected a date/wallet/amount designation, got {:?}", other),
+    }
+
+    assert!(parse_lot_designation("abc").is_err());
+    assert!(parse_lot_designation("01/01/2025|coinbase").is_err());
+    assert!(parse_lot_designation(" ; ").is_err());
+}
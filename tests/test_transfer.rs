@@ -1,6 +1,13 @@
 mod common;
 
-use common::{setup_test_db, create_test_csv_with_wallet, create_transfer_csv, default_config, get_acquisitions};
+use common::{
+    setup_test_db, create_test_csv_with_wallet, create_transfer_csv, create_transfer_csv_with_fee, default_config,
+    universal_config,
+    get_acquisitions, get_dispositions, get_tax_acq_disps, get_gaap_acq_disps,
+};
+use diesel::prelude::*;
+use rust_rgl_ledger::models::NewFairValue;
+use rust_rgl_ledger::schema::fair_values;
 use rust_rgl_ledger::commands::import::import_transactions;
 use rust_rgl_ledger::commands::transfer::transfer;
 
@@ -17,7 +24,7 @@ fn test_transfer_whole_lot() {
     let transfer_csv = create_transfer_csv(&[
         ("2024-06-15", "cold-storage", "exchange", "1.00000000"),
     ]);
-    transfer(&transfer_csv.path().to_path_buf(), &mut conn).unwrap();
+    transfer(&transfer_csv.path().to_path_buf(), &mut conn, &config, false).unwrap();
 
     let acqs = get_acquisitions(&mut conn);
     assert_eq!(acqs.len(), 1, "No new lots should be created for a whole-lot transfer");
@@ -43,7 +50,7 @@ fn test_transfer_requires_lot_split() {
     let transfer_csv = create_transfer_csv(&[
         ("2024-06-15", "cold-storage", "exchange", "0.75000000"),
     ]);
-    transfer(&transfer_csv.path().to_path_buf(), &mut conn).unwrap();
+    transfer(&transfer_csv.path().to_path_buf(), &mut conn, &config, false).unwrap();
 
     let acqs = get_acquisitions(&mut conn);
     assert_eq!(acqs.len(), 2, "Lot should be split into 2");
@@ -78,7 +85,7 @@ fn test_transfer_spans_multiple_lots_fifo() {
     let transfer_csv = create_transfer_csv(&[
         ("2024-06-15", "cold-storage", "exchange", "1.00000000"),
     ]);
-    transfer(&transfer_csv.path().to_path_buf(), &mut conn).unwrap();
+    transfer(&transfer_csv.path().to_path_buf(), &mut conn, &config, false).unwrap();
 
     let acqs = get_acquisitions(&mut conn);
     // 4 lots: 3 original (2 moved whole, 1 reduced) + 1 new from split
@@ -122,7 +129,7 @@ fn test_transfer_with_diverged_trackers() {
     let transfer_csv = create_transfer_csv(&[
         ("2024-07-01", "wallet-b", "wallet-c", "0.50000000"),
     ]);
-    transfer(&transfer_csv.path().to_path_buf(), &mut conn).unwrap();
+    transfer(&transfer_csv.path().to_path_buf(), &mut conn, &config, false).unwrap();
 
     let acqs = get_acquisitions(&mut conn);
     let lot_b_after = acqs.iter().find(|a| a.wallet == "wallet-b" && a.acquisition_date.format("%Y-%m-%d").to_string() == "2024-02-01").unwrap();
@@ -150,7 +157,7 @@ fn test_transfer_insufficient_sats_error() {
     let transfer_csv = create_transfer_csv(&[
         ("2024-06-15", "cold-storage", "exchange", "1.00000000"),
     ]);
-    let result = transfer(&transfer_csv.path().to_path_buf(), &mut conn);
+    let result = transfer(&transfer_csv.path().to_path_buf(), &mut conn, &config, false);
     assert!(result.is_err());
     assert!(result.unwrap_err().contains("Insufficient"));
 
@@ -176,7 +183,7 @@ fn test_transfer_multiple_rows_date_ordered() {
         ("2024-07-01", "wallet-b", "wallet-c", "0.30000000"),
         ("2024-06-01", "wallet-a", "wallet-b", "0.60000000"),
     ]);
-    transfer(&transfer_csv.path().to_path_buf(), &mut conn).unwrap();
+    transfer(&transfer_csv.path().to_path_buf(), &mut conn, &config, false).unwrap();
 
     let acqs = get_acquisitions(&mut conn);
 
@@ -207,7 +214,7 @@ fn test_transfer_split_sums_equal_original() {
     let transfer_csv = create_transfer_csv(&[
         ("2024-06-15", "cold-storage", "exchange", "0.33333333"),
     ]);
-    transfer(&transfer_csv.path().to_path_buf(), &mut conn).unwrap();
+    transfer(&transfer_csv.path().to_path_buf(), &mut conn, &config, false).unwrap();
 
     let acqs = get_acquisitions(&mut conn);
     assert_eq!(acqs.len(), 2);
@@ -237,7 +244,7 @@ fn test_transfer_rollback_on_error() {
         ("2024-06-01", "wallet-a", "wallet-c", "0.50000000"),
         ("2024-07-01", "wallet-b", "wallet-d", "0.50000000"),
     ]);
-    let result = transfer(&transfer_csv.path().to_path_buf(), &mut conn);
+    let result = transfer(&transfer_csv.path().to_path_buf(), &mut conn, &config, false);
     assert!(result.is_err());
 
     // First row's changes should be rolled back
@@ -252,3 +259,152 @@ fn test_transfer_rollback_on_error() {
     assert!(wallet_c_lots.is_empty(), "wallet-c should not exist after rollback");
     assert!(wallet_d_lots.is_empty(), "wallet-d should not exist after rollback");
 }
+
+#[test]
+fn test_transfer_fee_is_disposed_from_source_wallet() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+
+    let csv = create_test_csv_with_wallet(&[
+        ("2024-01-01", "1.00000000", "$40,000.00", "cold-storage"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    let transfer_csv = create_transfer_csv_with_fee(&[
+        ("2024-06-15", "cold-storage", "exchange", "0.50000000", "0.0001", "$50,000.00"),
+    ]);
+    let changes = transfer(&transfer_csv.path().to_path_buf(), &mut conn, &config, false).unwrap();
+    assert_eq!(changes.dispositions_created.len(), 1);
+
+    let disps = get_dispositions(&mut conn);
+    assert_eq!(disps.len(), 1);
+    assert_eq!(disps[0].wallet, "cold-storage");
+    assert_eq!(disps[0].satoshis, -10_000);
+    assert_eq!(disps[0].usd_cents_btc_basis, 5_000_000);
+
+    // 0.0001 BTC at $50k against a $40k lot
+    for matches in [get_tax_acq_disps(&mut conn), get_gaap_acq_disps(&mut conn)] {
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].satoshis, 10_000);
+        assert_eq!(matches[0].rgl, 100);
+    }
+
+    let acqs = get_acquisitions(&mut conn);
+    let exchange: i64 = acqs.iter().filter(|a| a.wallet == "exchange").map(|a| a.tax_undisposed_satoshis).sum();
+    let cold: i64 = acqs.iter().filter(|a| a.wallet == "cold-storage").map(|a| a.tax_undisposed_satoshis).sum();
+    assert_eq!(exchange, 49_990_000, "Destination receives the amount sent less the fee");
+    assert_eq!(cold, 50_000_000);
+}
+
+#[test]
+fn test_transfer_fee_priced_from_latest_mark() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+
+    let csv = create_test_csv_with_wallet(&[
+        ("2024-01-01", "1.00000000", "$40,000.00", "cold-storage"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+    for (date, cents) in [("2024-03-31", 4_500_000), ("2024-06-30", 6_000_000)] {
+        diesel::insert_into(fair_values::table)
+            .values(NewFairValue {
+                fair_value_cents: cents,
+                date: chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap().and_hms_opt(0, 0, 0).unwrap(),
+            })
+            .execute(&mut conn)
+            .unwrap();
+    }
+
+    let transfer_csv = create_transfer_csv_with_fee(&[
+        ("2024-06-15", "cold-storage", "exchange", "0.50000000", "0.0001 BTC", ""),
+    ]);
+    transfer(&transfer_csv.path().to_path_buf(), &mut conn, &config, false).unwrap();
+
+    assert_eq!(get_dispositions(&mut conn)[0].usd_cents_btc_basis, 4_500_000, "Uses the mark on or before the transfer");
+}
+
+#[test]
+fn test_transfer_fee_without_price_or_mark_is_rejected() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+
+    let csv = create_test_csv_with_wallet(&[
+        ("2024-01-01", "1.00000000", "$40,000.00", "cold-storage"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    let transfer_csv = create_transfer_csv_with_fee(&[
+        ("2024-06-15", "cold-storage", "exchange", "0.50000000", "0.0001", ""),
+    ]);
+    let err = transfer(&transfer_csv.path().to_path_buf(), &mut conn, &config, false).unwrap_err();
+    assert!(err.contains("needs a Price"), "got: {}", err);
+    assert!(get_dispositions(&mut conn).is_empty());
+    assert!(get_acquisitions(&mut conn).iter().all(|a| a.wallet == "cold-storage"));
+
+    let too_large = create_transfer_csv_with_fee(&[
+        ("2024-06-15", "cold-storage", "exchange", "0.50000000", "0.5", "$50,000.00"),
+    ]);
+    assert!(transfer(&too_large.path().to_path_buf(), &mut conn, &config, false).is_err());
+}
+
+#[test]
+fn test_backdated_transfer_with_fee_rematches_in_date_order() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+
+    let csv = create_test_csv_with_wallet(&[
+        ("2024-01-01", "1.00000000", "$40,000.00", "cold-storage"),
+        ("2024-02-01", "1.00000000", "$45,000.00", "cold-storage"),
+        ("2024-03-01", "-1.00000000", "$50,000.00", "cold-storage"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    // In January only the first lot was held, so it pays the fee and moves
+    let transfer_csv = create_transfer_csv_with_fee(&[
+        ("2024-01-15", "cold-storage", "exchange", "0.50000000", "0.0001", "$42,000.00"),
+    ]);
+    transfer(&transfer_csv.path().to_path_buf(), &mut conn, &config, false).unwrap();
+
+    let acqs = get_acquisitions(&mut conn);
+    let exchange = acqs.iter().find(|a| a.wallet == "exchange").unwrap();
+    assert_eq!(exchange.parent_acquisition_id, Some(1));
+    assert_eq!(exchange.tax_undisposed_satoshis, 49_990_000);
+
+    let disps = get_dispositions(&mut conn);
+    let fee = disps.iter().find(|d| d.satoshis == -10_000).unwrap();
+    let tax_ads = get_tax_acq_disps(&mut conn);
+    let fee_matches: Vec<_> = tax_ads.iter().filter(|ad| ad.disposition_id == fee.id).collect();
+    assert_eq!(fee_matches.len(), 1);
+    assert_eq!((fee_matches[0].acquisition_id, fee_matches[0].satoshis), (1, 10_000));
+
+    // The March sale is replayed against what the transfer left behind
+    let mut sale_matches: Vec<(i32, i64)> = tax_ads.iter()
+        .filter(|ad| ad.disposition_id != fee.id)
+        .map(|ad| (ad.acquisition_id, ad.satoshis))
+        .collect();
+    sale_matches.sort();
+    assert_eq!(sale_matches, vec![(1, 50_000_000), (2, 50_000_000)]);
+}
+
+#[test]
+fn test_transfer_fee_stays_in_source_wallet_under_universal_scope() {
+    let mut conn = setup_test_db();
+    let config = universal_config();
+
+    let csv = create_test_csv_with_wallet(&[
+        ("2024-01-01", "1.00000000", "$30,000.00", "hot"),
+        ("2024-02-01", "1.00000000", "$40,000.00", "cold-storage"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    let transfer_csv = create_transfer_csv_with_fee(&[
+        ("2024-06-15", "cold-storage", "exchange", "0.50000000", "0.0001", "$50,000.00"),
+    ]);
+    transfer(&transfer_csv.path().to_path_buf(), &mut conn, &config, false).unwrap();
+
+    let tax_ads = get_tax_acq_disps(&mut conn);
+    assert_eq!(tax_ads.len(), 1);
+    assert_eq!(tax_ads[0].acquisition_id, 2, "Fee is paid from the wallet being transferred out of");
+    let acqs = get_acquisitions(&mut conn);
+    assert_eq!(acqs[0].tax_undisposed_satoshis, 100_000_000);
+}
@@ -0,0 +1,118 @@
+mod common;
+
+use common::{setup_test_db, create_test_csv_with_wallet, create_transfer_csv, create_transfer_csv_with_fee, default_config};
+use diesel::prelude::*;
+use rust_rgl_ledger::commands::import::import_transactions;
+use rust_rgl_ledger::commands::transfer::transfer;
+use rust_rgl_ledger::commands::transfer_report::transfer_report;
+use rust_rgl_ledger::models::{Transfer, TransferAcquisition};
+use rust_rgl_ledger::schema::{transfers, transfer_acquisitions};
+use std::sync::Mutex;
+
+static CWD_LOCK: Mutex<()> = Mutex::new(());
+
+fn run_transfer_report_in_dir(beg: &str, end: &str, conn: &mut diesel::SqliteConnection) -> Vec<csv::StringRecord> {
+    let _lock = CWD_LOCK.lock().unwrap();
+    let tmp = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir_all(tmp.path().join("reports")).unwrap();
+
+    let original_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(tmp.path()).unwrap();
+    transfer_report(&beg.to_string(), &end.to_string(), conn).unwrap();
+    let content = std::fs::read_to_string("./reports/transfers_2024-01-01_2024-12-31.csv").unwrap();
+    std::env::set_current_dir(original_dir).unwrap();
+
+    let mut rdr = csv::Reader::from_reader(content.as_bytes());
+    let headers = rdr.headers().unwrap().clone();
+    assert_eq!(
+        headers.iter().collect::<Vec<_>>(),
+        vec!["TransferId", "TransferDate", "Kind", "FromWallet", "ToWallet", "AcquisitionId", "AcquisitionDate", "Btc", "CostPerBtc", "Basis"]
+    );
+    rdr.records().filter_map(|r| r.ok()).collect()
+}
+
+fn load_transfers(conn: &mut SqliteConnection) -> Vec<Transfer> {
+    transfers::table.order(transfers::id.asc()).select(Transfer::as_select()).load(conn).unwrap()
+}
+
+fn load_links(conn: &mut SqliteConnection) -> Vec<TransferAcquisition> {
+    transfer_acquisitions::table
+        .order((transfer_acquisitions::transfer_id.asc(), transfer_acquisitions::acquisition_id.asc()))
+        .select(TransferAcquisition::as_select())
+        .load(conn)
+        .unwrap()
+}
+
+#[test]
+fn test_transfer_records_history() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv_with_wallet(&[
+        ("2024-01-01", "1.00000000", "$40,000.00", "cold-storage"),
+        ("2024-02-01", "1.00000000", "$45,000.00", "cold-storage"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    let transfer_csv = create_transfer_csv(&[("2024-06-15 10:30:00", "cold-storage", "exchange", "1.25000000")]);
+    transfer(&transfer_csv.path().to_path_buf(), &mut conn, &config, false).unwrap();
+
+    let history = load_transfers(&mut conn);
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].transfer_date.format("%Y-%m-%d %H:%M:%S").to_string(), "2024-06-15 10:30:00");
+    assert_eq!((history[0].from_wallet.as_str(), history[0].to_wallet.as_str()), ("cold-storage", "exchange"));
+    assert_eq!(history[0].satoshis, 125_000_000);
+    assert_eq!(history[0].fee_disposition_id, None);
+
+    // Lot 1 moves whole; lot 2 is split and the new lot 3 carries 0.25 BTC
+    let links: Vec<(i32, i64)> = load_links(&mut conn).iter().map(|l| (l.acquisition_id, l.satoshis)).collect();
+    assert_eq!(links, vec![(1, 100_000_000), (3, 25_000_000)]);
+}
+
+#[test]
+fn test_transfer_history_rolled_back_on_error() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv_with_wallet(&[("2024-01-01", "1.00000000", "$40,000.00", "cold-storage")]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    let transfer_csv = create_transfer_csv(&[
+        ("2024-06-01", "cold-storage", "exchange", "0.50000000"),
+        ("2024-07-01", "cold-storage", "exchange", "5.00000000"),
+    ]);
+    assert!(transfer(&transfer_csv.path().to_path_buf(), &mut conn, &config, false).is_err());
+    assert!(load_transfers(&mut conn).is_empty());
+    assert!(load_links(&mut conn).is_empty());
+}
+
+#[test]
+fn test_transfer_report_lists_lots_and_fees() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv_with_wallet(&[
+        ("2024-01-01", "1.00000000", "$40,000.00", "cold-storage"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    let transfer_csv = create_transfer_csv_with_fee(&[
+        ("2024-06-15", "cold-storage", "exchange", "0.50000000", "0.0001", "$50,000.00"),
+    ]);
+    transfer(&transfer_csv.path().to_path_buf(), &mut conn, &config, false).unwrap();
+    assert!(load_transfers(&mut conn)[0].fee_disposition_id.is_some());
+
+    let rows = run_transfer_report_in_dir("01/01/2024", "12/31/2024", &mut conn);
+    assert_eq!(rows.len(), 2);
+
+    assert_eq!(&rows[0][2], "transfer");
+    assert_eq!((&rows[0][3], &rows[0][4]), ("cold-storage", "exchange"));
+    assert_eq!(&rows[0][5], "2");
+    assert_eq!(&rows[0][6], "2024-01-01T00:00:00");
+    assert_eq!(&rows[0][7], "0.4999");
+    assert_eq!(&rows[0][8], "40000");
+    assert_eq!(&rows[0][9], "19996");
+
+    assert_eq!(&rows[1][2], "fee");
+    assert_eq!(&rows[1][4], "");
+    assert_eq!(&rows[1][5], "1");
+    assert_eq!(&rows[1][7], "0.0001");
+    assert_eq!(&rows[1][9], "4");
+}
@@ -0,0 +1,126 @@
+mod common;
+
+use common::{setup_test_db, create_test_csv_with_fee, default_config};
+use rust_rgl_ledger::commands::import::import_transactions;
+use rust_rgl_ledger::commands::report::report;
+use rust_rgl_ledger::commands::txf::txf;
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+static CWD_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Debug)]
+struct TxfRecord {
+    reference: String,
+    description: String,
+    acquired: String,
+    sold: String,
+    cost: Decimal,
+    proceeds: Decimal,
+}
+
+/// Runs the TXF export and the tax CSV report over the same period and returns both files.
+fn run_exports(beg: &str, end: &str, conn: &mut diesel::SqliteConnection) -> (String, String) {
+    let _lock = CWD_LOCK.lock().unwrap();
+    let tmp = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir_all(tmp.path().join("reports")).unwrap();
+
+    let original_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(tmp.path()).unwrap();
+    txf(&beg.to_string(), &end.to_string(), conn).unwrap();
+    report(&beg.to_string(), &end.to_string(), "tax", conn).unwrap();
+    let read = |suffix: &str| -> String {
+        let path = std::fs::read_dir("./reports")
+            .unwrap()
+            .filter_map(|e| e.ok())
+            .find(|e| e.file_name().to_string_lossy().ends_with(suffix))
+            .unwrap()
+            .path();
+        std::fs::read_to_string(path).unwrap()
+    };
+    let files = (read(".txf"), read(".csv"));
+    std::env::set_current_dir(original_dir).unwrap();
+    files
+}
+
+/// Parses a TXF file into its header and detailed records.
+fn parse_txf(content: &str) -> (Vec<String>, Vec<TxfRecord>) {
+    assert!(content.ends_with("^\r\n") && !content.replace("\r\n", "").contains('\n'), "lines must end in CR LF");
+    let mut blocks = content.split("^\r\n").filter(|b| !b.is_empty());
+    let header: Vec<String> = blocks.next().unwrap().split("\r\n").filter(|l| !l.is_empty()).map(String::from).collect();
+
+    let records = blocks
+        .map(|block| {
+            let lines: Vec<&str> = block.split("\r\n").filter(|l| !l.is_empty()).collect();
+            assert_eq!(lines[0], "TD");
+            let field = |prefix: char| -> Vec<&str> {
+                lines.iter().filter(|l| l.starts_with(prefix)).map(|l| &l[1..]).collect()
+            };
+            let dates = field('D');
+            let amounts: Vec<Decimal> = field('$').iter().map(|a| a.parse().unwrap()).collect();
+            TxfRecord {
+                reference: field('N')[0].to_string(),
+                description: field('P')[0].to_string(),
+                acquired: dates[0].to_string(),
+                sold: dates[1].to_string(),
+                cost: amounts[0],
+                proceeds: amounts[1],
+            }
+        })
+        .collect();
+    (header, records)
+}
+
+#[test]
+fn test_txf_round_trips_to_the_tax_report_totals() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv_with_fee(&[
+        ("01/15/2023", "0.75000000", "$21,234.57", ""),
+        ("03/10/2024", "0.40000000", "$68,901.23", "$3.21"),
+        ("04/20/2024", "-0.83333333", "$64,444.44", "$12.34"),
+        ("09/05/2024", "-0.12345678", "$57,777.77", "0.00001 BTC"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    let (txf_content, csv_content) = run_exports("01/01/2024", "12/31/2024", &mut conn);
+    let (header, records) = parse_txf(&txf_content);
+    assert_eq!(header[0], "V042");
+    assert_eq!(header[1], "Arust_rgl_ledger");
+    // The April sale relieves both lots, and the September BTC fee is its own disposition
+    assert_eq!(records.len(), 4);
+    assert_eq!(
+        (records[0].reference.as_str(), records[0].description.as_str(), records[0].acquired.as_str(), records[0].sold.as_str()),
+        ("321", "0.08333333 BTC", "03/10/2024", "04/20/2024")
+    );
+    assert_eq!(records.iter().filter(|r| r.reference == "323").count(), 1);
+
+    // Each term's totals row in the CSV: DisposalFmv, Basis, Rgl, Term, Fee
+    let mut rdr = csv::Reader::from_reader(csv_content.as_bytes());
+    let totals: HashMap<String, (Decimal, Decimal, Decimal, Decimal)> = rdr.records()
+        .map(|r| r.unwrap())
+        .filter(|r| r[0].is_empty() && !r[8].is_empty())
+        .map(|r| (r[8].to_string(), (r[5].parse().unwrap(), r[9].parse().unwrap(), r[6].parse().unwrap(), r[7].parse().unwrap())))
+        .collect();
+    assert_eq!(totals.len(), 2);
+
+    for (term, reference) in [("short", "321"), ("long", "323")] {
+        let term_records: Vec<&TxfRecord> = records.iter().filter(|r| r.reference == reference).collect();
+        let cost: Decimal = term_records.iter().map(|r| r.cost).sum();
+        let proceeds: Decimal = term_records.iter().map(|r| r.proceeds).sum();
+        let (disposal_fmv, fee, basis, rgl) = totals[term];
+        assert_eq!(cost, basis, "{} cost basis", term);
+        assert_eq!(proceeds, disposal_fmv - fee, "{} proceeds", term);
+        assert_eq!(proceeds - cost, rgl, "{} gain", term);
+    }
+}
+
+#[test]
+fn test_txf_with_no_sales_writes_only_the_header() {
+    let mut conn = setup_test_db();
+    let (txf_content, _) = run_exports("01/01/2024", "12/31/2024", &mut conn);
+    let (header, records) = parse_txf(&txf_content);
+    assert_eq!(header.len(), 3);
+    assert!(records.is_empty());
+}
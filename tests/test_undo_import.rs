@@ -0,0 +1,133 @@
+mod common;
+
+use common::{
+    setup_test_db, create_test_csv, create_test_csv_with_wallet, create_transfer_csv, default_config,
+    get_acquisitions, get_dispositions, get_tax_acq_disps, get_gaap_acq_disps,
+};
+use diesel::prelude::*;
+use rust_rgl_ledger::commands::import::import_transactions;
+use rust_rgl_ledger::commands::transfer::transfer;
+use rust_rgl_ledger::commands::undo_import::undo_import;
+use rust_rgl_ledger::models::ImportBatch;
+use rust_rgl_ledger::schema::import_batches;
+
+fn load_batches(conn: &mut SqliteConnection) -> Vec<ImportBatch> {
+    import_batches::table.order(import_batches::id.asc()).select(ImportBatch::as_select()).load(conn).unwrap()
+}
+
+#[test]
+fn test_import_records_batch() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv(&[
+        ("01/01/2024", "1.00000000", "$40,000.00"),
+        ("02/01/2024", "-0.40000000", "$50,000.00"),
+    ]);
+    let summary = import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    let batches = load_batches(&mut conn);
+    assert_eq!(batches.len(), 1);
+    assert_eq!(summary.batch_id, batches[0].id);
+    assert_eq!(batches[0].file_name, csv.path().to_string_lossy());
+    assert_eq!(batches[0].row_count, 2);
+    assert_eq!(batches[0].file_hash.len(), 64);
+
+    assert!(get_acquisitions(&mut conn).iter().all(|a| a.import_batch_id == Some(summary.batch_id)));
+    assert!(get_dispositions(&mut conn).iter().all(|d| d.import_batch_id == Some(summary.batch_id)));
+
+    // The same file hashes the same on a second import
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+    let batches = load_batches(&mut conn);
+    assert_eq!(batches[1].file_hash, batches[0].file_hash);
+}
+
+#[test]
+fn test_undo_import_rematches_later_dispositions() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let first = create_test_csv(&[
+        ("03/01/2024", "1.00000000", "$40,000.00"),
+        ("04/01/2024", "-0.50000000", "$50,000.00"),
+    ]);
+    let first_batch = import_transactions(&first.path().to_path_buf(), &mut conn, &config).unwrap().batch_id;
+
+    // A mistaken backdated lot takes over the April sale's FIFO match
+    let mistake = create_test_csv(&[("01/01/2024", "1.00000000", "$30,000.00")]);
+    let mistake_batch = import_transactions(&mistake.path().to_path_buf(), &mut conn, &config).unwrap().batch_id;
+    assert!(get_tax_acq_disps(&mut conn).iter().all(|m| m.rgl == 1_000_000));
+
+    let summary = undo_import(mistake_batch, &mut conn, &config).unwrap();
+    assert_eq!(summary.deleted_acquisitions, 1);
+    assert_eq!(summary.deleted_dispositions, 0);
+    assert_eq!(summary.unwound, 2);
+
+    let acqs = get_acquisitions(&mut conn);
+    assert_eq!(acqs.len(), 1);
+    assert_eq!(acqs[0].import_batch_id, Some(first_batch));
+    assert_eq!(acqs[0].undisposed_satoshis, 50_000_000);
+    assert_eq!(acqs[0].tax_undisposed_satoshis, 50_000_000);
+
+    for matches in [get_tax_acq_disps(&mut conn), get_gaap_acq_disps(&mut conn)] {
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].acquisition_id, acqs[0].id);
+        assert_eq!(matches[0].rgl, 500_000);
+    }
+    assert_eq!(load_batches(&mut conn).len(), 1);
+}
+
+#[test]
+fn test_undo_import_removes_dispositions_and_restores_lots() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let buys = create_test_csv(&[("01/01/2024", "1.00000000", "$40,000.00")]);
+    import_transactions(&buys.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    let sales = create_test_csv(&[
+        ("02/01/2024", "-0.25000000", "$50,000.00"),
+        ("03/01/2024", "-0.25000000", "$55,000.00"),
+    ]);
+    let batch_id = import_transactions(&sales.path().to_path_buf(), &mut conn, &config).unwrap().batch_id;
+
+    let summary = undo_import(batch_id, &mut conn, &config).unwrap();
+    assert_eq!(summary.deleted_dispositions, 2);
+    assert_eq!(summary.unwound, 4);
+
+    assert!(get_dispositions(&mut conn).is_empty());
+    assert!(get_tax_acq_disps(&mut conn).is_empty());
+    assert!(get_gaap_acq_disps(&mut conn).is_empty());
+    let acqs = get_acquisitions(&mut conn);
+    assert_eq!(acqs[0].undisposed_satoshis, 100_000_000);
+    assert_eq!(acqs[0].tax_undisposed_satoshis, 100_000_000);
+}
+
+#[test]
+fn test_undo_import_fails_when_later_sale_needs_batch() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let buys = create_test_csv(&[("01/01/2024", "1.00000000", "$40,000.00")]);
+    let batch_id = import_transactions(&buys.path().to_path_buf(), &mut conn, &config).unwrap().batch_id;
+    let sales = create_test_csv(&[("02/01/2024", "-0.25000000", "$50,000.00")]);
+    import_transactions(&sales.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    let err = undo_import(batch_id, &mut conn, &config).unwrap_err();
+    assert!(err.contains("No undisposed acquisition lots available"), "got: {}", err);
+    assert_eq!(get_acquisitions(&mut conn).len(), 1);
+    assert_eq!(get_tax_acq_disps(&mut conn).len(), 1);
+    assert_eq!(load_batches(&mut conn).len(), 2);
+}
+
+#[test]
+fn test_undo_import_rejects_transferred_lots() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv_with_wallet(&[("2024-01-01", "1.00000000", "$40,000.00", "cold-storage")]);
+    let batch_id = import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap().batch_id;
+    let transfer_csv = create_transfer_csv(&[("2024-06-01", "cold-storage", "exchange", "1.00000000")]);
+    transfer(&transfer_csv.path().to_path_buf(), &mut conn, &config, false).unwrap();
+
+    let err = undo_import(batch_id, &mut conn, &config).unwrap_err();
+    assert!(err.contains("was moved by a transfer"), "got: {}", err);
+    assert_eq!(get_acquisitions(&mut conn).len(), 1);
+
+    assert_eq!(undo_import(99, &mut conn, &config).unwrap_err(), "Import batch 99 does not exist");
+}
@@ -0,0 +1,133 @@
+mod common;
+
+use common::{
+    setup_test_db, create_test_csv, create_test_csv_with_wallet, create_transfer_csv, default_config,
+    get_acquisitions, get_gaap_acq_disps, get_tax_acq_disps, get_fair_value_count, get_acq_fair_value_count,
+};
+use rust_rgl_ledger::commands::check::check_ledger;
+use rust_rgl_ledger::commands::import::import_transactions;
+use rust_rgl_ledger::commands::mark_to_market::mark_to_market;
+use rust_rgl_ledger::commands::period::close_period;
+use rust_rgl_ledger::commands::transfer::transfer;
+use rust_rgl_ledger::commands::undo_mark_to_market::undo_mark_to_market;
+use std::sync::Mutex;
+
+static CWD_LOCK: Mutex<()> = Mutex::new(());
+
+fn run_mtm_in_dir(price: &str, date: &str, conn: &mut diesel::SqliteConnection) {
+    let _lock = CWD_LOCK.lock().unwrap();
+    let tmp = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir_all(tmp.path().join("reports")).unwrap();
+
+    let original_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(tmp.path()).unwrap();
+    mark_to_market(&price.to_string(), &date.to_string(), conn, &default_config(), false).unwrap();
+    std::env::set_current_dir(original_dir).unwrap();
+}
+
+#[test]
+fn test_undo_restores_prior_marks() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv(&[("01/01/2024", "1.00000000", "$40,000.00")]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+    run_mtm_in_dir("$50,000.00", "03/31/2024", &mut conn);
+    let csv = create_test_csv(&[("04/15/2024", "1.00000000", "$55,000.00")]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+    // Mis-typed price
+    run_mtm_in_dir("$6,000.00", "06/30/2024", &mut conn);
+
+    let err = undo_mark_to_market("03/31/2024", false, &mut conn, &config).unwrap_err();
+    assert!(err.contains("dated 2024-06-30 was run after this one"), "got: {}", err);
+    assert!(undo_mark_to_market("05/31/2024", false, &mut conn, &config).unwrap_err().contains("No mark to market is dated 2024-05-31"));
+
+    let summary = undo_mark_to_market("06/30/2024", false, &mut conn, &config).unwrap();
+    assert_eq!(summary.fair_value_cents, 600_000);
+    assert_eq!(summary.lots_restored, 2);
+    assert_eq!(summary.unwound, 0);
+
+    let lots = get_acquisitions(&mut conn);
+    assert_eq!(lots[0].usd_cents_btc_fair_value, 5_000_000);
+    assert_eq!(lots[1].usd_cents_btc_fair_value, 5_500_000);
+    assert_eq!(get_fair_value_count(&mut conn), 1);
+    assert_eq!(get_acq_fair_value_count(&mut conn), 1);
+
+    undo_mark_to_market("03/31/2024", false, &mut conn, &config).unwrap();
+    assert_eq!(get_acquisitions(&mut conn)[0].usd_cents_btc_fair_value, 4_000_000);
+    assert_eq!(get_fair_value_count(&mut conn), 0);
+}
+
+#[test]
+fn test_undo_refuses_marks_used_by_later_dispositions_unless_rematched() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv(&[("01/01/2024", "1.00000000", "$40,000.00")]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+    run_mtm_in_dir("$6,000.00", "03/31/2024", &mut conn);
+    let sale = create_test_csv(&[("05/01/2024", "-0.50000000", "$60,000.00")]);
+    import_transactions(&sale.path().to_path_buf(), &mut conn, &config).unwrap();
+    assert_eq!(get_gaap_acq_disps(&mut conn)[0].basis, 300_000);
+
+    let err = undo_mark_to_market("03/31/2024", false, &mut conn, &config).unwrap_err();
+    assert!(err.contains("GAAP dispositions 1 were matched against the mark to market dated 2024-03-31"), "got: {}", err);
+    assert_eq!(get_fair_value_count(&mut conn), 1);
+
+    let summary = undo_mark_to_market("03/31/2024", true, &mut conn, &config).unwrap();
+    assert_eq!(summary.unwound, 1);
+    let gaap = get_gaap_acq_disps(&mut conn);
+    assert_eq!(gaap[0].basis, 2_000_000);
+    assert_eq!(gaap[0].rgl, 1_000_000);
+    assert_eq!(get_acquisitions(&mut conn)[0].usd_cents_btc_fair_value, 4_000_000);
+    assert!(check_ledger(&mut conn, &config).unwrap().violations.is_empty());
+}
+
+#[test]
+fn test_undo_restores_lots_split_after_the_mark() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv_with_wallet(&[("2024-01-01", "1.00000000", "$40,000.00", "cold-storage")]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+    run_mtm_in_dir("$50,000.00", "2024-03-31", &mut conn);
+    let transfer_csv = create_transfer_csv(&[("2024-04-01", "cold-storage", "exchange", "0.25000000")]);
+    transfer(&transfer_csv.path().to_path_buf(), &mut conn, &config, false).unwrap();
+
+    let summary = undo_mark_to_market("2024-03-31", false, &mut conn, &config).unwrap();
+    assert_eq!(summary.lots_restored, 2);
+    assert!(get_acquisitions(&mut conn).iter().all(|l| l.usd_cents_btc_fair_value == 4_000_000));
+    assert_eq!(get_acq_fair_value_count(&mut conn), 0);
+}
+
+#[test]
+fn test_undo_respects_period_lock() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv(&[("01/01/2024", "1.00000000", "$40,000.00")]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+    run_mtm_in_dir("$50,000.00", "03/31/2024", &mut conn);
+    close_period("03/31/2024", &mut conn).unwrap();
+
+    let err = undo_mark_to_market("03/31/2024", false, &mut conn, &config).unwrap_err();
+    assert!(err.starts_with("The mark to market is dated 2024-03-31, but the period is closed"), "got: {}", err);
+    assert_eq!(get_acquisitions(&mut conn)[0].usd_cents_btc_fair_value, 5_000_000);
+}
+
+#[test]
+fn test_undo_rematch_leaves_tax_matches_alone() {
+    let mut conn = setup_test_db();
+    let csv = create_test_csv(&[
+        ("01/01/2024", "1.00000000", "$40,000.00"),
+        ("02/01/2024", "1.00000000", "$42,000.00"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &default_config()).unwrap();
+    run_mtm_in_dir("$50,000.00", "03/31/2024", &mut conn);
+    let sale = create_test_csv(&[("05/01/2024", "-0.50000000", "$60,000.00")]);
+    import_transactions(&sale.path().to_path_buf(), &mut conn, &default_config()).unwrap();
+    let tax_before = get_tax_acq_disps(&mut conn);
+
+    // The tax method changed since the sale was matched; undoing a GAAP mark must not apply it
+    let config = rust_rgl_ledger::LotConfig { tax_lot_method: "lifo".to_string(), ..default_config() };
+    let summary = undo_mark_to_market("03/31/2024", true, &mut conn, &config).unwrap();
+    assert_eq!(summary.unwound, 1);
+    assert_eq!(get_gaap_acq_disps(&mut conn)[0].basis, 2_000_000);
+    assert_eq!(get_tax_acq_disps(&mut conn), tax_before);
+}
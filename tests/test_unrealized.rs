@@ -0,0 +1,126 @@
+mod common;
+
+use common::{setup_test_db, create_test_csv, default_config};
+use rust_rgl_ledger::commands::import::import_transactions;
+use rust_rgl_ledger::commands::mark_to_market::mark_to_market;
+use rust_rgl_ledger::commands::unrealized::unrealized;
+use rust_decimal::Decimal;
+use std::sync::Mutex;
+
+static CWD_LOCK: Mutex<()> = Mutex::new(());
+
+fn run_unrealized_in_dir(date: &str, price: &str, view: &str, conn: &mut diesel::SqliteConnection) -> Vec<Vec<String>> {
+    let _lock = CWD_LOCK.lock().unwrap();
+    let tmp = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir_all(tmp.path().join("reports")).unwrap();
+
+    let original_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(tmp.path()).unwrap();
+    let result = unrealized(&date.to_string(), price, view, conn, &default_config());
+    let rows = result.map(|_| {
+        let path = std::fs::read_dir("./reports").unwrap().next().unwrap().unwrap().path();
+        let mut rdr = csv::ReaderBuilder::new().has_headers(false).from_path(path).unwrap();
+        rdr.records().map(|r| r.unwrap().iter().map(String::from).collect()).collect()
+    });
+    std::env::set_current_dir(original_dir).unwrap();
+    rows.unwrap()
+}
+
+fn run_mtm_in_dir(price: &str, date: &str, conn: &mut diesel::SqliteConnection) {
+    let _lock = CWD_LOCK.lock().unwrap();
+    let tmp = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir_all(tmp.path().join("reports")).unwrap();
+
+    let original_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(tmp.path()).unwrap();
+    mark_to_market(&price.to_string(), &date.to_string(), conn, &default_config(), false).unwrap();
+    std::env::set_current_dir(original_dir).unwrap();
+}
+
+fn dec(s: &str) -> Decimal {
+    s.parse::<Decimal>().unwrap()
+}
+
+#[test]
+fn test_unrealized_splits_gain_by_holding_period() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv(&[
+        ("01/01/2023", "1.00000000", "$20,000.00"),
+        ("06/01/2024", "1.00000000", "$70,000.00"),
+        ("07/01/2024", "-0.50000000", "$60,000.00"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    let rows = run_unrealized_in_dir("12/31/2024", "$60,000.00", "tax", &mut conn);
+    assert_eq!(rows[0][0], "LotId");
+    assert_eq!(rows.len(), 4);
+
+    // First lot: half sold, held more than a year
+    let long = &rows[1];
+    assert_eq!(long[0], "1");
+    assert_eq!(dec(&long[3]), dec("0.5"));
+    assert_eq!(dec(&long[4]), dec("10000"));
+    assert_eq!(dec(&long[6]), dec("30000"));
+    assert_eq!(dec(&long[7]), dec("0"));
+    assert_eq!(dec(&long[8]), dec("20000"));
+    assert_eq!(long[10], "0");
+
+    // Second lot: underwater, held 213 days
+    let short = &rows[2];
+    assert_eq!(short[0], "2");
+    assert_eq!(dec(&short[4]), dec("70000"));
+    assert_eq!(dec(&short[7]), dec("-10000"));
+    assert_eq!(dec(&short[8]), dec("0"));
+    assert_eq!(short[10], "153");
+
+    let totals = &rows[3];
+    assert_eq!(dec(&totals[3]), dec("1.5"));
+    assert_eq!(dec(&totals[6]), dec("90000"));
+    assert_eq!(dec(&totals[7]), dec("-10000"));
+    assert_eq!(dec(&totals[8]), dec("20000"));
+}
+
+#[test]
+fn test_unrealized_as_of_earlier_date_adds_back_later_disposals() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv(&[
+        ("01/01/2024", "1.00000000", "$40,000.00"),
+        ("06/01/2024", "-1.00000000", "$60,000.00"),
+    ]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+
+    let rows = run_unrealized_in_dir("03/31/2024", "$50,000.00", "tax", &mut conn);
+    assert_eq!(rows.len(), 3);
+    assert_eq!(dec(&rows[1][3]), dec("1"));
+    assert_eq!(dec(&rows[1][7]), dec("10000"));
+    assert_eq!(rows[1][10], "276");
+
+    // Only the totals row is left once the lot is sold
+    let rows = run_unrealized_in_dir("12/31/2024", "$50,000.00", "tax", &mut conn);
+    assert_eq!(rows.len(), 1);
+    assert_eq!(dec(&rows[0][3]), dec("0"));
+}
+
+#[test]
+fn test_unrealized_uses_carrying_value_for_gaap_gain() {
+    let mut conn = setup_test_db();
+    let config = default_config();
+    let csv = create_test_csv(&[("01/01/2024", "1.00000000", "$40,000.00")]);
+    import_transactions(&csv.path().to_path_buf(), &mut conn, &config).unwrap();
+    run_mtm_in_dir("$55,000.00", "03/31/2024", &mut conn);
+
+    let rows = run_unrealized_in_dir("06/30/2024", "$60,000.00", "gaap", &mut conn);
+    assert_eq!(dec(&rows[1][4]), dec("40000"));
+    assert_eq!(dec(&rows[1][5]), dec("55000"));
+    assert_eq!(dec(&rows[1][7]), dec("20000"));
+    assert_eq!(dec(&rows[1][9]), dec("5000"));
+}
+
+#[test]
+fn test_unrealized_rejects_invalid_view() {
+    let mut conn = setup_test_db();
+    let err = unrealized(&"12/31/2024".to_string(), "$60,000.00", "book", &mut conn, &default_config()).unwrap_err();
+    assert!(err.to_string().contains("Invalid view 'book'"));
+}